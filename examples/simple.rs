@@ -23,7 +23,7 @@ async fn main() {
         status = 200_u16,
         start = unix_ms(),
         elapsed = 10_u64,
-        kv = log::as_serde!(kv);
+        kv:serde = kv;
         "",
     );
     // This log will be written to stdout:
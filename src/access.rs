@@ -0,0 +1,100 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! The access log records of the HTTP middlewares.
+
+use log::{kv::Value, Level, Record};
+
+use crate::{log_failure, Elapsed, Logger};
+
+/// The options of the access log of the HTTP middlewares.
+#[derive(Debug, Clone)]
+pub struct AccessLogOptions {
+    /// The target of the records, the default is `"access"`, so the access logs can be routed
+    /// to their own writer with [`Builder::with_target_writer`](crate::Builder::with_target_writer).
+    pub target: String,
+    /// The level of the records of the successful responses, the default is `Info`.
+    /// The records of the 4xx responses are logged at `Warn`, and of the 5xx responses and the errors at `Error`.
+    pub level: Level,
+    /// The request header of the request id, the default is `"x-request-id"`.
+    /// The `request_id` is omitted if the request has no such header.
+    pub request_id_header: String,
+    /// The logger of the records, the default is `None`, for the global logger.
+    pub logger: Option<Logger>,
+}
+
+impl Default for AccessLogOptions {
+    fn default() -> Self {
+        AccessLogOptions {
+            target: "access".to_string(),
+            level: Level::Info,
+            request_id_header: "x-request-id".to_string(),
+            logger: None,
+        }
+    }
+}
+
+// A request, and the status of its response and its error, if any, logged as one record.
+pub(crate) struct Access<'a> {
+    pub(crate) method: &'a str,
+    pub(crate) path: &'a str,
+    pub(crate) request_id: Option<&'a str>,
+    // `None` if the service failed without a response.
+    pub(crate) status: Option<u16>,
+    pub(crate) error: Option<&'a str>,
+    pub(crate) latency: Elapsed,
+}
+
+impl AccessLogOptions {
+    // Logs a request as `{"latency":{...},"message":"GET /hello 200","method":"GET","path":"/hello","status":200,...}`.
+    pub(crate) fn log(&self, access: &Access) {
+        let level = match access.status {
+            Some(status) if status >= 500 => Level::Error,
+            Some(status) if status >= 400 => Level::Warn,
+            Some(_) => self.level,
+            None => Level::Error,
+        };
+        if self.logger.is_none() && level > log::max_level() {
+            return;
+        }
+
+        let mut kvs: Vec<(&str, Value)> = vec![
+            ("method", Value::from(access.method)),
+            ("path", Value::from(access.path)),
+            ("latency", Value::from_serde(&access.latency)),
+        ];
+        if let Some(request_id) = access.request_id {
+            kvs.push(("request_id", Value::from(request_id)));
+        }
+        if let Some(error) = access.error {
+            kvs.push(("error", Value::from(error)));
+        }
+        let status = match access.status {
+            Some(status) => {
+                kvs.push(("status", Value::from(status)));
+                status.to_string()
+            }
+            None => "failed".to_string(),
+        };
+
+        self.write(
+            &Record::builder()
+                .args(format_args!("{} {} {}", access.method, access.path, status))
+                .level(level)
+                .target(&self.target)
+                .key_values(&kvs.as_slice())
+                .build(),
+        );
+    }
+
+    pub(crate) fn write(&self, record: &Record) {
+        match self.logger {
+            Some(ref logger) => {
+                if let Err(err) = logger.log_record(record) {
+                    log_failure(format!("failed to write the access log: {}", err).as_str());
+                }
+            }
+            None => log::logger().log(record),
+        }
+    }
+}
@@ -0,0 +1,235 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Actix Web Access Log
+//!
+//! An [`actix-web`](https://docs.rs/actix-web) middleware that logs one structured record per HTTP request
+//! through this logger, the counterpart of the [`tower`](crate::tower) layer for the actix ecosystem.
+//!
+//! A record has the `method`, the `path` without the query string, the `status` of the response,
+//! the `latency` as an [`Elapsed`](crate::Elapsed), the `request_id` read from the request header
+//! set in [`AccessLogOptions::request_id_header`], and the `error` of the handler, if any.
+//! The records have the `access` target by default, so they can be routed to their own writer
+//! with [`Builder::with_target_writer`](crate::Builder::with_target_writer).
+//!
+//! Example:
+//! ```rust,no_run
+//! use actix_web::{web, App, HttpServer};
+//! use structured_logger::{actix::AccessLog, json, Builder};
+//!
+//! fn main() -> std::io::Result<()> {
+//!     Builder::with_level("info")
+//!         .with_target_writer("access", json::new_writer(std::io::stdout()))
+//!         .init();
+//!
+//!     // {"latency":{"elapsed":"52.1µs","elapsed_ms":0},"level":"INFO","message":"GET / 200","method":"GET","path":"/","status":200,"target":"access",...}
+//!     actix_web::rt::System::new().block_on(
+//!         HttpServer::new(|| {
+//!             App::new()
+//!                 .wrap(AccessLog::default())
+//!                 .route("/", web::get().to(|| async { "hello world" }))
+//!         })
+//!         .bind("127.0.0.1:8080")?
+//!         .run(),
+//!     )
+//! }
+//! ```
+//!
+
+use actix_web::{
+    body::MessageBody,
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    Error,
+};
+use pin_project_lite::pin_project;
+use std::{
+    future::{ready, Future, Ready},
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+};
+
+use crate::access::Access;
+use crate::{start_timer, Stopwatch};
+
+pub use crate::access::AccessLogOptions;
+
+/// An actix-web middleware factory that logs the requests of the wrapped services,
+/// see the [module level documentation](self).
+#[derive(Debug, Clone, Default)]
+pub struct AccessLog {
+    opts: Arc<AccessLogOptions>,
+}
+
+impl AccessLog {
+    /// Creates a new AccessLog instance with the given options.
+    pub fn new(opts: AccessLogOptions) -> Self {
+        AccessLog {
+            opts: Arc::new(opts),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for AccessLog
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = AccessLogMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AccessLogMiddleware {
+            service,
+            opts: self.opts.clone(),
+        }))
+    }
+}
+
+/// The actix-web middleware created by [`AccessLog`].
+pub struct AccessLogMiddleware<S> {
+    service: S,
+    opts: Arc<AccessLogOptions>,
+}
+
+impl<S, B> Service<ServiceRequest> for AccessLogMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = ResponseFuture<S::Future>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let timer = start_timer();
+        let method = req.method().to_string();
+        let path = req.path().to_string();
+        let request_id = req
+            .headers()
+            .get(self.opts.request_id_header.as_str())
+            .and_then(|v| v.to_str().ok())
+            .map(String::from);
+        ResponseFuture {
+            inner: self.service.call(req),
+            opts: self.opts.clone(),
+            method,
+            path,
+            request_id,
+            timer,
+        }
+    }
+}
+
+pin_project! {
+    /// The response future of an [`AccessLogMiddleware`], it logs the request when the response is ready.
+    pub struct ResponseFuture<F> {
+        #[pin]
+        inner: F,
+        opts: Arc<AccessLogOptions>,
+        method: String,
+        path: String,
+        request_id: Option<String>,
+        timer: Stopwatch,
+    }
+}
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<ServiceResponse<B>, Error>>,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let res = ready!(this.inner.poll(cx));
+        // the errors of the handlers are converted to responses, with their status.
+        let (status, error) = match res {
+            Ok(ref res) => (
+                res.status().as_u16(),
+                res.response().error().map(|err| err.to_string()),
+            ),
+            Err(ref err) => (
+                err.as_response_error().status_code().as_u16(),
+                Some(err.to_string()),
+            ),
+        };
+        this.opts.log(&Access {
+            method: this.method,
+            path: this.path,
+            request_id: this.request_id.as_deref(),
+            status: Some(status),
+            error: error.as_deref(),
+            latency: this.timer.elapsed(),
+        });
+        Poll::Ready(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fn_writer, Builder, Key};
+    use actix_web::{error, test, web, App};
+    use std::sync::Mutex;
+
+    #[test]
+    fn access_log_middleware_works() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let logger = {
+            let lines = lines.clone();
+            Builder::with_level("info")
+                .with_default_writer(fn_writer(move |value| {
+                    let mut value = value.clone();
+                    assert!(value.remove(&Key::from("latency")).is_some());
+                    value.remove(&Key::from("timestamp"));
+                    let json = serde_json::to_string(&crate::fields::FieldMap(&value)).unwrap();
+                    lines.lock().unwrap().push(json);
+                    Ok(())
+                }))
+                .build()
+        };
+        let middleware = AccessLog::new(AccessLogOptions {
+            logger: Some(logger),
+            ..Default::default()
+        });
+
+        actix_web::rt::System::new().block_on(async move {
+            let app = test::init_service(
+                App::new()
+                    .wrap(middleware)
+                    .route("/hello", web::get().to(|| async { "hello" }))
+                    .route(
+                        "/fail",
+                        web::get().to(|| async {
+                            Err::<String, _>(error::ErrorServiceUnavailable("database is down"))
+                        }),
+                    ),
+            )
+            .await;
+            let req = test::TestRequest::get()
+                .uri("/hello?token=secret")
+                .insert_header(("x-request-id", "req-1"))
+                .to_request();
+            test::call_service(&app, req).await;
+            let req = test::TestRequest::post().uri("/missing").to_request();
+            test::call_service(&app, req).await;
+            let req = test::TestRequest::get().uri("/fail").to_request();
+            test::call_service(&app, req).await;
+        });
+
+        assert_eq!(
+            vec![
+                r#"{"level":"INFO","message":"GET /hello 200","method":"GET","path":"/hello","request_id":"req-1","status":200,"target":"access"}"#,
+                r#"{"level":"WARN","message":"POST /missing 404","method":"POST","path":"/missing","status":404,"target":"access"}"#,
+                r#"{"error":"database is down","level":"ERROR","message":"GET /fail 503","method":"GET","path":"/fail","status":503,"target":"access"}"#,
+            ],
+            *lines.lock().unwrap()
+        );
+    }
+}
@@ -7,49 +7,667 @@
 //! asynchronous in JSON format to a file, stderr, stdout, or any other destination, base on [`tokio`].
 //! To create a `Box<dyn Writer>` use the [`new_writer`] function.
 //!
+//! Encoded records are buffered in a bounded queue, the [`BackpressurePolicy`] decides what happens when it is full.
+//! Records are enqueued in call order and written in the same order by a single long-lived task per writer,
+//! which is spawned on the current tokio runtime by the first log call.
+//! A record logged outside of a tokio runtime before the task is spawned is rejected with an error.
+//! Use the [`ShutdownGuard`] returned by [`new_writer_with_guard`] to drain the queue before the process exits.
+//! On a current-thread runtime, such as `#[tokio::test]`, [`Writer::flush`] and [`Writer::shutdown`] can't block
+//! to wait for the queue and return a `WouldBlock` error, await [`ShutdownGuard::shutdown`] instead.
+//! The records are coalesced into batches of [`AsyncWriterOptions::batch_size`] records,
+//! or sized from the rate of the records with the [`AdaptiveBatching`].
+//!
 //! Example: <https://github.com/iorust/structured-logger/blob/main/examples/async_log.rs>
 //!
 //! [`tokio`]: https://crates.io/crates/tokio
 //!
 
-use std::{collections::BTreeMap, io, io::Write, pin::Pin, sync::Arc};
-use tokio::{io::AsyncWrite, sync::Mutex};
+use log::Level;
+use parking_lot::Mutex as SyncMutex;
+use std::{
+    collections::BTreeMap,
+    io,
+    io::Write,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, OnceLock,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::AsyncWrite,
+    runtime::{Handle, RuntimeFlavor},
+    sync::Mutex,
+};
+
+use crate::json::encode_owned;
+use crate::metrics::QueueMetrics;
+use crate::pool::BufferPool;
+use crate::queue::Queue;
+use crate::{log_failure, Fields, Key, Value, Writer};
+
+pub use crate::queue::{
+    AdaptiveBatching, BackpressurePolicy, DEFAULT_BATCH_SIZE, DEFAULT_QUEUE_CAPACITY,
+};
+
+/// How long [`Writer::flush`] waits for the queued records to be written and flushed.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The default time the [`ShutdownGuard`] waits for the queue to drain.
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// The options to create an [`AsyncJSONWriter`].
+#[derive(Debug, Clone)]
+pub struct AsyncWriterOptions {
+    /// The maximum number of records that can be buffered in the queue.
+    pub capacity: usize,
+    /// The policy to apply when the queue is full.
+    pub policy: BackpressurePolicy,
+    /// The maximum number of records coalesced into a single write.
+    pub batch_size: usize,
+    /// How long to wait for more records before writing a batch that is not full.
+    /// Zero (the default) writes the queued records immediately.
+    pub batch_interval: Duration,
+    /// The adaptive batching, which sizes the batches from the rate of the records instead of
+    /// the `batch_size` and `batch_interval`, the default is `None`. See [`AdaptiveBatching`].
+    pub adaptive: Option<AdaptiveBatching>,
+    /// The maximum time a write to the underlying writer may take, `None` (the default) waits forever.
+    /// A batch that times out is routed to the fallback writer, see [`AsyncJSONWriter::with_fallback`].
+    pub write_timeout: Option<Duration>,
+}
+
+impl Default for AsyncWriterOptions {
+    fn default() -> Self {
+        AsyncWriterOptions {
+            capacity: DEFAULT_QUEUE_CAPACITY,
+            policy: BackpressurePolicy::default(),
+            batch_size: DEFAULT_BATCH_SIZE,
+            batch_interval: Duration::ZERO,
+            adaptive: None,
+            write_timeout: None,
+        }
+    }
+}
 
-use crate::{log_failure, Key, Value, Writer};
+struct Shared<W: AsyncWrite + Sync + Send + 'static> {
+    w: Mutex<Pin<Box<W>>>,
+    queue: Queue,
+    write_timeout: Option<Duration>,
+    fallback: SyncMutex<Option<Box<dyn io::Write + Send>>>,
+    // whether the underlying writer has been shut down, by the writer or its guard.
+    shut_down: AtomicBool,
+    // the runtime of the consumer task, to drain the queue from outside of the runtime.
+    runtime: OnceLock<Handle>,
+}
 
 /// A Writer implementation that writes logs asynchronous in JSON format.
-pub struct AsyncJSONWriter<W: AsyncWrite + Sync + Send + 'static>(Arc<Mutex<Pin<Box<W>>>>);
+pub struct AsyncJSONWriter<W: AsyncWrite + Sync + Send + 'static> {
+    shared: Arc<Shared<W>>,
+    // whether the consumer task has been spawned.
+    started: AtomicBool,
+}
 
 impl<W: AsyncWrite + Sync + Send + 'static> AsyncJSONWriter<W> {
-    /// Creates a new AsyncJSONWriter instance.
+    /// Creates a new AsyncJSONWriter instance
+    /// with the [`DEFAULT_QUEUE_CAPACITY`] and the default [`BackpressurePolicy`].
     pub fn new(w: W) -> Self {
-        Self(Arc::new(Mutex::new(Box::pin(w))))
+        Self::with_policy(w, DEFAULT_QUEUE_CAPACITY, BackpressurePolicy::default())
+    }
+
+    /// Creates a new AsyncJSONWriter instance with a given queue `capacity` and backpressure `policy`.
+    pub fn with_policy(w: W, capacity: usize, policy: BackpressurePolicy) -> Self {
+        Self::with_options(
+            w,
+            AsyncWriterOptions {
+                capacity,
+                policy,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Creates a new AsyncJSONWriter instance with the given [`AsyncWriterOptions`].
+    pub fn with_options(w: W, opts: AsyncWriterOptions) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                w: Mutex::new(Box::pin(w)),
+                queue: Queue::new(
+                    opts.capacity,
+                    opts.policy,
+                    opts.batch_size,
+                    opts.batch_interval,
+                )
+                .with_adaptive(opts.adaptive),
+                write_timeout: opts.write_timeout,
+                fallback: SyncMutex::new(None),
+                shut_down: AtomicBool::new(false),
+                runtime: OnceLock::new(),
+            }),
+            started: AtomicBool::new(false),
+        }
+    }
+
+    /// Sets a std::io::Write instance, such as `std::io::stderr()`, that receives the records
+    /// whose write timed out, see [`AsyncWriterOptions::write_timeout`].
+    /// Without a fallback writer, these records are dropped.
+    pub fn with_fallback<F: io::Write + Send + 'static>(self, fallback: F) -> Self {
+        *self.shared.fallback.lock() = Some(Box::new(fallback));
+        self
+    }
+
+    /// Returns the number of records dropped because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.shared.queue.dropped()
+    }
+
+    /// Returns a [`QueueMetrics`] handle to read the queue depth and drop counters.
+    pub fn metrics(&self) -> QueueMetrics {
+        self.shared.queue.metrics()
+    }
+
+    /// Returns a [`ShutdownGuard`] that drains the queue and shuts down the underlying writer.
+    pub fn shutdown_guard(&self) -> ShutdownGuard<W> {
+        ShutdownGuard {
+            shared: self.shared.clone(),
+            timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            done: false,
+        }
     }
 }
 
 /// Implements Writer trait for AsyncJSONWriter.
 impl<W: AsyncWrite + Sync + Send + 'static> Writer for AsyncJSONWriter<W> {
     fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
-        let mut buf = Vec::with_capacity(256);
-        serde_json::to_writer(&mut buf, value).map_err(io::Error::from)?;
-        // must write the LINE FEED character.
-        buf.write_all(b"\n").map_err(io::Error::from)?;
-
-        let w = self.0.clone();
-        tokio::spawn(async move {
-            use tokio::io::AsyncWriteExt;
-
-            let mut w = w.lock().await;
-            if let Err(err) = w.as_mut().write_all(&buf).await {
-                // should never happen, but if it does, we log it.
-                log_failure(format!("AsyncJSONWriter failed to write log: {}", err).as_str());
+        self.enqueue(encode_owned(value)?, self.shared.queue.level_of(value))
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        self.enqueue(encode_owned(fields)?, self.shared.queue.level_of(fields))
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        drain_blocking(&self.shared, false, FLUSH_TIMEOUT)
+    }
+
+    fn shutdown(&self) -> Result<(), io::Error> {
+        self.shared.queue.close();
+        if self.shared.shut_down.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        drain_blocking(&self.shared, true, DEFAULT_SHUTDOWN_TIMEOUT)
+    }
+
+    fn healthy(&self) -> bool {
+        self.shared.queue.counters().health.healthy()
+    }
+}
+
+impl<W: AsyncWrite + Sync + Send + 'static> AsyncJSONWriter<W> {
+    fn enqueue(&self, buf: Vec<u8>, level: Option<Level>) -> Result<(), io::Error> {
+        let queue = &self.shared.queue;
+        if queue.is_closed() {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "AsyncJSONWriter has been shut down",
+            ));
+        }
+        if !self.started.load(Ordering::SeqCst) {
+            // the record is rejected, and the consumer task spawned by a later call, if there is no runtime.
+            let handle = Handle::try_current().map_err(|_| {
+                io::Error::other("AsyncJSONWriter must be used from within a tokio runtime")
+            })?;
+            if !self.started.swap(true, Ordering::SeqCst) {
+                let _ = self.shared.runtime.set(handle.clone());
+                handle.spawn(consume(self.shared.clone()));
             }
-        });
+        }
+
+        queue.push(buf, level);
         Ok(())
     }
 }
 
+impl<W: AsyncWrite + Sync + Send + 'static> Drop for AsyncJSONWriter<W> {
+    fn drop(&mut self) {
+        // no more records will be pushed, let the consumer task drain the queue and exit.
+        self.shared.queue.close();
+    }
+}
+
+/// A guard that drains the queued records of an [`AsyncJSONWriter`] and shuts down its underlying writer.
+///
+/// Call [`ShutdownGuard::shutdown`] at the end of `main`, or just hold the guard until then,
+/// dropping it drains the queue with a blocking wait.
+/// Records logged after the shutdown are rejected.
+#[must_use = "dropping the guard shuts down the async writer"]
+pub struct ShutdownGuard<W: AsyncWrite + Sync + Send + 'static> {
+    shared: Arc<Shared<W>>,
+    timeout: Duration,
+    done: bool,
+}
+
+impl<W: AsyncWrite + Sync + Send + 'static> ShutdownGuard<W> {
+    /// Sets how long the shutdown waits for the queue to drain, the default is [`DEFAULT_SHUTDOWN_TIMEOUT`].
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Waits for the queued records to be written, then flushes and shuts down the underlying writer.
+    /// Returns a `TimedOut` error if it doesn't complete within the timeout.
+    pub async fn shutdown(mut self) -> Result<(), io::Error> {
+        self.done = true;
+        self.shared.queue.close();
+        if self.shared.shut_down.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        match tokio::time::timeout(self.timeout, drain(&self.shared, true)).await {
+            Ok(res) => res,
+            Err(_) => Err(timed_out("AsyncJSONWriter shutdown timed out")),
+        }
+    }
+}
+
+impl<W: AsyncWrite + Sync + Send + 'static> Drop for ShutdownGuard<W> {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+
+        self.shared.queue.close();
+        if self.shared.shut_down.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        if let Err(err) = drain_blocking(&self.shared, true, self.timeout) {
+            log_failure(format!("AsyncJSONWriter failed to shut down: {}", err).as_str());
+        }
+    }
+}
+
+// The long-lived consumer task of a writer, it exits when the queue is closed and drained.
+async fn consume<W: AsyncWrite + Sync + Send + 'static>(shared: Arc<Shared<W>>) {
+    let queue = &shared.queue;
+    loop {
+        queue.wait().await;
+        // wait for more records if the batch is not full.
+        if let Some(delay) = queue.batch_delay() {
+            tokio::time::sleep(delay).await;
+        }
+
+        let mut w = shared.w.lock().await;
+        write_queued(&shared, &mut w).await;
+        if queue.is_closed() {
+            return;
+        }
+    }
+}
+
+// Records are popped while holding the writer lock, so only one task consumes the queue
+// at a time and records are written in the order they were enqueued.
+// The records of a batch are coalesced into a single write.
+async fn write_queued<W: AsyncWrite + Sync + Send + 'static>(
+    shared: &Shared<W>,
+    w: &mut Pin<Box<W>>,
+) {
+    use tokio::io::AsyncWriteExt;
+
+    let mut buf = BufferPool::global().get();
+    loop {
+        let n = shared.queue.pop_batch(&mut buf);
+        if n == 0 {
+            BufferPool::global().put(buf);
+            return;
+        }
+
+        let health = &shared.queue.counters().health;
+        let res = match shared.write_timeout {
+            None => health.track(w.as_mut().write_all(&buf).await),
+            Some(timeout) => {
+                match tokio::time::timeout(timeout, w.as_mut().write_all(&buf)).await {
+                    Ok(res) => health.track(res),
+                    Err(_) => {
+                        write_fallback(shared, &buf, n, timeout);
+                        Ok(())
+                    }
+                }
+            }
+        };
+        if let Err(err) = res {
+            // should never happen, but if it does, we log it.
+            log_failure(format!("AsyncJSONWriter failed to write log: {}", err).as_str());
+        }
+        buf.clear();
+    }
+}
+
+// Routes a batch that timed out to the fallback writer, part of it may have been written already.
+fn write_fallback<W: AsyncWrite + Sync + Send + 'static>(
+    shared: &Shared<W>,
+    buf: &[u8],
+    n: usize,
+    timeout: Duration,
+) {
+    shared.queue.counters().on_write_timeout();
+    shared.queue.counters().health.set(false);
+    let mut fallback = shared.fallback.lock();
+    let routed = match fallback.as_mut() {
+        Some(f) => f.write_all(buf).and_then(|_| f.flush()).is_ok(),
+        None => false,
+    };
+    log_failure(
+        format!(
+            "AsyncJSONWriter write timed out after {:?}, {} records {}",
+            timeout,
+            n,
+            if routed {
+                "routed to the fallback writer"
+            } else {
+                "dropped"
+            }
+        )
+        .as_str(),
+    );
+}
+
+// Writes the queued records, then flushes or shuts down the underlying writer.
+async fn drain<W: AsyncWrite + Sync + Send + 'static>(
+    shared: &Shared<W>,
+    shutdown: bool,
+) -> Result<(), io::Error> {
+    use tokio::io::AsyncWriteExt;
+
+    let mut w = shared.w.lock().await;
+    write_queued(shared, &mut w).await;
+    if shutdown {
+        w.as_mut().shutdown().await
+    } else {
+        w.as_mut().flush().await
+    }
+}
+
+// Runs `drain` on the current runtime and waits for it from a synchronous context.
+// It fails immediately on a current-thread runtime, whose only thread would be blocked by the wait,
+// the queue is drained by `ShutdownGuard::shutdown` there.
+fn drain_blocking<W: AsyncWrite + Sync + Send + 'static>(
+    shared: &Arc<Shared<W>>,
+    shutdown: bool,
+    timeout: Duration,
+) -> Result<(), io::Error> {
+    let current = Handle::try_current().ok();
+    if let Some(ref handle) = current {
+        if handle.runtime_flavor() == RuntimeFlavor::CurrentThread {
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "AsyncJSONWriter can't be drained by blocking a current-thread runtime, use ShutdownGuard::shutdown",
+            ));
+        }
+    }
+
+    let handle = match current.or_else(|| shared.runtime.get().cloned()) {
+        Some(handle) => handle,
+        // nothing to drain, the runtime may have been shut down.
+        None if shared.queue.len() == 0 => return Ok(()),
+        None => return Err(io::Error::other("AsyncJSONWriter runtime is not available")),
+    };
+
+    let (tx, rx) = mpsc::channel();
+    let shared = shared.clone();
+    handle.spawn(async move {
+        let _ = tx.send(drain(&shared, shutdown).await);
+    });
+
+    match rx.recv_timeout(timeout) {
+        Ok(res) => res,
+        Err(_) => Err(timed_out("AsyncJSONWriter drain timed out")),
+    }
+}
+
+fn timed_out(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, msg)
+}
+
 /// Creates a new `Box<dyn Writer>` instance with the AsyncJSONWriter for a given tokio::io::Write instance.
 pub fn new_writer<W: AsyncWrite + Sync + Send + 'static>(w: W) -> Box<dyn Writer> {
     Box::new(AsyncJSONWriter::new(w))
 }
+
+/// Creates a new `Box<dyn Writer>` instance with the AsyncJSONWriter for a given tokio::io::Write instance,
+/// with a given queue `capacity` and backpressure `policy`.
+pub fn new_writer_with_policy<W: AsyncWrite + Sync + Send + 'static>(
+    w: W,
+    capacity: usize,
+    policy: BackpressurePolicy,
+) -> Box<dyn Writer> {
+    Box::new(AsyncJSONWriter::with_policy(w, capacity, policy))
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the AsyncJSONWriter for a given tokio::io::Write instance,
+/// with the given [`AsyncWriterOptions`].
+pub fn new_writer_with_options<W: AsyncWrite + Sync + Send + 'static>(
+    w: W,
+    opts: AsyncWriterOptions,
+) -> Box<dyn Writer> {
+    Box::new(AsyncJSONWriter::with_options(w, opts))
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the AsyncJSONWriter for a given tokio::io::Write instance,
+/// and a [`ShutdownGuard`] to drain its queue before the process exits.
+pub fn new_writer_with_guard<W: AsyncWrite + Sync + Send + 'static>(
+    w: W,
+) -> (Box<dyn Writer>, ShutdownGuard<W>) {
+    let writer = AsyncJSONWriter::new(w);
+    let guard = writer.shutdown_guard();
+    (Box::new(writer), guard)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<parking_lot::Mutex<Vec<u8>>>);
+
+    impl AsyncWrite for SharedBuf {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> std::task::Poll<io::Result<usize>> {
+            self.0.lock().extend_from_slice(buf);
+            std::task::Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(
+            self: Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+    async fn write_order_works() {
+        let buf = SharedBuf::default();
+        let writer = AsyncJSONWriter::with_policy(buf.clone(), 10_000, BackpressurePolicy::Block);
+
+        for i in 0..1000_u64 {
+            let mut value = BTreeMap::new();
+            value.insert(Key::from("index"), Value::from(i));
+            writer.write_log(&value).unwrap();
+        }
+
+        writer.flush().unwrap();
+        let lines = buf.0.lock().iter().filter(|b| **b == b'\n').count();
+        assert_eq!(1000, lines);
+
+        let output = String::from_utf8(buf.0.lock().clone()).unwrap();
+        for (i, line) in output.lines().enumerate() {
+            assert_eq!(format!("{{\"index\":{}}}", i), line);
+        }
+    }
+
+    #[test]
+    fn write_outside_runtime_works() {
+        let buf = SharedBuf::default();
+        let writer = AsyncJSONWriter::new(buf.clone());
+
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("index"), Value::from(0));
+        assert!(writer.write_log(&value).is_err());
+        assert!(!writer.started.load(Ordering::SeqCst));
+        assert_eq!(0, writer.shared.queue.len());
+
+        // the consumer task is spawned by the first call from within a runtime.
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .build()
+            .unwrap();
+        let _guard = rt.enter();
+        value.insert(Key::from("index"), Value::from(1));
+        writer.write_log(&value).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(b"{\"index\":1}\n".to_vec(), buf.0.lock().clone());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn shutdown_guard_works() {
+        let buf = SharedBuf::default();
+        let writer = AsyncJSONWriter::new(buf.clone());
+        let guard = writer.shutdown_guard();
+
+        for i in 0..100_u64 {
+            let mut value = BTreeMap::new();
+            value.insert(Key::from("index"), Value::from(i));
+            writer.write_log(&value).unwrap();
+        }
+        guard.shutdown().await.unwrap();
+        assert_eq!(100, buf.0.lock().iter().filter(|b| **b == b'\n').count());
+
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("index"), Value::from(100));
+        assert!(writer.write_log(&value).is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn writer_shutdown_works() {
+        let buf = SharedBuf::default();
+        let writer = AsyncJSONWriter::new(buf.clone());
+        let guard = writer.shutdown_guard();
+
+        for i in 0..100_u64 {
+            let mut value = BTreeMap::new();
+            value.insert(Key::from("index"), Value::from(i));
+            writer.write_log(&value).unwrap();
+        }
+        tokio::task::block_in_place(|| Writer::shutdown(&writer)).unwrap();
+        assert_eq!(100, buf.0.lock().iter().filter(|b| **b == b'\n').count());
+
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("index"), Value::from(100));
+        assert!(writer.write_log(&value).is_err());
+        // the underlying writer has already been shut down.
+        guard.shutdown().await.unwrap();
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn flush_current_thread_works() {
+        let buf = SharedBuf::default();
+        let writer = AsyncJSONWriter::new(buf.clone());
+        let guard = writer.shutdown_guard();
+
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("index"), Value::from(0));
+        writer.write_log(&value).unwrap();
+
+        // the flush fails immediately instead of waiting for the timeout.
+        let start = std::time::Instant::now();
+        let err = writer.flush().unwrap_err();
+        assert_eq!(io::ErrorKind::WouldBlock, err.kind());
+        assert!(start.elapsed() < FLUSH_TIMEOUT);
+
+        guard.shutdown().await.unwrap();
+        assert_eq!(b"{\"index\":0}\n".to_vec(), buf.0.lock().clone());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn flush_multi_thread_works() {
+        let buf = SharedBuf::default();
+        let writer = AsyncJSONWriter::new(buf.clone());
+
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("index"), Value::from(0));
+        writer.write_log(&value).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(b"{\"index\":0}\n".to_vec(), buf.0.lock().clone());
+    }
+
+    struct PendingWriter;
+
+    impl AsyncWrite for PendingWriter {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            _buf: &[u8],
+        ) -> std::task::Poll<io::Result<usize>> {
+            std::task::Poll::Pending
+        }
+
+        fn poll_flush(
+            self: Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct SyncBuf(Arc<parking_lot::Mutex<Vec<u8>>>);
+
+    impl io::Write for SyncBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn write_timeout_works() {
+        let fallback = SyncBuf::default();
+        let writer = AsyncJSONWriter::with_options(
+            PendingWriter,
+            AsyncWriterOptions {
+                write_timeout: Some(Duration::from_millis(10)),
+                ..Default::default()
+            },
+        )
+        .with_fallback(fallback.clone());
+        let metrics = writer.metrics();
+
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("index"), Value::from(0));
+        writer.write_log(&value).unwrap();
+        writer.flush().unwrap();
+
+        assert_eq!(b"{\"index\":0}\n".to_vec(), fallback.0.lock().clone());
+        assert_eq!(1, metrics.write_timeouts());
+    }
+}
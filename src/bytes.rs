@@ -0,0 +1,358 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Byte-string Values
+//!
+//! The byte strings, such as protocol payload snippets, are serialized by `serde` as sequences of numbers,
+//! and their `Display` and `Debug` output doesn't round-trip. A byte-string value, serialized with
+//! `serialize_bytes` such as the [`Bytes`] wrapper or a `serde_bytes` value, is written as a string
+//! with the [`BytesEncoding`] set by [`Builder::with_bytes_encoding`](crate::Builder::with_bytes_encoding):
+//! * [`BytesEncoding::Lossy`], the default: the UTF-8 string, with the invalid sequences replaced by `U+FFFD`;
+//! * [`BytesEncoding::Base64`]: the standard base64 encoding, with padding, and the key has a `_base64` suffix;
+//! * [`BytesEncoding::Hex`]: the lowercase hex encoding, and the key has a `_hex` suffix.
+//!
+//! Only the top-level byte-string values of a record are encoded, the byte strings nested in a
+//! structured value are serialized by `serde` as usual.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{bytes::{Bytes, BytesEncoding}, Builder};
+//!
+//! fn main() {
+//!     Builder::with_level("info")
+//!         .with_bytes_encoding(BytesEncoding::Base64)
+//!         .init();
+//!
+//!     let payload = [0x16, 0x03, 0x01, 0x02, 0x00];
+//!     // {"level":"INFO","message":"handshake","payload_base64":"FgMBAgA=",...}
+//!     log::info!(payload = Bytes(&payload); "handshake");
+//! }
+//! ```
+//!
+
+use log::kv::{ToValue, Value};
+use serde::ser::{self, Impossible, Serialize, Serializer};
+use std::{
+    borrow::Cow,
+    fmt,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+/// The encoding of the byte-string values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BytesEncoding {
+    /// The UTF-8 string, with the invalid sequences replaced by `U+FFFD`.
+    #[default]
+    Lossy,
+    /// The standard base64 encoding, with padding, the key has a `_base64` suffix.
+    Base64,
+    /// The lowercase hex encoding, the key has a `_hex` suffix.
+    Hex,
+}
+
+impl BytesEncoding {
+    /// Returns the suffix appended to the keys of the encoded values.
+    pub fn key_suffix(self) -> &'static str {
+        match self {
+            BytesEncoding::Lossy => "",
+            BytesEncoding::Base64 => "_base64",
+            BytesEncoding::Hex => "_hex",
+        }
+    }
+
+    /// Encodes a byte string as a string.
+    pub fn encode(self, bytes: &[u8]) -> Cow<'_, str> {
+        match self {
+            BytesEncoding::Lossy => String::from_utf8_lossy(bytes),
+            BytesEncoding::Base64 => Cow::Owned(base64(bytes)),
+            BytesEncoding::Hex => Cow::Owned(hex(bytes)),
+        }
+    }
+}
+
+// The process-wide encoding, set by `Builder::init` and `Builder::try_init`.
+static ENCODING: AtomicU8 = AtomicU8::new(BytesEncoding::Lossy as u8);
+
+pub(crate) fn set_encoding(encoding: BytesEncoding) {
+    ENCODING.store(encoding as u8, Ordering::Relaxed);
+}
+
+pub(crate) fn encoding() -> BytesEncoding {
+    match ENCODING.load(Ordering::Relaxed) {
+        1 => BytesEncoding::Base64,
+        2 => BytesEncoding::Hex,
+        _ => BytesEncoding::Lossy,
+    }
+}
+
+/// A byte-string value, such as a protocol payload snippet, written with the [`BytesEncoding`] of the logger
+/// when passed as a key-value.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Bytes<'a>(pub &'a [u8]);
+
+impl fmt::Debug for Bytes<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", String::from_utf8_lossy(self.0))
+    }
+}
+
+impl fmt::Display for Bytes<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&encoding().encode(self.0))
+    }
+}
+
+impl Serialize for Bytes<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+impl ToValue for Bytes<'_> {
+    fn to_value(&self) -> Value<'_> {
+        Value::from_serde(self)
+    }
+}
+
+/// Returns the key suffix and the encoded string of a byte-string value, or `None` for the other values.
+pub(crate) fn encode_value(value: &Value) -> Option<(&'static str, String)> {
+    // the primitive values are never byte strings, skip the probe.
+    if value.to_borrowed_str().is_some()
+        || value.to_u64().is_some()
+        || value.to_i64().is_some()
+        || value.to_f64().is_some()
+        || value.to_bool().is_some()
+    {
+        return None;
+    }
+    let encoding = encoding();
+    value
+        .serialize(Probe(encoding))
+        .ok()
+        .map(|s| (encoding.key_suffix(), s))
+}
+
+const BASE64_CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                s.push(BASE64_CHARS[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                s.push('=');
+            }
+        }
+    }
+    s
+}
+
+fn hex(bytes: &[u8]) -> String {
+    const HEX_CHARS: &[u8; 16] = b"0123456789abcdef";
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        s.push(HEX_CHARS[(b >> 4) as usize] as char);
+        s.push(HEX_CHARS[(b & 0xf) as usize] as char);
+    }
+    s
+}
+
+// The error of the probe for a value that is not a byte string.
+#[derive(Debug)]
+struct NotBytes;
+
+impl fmt::Display for NotBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("not a byte string")
+    }
+}
+
+impl std::error::Error for NotBytes {}
+
+impl ser::Error for NotBytes {
+    fn custom<T: fmt::Display>(_msg: T) -> Self {
+        NotBytes
+    }
+}
+
+// A serializer that encodes a byte string, and fails fast on the other values,
+// without formatting the `Debug` and `Display` values.
+struct Probe(BytesEncoding);
+
+macro_rules! not_bytes {
+    ($($name:ident($ty:ty)),*) => {
+        $(
+            fn $name(self, _v: $ty) -> Result<String, NotBytes> {
+                Err(NotBytes)
+            }
+        )*
+    };
+}
+
+impl Serializer for Probe {
+    type Ok = String;
+    type Error = NotBytes;
+    type SerializeSeq = Impossible<String, NotBytes>;
+    type SerializeTuple = Impossible<String, NotBytes>;
+    type SerializeTupleStruct = Impossible<String, NotBytes>;
+    type SerializeTupleVariant = Impossible<String, NotBytes>;
+    type SerializeMap = Impossible<String, NotBytes>;
+    type SerializeStruct = Impossible<String, NotBytes>;
+    type SerializeStructVariant = Impossible<String, NotBytes>;
+
+    not_bytes!(
+        serialize_bool(bool),
+        serialize_i8(i8),
+        serialize_i16(i16),
+        serialize_i32(i32),
+        serialize_i64(i64),
+        serialize_u8(u8),
+        serialize_u16(u16),
+        serialize_u32(u32),
+        serialize_u64(u64),
+        serialize_f32(f32),
+        serialize_f64(f64),
+        serialize_char(char),
+        serialize_str(&str),
+        serialize_unit_struct(&'static str)
+    );
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<String, NotBytes> {
+        Ok(self.0.encode(v).into_owned())
+    }
+
+    fn serialize_none(self) -> Result<String, NotBytes> {
+        Err(NotBytes)
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<String, NotBytes> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<String, NotBytes> {
+        Err(NotBytes)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<String, NotBytes> {
+        Err(NotBytes)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<String, NotBytes> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<String, NotBytes> {
+        Err(NotBytes)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, NotBytes> {
+        Err(NotBytes)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, NotBytes> {
+        Err(NotBytes)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, NotBytes> {
+        Err(NotBytes)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, NotBytes> {
+        Err(NotBytes)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, NotBytes> {
+        Err(NotBytes)
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, NotBytes> {
+        Err(NotBytes)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, NotBytes> {
+        Err(NotBytes)
+    }
+
+    fn collect_str<T: ?Sized + fmt::Display>(self, _value: &T) -> Result<String, NotBytes> {
+        Err(NotBytes)
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_works() {
+        assert_eq!("", base64(b""));
+        assert_eq!("Zg==", base64(b"f"));
+        assert_eq!("Zm8=", base64(b"fo"));
+        assert_eq!("Zm9v", base64(b"foo"));
+        assert_eq!("Zm9vYg==", base64(b"foob"));
+        assert_eq!("FgMBAgA=", base64(&[0x16, 0x03, 0x01, 0x02, 0x00]));
+        assert_eq!("00ff7f", hex(&[0x00, 0xff, 0x7f]));
+        assert_eq!("a\u{fffd}b", BytesEncoding::Lossy.encode(b"a\xffb"));
+
+        let payload = [b'o', b'k', 0xff];
+        let bytes = Bytes(&payload);
+        let value = bytes.to_value();
+        let (suffix, encoded) = encode_value(&value).unwrap();
+        assert_eq!(encoding().key_suffix(), suffix);
+        assert_eq!(encoding().encode(&payload), encoded);
+        assert!(encode_value(&Value::from("ok")).is_none());
+        assert!(encode_value(&Value::from_debug(&payload)).is_none());
+        assert!(encode_value(&Value::from_serde(&[1, 2, 3])).is_none());
+
+        let mut map = std::collections::BTreeMap::new();
+        map.insert(log::kv::Key::from("payload"), value);
+        let expected = format!(
+            r#"{{"payload{}":"{}"}}"#,
+            encoding().key_suffix(),
+            encoding().encode(&payload)
+        );
+        assert_eq!(
+            expected,
+            serde_json::to_string(&crate::fields::FieldMap(&map)).unwrap()
+        );
+    }
+}
@@ -0,0 +1,547 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # ClickHouse Writer Implementation
+//!
+//! A [`Writer`] implementation that inserts structured values into a ClickHouse table
+//! through the [HTTP interface](https://clickhouse.com/docs/en/interfaces/http), in the `JSONEachRow` format,
+//! with the [`ureq`](https://docs.rs/ureq) client. The fields of a record are inserted into the columns
+//! of the same names, the fields without a column are skipped, in a table such as:
+//! ```sql
+//! CREATE TABLE logs (
+//!     timestamp UInt64,
+//!     time      DateTime64(3) MATERIALIZED fromUnixTimestamp64Milli(toInt64(timestamp)),
+//!     level     LowCardinality(String),
+//!     target    LowCardinality(String),
+//!     message   String
+//! ) ENGINE = MergeTree ORDER BY time;
+//! ```
+//!
+//! Records are encoded on the logging thread, and sent over a bounded channel to a worker thread that
+//! inserts them in batches of [`ClickHouseOptions::batch_size`] records, or every [`ClickHouseOptions::flush_interval`].
+//! A batch that fails with a network error or a server error is retried with backoff,
+//! up to [`ClickHouseOptions::max_retries`] times, then dropped and reported to the failure handler.
+//! The records are dropped when the channel is full, see [`ClickHouseWriter::dropped`].
+//! To create a `Box<dyn Writer>` use the [`new_writer`] function.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{clickhouse, Builder};
+//!
+//! fn main() {
+//!     let opts = clickhouse::ClickHouseOptions {
+//!         url: "http://127.0.0.1:8123".to_string(),
+//!         table: "app.logs".to_string(),
+//!         ..Default::default()
+//!     };
+//!     Builder::with_level("info")
+//!         .with_default_writer(clickhouse::new_writer(opts))
+//!         .init();
+//!
+//!     log::info!("hello world");
+//! }
+//! ```
+//!
+
+use std::{
+    collections::BTreeMap,
+    io,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::json::encode_owned;
+use crate::metrics::{QueueCounters, QueueMetrics};
+use crate::pool::BufferPool;
+use crate::retry::Backoff;
+use crate::{log_failure, Fields, Key, Value, Writer};
+
+/// How long [`Writer::flush`] waits for the worker thread to insert the queued records.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The options of a [`ClickHouseWriter`].
+#[derive(Debug, Clone)]
+pub struct ClickHouseOptions {
+    /// The URL of the HTTP interface, the default is `"http://localhost:8123"`.
+    pub url: String,
+    /// The table of the records, with an optional database, such as `"app.logs"`. The default is `"logs"`.
+    /// The names separated by `.` are quoted as identifiers in the `INSERT` query, so they are case-sensitive.
+    pub table: String,
+    /// The user, the default is `None`, for the `default` user.
+    pub user: Option<String>,
+    /// The password of the user, the default is `None`.
+    pub password: Option<String>,
+    /// The maximum number of records inserted by a request, the default is 10,000.
+    pub batch_size: usize,
+    /// How long the records wait for a batch to fill before they are inserted, the default is 1 second.
+    pub flush_interval: Duration,
+    /// The maximum number of records that can be buffered in the channel, the default is 128,000.
+    pub capacity: usize,
+    /// How many times a failed batch is retried, the default is 3.
+    pub max_retries: u32,
+    /// The backoff before the first retry, doubled on each retry, with jitter, the default is 200 ms.
+    /// See [`Backoff`].
+    pub initial_backoff: Duration,
+    /// The timeout of a request, the default is 10 seconds.
+    pub timeout: Duration,
+}
+
+impl Default for ClickHouseOptions {
+    fn default() -> Self {
+        ClickHouseOptions {
+            url: "http://localhost:8123".to_string(),
+            table: "logs".to_string(),
+            user: None,
+            password: None,
+            batch_size: 10_000,
+            flush_interval: Duration::from_secs(1),
+            capacity: 128_000,
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(200),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+enum Msg {
+    Record(Vec<u8>),
+    Flush(mpsc::Sender<io::Result<()>>),
+    Shutdown,
+}
+
+/// A Writer implementation that inserts logs into ClickHouse on a dedicated worker thread.
+pub struct ClickHouseWriter {
+    sender: SyncSender<Msg>,
+    counters: Arc<QueueCounters>,
+}
+
+impl ClickHouseWriter {
+    /// Creates a new ClickHouseWriter instance, and starts its worker thread,
+    /// which stops when the writer is dropped.
+    pub fn new(opts: ClickHouseOptions) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(opts.capacity.max(1));
+        let counters = Arc::new(QueueCounters::default());
+        let agent: ureq::Agent = ureq::Agent::config_builder()
+            .http_status_as_error(false)
+            .timeout_global(Some(opts.timeout))
+            .build()
+            .into();
+        let worker = Worker {
+            agent,
+            query: format!(
+                "INSERT INTO {} FORMAT JSONEachRow",
+                quote_table(&opts.table)
+            ),
+            opts,
+            receiver,
+            counters: counters.clone(),
+            batch: Vec::new(),
+            records: 0,
+        };
+        thread::Builder::new()
+            .name("structured-logger-clickhouse".to_string())
+            .spawn(move || worker.run())
+            .expect("failed to spawn the ClickHouse writer thread");
+        ClickHouseWriter { sender, counters }
+    }
+
+    /// Returns the number of records dropped because the channel was full.
+    pub fn dropped(&self) -> u64 {
+        self.counters.dropped()
+    }
+
+    /// Returns a [`QueueMetrics`] handle to read the channel depth and drop counters.
+    pub fn metrics(&self) -> QueueMetrics {
+        QueueMetrics(self.counters.clone())
+    }
+
+    fn send(&self, buf: Vec<u8>) -> Result<(), io::Error> {
+        // count the record before sending, the worker may pop it immediately.
+        self.counters.on_push();
+        match self.sender.try_send(Msg::Record(buf)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(msg)) => {
+                if let Msg::Record(buf) = msg {
+                    BufferPool::global().put(buf);
+                }
+                self.counters.on_pop(1);
+                self.counters.on_drop();
+                Ok(())
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                self.counters.on_pop(1);
+                Err(worker_stopped())
+            }
+        }
+    }
+}
+
+/// Implements Writer trait for ClickHouseWriter.
+impl Writer for ClickHouseWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        self.send(encode_owned(value)?)
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        self.send(encode_owned(fields)?)
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        let (tx, rx) = mpsc::channel();
+        self.sender
+            .send(Msg::Flush(tx))
+            .map_err(|_| worker_stopped())?;
+        match rx.recv_timeout(FLUSH_TIMEOUT) {
+            Ok(res) => res,
+            Err(RecvTimeoutError::Timeout) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "ClickHouseWriter flush timed out",
+            )),
+            Err(RecvTimeoutError::Disconnected) => Err(worker_stopped()),
+        }
+    }
+
+    fn shutdown(&self) -> Result<(), io::Error> {
+        self.flush()?;
+        let _ = self.sender.send(Msg::Shutdown);
+        Ok(())
+    }
+
+    fn healthy(&self) -> bool {
+        self.counters.health.healthy()
+    }
+}
+
+struct Worker {
+    agent: ureq::Agent,
+    query: String,
+    opts: ClickHouseOptions,
+    receiver: Receiver<Msg>,
+    counters: Arc<QueueCounters>,
+    // the JSON lines of the records of the current batch.
+    batch: Vec<u8>,
+    records: usize,
+}
+
+impl Worker {
+    fn run(mut self) {
+        // when the current batch must be inserted, `None` if it is empty.
+        let mut deadline: Option<Instant> = None;
+        loop {
+            let msg = match deadline {
+                None => self
+                    .receiver
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected),
+                Some(deadline) => self
+                    .receiver
+                    .recv_timeout(deadline.saturating_duration_since(Instant::now())),
+            };
+            match msg {
+                Ok(Msg::Record(buf)) => {
+                    self.counters.on_pop(1);
+                    self.batch.extend_from_slice(&buf);
+                    BufferPool::global().put(buf);
+                    self.records += 1;
+                    if self.records >= self.opts.batch_size {
+                        let _ = self.insert();
+                        deadline = None;
+                    } else if deadline.is_none() {
+                        deadline = Some(Instant::now() + self.opts.flush_interval);
+                    }
+                }
+                Ok(Msg::Flush(ack)) => {
+                    let _ = ack.send(self.insert());
+                    deadline = None;
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    let _ = self.insert();
+                    deadline = None;
+                }
+                Ok(Msg::Shutdown) | Err(RecvTimeoutError::Disconnected) => {
+                    let _ = self.insert();
+                    return;
+                }
+            }
+        }
+    }
+
+    // Inserts the current batch, retries it on the transient errors, and drops it after the last retry.
+    fn insert(&mut self) -> Result<(), io::Error> {
+        if self.records == 0 {
+            return Ok(());
+        }
+
+        let backoff = Backoff {
+            initial: self.opts.initial_backoff,
+            max: Duration::MAX,
+            max_attempts: self.opts.max_retries.saturating_add(1),
+            jitter: true,
+        };
+        let mut attempts = 0;
+        let res = loop {
+            attempts += 1;
+            match self.post() {
+                Ok(()) => break Ok(()),
+                Err((_, true)) if backoff.can_retry(attempts) => {
+                    self.counters.health.set(false);
+                    thread::sleep(backoff.delay(attempts));
+                }
+                Err((err, _)) => {
+                    log_failure(
+                        format!("ClickHouseWriter dropped {} logs: {}", self.records, err).as_str(),
+                    );
+                    break Err(err);
+                }
+            }
+        };
+        self.batch.clear();
+        self.records = 0;
+        self.counters.health.track(res)
+    }
+
+    // Returns the error, and whether it is transient.
+    fn post(&self) -> Result<(), (io::Error, bool)> {
+        let mut req = self
+            .agent
+            .post(&self.opts.url)
+            .query("query", &self.query)
+            .query("input_format_skip_unknown_fields", "1");
+        if let Some(ref user) = self.opts.user {
+            req = req.header("X-ClickHouse-User", user);
+        }
+        if let Some(ref password) = self.opts.password {
+            req = req.header("X-ClickHouse-Key", password);
+        }
+
+        let mut res = req
+            .send(&self.batch[..])
+            .map_err(|err| (io::Error::other(err), true))?;
+        let status = res.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        let body = res.body_mut().read_to_string().unwrap_or_default();
+        Err((
+            io::Error::other(format!("ClickHouse returned {}: {}", status, body.trim())),
+            status.is_server_error(),
+        ))
+    }
+}
+
+// Quotes the names of a table, optionally qualified by its database, as ClickHouse identifiers.
+fn quote_table(table: &str) -> String {
+    table
+        .split('.')
+        .map(|name| format!("`{}`", name.replace('\\', "\\\\").replace('`', "\\`")))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+fn worker_stopped() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        "ClickHouseWriter worker has stopped",
+    )
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the ClickHouseWriter.
+pub fn new_writer(opts: ClickHouseOptions) -> Box<dyn Writer> {
+    Box::new(ClickHouseWriter::new(opts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    // A minimal HTTP server that replies with the given statuses,
+    // it returns the request lines and the bodies of the requests.
+    fn serve(listener: TcpListener, statuses: Vec<u16>) -> Vec<(String, String)> {
+        let mut requests = Vec::new();
+        for status in statuses {
+            let (stream, _) = listener.accept().unwrap();
+            let mut w = stream.try_clone().unwrap();
+            let mut r = BufReader::new(stream);
+            let mut request_line = String::new();
+            r.read_line(&mut request_line).unwrap();
+            let mut len = 0;
+            loop {
+                let mut line = String::new();
+                r.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some(v) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    len = v.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0_u8; len];
+            r.read_exact(&mut body).unwrap();
+            w.write_all(
+                format!(
+                    "HTTP/1.1 {} X\r\nContent-Length: 5\r\nConnection: close\r\n\r\nerror",
+                    status
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+            requests.push((request_line, String::from_utf8(body).unwrap()));
+        }
+        requests
+    }
+
+    #[test]
+    fn clickhouse_writer_works() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || serve(listener, vec![503, 200]));
+
+        let writer = ClickHouseWriter::new(ClickHouseOptions {
+            url,
+            table: "app.logs".to_string(),
+            batch_size: 2,
+            flush_interval: Duration::from_secs(60),
+            initial_backoff: Duration::from_millis(10),
+            ..Default::default()
+        });
+        for i in 0..2 {
+            let mut value = BTreeMap::new();
+            value.insert(Key::from("message"), Value::from("hello"));
+            value.insert(Key::from("i"), Value::from(i));
+            writer.write_log(&value).unwrap();
+        }
+        writer.flush().unwrap();
+
+        let requests = server.join().unwrap();
+        assert_eq!(2, requests.len(), "the failed batch is retried");
+        assert_eq!(requests[0], requests[1]);
+        assert!(requests[1].0.starts_with(
+            "POST /?query=INSERT%20INTO%20%60app%60.%60logs%60%20FORMAT%20JSONEachRow&input_format_skip_unknown_fields=1 "
+        ));
+        assert_eq!(
+            "{\"i\":0,\"message\":\"hello\"}\n{\"i\":1,\"message\":\"hello\"}\n",
+            requests[1].1
+        );
+        assert_eq!(0, writer.dropped());
+    }
+
+    fn write(writer: &ClickHouseWriter, i: i64) -> Result<(), io::Error> {
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("i"), Value::from(i));
+        writer.write_log(&value)
+    }
+
+    #[test]
+    fn clickhouse_writer_drop_works() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || serve(listener, vec![400, 503, 503, 200]));
+
+        let writer = ClickHouseWriter::new(ClickHouseOptions {
+            url,
+            max_retries: 1,
+            flush_interval: Duration::from_secs(60),
+            initial_backoff: Duration::from_millis(10),
+            ..Default::default()
+        });
+        // a client error is not retried.
+        write(&writer, 0).unwrap();
+        assert!(writer.flush().is_err());
+        assert!(!writer.healthy());
+        // a server error is retried, then the batch is dropped.
+        write(&writer, 1).unwrap();
+        assert!(writer.flush().is_err());
+        assert!(!writer.healthy());
+        // the next batch doesn't contain the dropped records.
+        write(&writer, 2).unwrap();
+        writer.flush().unwrap();
+        assert!(writer.healthy());
+
+        let requests = server.join().unwrap();
+        let bodies: Vec<&str> = requests.iter().map(|r| r.1.as_str()).collect();
+        assert_eq!(
+            vec!["{\"i\":0}\n", "{\"i\":1}\n", "{\"i\":1}\n", "{\"i\":2}\n"],
+            bodies
+        );
+    }
+
+    #[test]
+    fn clickhouse_writer_full_works() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (release, released) = mpsc::channel::<()>();
+        let server = thread::spawn(move || {
+            released.recv().unwrap();
+            serve(listener, vec![200, 200])
+        });
+
+        let writer = ClickHouseWriter::new(ClickHouseOptions {
+            url,
+            batch_size: 1,
+            capacity: 1,
+            ..Default::default()
+        });
+        let metrics = writer.metrics();
+        // the worker is blocked by the server in the insert of the first record.
+        write(&writer, 0).unwrap();
+        while metrics.queue_len() > 0 {
+            thread::yield_now();
+        }
+        // the second record fills the channel, the others are dropped.
+        for i in 1..5 {
+            write(&writer, i).unwrap();
+        }
+        assert_eq!(1, metrics.queue_len());
+        assert_eq!(3, writer.dropped());
+        release.send(()).unwrap();
+        writer.flush().unwrap();
+
+        let requests = server.join().unwrap();
+        let bodies: Vec<&str> = requests.iter().map(|r| r.1.as_str()).collect();
+        assert_eq!(vec!["{\"i\":0}\n", "{\"i\":1}\n"], bodies);
+        assert_eq!(3, writer.dropped());
+    }
+
+    #[test]
+    fn clickhouse_writer_shutdown_works() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || serve(listener, vec![200]));
+
+        let writer = ClickHouseWriter::new(ClickHouseOptions {
+            url,
+            flush_interval: Duration::from_secs(60),
+            ..Default::default()
+        });
+        for i in 0..3 {
+            write(&writer, i).unwrap();
+        }
+        // the queued records are inserted before the worker stops.
+        writer.shutdown().unwrap();
+        let requests = server.join().unwrap();
+        assert_eq!(1, requests.len());
+        assert_eq!("{\"i\":0}\n{\"i\":1}\n{\"i\":2}\n", requests[0].1);
+
+        // the writer fails once the worker has stopped.
+        while write(&writer, 3).is_ok() {
+            thread::yield_now();
+        }
+        assert!(writer.flush().is_err());
+    }
+
+    #[test]
+    fn quote_table_works() {
+        assert_eq!("`logs`", quote_table("logs"));
+        assert_eq!("`app`.`Logs`", quote_table("app.Logs"));
+        assert_eq!(
+            "`logs FORMAT CSV; DROP TABLE users`",
+            quote_table("logs FORMAT CSV; DROP TABLE users")
+        );
+        assert_eq!(r"`logs\` (a) --`", quote_table("logs` (a) --"));
+        assert_eq!(r"`logs\\\``", quote_table(r"logs\`"));
+    }
+}
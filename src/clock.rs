@@ -0,0 +1,140 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Clock
+//!
+//! The [`Clock`] trait is the source of the `timestamp` of the records, the [`SystemClock`] by default.
+//! A custom clock is set with [`Builder::with_clock`](crate::Builder::with_clock), such as a [`ManualClock`]
+//! for deterministic tests, or the virtual time of a simulation framework.
+//! A function that returns the unix timestamp in milliseconds is a clock too.
+//!
+//! Example:
+//! ```rust
+//! use std::time::Duration;
+//! use structured_logger::{clock::ManualClock, json, Builder};
+//!
+//! fn main() {
+//!     let clock = ManualClock::new(1679745592127);
+//!     Builder::with_level("info")
+//!         .with_clock(clock.clone())
+//!         .with_default_writer(json::new_writer(std::io::stdout()))
+//!         .init();
+//!
+//!     // {"level":"INFO","message":"hello world","target":"rust_out","timestamp":1679745592127}
+//!     log::info!("hello world");
+//!     clock.advance(Duration::from_secs(1));
+//!     // {"level":"INFO","message":"hello world","target":"rust_out","timestamp":1679745593127}
+//!     log::info!("hello world");
+//! }
+//! ```
+//!
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// A source of the `timestamp` of the records, see the [module level documentation](self).
+pub trait Clock: Send + Sync + 'static {
+    /// Returns the current unix timestamp in milliseconds.
+    fn unix_ms(&self) -> u64;
+}
+
+/// Implements Clock trait for the functions that return the unix timestamp in milliseconds,
+/// such as [`coarse_unix_ms`](crate::coarse_unix_ms).
+impl<F> Clock for F
+where
+    F: Fn() -> u64 + Send + Sync + 'static,
+{
+    fn unix_ms(&self) -> u64 {
+        self()
+    }
+}
+
+/// The system clock, see [`unix_ms`](crate::unix_ms).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn unix_ms(&self) -> u64 {
+        crate::unix_ms()
+    }
+}
+
+/// A clock that only moves when it is set or advanced. The clones share the same time.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock(Arc<AtomicU64>);
+
+impl ManualClock {
+    /// Creates a new ManualClock instance at the given unix timestamp in milliseconds.
+    pub fn new(unix_ms: u64) -> Self {
+        ManualClock(Arc::new(AtomicU64::new(unix_ms)))
+    }
+
+    /// Sets the time to the given unix timestamp in milliseconds.
+    pub fn set(&self, unix_ms: u64) {
+        self.0.store(unix_ms, Ordering::SeqCst);
+    }
+
+    /// Moves the time forward by `d`.
+    pub fn advance(&self, d: Duration) {
+        self.0.fetch_add(d.as_millis() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn unix_ms(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fn_writer, Builder, Key};
+    use log::{Level, Log, Record};
+    use parking_lot::Mutex;
+
+    #[test]
+    fn clock_works() {
+        let timestamps = Arc::new(Mutex::new(Vec::new()));
+        let w = {
+            let timestamps = timestamps.clone();
+            fn_writer(move |value| {
+                let ts = value.get(&Key::from("timestamp")).and_then(|v| v.to_u64());
+                timestamps.lock().push(ts.unwrap());
+                Ok(())
+            })
+        };
+        let clock = ManualClock::new(1000);
+        let logger = Builder::with_level("info")
+            .with_clock(clock.clone())
+            .with_monotonic_timestamp()
+            .with_default_writer(w)
+            .build();
+        let log = || {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("hello"))
+                    .level(Level::Info)
+                    .target("app")
+                    .build(),
+            )
+        };
+
+        log();
+        clock.advance(Duration::from_millis(1500));
+        log();
+        // the monotonic timestamps still apply to a custom clock.
+        clock.set(2000);
+        log();
+        assert_eq!(vec![1000, 2500, 2501], *timestamps.lock());
+
+        let now = SystemClock.unix_ms();
+        assert!(now >= 1679745592127);
+        assert!((|| 42).unix_ms() == 42);
+    }
+}
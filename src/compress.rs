@@ -0,0 +1,303 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Streaming Compression
+//!
+//! A `std::io::Write` wrapper that compresses the bytes written to whatever it wraps, a file, a TCP stream,
+//! or any other destination, with gzip (the `gzip` feature) or zstd (the `zstd` feature).
+//!
+//! The compressed stream is split at periodic flush points, every [`CompressOptions::flush_bytes`]
+//! uncompressed bytes or [`CompressOptions::flush_interval`], checked on each write and flush:
+//! the current gzip member or zstd frame is completed, and a new one is started.
+//! The concatenated members and frames are read by the standard tools, such as `zcat` or `zstdcat`.
+//! Between two flush points, [`Write::flush`] writes the compressed blocks to the wrapped writer,
+//! so the logs written before a crash can still be decoded by a streaming decoder,
+//! up to the last flush, such as on [`shutdown`](crate::shutdown).
+//!
+//! To create a `Box<dyn Writer>` with the [`json`](crate::json) writer use the [`new_writer`] function.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{compress, json::FileOptions, Builder};
+//!
+//! fn main() {
+//!     let file = FileOptions::new()
+//!         .open_file(std::env::temp_dir().join("app.log.gz"))
+//!         .unwrap();
+//!     Builder::with_level("info")
+//!         .with_default_writer(compress::new_writer(file, compress::CompressOptions::default()).unwrap())
+//!         .init();
+//!
+//!     log::info!("hello world");
+//! }
+//! ```
+//!
+
+use std::{
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+use crate::Writer;
+
+/// The compression format of a [`CompressedWrite`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Codec {
+    /// The gzip format, the default with the `gzip` feature.
+    #[cfg(feature = "gzip")]
+    #[default]
+    Gzip,
+    /// The zstd format, the default without the `gzip` feature.
+    #[cfg(feature = "zstd")]
+    #[cfg_attr(not(feature = "gzip"), default)]
+    Zstd,
+}
+
+/// The options of a [`CompressedWrite`].
+#[derive(Debug, Clone)]
+pub struct CompressOptions {
+    /// The compression format, the default is gzip with the `gzip` feature, zstd otherwise.
+    pub codec: Codec,
+    /// The compression level, from 0 to 9 for gzip and from 1 to 22 for zstd,
+    /// the default is `None`, for the default level of the format.
+    pub level: Option<u32>,
+    /// The maximum number of uncompressed bytes between two flush points, the default is 1 MiB.
+    pub flush_bytes: u64,
+    /// The maximum time between two flush points, the default is 10 seconds.
+    pub flush_interval: Duration,
+}
+
+impl Default for CompressOptions {
+    fn default() -> Self {
+        CompressOptions {
+            codec: Codec::default(),
+            level: None,
+            flush_bytes: 1024 * 1024,
+            flush_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+enum Encoder<W: Write> {
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<W>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+}
+
+impl<W: Write> Encoder<W> {
+    fn new(w: W, codec: Codec, level: Option<u32>) -> Result<Self, io::Error> {
+        match codec {
+            #[cfg(feature = "gzip")]
+            Codec::Gzip => {
+                let level = level.map_or_else(flate2::Compression::default, |l| {
+                    flate2::Compression::new(l.min(9))
+                });
+                Ok(Encoder::Gzip(flate2::write::GzEncoder::new(w, level)))
+            }
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => {
+                // 0 is the default level of zstd.
+                let level = level.map_or(0, |l| l.min(22) as i32);
+                Ok(Encoder::Zstd(zstd::stream::write::Encoder::new(w, level)?))
+            }
+        }
+    }
+
+    fn get_ref(&self) -> &W {
+        match self {
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(e) => e.get_ref(),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(e) => e.get_ref(),
+        }
+    }
+
+    fn as_write(&mut self) -> &mut dyn Write {
+        match self {
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(e) => e,
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(e) => e,
+        }
+    }
+
+    // Completes the gzip member or the zstd frame, and returns the wrapped writer.
+    fn finish(self) -> Result<W, io::Error> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(e) => e.finish(),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(e) => e.finish(),
+        }
+    }
+}
+
+/// A `std::io::Write` implementation that compresses the bytes written to a wrapped writer,
+/// see the [module level documentation](self).
+pub struct CompressedWrite<W: Write> {
+    // `None` if a flush point failed, the wrapped writer is lost.
+    encoder: Option<Encoder<W>>,
+    opts: CompressOptions,
+    // the uncompressed bytes written since the last flush point.
+    pending: u64,
+    // whether bytes were written since the last flush.
+    unflushed: bool,
+    last_point: Instant,
+}
+
+impl<W: Write> CompressedWrite<W> {
+    /// Creates a new CompressedWrite instance that compresses the bytes written to `w`.
+    pub fn new(w: W, opts: CompressOptions) -> Result<Self, io::Error> {
+        Ok(CompressedWrite {
+            encoder: Some(Encoder::new(w, opts.codec, opts.level)?),
+            opts,
+            pending: 0,
+            unflushed: false,
+            last_point: Instant::now(),
+        })
+    }
+
+    /// Returns a reference to the wrapped writer, `None` if it was lost on an error.
+    pub fn get_ref(&self) -> Option<&W> {
+        self.encoder.as_ref().map(|e| e.get_ref())
+    }
+
+    /// Completes the compressed stream, and returns the wrapped writer.
+    pub fn finish(mut self) -> Result<W, io::Error> {
+        let encoder = self.encoder.take().ok_or_else(closed)?;
+        encoder.finish()
+    }
+
+    fn encoder(&mut self) -> Result<&mut dyn Write, io::Error> {
+        match self.encoder {
+            Some(ref mut e) => Ok(e.as_write()),
+            None => Err(closed()),
+        }
+    }
+
+    fn should_flush_point(&self) -> bool {
+        self.pending > 0
+            && (self.pending >= self.opts.flush_bytes
+                || self.last_point.elapsed() >= self.opts.flush_interval)
+    }
+
+    // Completes the current gzip member or zstd frame, and starts a new one.
+    fn flush_point(&mut self) -> Result<(), io::Error> {
+        let encoder = self.encoder.take().ok_or_else(closed)?;
+        let mut w = encoder.finish()?;
+        w.flush()?;
+        self.encoder = Some(Encoder::new(w, self.opts.codec, self.opts.level)?);
+        self.pending = 0;
+        self.unflushed = false;
+        self.last_point = Instant::now();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for CompressedWrite<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.encoder()?.write(buf)?;
+        self.pending += n as u64;
+        self.unflushed = true;
+        if self.should_flush_point() {
+            self.flush_point()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.should_flush_point() {
+            return self.flush_point();
+        }
+        if self.unflushed {
+            // writes the compressed blocks, the wrapped writer is flushed too.
+            self.encoder()?.flush()?;
+            self.unflushed = false;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Drop for CompressedWrite<W> {
+    fn drop(&mut self) {
+        if let Some(encoder) = self.encoder.take() {
+            let _ = encoder.finish();
+        }
+    }
+}
+
+fn closed() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        "CompressedWrite failed on a previous error",
+    )
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the [`JSONWriter`](crate::json::JSONWriter)
+/// for a [`CompressedWrite`] that compresses the logs written to `w`.
+pub fn new_writer<W: Write + Sync + Send + 'static>(
+    w: W,
+    opts: CompressOptions,
+) -> Result<Box<dyn Writer>, io::Error> {
+    Ok(crate::json::new_writer(CompressedWrite::new(w, opts)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    // Decodes the compressed bytes, up to the first error, such as a truncated stream.
+    fn decode(codec: Codec, data: &[u8]) -> String {
+        let mut out = Vec::new();
+        let _ = match codec {
+            #[cfg(feature = "gzip")]
+            Codec::Gzip => flate2::read::MultiGzDecoder::new(data).read_to_end(&mut out),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => zstd::stream::read::Decoder::new(data)
+                .unwrap()
+                .read_to_end(&mut out),
+        };
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn compressed_write_works() {
+        let codecs = [
+            #[cfg(feature = "gzip")]
+            Codec::Gzip,
+            #[cfg(feature = "zstd")]
+            Codec::Zstd,
+        ];
+        for codec in codecs {
+            let opts = CompressOptions {
+                codec,
+                flush_bytes: 16,
+                ..Default::default()
+            };
+            let mut w = CompressedWrite::new(Vec::new(), opts).unwrap();
+            w.write_all(b"{\"line\":1}\n").unwrap();
+            w.write_all(b"{\"line\":2}\n").unwrap();
+            w.write_all(b"{\"line\":3}\n").unwrap();
+            // the first two lines are in a completed member or frame.
+            assert_eq!(
+                "{\"line\":1}\n{\"line\":2}\n",
+                decode(codec, w.get_ref().unwrap())
+            );
+
+            // the flushed line can be decoded from the truncated stream, as after a crash.
+            w.flush().unwrap();
+            assert_eq!(
+                "{\"line\":1}\n{\"line\":2}\n{\"line\":3}\n",
+                decode(codec, w.get_ref().unwrap())
+            );
+
+            let data = w.finish().unwrap();
+            assert_eq!(
+                "{\"line\":1}\n{\"line\":2}\n{\"line\":3}\n",
+                decode(codec, &data)
+            );
+        }
+    }
+}
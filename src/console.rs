@@ -0,0 +1,164 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Console Writer Implementation
+//!
+//! A [`Writer`] implementation that logs structured values in JSON format to the console,
+//! for the frontend and edge runtimes compiled to WebAssembly:
+//! with `console.error`, `console.warn` or `console.log` on `wasm32-unknown-unknown`,
+//! and to stdout on the other targets, such as WASI.
+//! To create a `Box<dyn Writer>` use the [`new_writer`] function.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{console, Builder};
+//!
+//! fn main() {
+//!     Builder::with_level("info")
+//!         .with_default_writer(console::new_writer())
+//!         .init();
+//!
+//!     log::info!("hello world");
+//! }
+//! ```
+//!
+
+use log::kv::{Key, Value};
+use std::{collections::BTreeMap, io};
+
+use crate::json::with_encoded;
+use crate::{Fields, Writer};
+
+/// A Writer implementation that writes logs in JSON format to the console.
+#[derive(Debug, Default)]
+pub struct ConsoleWriter;
+
+impl ConsoleWriter {
+    /// Creates a new ConsoleWriter instance.
+    pub fn new() -> Self {
+        ConsoleWriter
+    }
+}
+
+/// Implements Writer trait for ConsoleWriter.
+impl Writer for ConsoleWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        let level = value
+            .get(&Key::from("level"))
+            .and_then(|v| v.to_borrowed_str())
+            .unwrap_or_default();
+        with_encoded(value, |buf| write_line(level, buf))
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        let mut level = "";
+        let _ = fields.visit(&mut |key, value| {
+            if key.as_str() == "level" {
+                level = value.to_borrowed_str().unwrap_or_default();
+            }
+            Ok(())
+        });
+        with_encoded(fields, |buf| write_line(level, buf))
+    }
+}
+
+// The console method of the records of a level.
+#[cfg(any(test, all(target_arch = "wasm32", target_os = "unknown")))]
+#[derive(Debug, PartialEq, Eq)]
+enum Method {
+    Error,
+    Warn,
+    Log,
+}
+
+#[cfg(any(test, all(target_arch = "wasm32", target_os = "unknown")))]
+fn method(level: &str) -> Method {
+    match level {
+        "ERROR" => Method::Error,
+        "WARN" => Method::Warn,
+        _ => Method::Log,
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+fn write_line(level: &str, buf: &[u8]) -> Result<(), io::Error> {
+    let line = std::str::from_utf8(buf.strip_suffix(b"\n").unwrap_or(buf))
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    let line = web_sys::wasm_bindgen::JsValue::from_str(line);
+    match method(level) {
+        Method::Error => web_sys::console::error_1(&line),
+        Method::Warn => web_sys::console::warn_1(&line),
+        Method::Log => web_sys::console::log_1(&line),
+    }
+    Ok(())
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+fn write_line(_level: &str, buf: &[u8]) -> Result<(), io::Error> {
+    use std::io::Write;
+
+    io::stdout().lock().write_all(buf)
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the ConsoleWriter.
+pub fn new_writer() -> Box<dyn Writer> {
+    Box::new(ConsoleWriter::new())
+}
+
+#[cfg(all(test, not(all(target_arch = "wasm32", target_os = "unknown"))))]
+mod tests {
+    use super::*;
+    use crate::Builder;
+    use gag::BufferRedirect;
+    use log::{Level, Log, Record};
+    use std::io::Read;
+
+    #[test]
+    fn method_works() {
+        assert_eq!(Method::Error, method("ERROR"));
+        assert_eq!(Method::Warn, method("WARN"));
+        assert_eq!(Method::Log, method("INFO"));
+        assert_eq!(Method::Log, method(""));
+    }
+
+    #[test]
+    fn console_writer_works() {
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("level"), Value::from("WARN"));
+        value.insert(Key::from("message"), Value::from("console-test \"map\""));
+        let logger = Builder::with_level("info")
+            .with_default_writer(new_writer())
+            .with_clock(|| 1679745592127)
+            .build();
+        let kvs = [("user", Value::from("u1"))];
+
+        let mut buf = BufferRedirect::stdout().unwrap();
+        new_writer().write_log(&value).unwrap();
+        // through the fields of the logger.
+        logger.log(
+            &Record::builder()
+                .args(format_args!("console-test fields"))
+                .level(Level::Info)
+                .target("api")
+                .key_values(&kvs)
+                .build(),
+        );
+        let mut output = String::new();
+        buf.read_to_string(&mut output).unwrap();
+        drop(buf);
+
+        // the other tests may write to stdout.
+        let lines: Vec<&str> = output
+            .lines()
+            .filter(|line| line.contains("console-test"))
+            .collect();
+        assert_eq!(
+            vec![
+                r#"{"level":"WARN","message":"console-test \"map\""}"#,
+                r#"{"level":"INFO","message":"console-test fields","target":"api","timestamp":1679745592127,"user":"u1"}"#,
+            ],
+            lines
+        );
+        assert!(output.ends_with('\n'));
+    }
+}
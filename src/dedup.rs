@@ -0,0 +1,314 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Deduplicating Writer
+//!
+//! A [`Writer`] wrapper that collapses the consecutive identical records, like "last message repeated N times":
+//! the records with the same target, message, and values of the [`DedupOptions::keys`] as the previous record
+//! are not written, and are counted instead. When the burst ends, by a different record, or when
+//! the [`DedupOptions::window`] expires, the first repeated record is written once with a `repeat_count` field,
+//! the number of the repeated records, and the timestamp of the last one.
+//!
+//! The expired bursts are written by a background thread, and when the writer is flushed or shut down.
+//! To create a `Box<dyn Writer>` use the [`new_writer`] function.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{dedup, json, Builder};
+//!
+//! fn main() {
+//!     let opts = dedup::DedupOptions {
+//!         keys: vec!["peer".to_string()],
+//!         ..Default::default()
+//!     };
+//!     Builder::with_level("info")
+//!         .with_default_writer(dedup::new_writer(opts, json::new_writer(std::io::stderr())))
+//!         .init();
+//!
+//!     for _ in 0..100 {
+//!         log::warn!(peer = "10.0.0.1"; "connection reset");
+//!     }
+//!     // {"level":"WARN","message":"connection reset","peer":"10.0.0.1",...}
+//!     // {"level":"WARN","message":"connection reset","peer":"10.0.0.1","repeat_count":99,...}
+//!     log::info!("recovered");
+//! }
+//! ```
+//!
+
+use parking_lot::Mutex;
+use std::{
+    collections::BTreeMap,
+    io,
+    sync::{Arc, Weak},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{log_failure, Fields, Key, Value, Writer};
+
+/// The options of a [`DedupWriter`].
+#[derive(Debug, Clone)]
+pub struct DedupOptions {
+    /// The keys whose values must also be identical, in addition to the target and the message,
+    /// the default is empty.
+    pub keys: Vec<String>,
+    /// The maximum duration of a burst of repeated records before it is written, the default is 10 seconds.
+    pub window: Duration,
+}
+
+impl Default for DedupOptions {
+    fn default() -> Self {
+        DedupOptions {
+            keys: Vec::new(),
+            window: Duration::from_secs(10),
+        }
+    }
+}
+
+// The repeated records of a burst.
+struct Pending {
+    // the fields of the first repeated record.
+    fields: Vec<(String, serde_json::Value)>,
+    count: u64,
+    last_timestamp: Option<u64>,
+    since: Instant,
+}
+
+#[derive(Default)]
+struct State {
+    // the identity of the previous record.
+    last: String,
+    pending: Option<Pending>,
+}
+
+struct Inner {
+    opts: DedupOptions,
+    state: Mutex<State>,
+    w: Box<dyn Writer>,
+}
+
+/// A Writer implementation that collapses the consecutive identical records written to a wrapped writer.
+pub struct DedupWriter(Arc<Inner>);
+
+impl DedupWriter {
+    /// Creates a new DedupWriter instance that writes the records to `w`, and collapses the repeated ones.
+    /// It starts a background thread that writes the expired bursts.
+    pub fn new(opts: DedupOptions, w: Box<dyn Writer>) -> Self {
+        let window = opts.window;
+        let inner = Arc::new(Inner {
+            opts,
+            state: Mutex::new(State::default()),
+            w,
+        });
+        let weak = Arc::downgrade(&inner);
+        let _ = thread::Builder::new()
+            .name("structured-logger-dedup".to_string())
+            .spawn(move || write_expired_periodically(weak, window));
+        DedupWriter(inner)
+    }
+}
+
+impl Inner {
+    // Returns the identity of a record from its fields.
+    fn identity<'a>(&self, mut get: impl FnMut(&str) -> Option<Value<'a>>) -> String {
+        let mut id = String::new();
+        let keys = ["target", "message"]
+            .iter()
+            .copied()
+            .chain(self.opts.keys.iter().map(|k| k.as_str()));
+        for key in keys {
+            if let Some(value) = get(key) {
+                id.push_str(&value.to_string());
+            }
+            // a separator that is rarely logged.
+            id.push('\u{1f}');
+        }
+        id
+    }
+
+    fn write(
+        &self,
+        id: String,
+        timestamp: Option<u64>,
+        to_fields: impl FnOnce() -> Vec<(String, serde_json::Value)>,
+        write: impl FnOnce(&dyn Writer) -> Result<(), io::Error>,
+    ) -> Result<(), io::Error> {
+        let mut state = self.state.lock();
+        if state.last == id {
+            match state.pending {
+                Some(ref mut pending) => {
+                    pending.count += 1;
+                    pending.last_timestamp = timestamp;
+                }
+                None => {
+                    state.pending = Some(Pending {
+                        fields: to_fields(),
+                        count: 1,
+                        last_timestamp: timestamp,
+                        since: Instant::now(),
+                    })
+                }
+            }
+            return Ok(());
+        }
+
+        state.last = id;
+        if let Some(pending) = state.pending.take() {
+            self.write_pending(pending)?;
+        }
+        write(self.w.as_ref())
+    }
+
+    fn write_pending(&self, pending: Pending) -> Result<(), io::Error> {
+        let mut value: BTreeMap<Key, Value> = pending
+            .fields
+            .iter()
+            .map(|(k, v)| (Key::from(k.as_str()), Value::from_serde(v)))
+            .collect();
+        if let Some(ts) = pending.last_timestamp {
+            value.insert(Key::from("timestamp"), Value::from(ts));
+        }
+        value.insert(Key::from("repeat_count"), Value::from(pending.count));
+        self.w.write_log(&value)
+    }
+
+    // Writes the burst of repeated records if it has expired, or any burst if `all` is true.
+    fn write_expired(&self, all: bool) -> Result<(), io::Error> {
+        let mut state = self.state.lock();
+        let expired = match state.pending {
+            Some(ref pending) => all || pending.since.elapsed() >= self.opts.window,
+            None => false,
+        };
+        if !expired {
+            return Ok(());
+        }
+        // the next identical record starts a new burst.
+        match state.pending.take() {
+            Some(pending) => self.write_pending(pending),
+            None => Ok(()),
+        }
+    }
+}
+
+fn write_expired_periodically(inner: Weak<Inner>, window: Duration) {
+    // checks at least every second, so a burst is written soon after its window.
+    let interval = window.clamp(Duration::from_millis(10), Duration::from_secs(1));
+    loop {
+        thread::sleep(interval);
+        match inner.upgrade() {
+            Some(inner) => {
+                if let Err(err) = inner.write_expired(false) {
+                    log_failure(format!("DedupWriter failed to write log: {}", err).as_str());
+                }
+            }
+            None => return,
+        }
+    }
+}
+
+/// Implements Writer trait for DedupWriter.
+impl Writer for DedupWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        let id = self.0.identity(|key| value.get(&Key::from(key)).cloned());
+        let timestamp = value.get(&Key::from("timestamp")).and_then(|v| v.to_u64());
+        let to_fields = || {
+            value
+                .iter()
+                .map(|(k, v)| (k.to_string(), to_json(v)))
+                .collect()
+        };
+        self.0
+            .write(id, timestamp, to_fields, |w| w.write_log(value))
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        let map = fields.to_sorted();
+        let id = self.0.identity(|key| map.get(key).cloned());
+        let timestamp = map.get("timestamp").and_then(|v| v.to_u64());
+        let to_fields = || {
+            map.iter()
+                .map(|(k, v)| (k.to_string(), to_json(v)))
+                .collect()
+        };
+        self.0
+            .write(id, timestamp, to_fields, |w| w.write_fields(fields))
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        self.0.write_expired(true)?;
+        self.0.w.flush()
+    }
+
+    fn shutdown(&self) -> Result<(), io::Error> {
+        self.0.write_expired(true)?;
+        self.0.w.shutdown()
+    }
+
+    fn healthy(&self) -> bool {
+        self.0.w.healthy()
+    }
+}
+
+fn to_json(value: &Value) -> serde_json::Value {
+    serde_json::to_value(value).unwrap_or(serde_json::Value::Null)
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the DedupWriter that writes the records to `w`,
+/// and collapses the repeated ones.
+pub fn new_writer(opts: DedupOptions, w: Box<dyn Writer>) -> Box<dyn Writer> {
+    Box::new(DedupWriter::new(opts, w))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fn_writer;
+
+    #[test]
+    fn dedup_writer_works() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let collected = lines.clone();
+        let opts = DedupOptions {
+            keys: vec!["peer".to_string()],
+            window: Duration::from_millis(50),
+        };
+        let w = new_writer(
+            opts,
+            fn_writer(move |value| {
+                let json = serde_json::to_string(&crate::fields::FieldMap(value)).unwrap();
+                collected.lock().push(json);
+                Ok(())
+            }),
+        );
+        let write = |message: &str, peer: &str, timestamp: u64| {
+            let mut value = BTreeMap::new();
+            value.insert(Key::from("message"), Value::from(message));
+            value.insert(Key::from("peer"), Value::from(peer));
+            value.insert(Key::from("timestamp"), Value::from(timestamp));
+            w.write_log(&value).unwrap();
+        };
+
+        for ts in 1..=4 {
+            write("reset", "a", ts);
+        }
+        write("reset", "b", 5);
+        write("reset", "b", 6);
+        assert_eq!(
+            vec![
+                r#"{"message":"reset","peer":"a","timestamp":1}"#,
+                r#"{"message":"reset","peer":"a","repeat_count":3,"timestamp":4}"#,
+                r#"{"message":"reset","peer":"b","timestamp":5}"#,
+            ],
+            *lines.lock()
+        );
+
+        // the burst is written when its window expires.
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(
+            r#"{"message":"reset","peer":"b","repeat_count":1,"timestamp":6}"#,
+            lines.lock()[3]
+        );
+        w.flush().unwrap();
+        assert_eq!(4, lines.lock().len());
+    }
+}
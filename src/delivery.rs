@@ -0,0 +1,414 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Delivery Writer Implementation
+//!
+//! A [`Writer`] wrapper for the remote sinks that retries the records the wrapped writer fails to write
+//! from an in-memory queue, on a worker thread, so the logging thread never sleeps on a backoff.
+//! The records are retried in order, with the exponential backoff with jitter of [`DeliveryOptions::backoff`],
+//! up to [`Backoff::max_attempts`] times, then routed to the dead-letter writer, such as a local file.
+//!
+//! While records are waiting in the queue, the new records are queued behind them, so they are not reordered.
+//! When the queue is full, the new records are routed to the dead-letter writer immediately.
+//! The records are dropped and reported by [`log_failure`] if there is no dead-letter writer.
+//!
+//! The records are queued as JSON lines, and retried to the wrapped writer as a map, see [`Writer::write_log`].
+//! To create a `Box<dyn Writer>` use the [`new_writer`] function.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{delivery, gelf, json, Builder};
+//!
+//! fn main() {
+//!     let gelf = gelf::new_writer("127.0.0.1:12201", gelf::GelfOptions::default()).unwrap();
+//!     let dead_letter = json::new_file_writer(std::env::temp_dir().join("dead-letter.log")).unwrap();
+//!     Builder::with_level("info")
+//!         .with_default_writer(delivery::new_writer(
+//!             gelf,
+//!             Some(dead_letter),
+//!             delivery::DeliveryOptions::default(),
+//!         ))
+//!         .init();
+//!
+//!     log::info!("hello world");
+//! }
+//! ```
+//!
+
+use parking_lot::{Condvar, Mutex};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Weak,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::json::{with_encoded, write_json_line, Encode};
+use crate::retry::Backoff;
+use crate::{log_failure, Fields, Key, Value, Writer};
+
+/// How long [`Writer::flush`] waits for the worker thread to deliver the queued records.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+// How long the worker thread waits before checking whether the writer is dropped.
+const IDLE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The options of a [`DeliveryWriter`].
+#[derive(Debug, Clone)]
+pub struct DeliveryOptions {
+    /// The backoff between the attempts to write a record, and the maximum number of attempts.
+    /// The default is [`Backoff::default`].
+    pub backoff: Backoff,
+    /// The maximum number of records waiting in the retry queue, the default is 10,000.
+    pub capacity: usize,
+}
+
+impl Default for DeliveryOptions {
+    fn default() -> Self {
+        DeliveryOptions {
+            backoff: Backoff::default(),
+            capacity: 10_000,
+        }
+    }
+}
+
+// A record waiting to be retried.
+struct Pending {
+    line: Vec<u8>,
+    attempts: u32,
+    retry_at: Instant,
+}
+
+struct Inner {
+    writer: Box<dyn Writer>,
+    dead_letter: Option<Box<dyn Writer>>,
+    backoff: Backoff,
+    capacity: usize,
+    queue: Mutex<VecDeque<Pending>>,
+    // notified when a record is queued, and when the queue is empty.
+    cond: Condvar,
+    retried: AtomicU64,
+    dead_lettered: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl Inner {
+    fn write<T: Encode + ?Sized>(
+        &self,
+        value: &T,
+        write: &dyn Fn(&dyn Writer) -> Result<(), io::Error>,
+    ) -> Result<(), io::Error> {
+        if !self.queue.lock().is_empty() {
+            return with_encoded(value, |buf| self.push(buf, 0, write));
+        }
+        match write(self.writer.as_ref()) {
+            Ok(()) => Ok(()),
+            Err(err) if !self.backoff.can_retry(1) => self.dead_letter(&err, write),
+            Err(_) => with_encoded(value, |buf| self.push(buf, 1, write)),
+        }
+    }
+
+    // Queues a record that failed `attempts` times, or routes it to the dead-letter writer if the queue is full.
+    fn push(
+        &self,
+        buf: &[u8],
+        attempts: u32,
+        write: &dyn Fn(&dyn Writer) -> Result<(), io::Error>,
+    ) -> Result<(), io::Error> {
+        let mut queue = self.queue.lock();
+        if queue.len() >= self.capacity {
+            drop(queue);
+            let err = io::Error::other("the retry queue is full");
+            return self.dead_letter(&err, write);
+        }
+        let retry_at = match attempts {
+            0 => Instant::now(),
+            n => Instant::now() + self.backoff.delay(n),
+        };
+        queue.push_back(Pending {
+            line: buf.to_vec(),
+            attempts,
+            retry_at,
+        });
+        self.cond.notify_all();
+        Ok(())
+    }
+
+    fn dead_letter(
+        &self,
+        err: &io::Error,
+        write: &dyn Fn(&dyn Writer) -> Result<(), io::Error>,
+    ) -> Result<(), io::Error> {
+        match self.dead_letter {
+            Some(ref w) => {
+                self.dead_lettered.fetch_add(1, Ordering::Relaxed);
+                write(w.as_ref())
+            }
+            None => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                log_failure(format!("DeliveryWriter dropped a log: {}", err).as_str());
+                Ok(())
+            }
+        }
+    }
+
+    // Retries the first record of the queue if it is due, returns false if the queue is empty.
+    fn retry_first(&self) -> bool {
+        let line = {
+            let mut queue = self.queue.lock();
+            let first = match queue.front() {
+                Some(first) => first,
+                None => {
+                    self.cond.wait_for(&mut queue, IDLE_TIMEOUT);
+                    return false;
+                }
+            };
+            let now = Instant::now();
+            if first.retry_at > now {
+                let timeout = (first.retry_at - now).min(IDLE_TIMEOUT);
+                self.cond.wait_for(&mut queue, timeout);
+                return true;
+            }
+            first.line.clone()
+        };
+
+        // only the worker thread pops the queue, the first record is still the same.
+        let res = write_json_line(self.writer.as_ref(), &line);
+        let mut queue = self.queue.lock();
+        let failed = match (res, queue.front_mut()) {
+            (Ok(()), _) | (_, None) => None,
+            (Err(err), Some(first)) => {
+                first.attempts += 1;
+                if err.kind() != io::ErrorKind::InvalidData
+                    && self.backoff.can_retry(first.attempts)
+                {
+                    self.retried.fetch_add(1, Ordering::Relaxed);
+                    first.retry_at = Instant::now() + self.backoff.delay(first.attempts);
+                    return true;
+                }
+                Some(err)
+            }
+        };
+        queue.pop_front();
+        if queue.is_empty() {
+            self.cond.notify_all();
+        }
+        drop(queue);
+        if let Some(err) = failed {
+            let _ = self.dead_letter(&err, &|w| write_json_line(w, &line));
+        }
+        true
+    }
+
+    // Routes the records left in the queue to the dead-letter writer.
+    fn drain(&self) {
+        let pending: Vec<Pending> = self.queue.lock().drain(..).collect();
+        self.cond.notify_all();
+        let err = io::Error::other("the writer is shut down");
+        for p in pending {
+            let _ = self.dead_letter(&err, &|w| write_json_line(w, &p.line));
+        }
+    }
+}
+
+/// A Writer implementation that retries the failed records of a wrapped writer from a queue,
+/// and routes the undeliverable records to a dead-letter writer.
+pub struct DeliveryWriter {
+    inner: Arc<Inner>,
+}
+
+impl DeliveryWriter {
+    /// Creates a new DeliveryWriter instance that wraps `writer`, and routes the records that can't be delivered
+    /// to `dead_letter`. It starts the worker thread that retries the records, which stops when the writer is dropped.
+    pub fn new(
+        writer: Box<dyn Writer>,
+        dead_letter: Option<Box<dyn Writer>>,
+        opts: DeliveryOptions,
+    ) -> Self {
+        let inner = Arc::new(Inner {
+            writer,
+            dead_letter,
+            backoff: opts.backoff,
+            capacity: opts.capacity.max(1),
+            queue: Mutex::new(VecDeque::new()),
+            cond: Condvar::new(),
+            retried: AtomicU64::new(0),
+            dead_lettered: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        });
+        let weak = Arc::downgrade(&inner);
+        if let Err(err) = thread::Builder::new()
+            .name("structured-logger-delivery".to_string())
+            .spawn(move || retry_queued(weak))
+        {
+            log_failure(format!("DeliveryWriter failed to start the worker: {}", err).as_str());
+        }
+        DeliveryWriter { inner }
+    }
+
+    /// Returns the number of records waiting in the retry queue.
+    pub fn queued(&self) -> usize {
+        self.inner.queue.lock().len()
+    }
+
+    /// Returns the number of retries of the queued records that failed again.
+    pub fn retried(&self) -> u64 {
+        self.inner.retried.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of records routed to the dead-letter writer.
+    pub fn dead_lettered(&self) -> u64 {
+        self.inner.dead_lettered.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of records dropped because there is no dead-letter writer.
+    pub fn dropped(&self) -> u64 {
+        self.inner.dropped.load(Ordering::Relaxed)
+    }
+}
+
+fn retry_queued(inner: Weak<Inner>) {
+    loop {
+        match inner.upgrade() {
+            Some(inner) => while inner.retry_first() {},
+            None => return,
+        }
+    }
+}
+
+/// Implements Writer trait for DeliveryWriter.
+impl Writer for DeliveryWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        self.inner.write(value, &|w| w.write_log(value))
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        self.inner.write(fields, &|w| w.write_fields(fields))
+    }
+
+    /// Waits for the queued records to be delivered, or routed to the dead-letter writer,
+    /// at most for 5 seconds, then flushes the writers.
+    fn flush(&self) -> Result<(), io::Error> {
+        {
+            let deadline = Instant::now() + FLUSH_TIMEOUT;
+            let mut queue = self.inner.queue.lock();
+            while !queue.is_empty() {
+                if self.inner.cond.wait_until(&mut queue, deadline).timed_out() {
+                    break;
+                }
+            }
+        }
+        if let Some(ref w) = self.inner.dead_letter {
+            w.flush()?;
+        }
+        self.inner.writer.flush()
+    }
+
+    /// Routes the records left in the queue to the dead-letter writer, and shuts down the writers.
+    fn shutdown(&self) -> Result<(), io::Error> {
+        let _ = self.flush();
+        self.inner.drain();
+        let res = self.inner.writer.shutdown();
+        if let Some(ref w) = self.inner.dead_letter {
+            w.shutdown()?;
+        }
+        res
+    }
+
+    /// Returns false while records are waiting in the retry queue.
+    fn healthy(&self) -> bool {
+        self.inner.queue.lock().is_empty() && self.inner.writer.healthy()
+    }
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the DeliveryWriter for a given writer and dead-letter writer.
+pub fn new_writer(
+    writer: Box<dyn Writer>,
+    dead_letter: Option<Box<dyn Writer>>,
+    opts: DeliveryOptions,
+) -> Box<dyn Writer> {
+    Box::new(DeliveryWriter::new(writer, dead_letter, opts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fn_writer;
+
+    // A remote sink that fails the given number of writes, then succeeds.
+    struct Sink {
+        failures: Arc<AtomicU64>,
+        messages: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Writer for Sink {
+        fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+            let failures = self.failures.load(Ordering::Relaxed);
+            if failures > 0 {
+                self.failures.store(failures - 1, Ordering::Relaxed);
+                return Err(io::ErrorKind::ConnectionRefused.into());
+            }
+            let message = value[&Key::from("message")].to_string();
+            self.messages.lock().push(message);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn delivery_writer_works() {
+        let failures = Arc::new(AtomicU64::new(0));
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let dead = Arc::new(Mutex::new(Vec::new()));
+        let sink = Box::new(Sink {
+            failures: failures.clone(),
+            messages: messages.clone(),
+        });
+        let dead_letter = {
+            let dead = dead.clone();
+            fn_writer(move |value| {
+                dead.lock().push(value[&Key::from("message")].to_string());
+                Ok(())
+            })
+        };
+        let opts = DeliveryOptions {
+            backoff: Backoff {
+                initial: Duration::from_millis(5),
+                max: Duration::from_millis(20),
+                max_attempts: 3,
+                jitter: true,
+            },
+            capacity: 10,
+        };
+        let w = DeliveryWriter::new(sink, Some(dead_letter), opts);
+        let write = |message: &str| {
+            let mut value = BTreeMap::new();
+            value.insert(Key::from("message"), Value::from(message));
+            w.write_log(&value).unwrap();
+        };
+
+        // the failed records are retried in order.
+        write("a");
+        failures.store(2, Ordering::Relaxed);
+        write("b");
+        write("c");
+        assert!(!w.healthy());
+        w.flush().unwrap();
+        assert!(w.healthy());
+        assert_eq!(vec!["a", "b", "c"], *messages.lock());
+        assert_eq!(1, w.retried());
+
+        // a record is routed to the dead-letter writer after the last attempt.
+        failures.store(3, Ordering::Relaxed);
+        write("d");
+        write("e");
+        w.flush().unwrap();
+        assert_eq!(vec!["a", "b", "c", "e"], *messages.lock());
+        assert_eq!(vec!["d"], *dead.lock());
+        assert_eq!(1, w.dead_lettered());
+        assert_eq!(0, w.queued());
+    }
+}
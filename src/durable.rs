@@ -0,0 +1,217 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Durable Writer Implementation
+//!
+//! A [`Writer`] implementation that logs structured values in JSON format to a file,
+//! and syncs the file data to the storage device with `sync_data`, for audit and compliance logs.
+//! By default the file is synced after every record, before the log call returns.
+//! With a [`DurableOptions::sync_interval`], the file is synced by a background thread at most that long
+//! after a record is written, which bounds the records lost on a crash while sharing a sync between records.
+//!
+//! The records are written synchronously on the logging thread and never dropped by the writer:
+//! a write or sync error is returned to the logger, which reports it by [`log_failure`](crate::log_failure).
+//! Wrap it in a [`retry`](crate::retry) writer to retry the errors.
+//!
+//! To create a `Box<dyn Writer>` use the [`new_writer`] or the [`new_file_writer`] function.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{durable, Builder};
+//!
+//! fn main() {
+//!     let path = std::env::temp_dir().join("audit.log");
+//!     Builder::with_level("info")
+//!         .with_field_writer(
+//!             "audit",
+//!             true,
+//!             durable::new_file_writer(path, durable::DurableOptions::default()).unwrap(),
+//!         )
+//!         .init();
+//!
+//!     log::info!(audit = true, user = "user123"; "deleted the account");
+//! }
+//! ```
+//!
+
+use parking_lot::Mutex;
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Weak,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::json::{with_encoded, FileOptions};
+use crate::metrics::Health;
+use crate::{log_failure, Fields, Key, Value, Writer};
+
+/// The options of a [`DurableWriter`].
+#[derive(Debug, Clone, Default)]
+pub struct DurableOptions {
+    /// The maximum time between writing a record and syncing it, `None` to sync after every record.
+    /// The default is `None`.
+    pub sync_interval: Option<Duration>,
+}
+
+struct Inner {
+    file: Mutex<File>,
+    // true if records were written since the last sync.
+    dirty: AtomicBool,
+    health: Health,
+}
+
+impl Inner {
+    fn sync(&self) -> Result<(), io::Error> {
+        if self.dirty.swap(false, Ordering::AcqRel) {
+            if let Err(err) = self.health.track(self.file.lock().sync_data()) {
+                self.dirty.store(true, Ordering::Release);
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A Writer implementation that writes logs in JSON format to a file, and syncs them to the storage device.
+pub struct DurableWriter {
+    inner: Arc<Inner>,
+    per_record: bool,
+}
+
+impl DurableWriter {
+    /// Creates a new DurableWriter instance for a given file.
+    /// With a sync interval, it starts the background thread that syncs the file, which stops when the writer is dropped.
+    pub fn new(file: File, opts: DurableOptions) -> Result<Self, io::Error> {
+        let inner = Arc::new(Inner {
+            file: Mutex::new(file),
+            dirty: AtomicBool::new(false),
+            health: Health::default(),
+        });
+        if let Some(interval) = opts.sync_interval {
+            let weak = Arc::downgrade(&inner);
+            thread::Builder::new()
+                .name("structured-logger-fsync".to_string())
+                .spawn(move || sync_periodically(weak, interval))?;
+        }
+        Ok(DurableWriter {
+            inner,
+            per_record: opts.sync_interval.is_none(),
+        })
+    }
+
+    fn write_line(&self, buf: &[u8]) -> Result<(), io::Error> {
+        let mut file = self.inner.file.lock();
+        self.inner.health.track(file.write_all(buf))?;
+        if self.per_record {
+            return self.inner.health.track(file.sync_data());
+        }
+        self.inner.dirty.store(true, Ordering::Release);
+        Ok(())
+    }
+}
+
+fn sync_periodically(inner: Weak<Inner>, interval: Duration) {
+    loop {
+        thread::sleep(interval);
+        match inner.upgrade() {
+            Some(inner) => {
+                if let Err(err) = inner.sync() {
+                    log_failure(format!("DurableWriter failed to sync: {}", err).as_str());
+                }
+            }
+            None => return,
+        }
+    }
+}
+
+/// Implements Writer trait for DurableWriter.
+impl Writer for DurableWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        with_encoded(value, |buf| self.write_line(buf))
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        with_encoded(fields, |buf| self.write_line(buf))
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        self.inner.sync()
+    }
+
+    fn healthy(&self) -> bool {
+        self.inner.health.healthy()
+    }
+}
+
+impl Drop for DurableWriter {
+    fn drop(&mut self) {
+        if let Err(err) = self.inner.sync() {
+            log_failure(format!("DurableWriter failed to sync: {}", err).as_str());
+        }
+    }
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the DurableWriter for a given file.
+pub fn new_writer(file: File, opts: DurableOptions) -> Result<Box<dyn Writer>, io::Error> {
+    Ok(Box::new(DurableWriter::new(file, opts)?))
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the DurableWriter for the file at a given path,
+/// opened with the default [`FileOptions`]: the parent directories are created, and the logs are appended to the file.
+pub fn new_file_writer<P: AsRef<Path>>(
+    path: P,
+    opts: DurableOptions,
+) -> Result<Box<dyn Writer>, io::Error> {
+    new_writer(FileOptions::new().open_file(path)?, opts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn durable_writer_works() {
+        let dir =
+            std::env::temp_dir().join(format!("structured-logger-durable-{}", std::process::id()));
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("message"), Value::from("hello"));
+
+        let path = dir.join("audit.log");
+        let w = new_file_writer(&path, DurableOptions::default()).unwrap();
+        w.write_log(&value).unwrap();
+        w.write_log(&value).unwrap();
+        assert_eq!(
+            "{\"message\":\"hello\"}\n{\"message\":\"hello\"}\n",
+            fs::read_to_string(&path).unwrap()
+        );
+
+        let path = dir.join("batch.log");
+        let opts = DurableOptions {
+            sync_interval: Some(Duration::from_millis(5)),
+        };
+        let w = DurableWriter::new(FileOptions::new().open_file(&path).unwrap(), opts).unwrap();
+        w.write_log(&value).unwrap();
+        assert!(w.inner.dirty.load(Ordering::Acquire));
+        thread::sleep(Duration::from_millis(50));
+        assert!(!w.inner.dirty.load(Ordering::Acquire));
+        w.write_log(&value).unwrap();
+        w.flush().unwrap();
+        assert!(!w.inner.dirty.load(Ordering::Acquire));
+        assert_eq!(2, fs::read_to_string(&path).unwrap().lines().count());
+
+        // the sync thread stops with the writer.
+        let weak = Arc::downgrade(&w.inner);
+        drop(w);
+        thread::sleep(Duration::from_millis(20));
+        assert!(weak.upgrade().is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,146 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # ECS Writer
+//!
+//! A [`Writer`] wrapper that writes the built-in fields of the records as expected by the
+//! [Elastic Common Schema](https://www.elastic.co/guide/en/ecs-logging/overview/current/intro.html),
+//! so the logs are ingested by Filebeat or the Elastic Agent without an ingest pipeline:
+//! * `timestamp` is replaced by `@timestamp`, an RFC 3339 UTC string with milliseconds;
+//! * `level` is renamed to `log.level`, and `target` to `log.logger`;
+//! * `file` and `line` are renamed to `log.origin.file.name` and `log.origin.file.line`;
+//! * `deployment.environment`, a [`Resource`](crate::Resource) attribute, is renamed to `service.environment`;
+//! * `ecs.version` is added, see [`ECS_VERSION`].
+//!
+//! The `message` and the other fields are written unchanged.
+//! The wrapped writer receives the records as a map, see [`Writer::write_log`].
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{ecs, json, Builder};
+//!
+//! fn main() {
+//!     Builder::with_level("info")
+//!         .with_default_writer(ecs::new_writer(json::new_writer(std::io::stdout())))
+//!         .init();
+//!
+//!     // {"@timestamp":"2023-03-25T11:59:52.127Z","ecs.version":"8.11.0","log.level":"INFO","log.logger":"rust_out","message":"hello world"}
+//!     log::info!("hello world");
+//! }
+//! ```
+//!
+
+use std::{collections::BTreeMap, io};
+
+use crate::{Fields, Key, Rfc3339, Value, Writer};
+
+/// The version of the Elastic Common Schema written as `ecs.version`,
+/// which defines all the written fields, as `service.environment` was added in ECS 8.4.
+pub const ECS_VERSION: &str = "8.11.0";
+
+// The built-in fields renamed to their ECS keys.
+const RENAMED: [(&str, &str); 4] = [
+    ("level", "log.level"),
+    ("target", "log.logger"),
+    ("file", "log.origin.file.name"),
+    ("line", "log.origin.file.line"),
+];
+
+// The resource attributes renamed to their ECS keys, the others have the same keys in ECS.
+const RESOURCE_RENAMED: [(&str, &str); 1] = [("deployment.environment", "service.environment")];
+
+/// A Writer implementation that writes the records with the ECS fields to a wrapped writer.
+pub struct EcsWriter {
+    inner: Box<dyn Writer>,
+}
+
+impl EcsWriter {
+    /// Creates a new EcsWriter instance that writes the records with the ECS fields to `w`.
+    pub fn new(w: Box<dyn Writer>) -> Self {
+        EcsWriter { inner: w }
+    }
+}
+
+/// Implements Writer trait for EcsWriter.
+impl Writer for EcsWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        let time;
+        let mut value = value.clone();
+        for &(from, to) in RENAMED.iter().chain(RESOURCE_RENAMED.iter()) {
+            if let Some(v) = value.remove(&Key::from(from)) {
+                value.insert(Key::from(to), v);
+            }
+        }
+
+        if let Some(ms) = value.get(&Key::from("timestamp")).and_then(|v| v.to_u64()) {
+            value.remove(&Key::from("timestamp"));
+            time = Rfc3339::from_unix_ms(ms);
+            value.insert(Key::from("@timestamp"), Value::from(time.as_str()));
+        }
+        value.insert(Key::from("ecs.version"), Value::from(ECS_VERSION));
+        self.inner.write_log(&value)
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        self.write_log(&fields.to_map())
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        self.inner.flush()
+    }
+
+    fn shutdown(&self) -> Result<(), io::Error> {
+        self.inner.shutdown()
+    }
+
+    fn healthy(&self) -> bool {
+        self.inner.healthy()
+    }
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the EcsWriter
+/// that writes the records with the ECS fields to `w`.
+pub fn new_writer(w: Box<dyn Writer>) -> Box<dyn Writer> {
+    Box::new(EcsWriter::new(w))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fn_writer;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn ecs_writer_works() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let w = {
+            let lines = lines.clone();
+            new_writer(fn_writer(move |value| {
+                let json = serde_json::to_string(&crate::fields::FieldMap(value)).unwrap();
+                lines.lock().unwrap().push(json);
+                Ok(())
+            }))
+        };
+
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("level"), Value::from("WARN"));
+        value.insert(Key::from("message"), Value::from("hello"));
+        value.insert(Key::from("target"), Value::from("api"));
+        value.insert(Key::from("file"), Value::from("src/main.rs"));
+        value.insert(Key::from("line"), Value::from(42));
+        value.insert(Key::from("status"), Value::from(500));
+        value.insert(
+            Key::from("deployment.environment"),
+            Value::from("production"),
+        );
+        value.insert(Key::from("timestamp"), Value::from(1679745592127_u64));
+        w.write_log(&value).unwrap();
+
+        assert_eq!(
+            vec![
+                r#"{"@timestamp":"2023-03-25T11:59:52.127Z","ecs.version":"8.11.0","log.level":"WARN","log.logger":"api","log.origin.file.line":42,"log.origin.file.name":"src/main.rs","message":"hello","service.environment":"production","status":500}"#
+            ],
+            *lines.lock().unwrap()
+        );
+    }
+}
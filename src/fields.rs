@@ -0,0 +1,524 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! The fields of a log record, visited without building an intermediate map.
+
+use log::kv::{Error, Key, Source, Value, Visitor};
+use serde::ser::{Error as _, Serialize, SerializeMap, Serializer};
+use smallvec::SmallVec;
+use std::{collections::BTreeMap, error, fmt};
+
+use crate::{bytes, float, integer};
+
+/// The number of fields stored inline by [`SortedFields`] before spilling to the heap.
+const INLINE_FIELDS: usize = 16;
+
+/// The static fields of a logger, set by [`Builder::with_static_field`](crate::Builder::with_static_field),
+/// and pre-serialized once into the JSON members that the JSON writers splice into each record.
+/// It is always empty without the `json` feature.
+#[derive(Default, Clone)]
+pub(crate) struct StaticFields {
+    #[cfg(feature = "json")]
+    fields: Vec<(String, serde_json::Value)>,
+    // the `"key":value,` JSON members of all fields.
+    #[cfg(feature = "json")]
+    prefix: Vec<u8>,
+}
+
+impl StaticFields {
+    #[cfg(feature = "json")]
+    pub(crate) fn insert(&mut self, key: &str, value: serde_json::Value) {
+        self.fields.retain(|(k, _)| k != key);
+        self.fields.push((key.to_string(), value));
+
+        self.prefix.clear();
+        for (key, value) in self.fields.iter() {
+            // serializing strings and JSON values can't fail.
+            let _ = serde_json::to_writer(&mut self.prefix, key);
+            self.prefix.push(b':');
+            let _ = serde_json::to_writer(&mut self.prefix, value);
+            self.prefix.push(b',');
+        }
+    }
+
+    #[cfg(feature = "json")]
+    fn contains(&self, key: &Key) -> bool {
+        self.fields.iter().any(|(k, _)| k.as_str() == key.as_str())
+    }
+
+    #[cfg(not(feature = "json"))]
+    fn contains(&self, _key: &Key) -> bool {
+        false
+    }
+
+    #[cfg(feature = "json")]
+    fn visit<'a>(
+        &'a self,
+        f: &mut dyn FnMut(Key<'a>, Value<'a>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        for (key, value) in self.fields.iter() {
+            f(Key::from(key.as_str()), Value::from_serde(value))?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "json"))]
+    fn visit<'a>(
+        &'a self,
+        _f: &mut dyn FnMut(Key<'a>, Value<'a>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl fmt::Debug for StaticFields {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut map = f.debug_map();
+        #[cfg(feature = "json")]
+        map.entries(self.fields.iter().map(|(k, v)| (k, v)));
+        map.finish()
+    }
+}
+
+/// The fields of a log record: the static fields of the logger, its key-values and the built-in fields
+/// (`target`, `message`, `level`, `timestamp`, ...).
+/// It is passed to [`Writer::write_fields`](crate::Writer::write_fields) when streaming is enabled
+/// by [`Builder::with_streaming`](crate::Builder::with_streaming).
+///
+/// The fields are visited and serialized in record order, not sorted by key: the static fields first,
+/// then the key-values, except the ones shadowed by a static or built-in field, then the built-in fields.
+/// A key-value key that is repeated in the record is visited more than once.
+///
+/// When the sorted fields are enabled by [`Builder::with_sorted_fields`](crate::Builder::with_sorted_fields),
+/// the fields are visited and serialized sorted by key, as [`SortedFields`], like the map passed to
+/// [`Writer::write_log`](crate::Writer::write_log).
+pub struct Fields<'a> {
+    statics: &'a StaticFields,
+    kvs: &'a dyn Source,
+    builtins: &'a [(Key<'a>, Value<'a>)],
+    sorted: bool,
+    // false when the static fields are written from their pre-serialized prefix.
+    visit_statics: bool,
+}
+
+impl<'a> Fields<'a> {
+    pub(crate) fn new(
+        statics: &'a StaticFields,
+        kvs: &'a dyn Source,
+        builtins: &'a [(Key<'a>, Value<'a>)],
+        sorted: bool,
+    ) -> Self {
+        Fields {
+            statics,
+            kvs,
+            builtins,
+            sorted,
+            visit_statics: true,
+        }
+    }
+
+    /// Returns the pre-serialized static fields and the fields to serialize after them,
+    /// if the static fields can be spliced into the JSON object.
+    #[cfg(feature = "json")]
+    pub(crate) fn split_static_prefix(&self) -> Option<(&'a [u8], Fields<'a>)> {
+        if self.sorted || !self.visit_statics || self.statics.fields.is_empty() {
+            return None;
+        }
+        let rest = Fields {
+            visit_statics: false,
+            ..*self
+        };
+        Some((&self.statics.prefix, rest))
+    }
+
+    /// Returns true if the fields are visited sorted by key.
+    pub fn is_sorted(&self) -> bool {
+        self.sorted
+    }
+
+    /// Calls `f` for each field, stops at the first error.
+    pub fn visit(
+        &self,
+        f: &mut dyn FnMut(Key<'a>, Value<'a>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        if self.sorted {
+            for (key, value) in self.to_sorted().0 {
+                f(key, value)?;
+            }
+            return Ok(());
+        }
+        self.visit_in_order(f)
+    }
+
+    fn visit_in_order(
+        &self,
+        f: &mut dyn FnMut(Key<'a>, Value<'a>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        if self.visit_statics {
+            self.statics.visit(f)?;
+        }
+        self.kvs.visit(&mut FieldsVisitor {
+            statics: self.statics,
+            builtins: self.builtins,
+            f: &mut *f,
+        })?;
+        for (key, value) in self.builtins {
+            f(key.clone(), value.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Collects the fields into a map, as passed to [`Writer::write_log`](crate::Writer::write_log).
+    pub fn to_map(&self) -> BTreeMap<Key<'a>, Value<'a>> {
+        let mut map = BTreeMap::new();
+        let _ = self.visit(&mut |key, value| {
+            map.insert(key, value);
+            Ok(())
+        });
+        map
+    }
+
+    /// Collects the fields into an inline vector sorted by key, without the node allocations of a map.
+    /// Like in the map, the last value of a repeated key wins.
+    pub fn to_sorted(&self) -> SortedFields<'a> {
+        let mut fields: SmallVec<[(Key<'a>, Value<'a>); INLINE_FIELDS]> = SmallVec::new();
+        let _ = self.visit_in_order(&mut |key, value| {
+            fields.push((key, value));
+            Ok(())
+        });
+        // the sort is stable, so the repeated keys keep the record order.
+        fields.sort_by(|a, b| a.0.cmp(&b.0));
+        fields.dedup_by(|next, prev| {
+            if next.0 == prev.0 {
+                std::mem::swap(next, prev);
+                true
+            } else {
+                false
+            }
+        });
+        SortedFields(fields)
+    }
+}
+
+/// The fields of a log record sorted by key, stored in an inline vector,
+/// as typical records carry only a few fields. See [`Fields::to_sorted`].
+pub struct SortedFields<'a>(SmallVec<[(Key<'a>, Value<'a>); INLINE_FIELDS]>);
+
+impl<'a> SortedFields<'a> {
+    /// Returns the value of a given key.
+    pub fn get(&self, key: &str) -> Option<&Value<'a>> {
+        self.0
+            .binary_search_by(|(k, _)| k.as_str().cmp(key))
+            .ok()
+            .map(|i| &self.0[i].1)
+    }
+
+    /// Returns an iterator over the fields, sorted by key.
+    pub fn iter(&self) -> impl Iterator<Item = (&Key<'a>, &Value<'a>)> {
+        self.0.iter().map(|(k, v)| (k, v))
+    }
+
+    /// Returns the number of fields.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns true if there is no field.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Debug for SortedFields<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl Serialize for SortedFields<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, value) in self.0.iter() {
+            serialize_field(&mut map, key, value)?;
+        }
+        map.end()
+    }
+}
+
+impl fmt::Debug for Fields<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut map = f.debug_map();
+        let _ = self.visit(&mut |key, value| {
+            map.entry(&key, &value);
+            Ok(())
+        });
+        map.finish()
+    }
+}
+
+impl Serialize for Fields<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.sorted {
+            return self.to_sorted().serialize(serializer);
+        }
+
+        let mut map = serializer.serialize_map(None)?;
+        let mut err = None;
+        let res = self.visit(&mut |key, value| {
+            serialize_field(&mut map, &key, &value).map_err(|e| {
+                err = Some(e);
+                Error::msg("failed to serialize field")
+            })
+        });
+        match (res, err) {
+            (_, Some(err)) => Err(err),
+            (Err(err), None) => Err(S::Error::custom(err)),
+            (Ok(()), None) => map.end(),
+        }
+    }
+}
+
+/// The fields of a record collected into a map, serialized like [`Fields`].
+#[cfg(feature = "json")]
+pub(crate) struct FieldMap<'a>(pub(crate) &'a BTreeMap<Key<'a>, Value<'a>>);
+
+#[cfg(feature = "json")]
+impl Serialize for FieldMap<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, value) in self.0.iter() {
+            serialize_field(&mut map, key, value)?;
+        }
+        map.end()
+    }
+}
+
+// Serializes a field, a float value is written with the format set by `Builder::with_float_format`,
+// a big integer as a string, see the `integer` module, and a byte-string value as a string
+// with the encoding set by `Builder::with_bytes_encoding`, and its key suffix.
+fn serialize_field<M: SerializeMap>(map: &mut M, key: &Key, value: &Value) -> Result<(), M::Error> {
+    if let Some(float) = float::format_value(value) {
+        return map.serialize_entry(key, &float);
+    }
+    if let Some(n) = integer::format_value(value) {
+        return map.serialize_entry(key, &n);
+    }
+    match bytes::encode_value(value) {
+        Some(("", encoded)) => map.serialize_entry(key, &encoded),
+        Some((suffix, encoded)) => {
+            map.serialize_entry(&format!("{}{}", key.as_str(), suffix), &encoded)
+        }
+        None => map.serialize_entry(key, &FieldValue(value)),
+    }
+}
+
+/// A field value serialized with its structure, whatever its capture modifier:
+/// an error captured with `:err` is serialized as its chain of sources, `"outer: inner: root"`,
+/// and a value captured with `:sval` keeps its nested maps and sequences with the `sval` feature.
+/// The values captured with `:debug` and `:display` are serialized as strings.
+struct FieldValue<'a, 'v>(&'a Value<'v>);
+
+impl Serialize for FieldValue<'_, '_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if let Some(err) = self.0.to_borrowed_error() {
+            return serializer.collect_str(&ErrorChain(err));
+        }
+
+        // the `sval` values are streamed by `sval_json`, the `serde` bridge of `log` flattens their nested sequences.
+        #[cfg(feature = "sval")]
+        if !is_primitive(self.0) {
+            let json = sval_json::stream_to_string(self.0).map_err(S::Error::custom)?;
+            let value: serde_json::Value = serde_json::from_str(&json).map_err(S::Error::custom)?;
+            return value.serialize(serializer);
+        }
+
+        self.0.serialize(serializer)
+    }
+}
+
+// Returns true if the value is a string, a number, a boolean or a char.
+#[cfg(feature = "json")]
+pub(crate) fn is_primitive(value: &Value) -> bool {
+    value.to_borrowed_str().is_some()
+        || value.to_u64().is_some()
+        || value.to_i64().is_some()
+        || value.to_u128().is_some()
+        || value.to_i128().is_some()
+        || value.to_f64().is_some()
+        || value.to_bool().is_some()
+        || value.to_char().is_some()
+}
+
+struct ErrorChain<'a>(&'a (dyn error::Error + 'static));
+
+impl fmt::Display for ErrorChain<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.0, f)?;
+        let mut source = self.0.source();
+        while let Some(err) = source {
+            write!(f, ": {}", err)?;
+            source = err.source();
+        }
+        Ok(())
+    }
+}
+
+struct FieldsVisitor<'a, 'f> {
+    statics: &'a StaticFields,
+    builtins: &'a [(Key<'a>, Value<'a>)],
+    f: &'f mut dyn FnMut(Key<'a>, Value<'a>) -> Result<(), Error>,
+}
+
+impl<'a> Visitor<'a> for FieldsVisitor<'a, '_> {
+    #[inline]
+    fn visit_pair(&mut self, key: Key<'a>, value: Value<'a>) -> Result<(), Error> {
+        // the static and built-in fields take precedence, as they do in the map.
+        if self.builtins.iter().any(|(k, _)| k == &key) || self.statics.contains(&key) {
+            return Ok(());
+        }
+        (self.f)(key, value)
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn fields_works() {
+        let kvs = [
+            ("uid", Value::from("user123")),
+            ("message", Value::from("shadowed")),
+            ("count", Value::from(2_u64)),
+        ];
+        let builtins = [
+            (Key::from("message"), Value::from("hello")),
+            (Key::from("level"), Value::from("INFO")),
+        ];
+        let statics = StaticFields::default();
+        let fields = Fields::new(&statics, &kvs, &builtins, false);
+
+        assert_eq!(
+            r#"{"uid":"user123","count":2,"message":"hello","level":"INFO"}"#,
+            serde_json::to_string(&fields).unwrap()
+        );
+        assert_eq!(
+            r#"{"count":2,"level":"INFO","message":"hello","uid":"user123"}"#,
+            serde_json::to_string(&fields.to_map()).unwrap()
+        );
+    }
+
+    #[test]
+    fn field_values_works() {
+        #[derive(Debug)]
+        struct Outer(io::Error);
+
+        impl fmt::Display for Outer {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("request failed")
+            }
+        }
+
+        impl error::Error for Outer {
+            fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+                Some(&self.0)
+            }
+        }
+
+        let err = Outer(io::Error::other("connection reset"));
+        let map: BTreeMap<&str, Vec<u32>> = vec![("a", vec![1, 2])].into_iter().collect();
+        #[cfg(feature = "sval")]
+        let seq = vec![vec![1_u32, 2], vec![3]];
+        #[allow(unused_mut)]
+        let mut kvs = vec![
+            ("err", Value::from_dyn_error(&err)),
+            ("debug", Value::from_debug(&map)),
+            ("display", Value::from_display(&err)),
+            ("serde", Value::from_serde(&map)),
+        ];
+        #[cfg(feature = "sval")]
+        kvs.push(("sval", Value::from_sval(&seq)));
+        let statics = StaticFields::default();
+        let fields = Fields::new(&statics, &kvs, &[], false);
+
+        let mut expected = r#"{"err":"request failed: connection reset","debug":"{\"a\": [1, 2]}","display":"request failed","serde":{"a":[1,2]}"#.to_string();
+        #[cfg(feature = "sval")]
+        expected.push_str(r#","sval":[[1,2],[3]]"#);
+        expected.push('}');
+        assert_eq!(expected, serde_json::to_string(&fields).unwrap());
+
+        let sorted = Fields::new(&statics, &kvs, &[], true);
+        let map = fields.to_map();
+        let from_map = serde_json::to_string(&FieldMap(&map)).unwrap();
+        assert_eq!(serde_json::to_string(&sorted).unwrap(), from_map);
+        assert!(from_map.contains(r#""err":"request failed: connection reset""#));
+    }
+
+    #[test]
+    fn sorted_fields_works() {
+        let kvs = [
+            ("uid", Value::from("user123")),
+            ("count", Value::from(1_u64)),
+            ("message", Value::from("shadowed")),
+            ("count", Value::from(2_u64)),
+        ];
+        let builtins = [
+            (Key::from("message"), Value::from("hello")),
+            (Key::from("level"), Value::from("INFO")),
+        ];
+        let statics = StaticFields::default();
+        let fields = Fields::new(&statics, &kvs, &builtins, true);
+        let expected = serde_json::to_string(&fields.to_map()).unwrap();
+        assert_eq!(
+            r#"{"count":2,"level":"INFO","message":"hello","uid":"user123"}"#,
+            expected
+        );
+        assert_eq!(expected, serde_json::to_string(&fields).unwrap());
+
+        let sorted = fields.to_sorted();
+        assert_eq!(4, sorted.len());
+        assert_eq!("hello", sorted.get("message").unwrap().to_string());
+        assert!(sorted.get("target").is_none());
+        assert_eq!(expected, serde_json::to_string(&sorted).unwrap());
+    }
+
+    #[test]
+    fn static_fields_works() {
+        let mut statics = StaticFields::default();
+        statics.insert("service", serde_json::Value::from("api"));
+        statics.insert("version", serde_json::Value::from(1));
+        statics.insert("service", serde_json::Value::from("web"));
+        assert_eq!(
+            br#""version":1,"service":"web","#,
+            statics.prefix.as_slice()
+        );
+
+        let kvs = [
+            ("service", Value::from("shadowed")),
+            ("uid", Value::from(1)),
+        ];
+        let builtins = [(Key::from("message"), Value::from("hello"))];
+        let encode = |fields: &Fields| {
+            crate::json::with_encoded(fields, |buf| Ok(String::from_utf8(buf.to_vec()).unwrap()))
+                .unwrap()
+        };
+
+        let fields = Fields::new(&statics, &kvs, &builtins, false);
+        let expected = r#"{"version":1,"service":"web","uid":1,"message":"hello"}"#;
+        assert_eq!(expected, serde_json::to_string(&fields).unwrap());
+        assert!(fields.split_static_prefix().is_some());
+        assert_eq!(format!("{}\n", expected), encode(&fields));
+
+        let shadowed = [("service", Value::from(0))];
+        let fields = Fields::new(&statics, &shadowed, &[], false);
+        assert_eq!("{\"version\":1,\"service\":\"web\"}\n", encode(&fields));
+
+        let fields = Fields::new(&statics, &kvs, &builtins, true);
+        assert!(fields.split_static_prefix().is_none());
+        assert_eq!(
+            "{\"message\":\"hello\",\"service\":\"web\",\"uid\":1,\"version\":1}\n",
+            encode(&fields)
+        );
+    }
+}
@@ -0,0 +1,210 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Float Values
+//!
+//! JSON has no representation of the non-finite floats, `serde_json` writes `NaN` and the infinities as `null`,
+//! and a float such as a ratio may be written with 17 significant digits. The [`FloatFormat`] set by
+//! [`Builder::with_float_format`](crate::Builder::with_float_format) controls how the float values are written:
+//! * [`FloatFormat::non_finite`]: as `null`, the default, as the strings `"NaN"`, `"Infinity"` and `"-Infinity"`,
+//!   or clamped to the largest finite floats, see [`NonFinite`];
+//! * [`FloatFormat::decimals`]: rounded to a fixed number of decimals.
+//!
+//! Only the top-level float values of a record are formatted, the floats nested in a
+//! structured value are serialized by `serde` as usual.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{
+//!     float::{FloatFormat, NonFinite},
+//!     Builder,
+//! };
+//!
+//! fn main() {
+//!     Builder::with_level("info")
+//!         .with_float_format(FloatFormat {
+//!             non_finite: NonFinite::String,
+//!             decimals: Some(3),
+//!         })
+//!         .init();
+//!
+//!     // {"hit_ratio":0.667,"latency_ratio":"NaN","level":"INFO","message":"cache stats",...}
+//!     log::info!(hit_ratio = 2.0 / 3.0, latency_ratio = f64::NAN; "cache stats");
+//! }
+//! ```
+//!
+
+use log::kv::{Error, Value, VisitValue};
+use serde::ser::{Serialize, Serializer};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// How the non-finite floats, `NaN` and the infinities, are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NonFinite {
+    /// As `null`, like `serde_json`.
+    #[default]
+    Null,
+    /// As the strings `"NaN"`, `"Infinity"` and `"-Infinity"`.
+    String,
+    /// The infinities are clamped to `f64::MAX` and `f64::MIN`, and `NaN` is written as `null`.
+    Clamp,
+}
+
+/// The format of the float values, see the [`float`](crate::float) module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FloatFormat {
+    /// How the non-finite floats are written, the default is [`NonFinite::Null`].
+    pub non_finite: NonFinite,
+    /// The number of decimals the finite floats are rounded to, the default is `None`, not rounded.
+    /// It is capped at 15 decimals.
+    pub decimals: Option<u8>,
+}
+
+impl FloatFormat {
+    /// Returns the float formatted as a JSON value, `None` for `null`.
+    pub fn format(&self, v: f64) -> Option<FloatValue> {
+        if v.is_nan() {
+            return match self.non_finite {
+                NonFinite::String => Some(FloatValue::Str("NaN")),
+                NonFinite::Null | NonFinite::Clamp => None,
+            };
+        }
+        if v.is_infinite() {
+            let positive = v > 0.0;
+            return match self.non_finite {
+                NonFinite::Null => None,
+                NonFinite::String if positive => Some(FloatValue::Str("Infinity")),
+                NonFinite::String => Some(FloatValue::Str("-Infinity")),
+                NonFinite::Clamp if positive => Some(FloatValue::Number(f64::MAX)),
+                NonFinite::Clamp => Some(FloatValue::Number(f64::MIN)),
+            };
+        }
+        match self.decimals {
+            Some(decimals) => {
+                let scale = 10f64.powi(decimals.min(15) as i32);
+                let rounded = (v * scale).round() / scale;
+                // a large float overflows when scaled, it has no decimals to round anyway.
+                Some(FloatValue::Number(if rounded.is_finite() {
+                    rounded
+                } else {
+                    v
+                }))
+            }
+            None => Some(FloatValue::Number(v)),
+        }
+    }
+}
+
+/// A float formatted by a [`FloatFormat`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FloatValue {
+    /// A finite float.
+    Number(f64),
+    /// A non-finite float written as a string.
+    Str(&'static str),
+}
+
+impl Serialize for FloatValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            FloatValue::Number(v) => serializer.serialize_f64(*v),
+            FloatValue::Str(s) => serializer.serialize_str(s),
+        }
+    }
+}
+
+// The process-wide format, set by `Builder::init` and `Builder::try_init`.
+// `NON_FINITE` holds the `NonFinite` discriminant, and `DECIMALS` the decimals plus one, zero for `None`.
+static NON_FINITE: AtomicU8 = AtomicU8::new(NonFinite::Null as u8);
+static DECIMALS: AtomicU8 = AtomicU8::new(0);
+
+pub(crate) fn set_format(format: FloatFormat) {
+    NON_FINITE.store(format.non_finite as u8, Ordering::Relaxed);
+    DECIMALS.store(
+        format.decimals.map_or(0, |d| d.min(15) + 1),
+        Ordering::Relaxed,
+    );
+}
+
+// Returns the process-wide format, or `None` for the default format, which `serde_json` already writes.
+fn format() -> Option<FloatFormat> {
+    let non_finite = match NON_FINITE.load(Ordering::Relaxed) {
+        1 => NonFinite::String,
+        2 => NonFinite::Clamp,
+        _ => NonFinite::Null,
+    };
+    let decimals = DECIMALS.load(Ordering::Relaxed).checked_sub(1);
+    let format = FloatFormat {
+        non_finite,
+        decimals,
+    };
+    (format != FloatFormat::default()).then_some(format)
+}
+
+/// Returns the formatted float of a float value, `Some(None)` for `null`, or `None` for the other values
+/// or with the default format.
+pub(crate) fn format_value(value: &Value) -> Option<Option<FloatValue>> {
+    let format = format()?;
+    let mut visitor = FloatVisitor(None);
+    let _ = value.visit(&mut visitor);
+    visitor.0.map(|v| format.format(v))
+}
+
+struct FloatVisitor(Option<f64>);
+
+impl<'v> VisitValue<'v> for FloatVisitor {
+    fn visit_any(&mut self, _value: Value) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn visit_f64(&mut self, value: f64) -> Result<(), Error> {
+        self.0 = Some(value);
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn float_format_works() {
+        let format = FloatFormat::default();
+        assert_eq!(None, format.format(f64::NAN));
+        assert_eq!(None, format.format(f64::INFINITY));
+        assert_eq!(Some(FloatValue::Number(0.1)), format.format(0.1));
+
+        let format = FloatFormat {
+            non_finite: NonFinite::String,
+            decimals: Some(3),
+        };
+        assert_eq!(Some(FloatValue::Str("NaN")), format.format(f64::NAN));
+        assert_eq!(
+            Some(FloatValue::Str("-Infinity")),
+            format.format(f64::NEG_INFINITY)
+        );
+        assert_eq!(Some(FloatValue::Number(0.667)), format.format(2.0 / 3.0));
+        assert_eq!(Some(FloatValue::Number(1e300)), format.format(1e300));
+        assert_eq!(
+            "0.667",
+            serde_json::to_string(&format.format(2.0 / 3.0)).unwrap()
+        );
+
+        let format = FloatFormat {
+            non_finite: NonFinite::Clamp,
+            decimals: None,
+        };
+        assert_eq!(None, format.format(f64::NAN));
+        assert_eq!(
+            Some(FloatValue::Number(f64::MAX)),
+            format.format(f64::INFINITY)
+        );
+
+        let mut visitor = FloatVisitor(None);
+        Value::from(1.5).visit(&mut visitor).unwrap();
+        assert_eq!(Some(1.5), visitor.0);
+        let mut visitor = FloatVisitor(None);
+        Value::from(2_u64).visit(&mut visitor).unwrap();
+        assert_eq!(None, visitor.0);
+    }
+}
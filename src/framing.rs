@@ -0,0 +1,246 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Framed Writer Implementation
+//!
+//! A [`Writer`] implementation for the binary formats, such as CBOR, MessagePack or Protobuf, over a stream:
+//! every record is encoded by a given function, and written with a length prefix, so the consumers
+//! can delimit the records without sentinel bytes. The prefix is selected with [`Framing`]:
+//! * [`Framing::U32Le`]: the length as a 4-byte little-endian unsigned integer;
+//! * [`Framing::Varint`]: the length as an unsigned LEB128 varint, like the Protobuf delimited messages.
+//!
+//! The consumers read the records with [`Framing::read_frame`].
+//! To create a `Box<dyn Writer>` use the [`new_writer`] function.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{framing::{self, Framing}, Builder};
+//!
+//! fn main() {
+//!     let stream = std::io::stdout();
+//!     Builder::with_level("info")
+//!         .with_default_writer(framing::new_writer(Framing::Varint, stream, |value, buf| {
+//!             // encode the record with a binary format, such as `ciborium::into_writer(value, buf)`.
+//!             serde_json::to_writer(buf, value).map_err(std::io::Error::from)
+//!         }))
+//!         .init();
+//!
+//!     log::info!("hello world");
+//! }
+//! ```
+//!
+
+use parking_lot::Mutex;
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    io::{self, Read, Write},
+};
+
+use crate::metrics::Health;
+use crate::pool::BufferPool;
+use crate::{Key, Value, Writer};
+
+/// The length prefix of the records written by a [`FramedWriter`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    /// The length as a 4-byte little-endian unsigned integer.
+    U32Le,
+    /// The length as an unsigned LEB128 varint, 1 byte for the records shorter than 128 bytes.
+    Varint,
+}
+
+impl Framing {
+    /// Writes the length prefix of a record of `len` bytes into `buf`.
+    pub fn encode_len(&self, len: usize, buf: &mut Vec<u8>) -> Result<(), io::Error> {
+        match self {
+            Framing::U32Le => {
+                let len = u32::try_from(len).map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("a record of {} bytes is too large for the u32 framing", len),
+                    )
+                })?;
+                buf.extend_from_slice(&len.to_le_bytes());
+            }
+            Framing::Varint => {
+                let mut len = len as u64;
+                while len >= 0x80 {
+                    buf.push((len as u8 & 0x7f) | 0x80);
+                    len >>= 7;
+                }
+                buf.push(len as u8);
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a record written by a [`FramedWriter`] with this framing from `r`,
+    /// returns `None` at the end of the stream.
+    pub fn read_frame<R: Read>(&self, r: &mut R) -> Result<Option<Vec<u8>>, io::Error> {
+        let mut first = [0u8; 1];
+        if r.read(&mut first)? == 0 {
+            return Ok(None);
+        }
+        let len = match self {
+            Framing::U32Le => {
+                let mut rest = [0u8; 3];
+                r.read_exact(&mut rest)?;
+                u32::from_le_bytes([first[0], rest[0], rest[1], rest[2]]) as u64
+            }
+            Framing::Varint => {
+                let mut len = 0u64;
+                let mut byte = first[0];
+                let mut shift = 0;
+                loop {
+                    if shift > 63 {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "the varint length prefix is too long",
+                        ));
+                    }
+                    len |= ((byte & 0x7f) as u64) << shift;
+                    if byte < 0x80 {
+                        break;
+                    }
+                    shift += 7;
+                    r.read_exact(&mut first)?;
+                    byte = first[0];
+                }
+                len
+            }
+        };
+        let mut buf = vec![0u8; len as usize];
+        r.read_exact(&mut buf)?;
+        Ok(Some(buf))
+    }
+}
+
+/// A Writer implementation that writes the records encoded by a given function, with a length prefix.
+pub struct FramedWriter<W, F> {
+    framing: Framing,
+    w: Mutex<W>,
+    encode: F,
+    health: Health,
+}
+
+impl<W, F> FramedWriter<W, F>
+where
+    W: Write + Send + 'static,
+    F: Fn(&BTreeMap<Key, Value>, &mut Vec<u8>) -> Result<(), io::Error> + Send + Sync + 'static,
+{
+    /// Creates a new FramedWriter instance that encodes the records with `encode` into the given buffer,
+    /// and writes them to `w` with the given framing.
+    pub fn new(framing: Framing, w: W, encode: F) -> Self {
+        FramedWriter {
+            framing,
+            w: Mutex::new(w),
+            encode,
+            health: Health::default(),
+        }
+    }
+
+    fn write_frame(
+        &self,
+        value: &BTreeMap<Key, Value>,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), io::Error> {
+        // reserves the longest prefix, 10 bytes for a varint, and moves the record after the actual one.
+        buf.resize(10, 0);
+        (self.encode)(value, buf)?;
+        let len = buf.len() - 10;
+        let mut prefix = Vec::with_capacity(10);
+        self.framing.encode_len(len, &mut prefix)?;
+        let start = 10 - prefix.len();
+        buf[start..10].copy_from_slice(&prefix);
+        // the prefix and the record in one write, so they are never split by another record.
+        self.health.track(self.w.lock().write_all(&buf[start..]))
+    }
+}
+
+/// Implements Writer trait for FramedWriter.
+impl<W, F> Writer for FramedWriter<W, F>
+where
+    W: Write + Send + 'static,
+    F: Fn(&BTreeMap<Key, Value>, &mut Vec<u8>) -> Result<(), io::Error> + Send + Sync + 'static,
+{
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        let pool = BufferPool::global();
+        let mut buf = pool.get();
+        let res = self.write_frame(value, &mut buf);
+        pool.put(buf);
+        res
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        self.w.lock().flush()
+    }
+
+    fn healthy(&self) -> bool {
+        self.health.healthy()
+    }
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the FramedWriter that encodes the records with `encode`,
+/// and writes them to `w` with the given framing.
+pub fn new_writer<W, F>(framing: Framing, w: W, encode: F) -> Box<dyn Writer>
+where
+    W: Write + Send + 'static,
+    F: Fn(&BTreeMap<Key, Value>, &mut Vec<u8>) -> Result<(), io::Error> + Send + Sync + 'static,
+{
+    Box::new(FramedWriter::new(framing, w, encode))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct Stream(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Stream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn framed_writer_works() {
+        let mut prefix = Vec::new();
+        Framing::Varint.encode_len(300, &mut prefix).unwrap();
+        assert_eq!(vec![0xac, 0x02], prefix);
+
+        for framing in [Framing::U32Le, Framing::Varint] {
+            let stream = Stream::default();
+            let w = new_writer(framing, stream.clone(), |value, buf| {
+                let message = value
+                    .get(&Key::from("message"))
+                    .and_then(|v| v.to_borrowed_str())
+                    .unwrap_or_default();
+                buf.extend_from_slice(message.as_bytes());
+                Ok(())
+            });
+
+            let messages = ["", "hello", &"x".repeat(200)];
+            for message in messages {
+                let mut value = BTreeMap::new();
+                value.insert(Key::from("message"), Value::from(message));
+                w.write_log(&value).unwrap();
+            }
+
+            let data = stream.0.lock().clone();
+            let mut r = data.as_slice();
+            for message in messages {
+                let frame = framing.read_frame(&mut r).unwrap().unwrap();
+                assert_eq!(message.as_bytes(), frame.as_slice());
+            }
+            assert!(framing.read_frame(&mut r).unwrap().is_none());
+        }
+    }
+}
@@ -0,0 +1,365 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Runtime-agnostic Async JSON Writer Implementation
+//!
+//! A [`Writer`] implementation that logs structured values
+//! asynchronous in JSON format to a file, stderr, stdout, or any other destination,
+//! base on [`futures::io::AsyncWrite`] and a pluggable [`Spawner`],
+//! so it works with [`async-std`], [`smol`], or any other runtime.
+//! To create a `Box<dyn Writer>` use the [`new_writer`] function.
+//!
+//! It shares the bounded queue and the [`BackpressurePolicy`] of the `async_json` writer, without depending on tokio,
+//! records are written in call order, up to [`DEFAULT_BATCH_SIZE`] records per write,
+//! by a single long-lived task per writer, which is spawned by the first log call.
+//!
+//! This module requires the `futures` feature.
+//!
+//! Example with `async-std`:
+//! ```rust,ignore
+//! use structured_logger::{futures_json::new_writer, Builder};
+//!
+//! #[async_std::main]
+//! async fn main() {
+//!     Builder::with_level("info")
+//!         .with_default_writer(new_writer(async_std::io::stdout(), |fut| {
+//!             async_std::task::spawn(fut);
+//!         }))
+//!         .init();
+//!
+//!     log::info!("hello world");
+//! }
+//! ```
+//!
+//! [`futures::io::AsyncWrite`]: https://docs.rs/futures-io/latest/futures_io/trait.AsyncWrite.html
+//! [`async-std`]: https://crates.io/crates/async-std
+//! [`smol`]: https://crates.io/crates/smol
+//!
+
+use futures_io::AsyncWrite;
+use futures_util::lock::Mutex;
+use log::Level;
+use std::{
+    collections::BTreeMap,
+    future::{poll_fn, Future},
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    time::Duration,
+};
+
+use crate::json::encode_owned;
+use crate::metrics::QueueMetrics;
+use crate::pool::BufferPool;
+use crate::queue::Queue;
+use crate::{log_failure, Fields, Key, Value, Writer};
+
+pub use crate::queue::{BackpressurePolicy, DEFAULT_BATCH_SIZE, DEFAULT_QUEUE_CAPACITY};
+
+/// How long [`Writer::flush`] waits for the queued records to be written and flushed.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A boxed future spawned by the [`Spawner`].
+pub type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+/// A trait that defines how to spawn the writing tasks on an async runtime.
+///
+/// It is implemented for closures, such as `|fut| { async_std::task::spawn(fut); }`
+/// or `|fut| smol::spawn(fut).detach()`.
+pub trait Spawner: Send + Sync + 'static {
+    /// Spawns a future on the runtime, the future must be polled to completion.
+    fn spawn(&self, fut: BoxFuture);
+}
+
+impl<F: Fn(BoxFuture) + Send + Sync + 'static> Spawner for F {
+    fn spawn(&self, fut: BoxFuture) {
+        self(fut)
+    }
+}
+
+/// A Writer implementation that writes logs asynchronous in JSON format on any runtime.
+pub struct FuturesJSONWriter<W: AsyncWrite + Send + 'static> {
+    w: Arc<Mutex<Pin<Box<W>>>>,
+    queue: Arc<Queue>,
+    spawner: Arc<dyn Spawner>,
+    // whether the consumer task has been spawned.
+    started: AtomicBool,
+}
+
+impl<W: AsyncWrite + Send + 'static> FuturesJSONWriter<W> {
+    /// Creates a new FuturesJSONWriter instance
+    /// with the [`DEFAULT_QUEUE_CAPACITY`] and the default [`BackpressurePolicy`].
+    pub fn new<S: Spawner>(w: W, spawner: S) -> Self {
+        Self::with_policy(
+            w,
+            spawner,
+            DEFAULT_QUEUE_CAPACITY,
+            BackpressurePolicy::default(),
+        )
+    }
+
+    /// Creates a new FuturesJSONWriter instance with a given queue `capacity` and backpressure `policy`.
+    pub fn with_policy<S: Spawner>(
+        w: W,
+        spawner: S,
+        capacity: usize,
+        policy: BackpressurePolicy,
+    ) -> Self {
+        Self {
+            w: Arc::new(Mutex::new(Box::pin(w))),
+            queue: Arc::new(Queue::new(
+                capacity,
+                policy,
+                DEFAULT_BATCH_SIZE,
+                Duration::ZERO,
+            )),
+            spawner: Arc::new(spawner),
+            started: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the number of records dropped because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.queue.dropped()
+    }
+
+    /// Returns a [`QueueMetrics`] handle to read the queue depth and drop counters.
+    pub fn metrics(&self) -> QueueMetrics {
+        self.queue.metrics()
+    }
+}
+
+/// Implements Writer trait for FuturesJSONWriter.
+impl<W: AsyncWrite + Send + 'static> Writer for FuturesJSONWriter<W> {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        self.enqueue(encode_owned(value)?, self.queue.level_of(value))
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        self.enqueue(encode_owned(fields)?, self.queue.level_of(fields))
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        self.drain_blocking(false)
+    }
+
+    fn shutdown(&self) -> Result<(), io::Error> {
+        self.queue.close();
+        self.drain_blocking(true)
+    }
+}
+
+impl<W: AsyncWrite + Send + 'static> FuturesJSONWriter<W> {
+    // Writes the queued records, then flushes or closes the underlying writer, and waits for it.
+    fn drain_blocking(&self, close: bool) -> Result<(), io::Error> {
+        let (tx, rx) = mpsc::channel();
+        let w = self.w.clone();
+        let queue = self.queue.clone();
+        self.spawner.spawn(Box::pin(async move {
+            let mut w = w.lock().await;
+            write_queued(&mut w, &queue).await;
+            let res = if close {
+                poll_fn(|cx| w.as_mut().poll_close(cx)).await
+            } else {
+                poll_fn(|cx| w.as_mut().poll_flush(cx)).await
+            };
+            let _ = tx.send(res);
+        }));
+
+        // it can't complete if the caller blocks the only thread of a single-threaded executor.
+        match rx.recv_timeout(FLUSH_TIMEOUT) {
+            Ok(res) => res,
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "FuturesJSONWriter flush timed out",
+            )),
+        }
+    }
+
+    fn enqueue(&self, buf: Vec<u8>, level: Option<Level>) -> Result<(), io::Error> {
+        if self.queue.is_closed() {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "FuturesJSONWriter has been shut down",
+            ));
+        }
+        if !self.queue.push(buf, level) {
+            return Ok(());
+        }
+
+        if !self.started.swap(true, Ordering::SeqCst) {
+            self.spawner
+                .spawn(Box::pin(consume(self.w.clone(), self.queue.clone())));
+        }
+        Ok(())
+    }
+}
+
+impl<W: AsyncWrite + Send + 'static> Drop for FuturesJSONWriter<W> {
+    fn drop(&mut self) {
+        // no more records will be pushed, let the consumer task drain the queue and exit.
+        self.queue.close();
+    }
+}
+
+// The long-lived consumer task of a writer, it exits when the queue is closed and drained.
+async fn consume<W: AsyncWrite + Send + 'static>(w: Arc<Mutex<Pin<Box<W>>>>, queue: Arc<Queue>) {
+    loop {
+        queue.wait().await;
+        let mut w = w.lock().await;
+        write_queued(&mut w, &queue).await;
+        if queue.is_closed() {
+            return;
+        }
+    }
+}
+
+// Records are popped while holding the writer lock, so records are written in the order they were enqueued.
+async fn write_queued<W: AsyncWrite + Send + 'static>(w: &mut Pin<Box<W>>, queue: &Queue) {
+    let mut buf = BufferPool::global().get();
+    while queue.pop_batch(&mut buf) > 0 {
+        if let Err(err) = write_all(w.as_mut(), &buf).await {
+            // should never happen, but if it does, we log it.
+            log_failure(format!("FuturesJSONWriter failed to write log: {}", err).as_str());
+        }
+        buf.clear();
+    }
+    BufferPool::global().put(buf);
+}
+
+async fn write_all<W: AsyncWrite + ?Sized>(mut w: Pin<&mut W>, mut buf: &[u8]) -> io::Result<()> {
+    while !buf.is_empty() {
+        let n = poll_fn(|cx| w.as_mut().poll_write(cx, buf)).await?;
+        if n == 0 {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        buf = &buf[n..];
+    }
+    Ok(())
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the FuturesJSONWriter
+/// for a given futures::io::AsyncWrite instance and [`Spawner`].
+pub fn new_writer<W: AsyncWrite + Send + 'static, S: Spawner>(w: W, spawner: S) -> Box<dyn Writer> {
+    Box::new(FuturesJSONWriter::new(w, spawner))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::thread::{self, Thread};
+
+    // A minimal executor that polls a future on the current thread until it completes, without tokio.
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on(mut fut: BoxFuture) {
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        while fut.as_mut().poll(&mut cx).is_pending() {
+            thread::park();
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<parking_lot::Mutex<Vec<u8>>>);
+
+    impl AsyncWrite for SharedBuf {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.0.lock().extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[test]
+    fn futures_writer_works() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let handle = rt.handle().clone();
+        let buf = SharedBuf::default();
+        let writer = FuturesJSONWriter::new(buf.clone(), move |fut| {
+            handle.spawn(fut);
+        });
+
+        for i in 0..100_u64 {
+            let mut value = BTreeMap::new();
+            value.insert(Key::from("index"), Value::from(i));
+            writer.write_log(&value).unwrap();
+        }
+        writer.flush().unwrap();
+
+        let output = String::from_utf8(buf.0.lock().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(100, lines.len());
+        for (i, line) in lines.iter().enumerate() {
+            assert_eq!(format!("{{\"index\":{}}}", i), *line);
+        }
+    }
+
+    #[test]
+    fn write_after_shutdown_works() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let handle = rt.handle().clone();
+        let buf = SharedBuf::default();
+        let writer = FuturesJSONWriter::new(buf.clone(), move |fut| {
+            handle.spawn(fut);
+        });
+
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("message"), Value::from("hello"));
+        writer.write_log(&value).unwrap();
+        writer.shutdown().unwrap();
+
+        // the sink is closed, the records are rejected instead of being queued forever.
+        let err = writer.write_log(&value).unwrap_err();
+        assert_eq!(io::ErrorKind::BrokenPipe, err.kind());
+        assert_eq!(0, writer.queue.len());
+        assert_eq!(
+            "{\"message\":\"hello\"}\n",
+            String::from_utf8(buf.0.lock().clone()).unwrap()
+        );
+    }
+
+    #[test]
+    fn thread_spawner_works() {
+        let buf = SharedBuf::default();
+        let writer = FuturesJSONWriter::new(buf.clone(), |fut| {
+            thread::spawn(move || block_on(fut));
+        });
+
+        for i in 0..100_u64 {
+            let mut value = BTreeMap::new();
+            value.insert(Key::from("index"), Value::from(i));
+            writer.write_log(&value).unwrap();
+        }
+        writer.flush().unwrap();
+        writer.shutdown().unwrap();
+
+        let output = String::from_utf8(buf.0.lock().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(100, lines.len());
+        for (i, line) in lines.iter().enumerate() {
+            assert_eq!(format!("{{\"index\":{}}}", i), *line);
+        }
+    }
+}
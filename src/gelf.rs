@@ -0,0 +1,342 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # GELF UDP Writer Implementation
+//!
+//! A [`Writer`] implementation that sends structured values to Graylog in the
+//! [GELF 1.1](https://go2docs.graylog.org/current/getting_in_log_data/gelf.html) format over UDP.
+//! The messages larger than [`GelfOptions::chunk_size`] are split into GELF chunks, up to 128 chunks,
+//! so large records, such as the panics with a backtrace, reach Graylog intact.
+//! The messages are compressed with zlib if [`GelfOptions::compress`] is enabled, with the `gzip` feature.
+//!
+//! The fields are mapped to GELF: `message` to `short_message`, `backtrace` to `full_message`,
+//! `level` to the syslog severity, `timestamp` to seconds, and the other fields to additional fields
+//! prefixed with `_`.
+//! To create a `Box<dyn Writer>` use the [`new_writer`] function.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{gelf, Builder};
+//!
+//! fn main() {
+//!     let opts = gelf::GelfOptions::default();
+//!     Builder::with_level("info")
+//!         .with_default_writer(gelf::new_writer("127.0.0.1:12201", opts).unwrap())
+//!         .init();
+//!
+//!     log::info!("hello world");
+//! }
+//! ```
+//!
+
+use log::kv::{Key, Value};
+use serde_json::Map;
+use std::{
+    collections::BTreeMap,
+    io,
+    net::{ToSocketAddrs, UdpSocket},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::fields::FieldMap;
+use crate::{log_failure, unix_ns, Fields, Writer};
+
+/// The default size of a GELF chunk, that fits in the common 1500 bytes MTU.
+pub const DEFAULT_CHUNK_SIZE: usize = 1420;
+
+/// The maximum number of chunks of a GELF message.
+pub const MAX_CHUNKS: usize = 128;
+
+// The magic bytes, the message id, the sequence number and the sequence count of a chunk.
+const CHUNK_HEADER_SIZE: usize = 12;
+
+/// The options of a [`GelfWriter`].
+#[derive(Debug, Clone)]
+pub struct GelfOptions {
+    /// The `host` field of the messages, the default is the `HOSTNAME` environment variable, or `"localhost"`.
+    pub host: String,
+    /// The maximum size of a UDP datagram, the default is [`DEFAULT_CHUNK_SIZE`].
+    /// The messages larger than it are chunked.
+    pub chunk_size: usize,
+    /// Whether the messages are compressed with zlib, the default is false.
+    #[cfg(feature = "gzip")]
+    pub compress: bool,
+}
+
+impl Default for GelfOptions {
+    fn default() -> Self {
+        GelfOptions {
+            host: std::env::var("HOSTNAME").unwrap_or_else(|_| "localhost".to_string()),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            #[cfg(feature = "gzip")]
+            compress: false,
+        }
+    }
+}
+
+/// A Writer implementation that sends logs in GELF format over UDP.
+pub struct GelfWriter {
+    socket: UdpSocket,
+    opts: GelfOptions,
+    next_id: AtomicU64,
+}
+
+impl GelfWriter {
+    /// Creates a new GelfWriter instance that sends the messages to a given address.
+    pub fn new<A: ToSocketAddrs>(addr: A, opts: GelfOptions) -> Result<Self, io::Error> {
+        let addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to send to"))?;
+        let local = if addr.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(addr)?;
+        Ok(GelfWriter {
+            socket,
+            opts,
+            // a random start, so the message ids of the processes sending to the same server don't collide.
+            next_id: AtomicU64::new(unix_ns() ^ u64::from(std::process::id()).rotate_left(32)),
+        })
+    }
+
+    fn send(&self, fields: Map<String, serde_json::Value>) -> Result<(), io::Error> {
+        let msg = serde_json::to_vec(&to_gelf(fields, &self.opts.host))?;
+        #[cfg(feature = "gzip")]
+        let msg = if self.opts.compress {
+            compress(&msg)?
+        } else {
+            msg
+        };
+
+        if msg.len() <= self.opts.chunk_size {
+            self.socket.send(&msg)?;
+            return Ok(());
+        }
+
+        let data_size = self
+            .opts
+            .chunk_size
+            .saturating_sub(CHUNK_HEADER_SIZE)
+            .max(1);
+        let count = msg.len().div_ceil(data_size);
+        if count > MAX_CHUNKS {
+            log_failure(
+                format!(
+                    "GelfWriter dropped a message of {} bytes: too many chunks",
+                    msg.len()
+                )
+                .as_str(),
+            );
+            return Ok(());
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed).to_be_bytes();
+        let mut chunk = Vec::with_capacity(self.opts.chunk_size);
+        for (seq, data) in msg.chunks(data_size).enumerate() {
+            chunk.clear();
+            chunk.extend_from_slice(&[0x1e, 0x0f]);
+            chunk.extend_from_slice(&id);
+            chunk.push(seq as u8);
+            chunk.push(count as u8);
+            chunk.extend_from_slice(data);
+            self.socket.send(&chunk)?;
+        }
+        Ok(())
+    }
+}
+
+// Maps the fields of a record to a GELF message.
+fn to_gelf(fields: Map<String, serde_json::Value>, host: &str) -> Map<String, serde_json::Value> {
+    let mut gelf = Map::new();
+    gelf.insert("version".to_string(), "1.1".into());
+    gelf.insert("host".to_string(), host.into());
+    for (key, value) in fields {
+        match (key.as_str(), value) {
+            ("message", serde_json::Value::String(msg)) if !msg.is_empty() => {
+                gelf.insert("short_message".to_string(), msg.into());
+            }
+            ("backtrace", serde_json::Value::String(bt)) => {
+                gelf.insert("full_message".to_string(), bt.into());
+            }
+            ("level", serde_json::Value::String(level)) => {
+                gelf.insert("level".to_string(), syslog_level(&level).into());
+            }
+            ("timestamp", serde_json::Value::Number(ms)) => {
+                let secs = ms.as_u64().unwrap_or_default() as f64 / 1000.0;
+                gelf.insert("timestamp".to_string(), secs.into());
+            }
+            (_, serde_json::Value::Null) => {}
+            (key, value) => {
+                // the additional fields are strings or numbers, named `_[\w.-]+` except `_id`.
+                let mut name = String::with_capacity(key.len() + 1);
+                name.push('_');
+                name.extend(key.chars().map(|c| {
+                    if c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-') {
+                        c
+                    } else {
+                        '_'
+                    }
+                }));
+                if name == "_id" {
+                    name.push('_');
+                }
+                let value = match value {
+                    serde_json::Value::Number(n) => serde_json::Value::Number(n),
+                    serde_json::Value::String(s) => serde_json::Value::String(s),
+                    value => serde_json::Value::String(value.to_string()),
+                };
+                gelf.insert(name, value);
+            }
+        }
+    }
+    gelf.entry("short_message").or_insert_with(|| "-".into());
+    gelf
+}
+
+// The syslog severity of a level.
+fn syslog_level(level: &str) -> u8 {
+    match level {
+        "ERROR" => 3,
+        "WARN" => 4,
+        "INFO" => 6,
+        _ => 7,
+    }
+}
+
+#[cfg(feature = "gzip")]
+fn compress(msg: &[u8]) -> Result<Vec<u8>, io::Error> {
+    use flate2::{write::ZlibEncoder, Compression};
+    use std::io::Write;
+
+    let mut encoder = ZlibEncoder::new(Vec::with_capacity(msg.len() / 2), Compression::default());
+    encoder.write_all(msg)?;
+    encoder.finish()
+}
+
+fn to_object(value: serde_json::Value) -> Map<String, serde_json::Value> {
+    match value {
+        serde_json::Value::Object(map) => map,
+        _ => Map::new(),
+    }
+}
+
+/// Implements Writer trait for GelfWriter.
+impl Writer for GelfWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        self.send(to_object(serde_json::to_value(FieldMap(value))?))
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        self.send(to_object(serde_json::to_value(fields)?))
+    }
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the GelfWriter for a given address, such as `"graylog:12201"`.
+pub fn new_writer<A: ToSocketAddrs>(
+    addr: A,
+    opts: GelfOptions,
+) -> Result<Box<dyn Writer>, io::Error> {
+    Ok(Box::new(GelfWriter::new(addr, opts)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn receive(server: &UdpSocket) -> Vec<u8> {
+        let mut chunks: BTreeMap<u8, Vec<u8>> = BTreeMap::new();
+        let mut buf = [0_u8; 2048];
+        loop {
+            let n = server.recv(&mut buf).unwrap();
+            if buf[..2] != [0x1e, 0x0f] {
+                return buf[..n].to_vec();
+            }
+            let count = buf[11];
+            chunks.insert(buf[10], buf[CHUNK_HEADER_SIZE..n].to_vec());
+            if chunks.len() == count as usize {
+                return chunks.into_values().flatten().collect();
+            }
+        }
+    }
+
+    #[test]
+    fn gelf_writer_works() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let opts = GelfOptions {
+            chunk_size: 256,
+            ..Default::default()
+        };
+        let host = opts.host.clone();
+        let writer = GelfWriter::new(server.local_addr().unwrap(), opts).unwrap();
+
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("message"), Value::from("hello"));
+        value.insert(Key::from("level"), Value::from("WARN"));
+        value.insert(Key::from("timestamp"), Value::from(1679745592127_u64));
+        value.insert(Key::from("id"), Value::from(7_u64));
+        value.insert(Key::from("ok"), Value::from(true));
+        writer.write_log(&value).unwrap();
+
+        let msg: serde_json::Value = serde_json::from_slice(&receive(&server)).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "version": "1.1",
+                "host": host,
+                "short_message": "hello",
+                "level": 4,
+                "timestamp": 1679745592.127,
+                "_id_": 7,
+                "_ok": "true",
+            }),
+            msg
+        );
+
+        // a large record is chunked.
+        let backtrace = "frame\n".repeat(200);
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("message"), Value::from("panicked"));
+        value.insert(Key::from("backtrace"), Value::from(backtrace.as_str()));
+        writer.write_log(&value).unwrap();
+
+        let msg: serde_json::Value = serde_json::from_slice(&receive(&server)).unwrap();
+        assert_eq!("panicked", msg["short_message"]);
+        assert_eq!(backtrace, msg["full_message"]);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gelf_writer_compress_works() {
+        use std::io::Read;
+
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let opts = GelfOptions {
+            chunk_size: 64,
+            compress: true,
+            ..Default::default()
+        };
+        let writer = GelfWriter::new(server.local_addr().unwrap(), opts).unwrap();
+
+        let message = "hello world ".repeat(50);
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("message"), Value::from(message.as_str()));
+        writer.write_log(&value).unwrap();
+
+        let mut json = String::new();
+        flate2::read::ZlibDecoder::new(receive(&server).as_slice())
+            .read_to_string(&mut json)
+            .unwrap();
+        let msg: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(message, msg["short_message"]);
+    }
+}
@@ -0,0 +1,235 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Hash-chained Writer Implementation
+//!
+//! A [`Writer`] implementation that logs structured values in JSON format, and appends to each record
+//! a `record_hash` field computed as `SHA-256(prev_hash || record)`, where `record` is the JSON record
+//! without the `record_hash` field, and `prev_hash` is the hash of the previous record of the writer,
+//! empty for the first record. Deleting, reordering or modifying a line breaks the chain,
+//! which is detected by the [`verify`] function.
+//!
+//! This module requires the `hash-chain` feature.
+//! To create a `Box<dyn Writer>` use the [`new_writer`] function.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{hash_chain, Builder};
+//!
+//! fn main() {
+//!     let path = std::env::temp_dir().join("audit-chain.log");
+//!     let file = std::fs::File::create(&path).unwrap();
+//!     Builder::with_level("info")
+//!         .with_default_writer(hash_chain::new_writer(file))
+//!         .init();
+//!
+//!     log::info!(user = "user123"; "deleted the account");
+//!     // {"level":"INFO","message":"deleted the account",...,"user":"user123","record_hash":"5d2c..."}
+//!
+//!     let file = std::fs::File::open(&path).unwrap();
+//!     hash_chain::verify(std::io::BufReader::new(file)).unwrap();
+//! }
+//! ```
+//!
+
+use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{self, BufRead, Write},
+};
+
+use crate::json::with_encoded;
+use crate::{Fields, Key, Value, Writer};
+
+const HASH_KEY: &str = ",\"record_hash\":\"";
+
+struct Chain<W> {
+    w: W,
+    prev: Vec<u8>,
+    line: Vec<u8>,
+}
+
+/// A Writer implementation that writes logs in JSON format with a `record_hash` chain.
+pub struct HashChainWriter<W: Write + Send + 'static>(Mutex<Chain<W>>);
+
+impl<W: Write + Send + 'static> HashChainWriter<W> {
+    /// Creates a new HashChainWriter instance, that starts a new chain.
+    pub fn new(w: W) -> Self {
+        HashChainWriter(Mutex::new(Chain {
+            w,
+            prev: Vec::new(),
+            line: Vec::new(),
+        }))
+    }
+
+    /// Creates a new HashChainWriter instance, that continues the chain of a given hex-encoded hash,
+    /// such as the last hash returned by [`verify`] for the file it appends to.
+    pub fn resume(w: W, prev_hash: &str) -> Result<Self, io::Error> {
+        let prev = decode_hex(prev_hash)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid hash"))?;
+        let writer = Self::new(w);
+        writer.0.lock().prev = prev;
+        Ok(writer)
+    }
+
+    fn write_line(&self, buf: &[u8]) -> Result<(), io::Error> {
+        // the encoded record ends with "}\n".
+        let record = buf.strip_suffix(b"\n").unwrap_or(buf);
+        let mut chain = self.0.lock();
+        let hash = chain_hash(&chain.prev, record);
+
+        let Chain { w, prev, line } = &mut *chain;
+        line.clear();
+        line.extend_from_slice(&record[..record.len() - 1]);
+        if record.len() > 2 {
+            line.extend_from_slice(HASH_KEY.as_bytes());
+        } else {
+            line.extend_from_slice(&HASH_KEY.as_bytes()[1..]);
+        }
+        line.extend_from_slice(encode_hex(&hash).as_bytes());
+        line.extend_from_slice(b"\"}\n");
+        w.write_all(line)?;
+        *prev = hash;
+        Ok(())
+    }
+}
+
+/// Implements Writer trait for HashChainWriter.
+impl<W: Write + Send + 'static> Writer for HashChainWriter<W> {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        with_encoded(value, |buf| self.write_line(buf))
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        with_encoded(fields, |buf| self.write_line(buf))
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        self.0.lock().w.flush()
+    }
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the HashChainWriter for a given std::io::Write instance.
+pub fn new_writer<W: Write + Send + 'static>(w: W) -> Box<dyn Writer> {
+    Box::new(HashChainWriter::new(w))
+}
+
+/// Verifies the `record_hash` chain of the lines written by a [`HashChainWriter`] from its first record,
+/// and returns the hex-encoded hash of the last record, `None` if there is no record.
+/// It returns an `InvalidData` error with the line number of the first line that breaks the chain.
+pub fn verify<R: BufRead>(r: R) -> Result<Option<String>, io::Error> {
+    let mut prev = Vec::new();
+    let mut last = None;
+    for (i, line) in r.lines().enumerate() {
+        let line = line?;
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("record_hash chain broken at line {}", i + 1),
+            )
+        };
+
+        let body = line.strip_suffix("\"}").ok_or_else(invalid)?;
+        let (record, hash) = match body.rfind(HASH_KEY) {
+            Some(at) => (format!("{}}}", &body[..at]), &body[at + HASH_KEY.len()..]),
+            // the record without other fields.
+            None => (
+                "{}".to_string(),
+                body.strip_prefix("{\"record_hash\":\"")
+                    .ok_or_else(invalid)?,
+            ),
+        };
+        let expected = chain_hash(&prev, record.as_bytes());
+        if decode_hex(hash).as_deref() != Some(expected.as_slice()) {
+            return Err(invalid());
+        }
+        prev = expected;
+        last = Some(hash.to_string());
+    }
+    Ok(last)
+}
+
+fn chain_hash(prev: &[u8], record: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(prev);
+    hasher.update(record);
+    hasher.finalize().to_vec()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        let _ = write!(s, "{:02x}", b);
+    }
+    s
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn hash_chain_works() {
+        let buf = SharedBuf::default();
+        let writer = HashChainWriter::new(buf.clone());
+        for i in 0..3_u64 {
+            let mut value = BTreeMap::new();
+            value.insert(Key::from("line"), Value::from(i));
+            writer.write_log(&value).unwrap();
+        }
+        writer.write_log(&BTreeMap::new()).unwrap();
+
+        let output = String::from_utf8(buf.0.lock().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(4, lines.len());
+        assert!(lines[0].starts_with("{\"line\":0,\"record_hash\":\""));
+        assert!(lines[3].starts_with("{\"record_hash\":\""));
+        let last = verify(output.as_bytes()).unwrap().unwrap();
+        assert_eq!(64, last.len());
+
+        // the chain can be resumed.
+        let resumed = HashChainWriter::resume(buf.clone(), &last).unwrap();
+        resumed.write_log(&BTreeMap::new()).unwrap();
+        let output = String::from_utf8(buf.0.lock().clone()).unwrap();
+        assert!(verify(output.as_bytes()).is_ok());
+
+        // a modified, deleted or reordered line breaks the chain.
+        let modified = output.replacen("\"line\":1", "\"line\":9", 1);
+        let err = verify(modified.as_bytes()).unwrap_err();
+        assert_eq!("record_hash chain broken at line 2", err.to_string());
+        let deleted: Vec<&str> = output
+            .lines()
+            .filter(|l| !l.contains("\"line\":0"))
+            .collect();
+        assert!(verify(deleted.join("\n").as_bytes()).is_err());
+        let mut reordered: Vec<&str> = output.lines().collect();
+        reordered.swap(1, 2);
+        assert!(verify(reordered.join("\n").as_bytes()).is_err());
+    }
+}
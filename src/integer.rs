@@ -0,0 +1,145 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Integer Values
+//!
+//! The 128-bit integers, `u128` and `i128`, are supported as key-values. The ones out of the 64-bit range
+//! are written as decimal strings, as most JSON parsers, and the writers that build a `serde_json::Value`,
+//! don't support them.
+//!
+//! The JavaScript parsers read the JSON numbers as doubles, which corrupts the integers above
+//! [`MAX_SAFE_INTEGER`], such as snowflake IDs. With
+//! [`Builder::with_big_integers_as_strings`](crate::Builder::with_big_integers_as_strings),
+//! these integers are written as decimal strings too.
+//!
+//! Only the top-level integer values of a record are written as strings, the integers nested in a
+//! structured value are serialized by `serde` as usual.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::Builder;
+//!
+//! fn main() {
+//!     Builder::with_level("info")
+//!         .with_big_integers_as_strings()
+//!         .init();
+//!
+//!     // {"attempt":1,"level":"INFO","message":"order created","order_id":"1541815603606036480",...}
+//!     log::info!(order_id = 1541815603606036480_u64, attempt = 1; "order created");
+//! }
+//! ```
+//!
+
+use log::kv::{Error, Value, VisitValue};
+use std::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// The largest integer that a double represents exactly, `2^53 - 1`, like `Number.MAX_SAFE_INTEGER` in JavaScript.
+pub const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+// Whether the integers above `MAX_SAFE_INTEGER` are written as strings, set by `Builder::init` and `Builder::try_init`.
+static BIG_AS_STRING: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn set_big_as_string(enabled: bool) {
+    BIG_AS_STRING.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns the decimal string of an integer value that is written as a string, or `None` for the other values.
+pub(crate) fn format_value(value: &Value) -> Option<String> {
+    if value.to_borrowed_str().is_some() {
+        return None;
+    }
+    let mut visitor = IntegerVisitor(None);
+    let _ = value.visit(&mut visitor);
+    let n = visitor.0?;
+    n.is_big(BIG_AS_STRING.load(Ordering::Relaxed))
+        .then(|| n.to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Integer {
+    Unsigned(u128),
+    Signed(i128),
+}
+
+impl Integer {
+    // Returns true if the integer is written as a string.
+    fn is_big(self, big_as_string: bool) -> bool {
+        match (self, big_as_string) {
+            (Integer::Unsigned(n), true) => n > MAX_SAFE_INTEGER as u128,
+            (Integer::Signed(n), true) => n.unsigned_abs() > MAX_SAFE_INTEGER as u128,
+            // out of the 64-bit range, from `i64::MIN` to `u64::MAX`.
+            (Integer::Unsigned(n), false) => n > u64::MAX as u128,
+            (Integer::Signed(n), false) => n < i64::MIN as i128 || n > u64::MAX as i128,
+        }
+    }
+}
+
+impl fmt::Display for Integer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Integer::Unsigned(n) => n.fmt(f),
+            Integer::Signed(n) => n.fmt(f),
+        }
+    }
+}
+
+struct IntegerVisitor(Option<Integer>);
+
+impl<'v> VisitValue<'v> for IntegerVisitor {
+    fn visit_any(&mut self, _value: Value) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn visit_u64(&mut self, value: u64) -> Result<(), Error> {
+        self.0 = Some(Integer::Unsigned(value as u128));
+        Ok(())
+    }
+
+    fn visit_i64(&mut self, value: i64) -> Result<(), Error> {
+        self.0 = Some(Integer::Signed(value as i128));
+        Ok(())
+    }
+
+    fn visit_u128(&mut self, value: u128) -> Result<(), Error> {
+        self.0 = Some(Integer::Unsigned(value));
+        Ok(())
+    }
+
+    fn visit_i128(&mut self, value: i128) -> Result<(), Error> {
+        self.0 = Some(Integer::Signed(value));
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integer_works() {
+        assert_eq!(None, format_value(&Value::from(u64::MAX)));
+        assert_eq!(None, format_value(&Value::from(i64::MIN)));
+        assert_eq!(None, format_value(&Value::from(1.5)));
+        assert_eq!(None, format_value(&Value::from("1")));
+        assert_eq!(
+            Some(u128::MAX.to_string()),
+            format_value(&Value::from(u128::MAX))
+        );
+        assert_eq!(
+            Some(i128::MIN.to_string()),
+            format_value(&Value::from(i128::MIN))
+        );
+        assert_eq!(None, format_value(&Value::from(u64::MAX as u128)));
+        assert_eq!(None, format_value(&Value::from(-42_i128)));
+
+        let safe = MAX_SAFE_INTEGER as u128;
+        assert!(!Integer::Unsigned(safe).is_big(true));
+        assert!(Integer::Unsigned(safe + 1).is_big(true));
+        assert!(!Integer::Signed(-(safe as i128)).is_big(true));
+        assert!(Integer::Signed(-(safe as i128) - 1).is_big(true));
+        assert!(!Integer::Unsigned(safe + 1).is_big(false));
+    }
+}
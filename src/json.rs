@@ -5,45 +5,575 @@
 //!
 //! A [`Writer`] implementation that logs structured values
 //! synchronous in JSON format to a file, stderr, stdout, or any other destination.
-//! To create a `Box<dyn Writer>` use the [`new_writer`] function.
+//! To create a `Box<dyn Writer>` use the [`new_writer`] function,
+//! or the [`new_file_writer`] function and the [`FileOptions`] builder to log to a file.
+//!
+//! Each record is encoded with its trailing newline into a reused buffer, and written with one `write_all` call.
+//! The [`new_buffered_writer`] function writes the records through a persistent `BufWriter` instead,
+//! so many records are written per system call, such as to stdout, which is flushed on every newline.
+//! The buffered records are lost at exit unless the logger is flushed or shut down, see [`new_buffered_writer`].
 //!
 //! Example: <https://github.com/iorust/structured-logger/blob/main/examples/simple.rs>
 //!
+//! Log to a file:
+//! ```rust
+//! use structured_logger::{json::FileOptions, Builder};
+//!
+//! fn main() {
+//!     let path = std::env::temp_dir().join("logs").join("app.log");
+//!     Builder::with_level("info")
+//!         .with_default_writer(FileOptions::new().with_mode(0o640).open(path).unwrap())
+//!         .init();
+//!
+//!     log::info!("hello world");
+//! }
+//! ```
+//!
 
 use parking_lot::Mutex;
-use std::{cell::RefCell, collections::BTreeMap, io, io::Write};
+use serde::Serialize;
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::{Arc, Weak},
+    thread,
+    time::Duration,
+};
+
+use crate::metrics::Health;
+use crate::pool::BufferPool;
+use crate::{fields::FieldMap, log_failure, Fields, Key, Value, Writer};
+
+thread_local! {
+    static ENCODE_BUF: RefCell<Vec<u8>> = RefCell::new(Vec::with_capacity(256));
+}
+
+/// A structured log that can be encoded as a JSON object:
+/// either a `BTreeMap` of fields or the streamed [`Fields`].
+pub(crate) trait Encode {
+    fn encode_json(&self, buf: &mut Vec<u8>) -> Result<(), io::Error>;
+}
+
+impl Encode for BTreeMap<Key<'_>, Value<'_>> {
+    fn encode_json(&self, buf: &mut Vec<u8>) -> Result<(), io::Error> {
+        to_writer(buf, &FieldMap(self))
+    }
+}
+
+impl Encode for Fields<'_> {
+    fn encode_json(&self, buf: &mut Vec<u8>) -> Result<(), io::Error> {
+        let (prefix, rest) = match self.split_static_prefix() {
+            Some(split) => split,
+            None => return to_writer(buf, self),
+        };
+
+        // splice the pre-serialized static fields in front of the other fields.
+        buf.push(b'{');
+        buf.extend_from_slice(prefix);
+        let start = buf.len();
+        to_writer(buf, &rest)?;
+        if buf.len() == start + 2 {
+            // no other field, replace the trailing comma of the prefix.
+            buf.truncate(start - 1);
+            buf.push(b'}');
+        } else {
+            buf.remove(start);
+        }
+        Ok(())
+    }
+}
+
+// Serializes a value as JSON into a buffer, with `simd-json` if the feature is enabled, for the same output.
+#[cfg(not(feature = "simd-json"))]
+fn to_writer<T: Serialize + ?Sized>(buf: &mut Vec<u8>, value: &T) -> Result<(), io::Error> {
+    serde_json::to_writer(buf, value).map_err(io::Error::from)
+}
+
+// Serializes a value as JSON into a buffer, with `simd-json` if the feature is enabled, for the same output.
+#[cfg(feature = "simd-json")]
+fn to_writer<T: Serialize + ?Sized>(buf: &mut Vec<u8>, value: &T) -> Result<(), io::Error> {
+    crate::simd::to_writer(buf, value)
+}
+
+fn encode<T: Encode + ?Sized>(buf: &mut Vec<u8>, value: &T) -> Result<(), io::Error> {
+    value.encode_json(buf)?;
+    // must write the LINE FEED character.
+    buf.write_all(b"\n")
+}
+
+/// Encodes a structured log as a JSON line into a buffer taken from the [`BufferPool::global`] pool.
+/// It is used by the writers that must own the bytes, they should return the buffer to the pool once written.
+pub(crate) fn encode_owned<T: Encode + ?Sized>(value: &T) -> Result<Vec<u8>, io::Error> {
+    let pool = BufferPool::global();
+    let mut buf = pool.get();
+    match encode(&mut buf, value) {
+        Ok(()) => Ok(buf),
+        Err(err) => {
+            pool.put(buf);
+            Err(err)
+        }
+    }
+}
+
+/// Encodes a structured log as a JSON line into a reused thread-local buffer, and passes the bytes to `f`.
+pub(crate) fn with_encoded<T: Encode + ?Sized, R>(
+    value: &T,
+    f: impl FnOnce(&[u8]) -> Result<R, io::Error>,
+) -> Result<R, io::Error> {
+    ENCODE_BUF.with(|cell| match cell.try_borrow_mut() {
+        Ok(mut buf) => {
+            buf.clear();
+            let res = encode(&mut buf, value).and_then(|_| f(&buf));
+            if buf.capacity() > BufferPool::global().max_retained_capacity() {
+                *buf = Vec::with_capacity(256);
+            }
+            res
+        }
+        // re-entrant logging while encoding, such as from a `Display` implementation.
+        Err(_) => {
+            let mut buf = Vec::with_capacity(256);
+            encode(&mut buf, value)?;
+            f(&buf)
+        }
+    })
+}
+
+/// Decodes a JSON line encoded by [`with_encoded`], and writes it to a writer as a map.
+/// An invalid line returns an `InvalidData` error.
+pub(crate) fn write_json_line(writer: &dyn Writer, line: &[u8]) -> Result<(), io::Error> {
+    let record: BTreeMap<String, serde_json::Value> = serde_json::from_slice(line)?;
+    let value: BTreeMap<Key, Value> = record
+        .iter()
+        .map(|(k, v)| (Key::from(k.as_str()), json_value(v)))
+        .collect();
+    writer.write_log(&value)
+}
+
+// Returns the value of a JSON value, the strings, numbers and booleans as primitive values.
+fn json_value(v: &serde_json::Value) -> Value<'_> {
+    match v {
+        serde_json::Value::String(s) => Value::from(s.as_str()),
+        serde_json::Value::Bool(b) => Value::from(*b),
+        serde_json::Value::Number(n) => match (n.as_u64(), n.as_i64(), n.as_f64()) {
+            (Some(n), _, _) => Value::from(n),
+            (_, Some(n), _) => Value::from(n),
+            (_, _, Some(n)) => Value::from(n),
+            _ => Value::from_serde(v),
+        },
+        v => Value::from_serde(v),
+    }
+}
 
-use crate::{log_failure, Key, Value, Writer};
 /// A Writer implementation that writes logs in JSON format.
-pub struct JSONWriter<W: Write + Sync + Send + 'static>(Mutex<RefCell<Box<W>>>);
+pub struct JSONWriter<W: Write + Sync + Send + 'static>(Mutex<RefCell<Box<W>>>, Health);
 
 impl<W: Write + Sync + Send + 'static> JSONWriter<W> {
     /// Creates a new JSONWriter instance.
     pub fn new(w: W) -> Self {
-        Self(Mutex::new(RefCell::new(Box::new(w))))
+        Self(Mutex::new(RefCell::new(Box::new(w))), Health::default())
+    }
+
+    fn write_line(&self, buf: &[u8]) -> Result<(), io::Error> {
+        let w = self.0.lock();
+        if let Ok(mut w) = w.try_borrow_mut() {
+            self.1.track(w.as_mut().write_all(buf))?;
+        } else {
+            // should never happen, but if it does, we log it.
+            log_failure("JSONWriter failed to write log: writer already borrowed");
+        }
+        Ok(())
     }
 }
 
 /// Implements Writer trait for JSONWriter.
 impl<W: Write + Sync + Send + 'static> Writer for JSONWriter<W> {
     fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
-        let mut buf = Vec::with_capacity(256);
-        serde_json::to_writer(&mut buf, value).map_err(io::Error::from)?;
-        // must write the LINE FEED character.
-        buf.write_all(b"\n").map_err(io::Error::from)?;
+        with_encoded(value, |buf| self.write_line(buf))
+    }
 
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        with_encoded(fields, |buf| self.write_line(buf))
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
         let w = self.0.lock();
         if let Ok(mut w) = w.try_borrow_mut() {
-            w.as_mut().write_all(&buf).map_err(io::Error::from)?;
+            self.1.track(w.as_mut().flush())?;
         } else {
             // should never happen, but if it does, we log it.
-            log_failure("JSONWriter failed to write log: writer already borrowed");
+            log_failure("JSONWriter failed to flush: writer already borrowed");
         }
         Ok(())
     }
+
+    fn healthy(&self) -> bool {
+        self.1.healthy()
+    }
 }
 
 /// Creates a new `Box<dyn Writer>` instance with the JSONWriter for a given std::io::Write instance.
 pub fn new_writer<W: Write + Sync + Send + 'static>(w: W) -> Box<dyn Writer> {
     Box::new(JSONWriter::new(w))
 }
+
+/// Creates a new `Box<dyn Writer>` instance with the JSONWriter for the file at a given path,
+/// with the default [`FileOptions`]: the parent directories are created,
+/// and the logs are appended to the file.
+pub fn new_file_writer<P: AsRef<Path>>(path: P) -> Result<Box<dyn Writer>, io::Error> {
+    FileOptions::new().open(path)
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the JSONWriter that writes to `w` through a persistent `BufWriter`
+/// of a given capacity, or [`DEFAULT_BUFFER_CAPACITY`] if it is 0.
+/// The buffered logs are written when the buffer is full, or on [`Writer::flush`], and from a background thread
+/// every `flush_interval` if it is set, so the logs are written with a bounded delay.
+/// The thread stops when the writer is dropped, the buffer is also flushed on [`shutdown`](crate::shutdown).
+///
+/// The global logger is never dropped: without a flush interval, the logs still in the buffer when the process exits
+/// are lost, unless `log::logger().flush()` or [`shutdown`](crate::shutdown) is called before, such as at the end of `main`.
+/// The panic hook of the `log-panic` feature flushes the logger after logging a panic.
+///
+/// It returns an error if the background thread can't be started.
+///
+/// Example: `json::new_buffered_writer(std::io::stdout(), 0, Some(Duration::from_millis(200)))`.
+pub fn new_buffered_writer<W: Write + Sync + Send + 'static>(
+    w: W,
+    capacity: usize,
+    flush_interval: Option<Duration>,
+) -> Result<Box<dyn Writer>, io::Error> {
+    let capacity = match capacity {
+        0 => DEFAULT_BUFFER_CAPACITY,
+        capacity => capacity,
+    };
+    let w = new_writer(BufWriter::with_capacity(capacity, w));
+    match flush_interval {
+        Some(interval) => {
+            let w = Arc::new(w);
+            let weak = Arc::downgrade(&w);
+            thread::Builder::new()
+                .name("structured-logger-flush".to_string())
+                .spawn(move || flush_periodically(weak, interval))?;
+            Ok(Box::new(IntervalFlushWriter(w)))
+        }
+        None => Ok(w),
+    }
+}
+
+/// The default capacity of the write buffer of a file writer with a flush interval, see [`FileOptions::with_flush_interval`],
+/// and of [`new_buffered_writer`].
+pub const DEFAULT_BUFFER_CAPACITY: usize = 64 * 1024;
+
+/// The options to open a log file, a builder for the file writers.
+#[derive(Debug, Clone)]
+pub struct FileOptions {
+    create_dirs: bool,
+    append: bool,
+    mode: Option<u32>,
+    buffer_capacity: usize,
+    flush_interval: Option<Duration>,
+}
+
+impl Default for FileOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FileOptions {
+    /// Returns the default options: the parent directories are created,
+    /// the logs are appended to the file, and the writes are not buffered.
+    pub fn new() -> Self {
+        FileOptions {
+            create_dirs: true,
+            append: true,
+            mode: None,
+            buffer_capacity: 0,
+            flush_interval: None,
+        }
+    }
+
+    /// Sets whether the missing parent directories of the file are created, the default is true.
+    pub fn with_create_dirs(self, create_dirs: bool) -> Self {
+        FileOptions {
+            create_dirs,
+            ..self
+        }
+    }
+
+    /// Sets whether the logs are appended to an existing file, the default is true.
+    /// The existing file is truncated if false.
+    pub fn with_append(self, append: bool) -> Self {
+        FileOptions { append, ..self }
+    }
+
+    /// Sets the Unix permissions of a created file, such as `0o640`.
+    /// The default is `0o666`, minus the umask of the process. It is ignored on other platforms.
+    pub fn with_mode(self, mode: u32) -> Self {
+        FileOptions {
+            mode: Some(mode),
+            ..self
+        }
+    }
+
+    /// Sets the capacity of the write buffer, the default is 0: every log is written to the file.
+    /// The buffered logs are written when the buffer is full, or on [`Writer::flush`],
+    /// so call `log::logger().flush()` or [`shutdown`](crate::shutdown) before exiting.
+    pub fn with_buffer_capacity(self, buffer_capacity: usize) -> Self {
+        FileOptions {
+            buffer_capacity,
+            ..self
+        }
+    }
+
+    /// Sets the interval to flush the write buffer from a background thread, such as every 200 ms,
+    /// so the buffered logs are written with a bounded delay even if the buffer is not full.
+    /// If the buffer capacity is not set, it is set to [`DEFAULT_BUFFER_CAPACITY`].
+    /// The thread stops when the writer is dropped, the buffer is also flushed on [`shutdown`](crate::shutdown).
+    pub fn with_flush_interval(self, interval: Duration) -> Self {
+        FileOptions {
+            flush_interval: Some(interval),
+            ..self
+        }
+    }
+
+    /// Opens the file at a given path, and creates a new `Box<dyn Writer>` instance with the JSONWriter for it.
+    pub fn open<P: AsRef<Path>>(&self, path: P) -> Result<Box<dyn Writer>, io::Error> {
+        let file = self.open_file(path)?;
+        let capacity = match (self.buffer_capacity, self.flush_interval) {
+            (0, Some(_)) => DEFAULT_BUFFER_CAPACITY,
+            (capacity, _) => capacity,
+        };
+        if capacity == 0 {
+            return Ok(new_writer(file));
+        }
+
+        new_buffered_writer(file, capacity, self.flush_interval)
+    }
+
+    /// Opens the file at a given path with the options, the buffer capacity is ignored.
+    pub fn open_file<P: AsRef<Path>>(&self, path: P) -> Result<File, io::Error> {
+        let path = path.as_ref();
+        if self.create_dirs {
+            if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                fs::create_dir_all(dir)?;
+            }
+        }
+
+        let mut opts = OpenOptions::new();
+        opts.create(true);
+        if self.append {
+            opts.append(true);
+        } else {
+            opts.write(true).truncate(true);
+        }
+        #[cfg(unix)]
+        if let Some(mode) = self.mode {
+            use std::os::unix::fs::OpenOptionsExt;
+            opts.mode(mode);
+        }
+        #[cfg(not(unix))]
+        let _ = self.mode;
+
+        opts.open(path)
+    }
+}
+
+// A buffered file writer flushed periodically by a background thread.
+struct IntervalFlushWriter(Arc<Box<dyn Writer>>);
+
+impl Writer for IntervalFlushWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        self.0.write_log(value)
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        self.0.write_fields(fields)
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        self.0.flush()
+    }
+
+    fn healthy(&self) -> bool {
+        self.0.healthy()
+    }
+}
+
+fn flush_periodically(w: Weak<Box<dyn Writer>>, interval: Duration) {
+    loop {
+        thread::sleep(interval);
+        match w.upgrade() {
+            Some(w) => {
+                if let Err(err) = w.flush() {
+                    log_failure(format!("JSONWriter failed to flush: {}", err).as_str());
+                }
+            }
+            None => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_options_works() {
+        let dir = std::env::temp_dir().join(format!("structured-logger-{}", std::process::id()));
+        let path = dir.join("nested").join("app.log");
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("message"), Value::from("hello"));
+
+        let w = new_file_writer(&path).unwrap();
+        w.write_log(&value).unwrap();
+        let w = new_file_writer(&path).unwrap();
+        w.write_log(&value).unwrap();
+        assert_eq!(2, fs::read_to_string(&path).unwrap().lines().count());
+
+        let w = FileOptions::new()
+            .with_append(false)
+            .with_buffer_capacity(1024)
+            .open(&path)
+            .unwrap();
+        w.write_log(&value).unwrap();
+        assert_eq!("", fs::read_to_string(&path).unwrap());
+        w.flush().unwrap();
+        assert_eq!(
+            "{\"message\":\"hello\"}\n",
+            fs::read_to_string(&path).unwrap()
+        );
+
+        let path = dir.join("interval.log");
+        let w = FileOptions::new()
+            .with_flush_interval(Duration::from_millis(5))
+            .open(&path)
+            .unwrap();
+        w.write_log(&value).unwrap();
+        assert_eq!("", fs::read_to_string(&path).unwrap());
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(1, fs::read_to_string(&path).unwrap().lines().count());
+        w.write_log(&value).unwrap();
+        w.shutdown().unwrap();
+        assert_eq!(2, fs::read_to_string(&path).unwrap().lines().count());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let path = dir.join("private.log");
+            FileOptions::new().with_mode(0o600).open(&path).unwrap();
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(0o600, mode & 0o777);
+        }
+
+        assert!(FileOptions::new()
+            .with_create_dirs(false)
+            .open(dir.join("missing").join("app.log"))
+            .is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    // counts the write calls to the sink.
+    #[derive(Clone, Default)]
+    struct Sink(Arc<Mutex<(usize, Vec<u8>)>>);
+
+    impl Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let mut sink = self.0.lock();
+            sink.0 += 1;
+            sink.1.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn buffered_writer_works() {
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("message"), Value::from("hello"));
+
+        let sink = Sink::default();
+        let w = new_writer(sink.clone());
+        for _ in 0..100 {
+            w.write_log(&value).unwrap();
+        }
+        assert_eq!(100, sink.0.lock().0);
+
+        let sink = Sink::default();
+        let w = new_buffered_writer(sink.clone(), 1024, None).unwrap();
+        for _ in 0..100 {
+            w.write_log(&value).unwrap();
+        }
+        w.flush().unwrap();
+        let sink = sink.0.lock();
+        assert_eq!(2, sink.0);
+        assert_eq!("{\"message\":\"hello\"}\n".repeat(100).as_bytes(), sink.1);
+    }
+
+    #[test]
+    fn buffered_writer_shutdown_works() {
+        let sink = Sink::default();
+        let logger = crate::Builder::with_level("info")
+            .with_default_writer(new_buffered_writer(sink.clone(), 0, None).unwrap())
+            .build();
+        for _ in 0..10 {
+            logger
+                .log_record(
+                    &log::Record::builder()
+                        .args(format_args!("hello"))
+                        .level(log::Level::Info)
+                        .target("api")
+                        .build(),
+                )
+                .unwrap();
+        }
+        // the records are still in the buffer.
+        assert_eq!(0, sink.0.lock().0);
+
+        logger.shutdown();
+        let sink = sink.0.lock();
+        assert_eq!(1, sink.0);
+        assert_eq!(10, sink.1.iter().filter(|b| **b == b'\n').count());
+    }
+
+    // the `simd-json` backend writes the same bytes as `serde_json`.
+    #[cfg(feature = "simd-json")]
+    #[test]
+    fn to_writer_works() {
+        let nested =
+            serde_json::json!({"a": [1, -2, 0.5, null, true], "b": {"c": "d\u{7f}", "1e5": "1e5"}});
+        let mut value = BTreeMap::new();
+        value.insert(
+            Key::from("control"),
+            Value::from("\u{0}\u{1}\u{8}\u{c}\u{1f}\t\r\n"),
+        );
+        value.insert(
+            Key::from("escaped"),
+            Value::from("\"quoted\" \\ / </script>"),
+        );
+        value.insert(Key::from("unicode"), Value::from("é 日本 🦀 \u{2028}"));
+        value.insert(Key::from("empty"), Value::from(""));
+        value.insert(
+            Key::from("floats"),
+            Value::from_serde(&[0.1, 1e300, 1e21, -0.0, 1.0, 1e-7, f64::NAN, f64::INFINITY]),
+        );
+        value.insert(Key::from("nan"), Value::from(f64::NAN));
+        value.insert(Key::from("u64"), Value::from(u64::MAX));
+        value.insert(Key::from("i64"), Value::from(i64::MIN));
+        value.insert(Key::from("u128"), Value::from(u128::MAX));
+        value.insert(Key::from("bool"), Value::from(false));
+        value.insert(Key::from("nested"), Value::from_serde(&nested));
+
+        let mut buf = Vec::new();
+        value.encode_json(&mut buf).unwrap();
+        assert_eq!(
+            serde_json::to_string(&FieldMap(&value)).unwrap(),
+            String::from_utf8(buf).unwrap()
+        );
+    }
+}
@@ -0,0 +1,130 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! An ad-hoc structured map built by the [`kv!`](crate::kv) macro.
+
+use log::kv::{ToValue, Value};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use std::fmt;
+
+/// A map of serialized values that keeps the insertion order, built by the [`kv!`](crate::kv) macro.
+/// It can be logged as a key-value, with or without the `:serde` capture.
+#[derive(Default, Clone, PartialEq)]
+pub struct KvMap(Vec<(String, serde_json::Value)>);
+
+impl KvMap {
+    /// Creates a new empty KvMap instance.
+    pub fn new() -> Self {
+        KvMap(Vec::new())
+    }
+
+    /// Inserts a given key and value, replacing the value of an existing key.
+    /// The value is serialized immediately, it is `null` if it fails to serialize.
+    pub fn insert<K: Into<String>, T: Serialize + ?Sized>(&mut self, key: K, value: &T) {
+        let key = key.into();
+        let value = serde_json::to_value(value).unwrap_or(serde_json::Value::Null);
+        match self.0.iter_mut().find(|(k, _)| *k == key) {
+            Some(entry) => entry.1 = value,
+            None => self.0.push((key, value)),
+        }
+    }
+
+    /// Returns the value of a given key.
+    pub fn get(&self, key: &str) -> Option<&serde_json::Value> {
+        self.0.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    /// Returns the number of entries.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns true if there is no entry.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Debug for KvMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.0.iter().map(|(k, v)| (k, v)))
+            .finish()
+    }
+}
+
+impl Serialize for KvMap {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, value) in self.0.iter() {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+impl ToValue for KvMap {
+    fn to_value(&self) -> Value<'_> {
+        Value::from_serde(self)
+    }
+}
+
+/// Builds a [`KvMap`] from `key => value` pairs, where the values implement `serde::Serialize`,
+/// to log a group of fields without defining a `#[derive(Serialize)]` struct.
+///
+/// Example:
+/// ```rust
+/// use structured_logger::kv;
+///
+/// let items = vec!["book", "pen"];
+/// let ctx = kv! {"uid" => "user123", "items" => items.len()};
+/// log::info!(ctx:serde = ctx; "checkout");
+/// // {"ctx":{"uid":"user123","items":2},"level":"INFO","message":"checkout",...}
+/// assert_eq!(r#"{"uid":"user123","items":2}"#, serde_json::to_string(&ctx).unwrap());
+/// ```
+#[macro_export]
+macro_rules! kv {
+    ($($key:expr => $value:expr),* $(,)?) => {{
+        #[allow(unused_mut)]
+        let mut map = $crate::KvMap::new();
+        $(map.insert($key, &$value);)*
+        map
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(serde::Serialize)]
+    struct Book {
+        id: u32,
+    }
+
+    #[test]
+    fn kv_works() {
+        let empty = kv! {};
+        assert!(empty.is_empty());
+
+        let uid = String::from("user123");
+        let map = kv! {
+            "uid" => uid,
+            "book" => Book { id: 1 },
+            "tags" => ["a", "b"],
+            "uid" => "user456",
+        };
+        assert_eq!(3, map.len());
+        assert_eq!(
+            Some(&serde_json::Value::from(1)),
+            map.get("book").unwrap().get("id")
+        );
+        assert_eq!(
+            r#"{"uid":"user456","book":{"id":1},"tags":["a","b"]}"#,
+            serde_json::to_string(&map).unwrap()
+        );
+        assert_eq!(
+            serde_json::to_string(&map).unwrap(),
+            serde_json::to_string(&map.to_value()).unwrap()
+        );
+    }
+}
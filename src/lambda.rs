@@ -0,0 +1,312 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # AWS Lambda Writer Implementation
+//!
+//! A [`Writer`] implementation that logs structured values in JSON format for AWS Lambda,
+//! whose stdout is collected by CloudWatch Logs:
+//! - the `requestId` of the current invocation, set by [`set_request_id`], is added to every record.
+//! - every record is written on a single line, and the records larger than [`LambdaOptions::max_record_size`]
+//!   are truncated and marked with `"truncated":true`, instead of being split by CloudWatch.
+//! - the numeric fields declared in [`LambdaOptions::metrics`] are emitted as CloudWatch metrics,
+//!   with the [Embedded Metric Format](https://docs.aws.amazon.com/AmazonCloudWatch/latest/monitoring/CloudWatch_Embedded_Metric_Format_Specification.html)
+//!   metadata in the `_aws` field of the records that have them.
+//!
+//! To create a `Box<dyn Writer>` use the [`new_writer`] function.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{lambda, Builder};
+//!
+//! fn main() {
+//!     let opts = lambda::LambdaOptions {
+//!         namespace: "orders".to_string(),
+//!         dimensions: vec!["function".to_string()],
+//!         metrics: vec![("latency_ms".to_string(), "Milliseconds".to_string())],
+//!         ..Default::default()
+//!     };
+//!     Builder::with_level("info")
+//!         .with_default_writer(lambda::new_writer(std::io::stdout(), opts))
+//!         .init();
+//!
+//!     // at the start of each invocation, with the request id of the Lambda context.
+//!     lambda::set_request_id("8f507cfc-example");
+//!     log::info!(function = "checkout", latency_ms = 42; "order placed");
+//! }
+//! ```
+//!
+
+use log::kv::{Key, Value};
+use parking_lot::{const_rwlock, Mutex, RwLock};
+use serde_json::{json, Map};
+use std::{collections::BTreeMap, io, io::Write};
+
+use crate::fields::FieldMap;
+use crate::{unix_ms, Fields, Writer};
+
+/// The maximum size of a CloudWatch Logs event, 256 KiB minus the 26 bytes of the event overhead.
+pub const MAX_EVENT_SIZE: usize = 256 * 1024 - 26;
+
+// The fields kept in a record that is still too large once its message is truncated.
+const ESSENTIAL_KEYS: [&str; 7] = [
+    "level",
+    "severity",
+    "message",
+    "target",
+    "timestamp",
+    "time",
+    "requestId",
+];
+
+static REQUEST_ID: RwLock<Option<Box<str>>> = const_rwlock(None);
+
+/// Sets the request id of the current invocation, added to the records as `requestId`.
+/// Call it at the start of each invocation with the request id of the Lambda context,
+/// a Lambda execution environment handles one invocation at a time.
+pub fn set_request_id(id: &str) {
+    *REQUEST_ID.write() = Some(id.into());
+}
+
+/// Clears the request id set by [`set_request_id`], such as at the end of an invocation.
+pub fn clear_request_id() {
+    *REQUEST_ID.write() = None;
+}
+
+/// The options of a [`LambdaWriter`].
+#[derive(Debug, Clone)]
+pub struct LambdaOptions {
+    /// The maximum size of a record in bytes, the default is [`MAX_EVENT_SIZE`].
+    pub max_record_size: usize,
+    /// The CloudWatch namespace of the metrics, the default is `"structured-logger"`.
+    pub namespace: String,
+    /// The keys of the string fields used as the dimensions of the metrics, if the record has them.
+    pub dimensions: Vec<String>,
+    /// The keys and the CloudWatch units, such as `"Milliseconds"` or `"Count"`, of the numeric fields
+    /// emitted as metrics. The default is none, without Embedded Metric Format metadata.
+    pub metrics: Vec<(String, String)>,
+}
+
+impl Default for LambdaOptions {
+    fn default() -> Self {
+        LambdaOptions {
+            max_record_size: MAX_EVENT_SIZE,
+            namespace: "structured-logger".to_string(),
+            dimensions: Vec::new(),
+            metrics: Vec::new(),
+        }
+    }
+}
+
+/// A Writer implementation that writes logs in JSON format for AWS Lambda.
+pub struct LambdaWriter<W: Write + Sync + Send + 'static> {
+    w: Mutex<W>,
+    opts: LambdaOptions,
+}
+
+impl<W: Write + Sync + Send + 'static> LambdaWriter<W> {
+    /// Creates a new LambdaWriter instance.
+    pub fn new(w: W, opts: LambdaOptions) -> Self {
+        LambdaWriter {
+            w: Mutex::new(w),
+            opts,
+        }
+    }
+
+    fn write_record(&self, mut record: Map<String, serde_json::Value>) -> Result<(), io::Error> {
+        if let Some(ref id) = *REQUEST_ID.read() {
+            record.insert("requestId".to_string(), id.as_ref().into());
+        }
+        self.add_metrics(&mut record);
+
+        let mut buf = serde_json::to_vec(&record)?;
+        if buf.len() > self.opts.max_record_size {
+            buf = self.truncate(record)?;
+        }
+        buf.push(b'\n');
+        self.w.lock().write_all(&buf)
+    }
+
+    fn add_metrics(&self, record: &mut Map<String, serde_json::Value>) {
+        let metrics: Vec<_> = self
+            .opts
+            .metrics
+            .iter()
+            .filter(|(key, _)| record.get(key).is_some_and(|v| v.is_number()))
+            .map(|(key, unit)| json!({"Name": key, "Unit": unit}))
+            .collect();
+        if metrics.is_empty() {
+            return;
+        }
+
+        let dimensions: Vec<&String> = self
+            .opts
+            .dimensions
+            .iter()
+            .filter(|key| record.get(*key).is_some_and(|v| v.is_string()))
+            .collect();
+        let timestamp = record
+            .get("timestamp")
+            .and_then(|v| v.as_u64())
+            .unwrap_or_else(unix_ms);
+        let aws = json!({
+            "Timestamp": timestamp,
+            "CloudWatchMetrics": [{
+                "Namespace": self.opts.namespace,
+                "Dimensions": [dimensions],
+                "Metrics": metrics,
+            }],
+        });
+        record.insert("_aws".to_string(), aws);
+    }
+
+    // Truncates the message of a record too large, then drops its other fields if it is still too large.
+    fn truncate(&self, mut record: Map<String, serde_json::Value>) -> Result<Vec<u8>, io::Error> {
+        let max = self.opts.max_record_size;
+        record.insert("truncated".to_string(), true.into());
+        let over = serde_json::to_vec(&record)?.len().saturating_sub(max);
+        if let Some(serde_json::Value::String(msg)) = record.get_mut("message") {
+            // the message may be longer once escaped, it is truncated by the overflow at most.
+            if msg.len() > over {
+                truncate_str(msg, msg.len() - over);
+            }
+        }
+        let buf = serde_json::to_vec(&record)?;
+        if buf.len() <= max {
+            return Ok(buf);
+        }
+
+        record.retain(|key, _| ESSENTIAL_KEYS.contains(&key.as_str()) || key == "truncated");
+        if let Some(serde_json::Value::String(msg)) = record.get_mut("message") {
+            truncate_str(msg, max / 2);
+        }
+        Ok(serde_json::to_vec(&record)?)
+    }
+}
+
+fn truncate_str(s: &mut String, mut len: usize) {
+    if len >= s.len() {
+        return;
+    }
+    while !s.is_char_boundary(len) {
+        len -= 1;
+    }
+    s.truncate(len);
+}
+
+fn to_object(value: serde_json::Value) -> Map<String, serde_json::Value> {
+    match value {
+        serde_json::Value::Object(map) => map,
+        _ => Map::new(),
+    }
+}
+
+/// Implements Writer trait for LambdaWriter.
+impl<W: Write + Sync + Send + 'static> Writer for LambdaWriter<W> {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        self.write_record(to_object(serde_json::to_value(FieldMap(value))?))
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        self.write_record(to_object(serde_json::to_value(fields)?))
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        self.w.lock().flush()
+    }
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the LambdaWriter for a given std::io::Write instance,
+/// usually `std::io::stdout()`.
+pub fn new_writer<W: Write + Sync + Send + 'static>(w: W, opts: LambdaOptions) -> Box<dyn Writer> {
+    Box::new(LambdaWriter::new(w, opts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct Buf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn lines(buf: &Buf) -> Vec<serde_json::Value> {
+        let out = String::from_utf8(buf.0.lock().clone()).unwrap();
+        out.lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn lambda_writer_works() {
+        let buf = Buf::default();
+        let opts = LambdaOptions {
+            max_record_size: 400,
+            namespace: "orders".to_string(),
+            dimensions: vec!["function".to_string(), "missing".to_string()],
+            metrics: vec![
+                ("latency_ms".to_string(), "Milliseconds".to_string()),
+                ("count".to_string(), "Count".to_string()),
+            ],
+        };
+        let writer = LambdaWriter::new(buf.clone(), opts);
+
+        set_request_id("req-1");
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("message"), Value::from("order placed"));
+        value.insert(Key::from("function"), Value::from("checkout"));
+        value.insert(Key::from("latency_ms"), Value::from(42_u64));
+        value.insert(Key::from("timestamp"), Value::from(1679745592127_u64));
+        writer.write_log(&value).unwrap();
+        clear_request_id();
+
+        let long = "x".repeat(500);
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("message"), Value::from(long.as_str()));
+        value.insert(Key::from("level"), Value::from("INFO"));
+        writer.write_log(&value).unwrap();
+
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("message"), Value::from("short"));
+        value.insert(Key::from("payload"), Value::from(long.as_str()));
+        writer.write_log(&value).unwrap();
+
+        let records = lines(&buf);
+        assert_eq!(3, records.len());
+        assert_eq!("req-1", records[0]["requestId"]);
+        assert_eq!(
+            json!({
+                "Timestamp": 1679745592127_u64,
+                "CloudWatchMetrics": [{
+                    "Namespace": "orders",
+                    "Dimensions": [["function"]],
+                    "Metrics": [{"Name": "latency_ms", "Unit": "Milliseconds"}],
+                }],
+            }),
+            records[0]["_aws"]
+        );
+
+        assert!(records[1].get("requestId").is_none());
+        assert!(records[1].get("_aws").is_none());
+        assert_eq!(true, records[1]["truncated"]);
+        assert_eq!("INFO", records[1]["level"]);
+        assert!(records[1]["message"].as_str().unwrap().len() < 400);
+
+        assert_eq!(true, records[2]["truncated"]);
+        assert_eq!("short", records[2]["message"]);
+        assert!(records[2].get("payload").is_none());
+        for line in String::from_utf8(buf.0.lock().clone()).unwrap().lines() {
+            assert!(line.len() <= 400);
+        }
+    }
+}
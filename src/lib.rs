@@ -12,20 +12,159 @@
 //! This crate provides only a logging implementation. To do actual logging use
 //! the [`log`] crate and it's various macros.
 //!
+//! ## Non-blocking logging
+//! You can use [`non_blocking::non_blocking`] to write logs on a dedicated thread without an async runtime,
+//! or [`non_blocking::stdout`] to write logs to stdout without ever blocking on a full pipe.
+//!
+//! You can use [`sharded::new_writer`] to write the logs of each thread to its own segment, without contention between threads.
+//! You can use [`shared_file::new_writer`] to append the logs of several processes to the same file,
+//! without splitting or interleaving the records.
+//!
+//! You can use [`framing::new_writer`] to write the records encoded in a binary format, such as CBOR or Protobuf,
+//! with a length prefix to a stream.
+//!
+//! You can use [`Builder::with_auto_format`] method to write human-readable colored lines when stderr is a terminal,
+//! and JSON otherwise, see the [`pretty`] module.
+//!
+//! You can use the [`logfmt`] writer to write the records as `key=value` lines, and the [`ecs`] writer to write
+//! the fields of the Elastic Common Schema.
+//!
+//! You can use [`Builder::from_env`] method to let the operators choose the destination of the logs with the `LOG_OUTPUT`
+//! environment variable, such as `stdout` in a container and `file:/var/log/app.log` on a VM,
+//! and the format with the `LOG_FORMAT` environment variable, such as `pretty` on a developer machine.
+//!
+//! The encoded records are buffered in reusable buffers, see [`pool::BufferPool`] to configure the pool.
+//!
+//! ## Streaming serialization
+//! The records are passed to the writers as their [`Fields`], sorted by key in an inline vector,
+//! so the built-in JSON writers don't build an intermediate `BTreeMap` for every record.
+//! You can use [`Builder::with_streaming`] method to serialize the key-values as they are visited, in record order.
+//!
 //! ## Limiting logging targets
 //! You can use [`Builder::with_target_writer`] method to log messages related specific target to a specific writer.
+//! You can use [`Builder::with_target_level`] method to filter the messages of specific targets with a specific level.
+//! You can use [`Logger::routes`] method to add or remove target writers at runtime, after the logger is initialized.
+//! You can use [`Builder::with_field_writer`] method to log messages with a specific key-value, such as `audit = true`, to a specific writer.
+//! You can use the [`route`] writers to write the records to several writers, or only the records of a minimum level,
+//! and [`Builder::with_split_files`] method to write all the records to a file, and the warnings and errors also to an error file.
+//! You can use the [`dedup`] writer to collapse the consecutive identical records into one with a `repeat_count` field.
+//! You can use the [`rate_limit`] writer to limit the records written per second for each target, or each value of a field,
+//! and to write the number of the suppressed records instead.
+//! You can use the [`spill`] writer to spill the records of a network writer to disk while its destination is unavailable,
+//! and to replay them once it recovers, and the [`delivery`] writer to retry the failed records of a network writer
+//! from an in-memory queue, with an exponential backoff, then to route them to a dead-letter writer.
+//! You can use the [`WriterExt`] decorators to change, filter or enrich the records of any writer,
+//! such as `json::new_writer(stdout()).filtered(predicate).with_fields(fields)`, see the [`middleware`] module.
+//! You can use [`Builder::with_sampler`] method to decide whether to write every record with your own [`sampler::Sampler`],
+//! such as to keep all the records of the traces with an error.
+//!
+//! ## Byte-string values
+//! You can use [`Builder::with_bytes_encoding`] method to write the byte-string values, such as protocol payload snippets
+//! logged with the [`bytes::Bytes`] wrapper, as lossy UTF-8, base64 or hex strings, see the [`bytes`] module.
+//!
+//! ## Float values
+//! You can use [`Builder::with_float_format`] method to write the `NaN` and infinite floats as strings or clamped
+//! instead of `null`, and to round the floats to a fixed number of decimals, see the [`float`] module.
+//!
+//! ## Integer values
+//! The `u128` and `i128` values out of the 64-bit range are written as strings. You can use
+//! [`Builder::with_big_integers_as_strings`] method to write the integers above 2^53 as strings too,
+//! such as snowflake IDs read by JavaScript log viewers, see the [`integer`] module.
+//!
+//! ## Per-writer timestamps
+//! You can use the [`timestamp`] writer to write the timestamp of the records with another key and format for a writer,
+//! such as `time` in RFC 3339 on stdout, while the other writers keep the `timestamp` in Unix milliseconds.
+//!
+//! ## Clock
+//! You can use [`Builder::with_clock`] method to timestamp the records with a custom [`Clock`], such as in deterministic tests.
+//!
+//! ## Per-writer message keys
+//! You can use the [`message`] writer to write the message of the records with another key for a writer, such as `msg`
+//! for `pino` on stdout, while the other writers keep the `message` key.
+//!
+//! ## Field rewrite rules
+//! You can use [`Builder::with_remap`] method to rename, move, default or drop the fields of the records
+//! with declarative rules, loaded from a JSON config file or set in code, see the [`remap`] module.
+//!
+//! ## Multi-line values
+//! You can use the [`multiline`] writer to replace the newlines of the messages and values with a visible marker for a writer,
+//! or to split them into arrays of lines, for the downstream parsers that split the records on the newlines.
+//!
+//! ## Key sanitization
+//! You can use the [`sanitize`] writer to normalize the keys of the records for a writer, and the keys of their nested
+//! objects captured with `:serde`, by removing the control characters, enforcing a charset, and converting them
+//! to lowercase or snake_case.
+//!
+//! ## Maximum record size
+//! You can use the [`max_size`] writer to cap the size of the records for a writer, such as for UDP, Kafka or CloudWatch,
+//! by truncating the largest values of the oversized records, or by replacing them with a summary.
+//!
+//! ## Self-statistics
+//! You can use [`Builder::with_stats`] method to log the number of records written, failed and dropped periodically,
+//! see the [`stats`] module.
+//!
+//! ## Cloud platforms
+//! You can use [`Builder::with_gcp_fields`] or [`Builder::with_datadog_fields`] method to write the fields expected by
+//! Google Cloud Logging or Datadog, and the [`lambda`] writer to log for AWS Lambda, with the request ids and the CloudWatch embedded metrics.
+//! You can use the [`gelf`] writer to send the logs to Graylog over UDP.
+//! You can use [`Builder::with_resource`] method to declare the OpenTelemetry resource attributes of the logs once,
+//! such as `service.name` or `deployment.environment`, see the [`resource`] module.
+//! You can use [`Builder::with_severity_number`] method to add the OpenTelemetry `severity_number` of the level to every record.
+//!
+//! ## Testing
+//! You can use the [`test::Capture`] writer to capture the records in memory in the unit tests,
+//! and the [`assert_logged!`] macro to assert on their level, target, message and fields.
+//!
+//! ## Message templates
+//! You can use the [`log_template!`] macro to log the format string and the positional arguments
+//! as the `message_template` and `message_args` fields, to group the records of the same log statement.
+//!
+//! ## WebAssembly
+//! The logger compiles for `wasm32-unknown-unknown`, where the timestamps are taken from the JavaScript clock,
+//! and for WASI. You can use [`console::new_writer`] to write the logs to the browser console, or to stdout on WASI.
+//! The writers that need threads or the file system may not work on these targets.
 //!
 //! ## Crate features
 //!
-//! This crate has three features:
+//! This crate has the following features:
 //! * `log-panic`, enabled by default.
+//! * `json`, enabled by default, enables the JSON writers and the features that serialize values with `serde_json`,
+//!   such as `Builder::with_static_field` and `Builder::with_schema`. Without it, only the core [`Builder`], [`Logger`]
+//!   and [`Writer`] plumbing is built, for a custom writer such as a binary format, and there is no default writer.
+//! * `tokio`, enabled by default, enables the [`async_json`] writer and the other writers that run on a tokio runtime,
+//!   such as [`rotation::new_async_writer`]. It enables the `json` feature.
+//! * `futures`, enables the [`futures_json`] writer for `async-std`, `smol`, or any other runtime, without tokio.
+//! * `crossbeam`, enables the [`lock_free`] writer for many threads logging concurrently.
+//! * `signal`, enables the [`signal`] module to shut down the logger on SIGTERM and SIGINT,
+//!   and [`reopen::ReopenHandle::reopen_on_signals`] to reopen a log file on SIGHUP and SIGUSR1.
+//! * `gzip`, enables the compression of the files rotated by the [`rotation`] module,
+//!   and the gzip format of the [`compress`] wrapper that compresses the logs written to a file or a stream.
+//! * `zstd`, enables the zstd format of the [`compress`] wrapper.
+//! * `hash-chain`, enables the [`hash_chain`] writer for tamper-evident audit logs.
+//! * `simd-json`, serializes the records of the JSON writers with `simd-json` instead of `serde_json`, for the same output.
+//! * `sval`, enables the `:sval` capture of the [`log`] macros, the captured values keep their nested structure in JSON.
+//! * `mqtt`, enables the [`mqtt`] writer that publishes the records to an MQTT broker.
+//! * `nats`, enables the [`nats`] writer that publishes the records to a NATS subject, or to JetStream.
+//! * `redis`, enables the [`redis`](crate::redis) writer that appends the records to a Redis stream.
+//! * `postgres`, enables the [`postgres`] writer that inserts the records into a PostgreSQL table.
+//! * `sqlite`, enables the [`sqlite`] writer that appends the records to a table of a local SQLite database.
+//! * `clickhouse`, enables the [`clickhouse`] writer that inserts the records into a ClickHouse table over HTTP.
+//! * `webhook`, enables the [`webhook`] writer that posts the error records to a Slack, Discord or PagerDuty webhook.
+//! * `otlp`, enables the [`otlp`] writer that exports the records to an OpenTelemetry collector over OTLP/HTTP.
+//! * `actix`, enables the [`actix`] middleware that logs one record per HTTP request of `actix-web`.
+//! * `rocket`, enables the [`rocket`] fairing that logs the launch and the HTTP requests of Rocket,
+//!   and the initialization of the logger from the Rocket configuration.
+//! * `tower`, enables the [`tower`] layer that logs one record per HTTP request, such as for `axum`.
+//! * `s3`, enables the [`s3`] hook that uploads the files rotated by the [`rotation`] module to S3 or GCS.
 //!
 //! ### Log-panic feature
 //!
 //! The `log-panic` feature will log all panics using the `error` severity,
 //! rather then using the default panic handler. It will log the panic message
 //! as well as the location and a backtrace, see the log output for an
-//! [`panic_log`] example.
+//! [`panic_log`] example. The panic payload, the thread id and the location are also logged
+//! as separate fields: `panic.payload`, `thread_id`, `location.file`, `location.line` and `location.column`.
+//! The backtrace can be disabled by [`Builder::with_panic_backtrace`], or by setting `RUST_BACKTRACE=0`.
 //!
 //! ## Examples
 //!
@@ -59,7 +198,7 @@
 //!         status = 200_u16,
 //!         start = unix_ms(),
 //!         elapsed = 10_u64,
-//!         kv = log::as_serde!(kv);
+//!         kv:serde = kv;
 //!         "",
 //!     );
 //!     // This log will be written to stdout:
@@ -80,14 +219,19 @@
 #![doc(html_root_url = "https://docs.rs/structured-logger/latest")]
 #![allow(clippy::needless_doctest_main)]
 
-use log::{
-    kv::Error, kv::Key, kv::Value, kv::Visitor, Level, LevelFilter, Metadata, Record,
-    SetLoggerError,
-};
+use arc_swap::ArcSwap;
+use log::{kv::Key, kv::Value, Level, LevelFilter, Metadata, Record, SetLoggerError};
+use parking_lot::{const_rwlock, RwLock};
+use smallvec::SmallVec;
 use std::{
+    cell::RefCell,
     collections::BTreeMap,
-    env, io,
-    time::{SystemTime, UNIX_EPOCH},
+    env, fmt,
+    io::{self, Write},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    sync::{Arc, OnceLock, Weak},
+    thread,
+    time::Duration,
 };
 
 // /// A type alias for BTreeMap<Key<'a>, Value<'a>>.
@@ -95,26 +239,313 @@ use std::{
 // type Log<'a> = BTreeMap<Key<'a>, Value<'a>>;
 
 /// A trait that defines how to write a log. You can implement this trait for your custom formatting and writing destination.
+/// A writer is shared by all logging threads, so it must be `Send` and `Sync`.
 ///
 /// Implementation examples:
 /// * <https://github.com/iorust/structured-logger/blob/main/src/json.rs>
 /// * <https://github.com/iorust/structured-logger/blob/main/src/async_json.rs>
-pub trait Writer {
+pub trait Writer: Send + Sync {
     /// Writes a structured log to the underlying io::Write instance.
     fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error>;
+
+    /// Writes a structured log from its [`Fields`], without an intermediate map.
+    /// It is called by the logger for every record, with the fields sorted by key, see [`Builder::with_sorted_fields`],
+    /// or in record order with [`Builder::with_streaming`].
+    /// The default implementation collects the fields into a map and calls [`Writer::write_log`].
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        self.write_log(&fields.to_map())
+    }
+
+    /// Flushes the buffered logs to the underlying io::Write instance.
+    /// It is called by [`log::Log::flush`], the default implementation does nothing.
+    fn flush(&self) -> Result<(), io::Error> {
+        Ok(())
+    }
+
+    /// Flushes the buffered logs and closes the underlying io::Write instance,
+    /// such as a file or a network connection. The writer may reject the logs written after.
+    /// It is called by [`shutdown`], the default implementation calls [`Writer::flush`].
+    fn shutdown(&self) -> Result<(), io::Error> {
+        self.flush()
+    }
+
+    /// Returns whether the writer can currently write logs to its destination, such as false after a write
+    /// failed because the disk is full, or while a network writer is disconnected. It is called by [`Logger::healthy`]
+    /// for the readiness probes, so it should be cheap. The default implementation returns true.
+    fn healthy(&self) -> bool {
+        true
+    }
 }
 
+impl<W: Writer + ?Sized> Writer for Box<W> {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        (**self).write_log(value)
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        (**self).write_fields(fields)
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        (**self).flush()
+    }
+
+    fn shutdown(&self) -> Result<(), io::Error> {
+        (**self).shutdown()
+    }
+
+    fn healthy(&self) -> bool {
+        (**self).healthy()
+    }
+}
+
+struct FnWriter<F>(F);
+
+impl<F> Writer for FnWriter<F>
+where
+    F: Fn(&BTreeMap<Key, Value>) -> Result<(), io::Error> + Send + Sync,
+{
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        (self.0)(value)
+    }
+}
+
+/// Creates a new `Box<dyn Writer>` instance that calls `f` with each structured log,
+/// to plug in a custom destination (a channel, an FFI callback, a test hook, ...)
+/// without implementing the [`Writer`] trait.
+///
+/// Example:
+/// ```rust
+/// use structured_logger::{fn_writer, Builder};
+///
+/// let (tx, rx) = std::sync::mpsc::channel();
+/// let tx = std::sync::Mutex::new(tx);
+/// Builder::with_level("info")
+///     .with_default_writer(fn_writer(move |value| {
+///         let line = serde_json::to_string(value)?;
+///         tx.lock().unwrap().send(line).map_err(std::io::Error::other)
+///     }))
+///     .init();
+///
+/// log::info!("hello world");
+/// assert!(rx.recv().unwrap().contains("hello world"));
+/// ```
+pub fn fn_writer<F>(f: F) -> Box<dyn Writer>
+where
+    F: Fn(&BTreeMap<Key, Value>) -> Result<(), io::Error> + Send + Sync + 'static,
+{
+    Box::new(FnWriter(f))
+}
+
+// The default writer of a `Builder`: JSON to stderr, or a writer that discards the records without the `json` feature.
+#[cfg(feature = "json")]
+fn default_writer() -> Box<dyn Writer> {
+    json::new_writer(io::stderr())
+}
+
+#[cfg(not(feature = "json"))]
+fn default_writer() -> Box<dyn Writer> {
+    fn_writer(|_| Ok(()))
+}
+
+#[cfg(any(feature = "actix", feature = "rocket", feature = "tower"))]
+mod access;
+#[cfg(feature = "actix")]
+pub mod actix;
+#[cfg(feature = "tokio")]
 pub mod async_json;
+pub mod bytes;
+#[cfg(feature = "clickhouse")]
+pub mod clickhouse;
+pub mod clock;
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+pub mod compress;
+#[cfg(feature = "json")]
+pub mod console;
+#[cfg(feature = "json")]
+pub mod dedup;
+#[cfg(feature = "json")]
+pub mod delivery;
+#[cfg(feature = "json")]
+pub mod durable;
+#[cfg(feature = "json")]
+pub mod ecs;
+mod fields;
+pub mod float;
+#[cfg(feature = "json")]
+pub mod framing;
+#[cfg(feature = "futures")]
+pub mod futures_json;
+#[cfg(feature = "json")]
+pub mod gelf;
+#[cfg(feature = "hash-chain")]
+pub mod hash_chain;
+pub mod integer;
+#[cfg(feature = "json")]
 pub mod json;
-use json::new_writer;
+#[cfg(feature = "json")]
+mod kv_map;
+#[cfg(feature = "json")]
+pub mod lambda;
+#[cfg(feature = "crossbeam")]
+pub mod lock_free;
+#[cfg(feature = "json")]
+pub mod logfmt;
+#[cfg(feature = "json")]
+pub mod max_size;
+pub mod message;
+#[cfg(feature = "json")]
+pub mod metrics;
+pub mod middleware;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+pub mod multiline;
+#[cfg(feature = "nats")]
+pub mod nats;
+#[cfg(feature = "json")]
+pub mod non_blocking;
+#[cfg(feature = "otlp")]
+pub mod otlp;
+pub mod pool;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+#[cfg(feature = "json")]
+pub mod pretty;
+// the batching of the queue is only used by the tokio writers.
+#[cfg(any(feature = "tokio", feature = "futures"))]
+#[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+mod queue;
+pub mod rate_limit;
+#[cfg(feature = "redis")]
+pub mod redis;
+#[cfg(feature = "json")]
+pub mod remap;
+#[cfg(feature = "json")]
+pub mod reopen;
+#[cfg(feature = "json")]
+pub mod resource;
+pub mod retry;
+#[cfg(feature = "rocket")]
+pub mod rocket;
+#[cfg(feature = "json")]
+pub mod rotation;
+pub mod route;
+#[cfg(feature = "s3")]
+pub mod s3;
+pub mod sampler;
+#[cfg(feature = "json")]
+pub mod sanitize;
+#[cfg(feature = "json")]
+pub mod schema;
+#[cfg(feature = "json")]
+pub mod sharded;
+#[cfg(feature = "json")]
+pub mod shared_file;
+#[cfg(feature = "signal")]
+pub mod signal;
+#[cfg(feature = "simd-json")]
+mod simd;
+#[cfg(feature = "json")]
+pub mod spill;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod stats;
+pub mod swap;
+mod template;
+#[cfg(feature = "json")]
+pub mod test;
+pub mod timer;
+pub mod timestamp;
+#[cfg(feature = "tower")]
+pub mod tower;
+#[cfg(feature = "webhook")]
+pub mod webhook;
+pub use clock::Clock;
+use fields::StaticFields;
+pub use fields::{Fields, SortedFields};
+#[cfg(feature = "json")]
+pub use kv_map::KvMap;
+pub use middleware::WriterExt;
+#[cfg(feature = "json")]
+use remap::Remap;
+#[cfg(feature = "json")]
+pub use resource::Resource;
+#[cfg(feature = "json")]
+use schema::Schema;
+use stats::Stats;
+pub use timer::{start_timer, Elapsed, Stopwatch};
 
 /// A struct to initialize the logger for [`log`] crate.
 pub struct Builder {
     filter: LevelFilter,
     default_writer: Box<dyn Writer>,
     writers: Vec<(Target, Box<dyn Writer>)>,
+    #[cfg(feature = "json")]
+    field_writers: Vec<FieldRoute>,
+    #[cfg(feature = "json")]
+    schemas: Vec<(Target, Schema)>,
+    quarantine: Option<Box<dyn Writer>>,
+    #[cfg(feature = "json")]
+    remap: Option<Remap>,
+    target_levels: Vec<(Target, LevelFilter)>,
+    statics: StaticFields,
+    #[cfg(feature = "json")]
+    resource: Option<Resource>,
+    fields: FieldsMode,
+    clock: Box<dyn Clock>,
+    monotonic: bool,
+    platform: Platform,
+    severity_number: bool,
+    trace_context: Option<TraceContextFn>,
+    record_filter: Option<RecordFilter>,
+    sampler: Option<Box<dyn sampler::Sampler>>,
+    failure: FailureHandler,
+    bytes_encoding: bytes::BytesEncoding,
+    float_format: float::FloatFormat,
+    big_integers_as_strings: bool,
+    stats_interval: Option<Duration>,
+    #[cfg(feature = "json")]
+    stats_queues: Vec<(String, metrics::QueueMetrics)>,
+    #[cfg(feature = "log-panic")]
+    panic_backtrace: bool,
 }
 
+// A key-value and the writer of the records with it, see `Builder::with_field_writer`.
+#[cfg(feature = "json")]
+type FieldRoute = (Box<str>, serde_json::Value, Box<dyn Writer>);
+
+// Returns whether a key-value is equal to the value of a field writer. The strings, booleans and numbers
+// are compared without allocating, the other values are compared by their JSON value.
+#[cfg(feature = "json")]
+fn field_value_eq(v: &Value, expected: &serde_json::Value) -> bool {
+    match expected {
+        serde_json::Value::String(s) => match v.to_borrowed_str() {
+            Some(v) => v == s,
+            None => serde_json::to_value(v).is_ok_and(|v| &v == expected),
+        },
+        serde_json::Value::Bool(b) => v.to_bool() == Some(*b),
+        serde_json::Value::Number(n) if n.is_u64() => v.to_u64() == n.as_u64(),
+        serde_json::Value::Number(n) if n.is_i64() => v.to_i64() == n.as_i64(),
+        serde_json::Value::Number(n) => v.to_i64().is_none() && v.to_f64() == n.as_f64(),
+        _ => serde_json::to_value(v).is_ok_and(|v| &v == expected),
+    }
+}
+
+/// A function that returns the trace context of the current thread or task, see [`Builder::with_trace_context`].
+pub type TraceContextFn = Box<dyn Fn() -> Option<TraceContext> + Send + Sync>;
+
+/// The trace context of a record, the W3C trace id and span id of the current span.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceContext {
+    /// The 128-bit trace id.
+    pub trace_id: u128,
+    /// The 64-bit span id.
+    pub span_id: u64,
+}
+
+/// A record filter that returns false for the records to drop, see [`Builder::with_filter`].
+pub type RecordFilter = Box<dyn Fn(&Metadata, &BTreeMap<Key, Value>) -> bool + Send + Sync>;
+
 impl Default for Builder {
     fn default() -> Self {
         Self::new()
@@ -125,35 +556,110 @@ impl Builder {
     /// Returns a [`Builder`] with default configuration.
     /// The default configuration is:
     /// - level filter: get from the environment variable by `get_env_level()`.
-    /// - default writer: write to stderr in JSON format.
+    /// - default writer: write to stderr in JSON format,
+    ///   or discard the records without the `json` feature, see [`Builder::with_default_writer`].
     pub fn new() -> Self {
-        Builder {
-            filter: get_env_level(),
-            default_writer: new_writer(io::stderr()),
-            writers: Vec::new(),
-        }
+        Self::with_filter_level(get_env_level())
     }
 
     /// Returns a [`Builder`] with a given level filter.
     /// `level` is a string that can be parsed to `log::LevelFilter`.
     /// Such as "OFF", "ERROR", "WARN", "INFO", "DEBUG", "TRACE", ignore ascii case.
     pub fn with_level(level: &str) -> Self {
+        Self::with_filter_level(level.parse().unwrap_or(LevelFilter::Info))
+    }
+
+    /// Returns a [`Builder`] configured by the environment variables, so the operators can choose
+    /// the destination and the format of the logs per deployment, with no code change:
+    /// - level filter: get from the environment variable by `get_env_level()`.
+    /// - default writer: write to the destination of the `LOG_OUTPUT` environment variable, `stderr` (the default),
+    ///   `stdout`, `file:<path>` to append to a file, whose parent directories are created,
+    ///   or `null` to discard the records, in the format of the `LOG_FORMAT` environment variable,
+    ///   `json` (the default), `logfmt` (see the [`logfmt`] module), `pretty` (see the [`pretty`] module,
+    ///   colored if the destination is a terminal), or `ecs` (JSON with the fields of the [`ecs`] module).
+    ///
+    /// It returns an error if `LOG_OUTPUT` or `LOG_FORMAT` is invalid, or if the file can't be opened.
+    ///
+    /// Example:
+    /// ```rust
+    /// use structured_logger::Builder;
+    ///
+    /// // LOG_OUTPUT=stdout in Kubernetes, LOG_OUTPUT=file:/var/log/app.log LOG_FORMAT=ecs on VMs.
+    /// Builder::from_env().unwrap().init();
+    ///
+    /// log::info!("hello world");
+    /// ```
+    #[cfg(feature = "json")]
+    pub fn from_env() -> Result<Self, io::Error> {
+        let builder = Self::new();
+        match (env::var("LOG_OUTPUT"), env::var("LOG_FORMAT")) {
+            (Err(_), Err(_)) => Ok(builder),
+            (output, format) => {
+                let output = output.unwrap_or_default();
+                let format = format.unwrap_or_default();
+                Ok(builder.with_default_writer(env_writer(&output, &format)?))
+            }
+        }
+    }
+
+    fn with_filter_level(filter: LevelFilter) -> Self {
         Builder {
-            filter: level.parse().unwrap_or(LevelFilter::Info),
-            default_writer: new_writer(io::stderr()),
+            filter,
+            default_writer: default_writer(),
             writers: Vec::new(),
+            #[cfg(feature = "json")]
+            field_writers: Vec::new(),
+            #[cfg(feature = "json")]
+            schemas: Vec::new(),
+            quarantine: None,
+            #[cfg(feature = "json")]
+            remap: None,
+            target_levels: Vec::new(),
+            statics: StaticFields::default(),
+            #[cfg(feature = "json")]
+            resource: None,
+            fields: FieldsMode::Sorted,
+            clock: Box::new(clock::SystemClock),
+            monotonic: false,
+            platform: Platform::Default,
+            severity_number: false,
+            trace_context: None,
+            record_filter: None,
+            sampler: None,
+            failure: FailureHandler::Output(FailureOutput::Stderr),
+            bytes_encoding: bytes::BytesEncoding::Lossy,
+            float_format: float::FloatFormat::default(),
+            big_integers_as_strings: false,
+            stats_interval: None,
+            #[cfg(feature = "json")]
+            stats_queues: Vec::new(),
+            #[cfg(feature = "log-panic")]
+            panic_backtrace: true,
         }
     }
 
     /// Returns a [`Builder`] with a given `writer` as default writer.
     pub fn with_default_writer(self, writer: Box<dyn Writer>) -> Self {
         Builder {
-            filter: self.filter,
             default_writer: writer,
-            writers: self.writers,
+            ..self
         }
     }
 
+    /// Returns a [`Builder`] that writes to stderr in the human-readable colored format of the [`pretty`] module
+    /// when stderr is a terminal, and in JSON when it is piped or redirected. It replaces the default writer.
+    #[cfg(feature = "json")]
+    pub fn with_auto_format(self) -> Self {
+        use std::io::IsTerminal;
+
+        let writer = if io::stderr().is_terminal() {
+            pretty::new_writer(io::stderr(), true)
+        } else {
+            json::new_writer(io::stderr())
+        };
+        self.with_default_writer(writer)
+    }
+
     /// Returns a [`Builder`] with a given `targets` pattern and `writer`.
     /// `targets` is a pattern that be used to test log target, if true, the log will be written to the `writer`.
     /// `writer` is a boxed struct that implements the `Writer` trait.
@@ -164,15 +670,350 @@ impl Builder {
     /// - `"api,db"`: match the target "api" or "db".
     /// - `"api*,db"`: match the target "db", "api", "api::v1", "api::v2", etc.
     /// - `"*"`: match all targets.
-    pub fn with_target_writer(self, targets: &str, writer: Box<dyn Writer>) -> Self {
-        let mut cfg = Builder {
-            filter: self.filter,
-            default_writer: self.default_writer,
-            writers: self.writers,
-        };
+    pub fn with_target_writer(mut self, targets: &str, writer: Box<dyn Writer>) -> Self {
+        self.writers.push((Target::from(targets), writer));
+        self
+    }
+
+    /// Returns a [`Builder`] with a default writer for the common layout of the log files of a service:
+    /// all the records are written to the `combined` file, the warnings and the errors also to the `errors` file,
+    /// and the errors also to stderr, in JSON format. It is built on the [`route::tee`] and [`route::min_level`] writers.
+    /// The files are opened with the default [`json::FileOptions`]: the parent directories are created,
+    /// and the logs are appended to the files.
+    ///
+    /// Example:
+    /// ```rust
+    /// use structured_logger::Builder;
+    ///
+    /// let dir = std::env::temp_dir().join("logs");
+    /// Builder::with_level("info")
+    ///     .with_split_files(dir.join("app.log"), dir.join("error.log"))
+    ///     .unwrap()
+    ///     .init();
+    ///
+    /// log::warn!("written to app.log and error.log");
+    /// ```
+    #[cfg(feature = "json")]
+    pub fn with_split_files<P: AsRef<std::path::Path>, Q: AsRef<std::path::Path>>(
+        self,
+        combined: P,
+        errors: Q,
+    ) -> Result<Self, io::Error> {
+        Ok(self.with_default_writer(route::tee(vec![
+            json::new_file_writer(combined)?,
+            route::min_level(Level::Warn, json::new_file_writer(errors)?),
+            route::min_level(Level::Error, json::new_writer(io::stderr())),
+        ])))
+    }
+
+    /// Returns a [`Builder`] that writes the records with a key-value `key` equal to `value` to the `writer`,
+    /// regardless of their target, such as the records with `audit = true` or `tenant = "acme"`.
+    /// The field writers are tested before the routes added at runtime by [`Logger::routes`] and the target writers,
+    /// in the order they are added. You can call this method multiple times in order to add multiple writers.
+    #[cfg(feature = "json")]
+    pub fn with_field_writer<T: serde::Serialize>(
+        mut self,
+        key: &str,
+        value: T,
+        writer: Box<dyn Writer>,
+    ) -> Self {
+        let value = serde_json::to_value(value).unwrap_or(serde_json::Value::Null);
+        self.field_writers.push((key.into(), value, writer));
+        self
+    }
+
+    /// Returns a [`Builder`] that validates the key-values of the records of the matched targets with a given `schema`.
+    /// A record that violates it is annotated with a `schema_error` field, see the [`schema`] module.
+    /// `targets` is a pattern like the one of [`Builder::with_target_writer`], the first matched schema is used.
+    #[cfg(feature = "json")]
+    pub fn with_schema(mut self, targets: &str, schema: Schema) -> Self {
+        self.schemas.push((Target::from(targets), schema));
+        self
+    }
+
+    /// Returns a [`Builder`] that writes the records that violate their schema to a given `writer`,
+    /// instead of the writer of their target.
+    #[cfg(feature = "json")]
+    pub fn with_schema_quarantine(mut self, writer: Box<dyn Writer>) -> Self {
+        self.quarantine = Some(writer);
+        self
+    }
+
+    /// Returns a [`Builder`] that rewrites the fields of the records with the rules of `remap`,
+    /// before they are written by any writer, see the [`remap`] module.
+    #[cfg(feature = "json")]
+    pub fn with_remap(mut self, remap: Remap) -> Self {
+        self.remap = Some(remap);
+        self
+    }
+
+    /// Returns a [`Builder`] with a given `targets` pattern and `level` filter,
+    /// the logs of the matched targets are filtered by `level` instead of the builder level.
+    /// `targets` is a pattern like the one of [`Builder::with_target_writer`], and `level` is like the one of [`Builder::with_level`].
+    /// An exact target takes precedence over a prefix, a longer prefix over a shorter one,
+    /// and `"*"` replaces the builder level.
+    ///
+    /// Example: `Builder::with_level("info").with_target_level("db*", "warn").with_target_level("api", "debug")`.
+    pub fn with_target_level(mut self, targets: &str, level: &str) -> Self {
+        self.target_levels.push((
+            Target::from(targets),
+            level.parse().unwrap_or(LevelFilter::Info),
+        ));
+        self
+    }
+
+    /// Returns a [`Builder`] with a static field, such as the service name, version, or hostname,
+    /// that is added to every record. The value is serialized once, and the built-in JSON writers
+    /// splice the pre-serialized fields into each record when streaming is enabled by [`Builder::with_streaming`].
+    /// A static field takes precedence over a record key-value with the same key,
+    /// the built-in keys (`target`, `message`, `level`, `module`, `file`, `line` and `timestamp`) are ignored.
+    ///
+    /// Example: `Builder::with_level("info").with_static_field("service", "api").with_static_field("version", 2)`.
+    #[cfg(feature = "json")]
+    pub fn with_static_field<T: serde::Serialize>(mut self, key: &str, value: T) -> Self {
+        if !BUILTIN_KEYS.contains(&key) {
+            let value = serde_json::to_value(value).unwrap_or(serde_json::Value::Null);
+            self.statics.insert(key, value);
+        }
+        self
+    }
+
+    /// Returns a [`Builder`] with the resource attributes of the logs, such as `service.name`,
+    /// that are added to every record as static fields, see [`Builder::with_static_field`],
+    /// and that are the resource of the [`Resource::global`] once the logger is initialized.
+    /// See the [`resource`] module for how the writers emit them.
+    ///
+    /// Example: `Builder::with_level("info").with_resource(Resource::new().with_service_name("checkout"))`.
+    #[cfg(feature = "json")]
+    pub fn with_resource(mut self, resource: Resource) -> Self {
+        for (key, value) in resource.attributes() {
+            self = self.with_static_field(key, value);
+        }
+        Builder {
+            resource: Some(resource),
+            ..self
+        }
+    }
+
+    /// Returns a [`Builder`] that passes the records to [`Writer::write_fields`] in record order,
+    /// so the built-in JSON writers serialize the key-values as they are visited, without sorting them.
+    /// The fields are written in record order instead of sorted by key, see [`Fields`].
+    pub fn with_streaming(self) -> Self {
+        Builder {
+            fields: FieldsMode::Streaming,
+            ..self
+        }
+    }
+
+    /// Returns a [`Builder`] that passes the records to [`Writer::write_fields`] instead of [`Writer::write_log`],
+    /// with the fields sorted by key in an inline vector, see [`SortedFields`].
+    /// The built-in JSON writers write the same output as with a `BTreeMap`, without its node allocations.
+    /// This is the default, the writers that only implement [`Writer::write_log`] still receive a map.
+    pub fn with_sorted_fields(self) -> Self {
+        Builder {
+            fields: FieldsMode::Sorted,
+            ..self
+        }
+    }
+
+    /// Returns a [`Builder`] that timestamps the records with the cached [`coarse_unix_ms`] clock
+    /// instead of calling the system clock for every record.
+    pub fn with_coarse_timestamp(self) -> Self {
+        self.with_clock(coarse_unix_ms)
+    }
 
-        cfg.writers.push((Target::from(targets), writer));
-        cfg
+    /// Returns a [`Builder`] that timestamps the records with a custom [`Clock`] instead of the system clock,
+    /// such as a [`clock::ManualClock`] for deterministic tests, see the [`clock`] module.
+    pub fn with_clock<C: Clock>(self, clock: C) -> Self {
+        Builder {
+            clock: Box::new(clock),
+            ..self
+        }
+    }
+
+    /// Returns a [`Builder`] that keeps the record timestamps monotonically non-decreasing:
+    /// when the wall clock steps backwards, such as on an NTP correction, the records are timestamped
+    /// 1 millisecond after the previous record instead, until the clock catches up.
+    pub fn with_monotonic_timestamp(self) -> Self {
+        Builder {
+            monotonic: true,
+            ..self
+        }
+    }
+
+    /// Returns a [`Builder`] that writes the built-in fields as expected by Google Cloud Logging,
+    /// so the logs written to stdout on Cloud Run or GKE are parsed and classified without an agent config:
+    /// `level` is renamed to `severity`, with the `DEBUG`, `INFO`, `WARNING` and `ERROR` values,
+    /// and `timestamp` is replaced by `time`, an RFC 3339 UTC string with milliseconds.
+    /// The `message` field is already the text payload of Cloud Logging.
+    ///
+    /// Example: `{"message":"hello","severity":"WARNING","target":"api","time":"2023-03-25T11:59:52.127Z"}`.
+    pub fn with_gcp_fields(self) -> Self {
+        Builder {
+            platform: Platform::Gcp,
+            ..self
+        }
+    }
+
+    /// Returns a [`Builder`] that adds the OpenTelemetry `severity_number` of the level to every record,
+    /// from 1 for `TRACE` to 17 for `ERROR`, see [`severity_number`]. Some backends filter the records by it,
+    /// and it can't be derived reliably from the level strings downstream, such as `warning` or `WARN`.
+    ///
+    /// Example: `{"level":"WARN","message":"hello","severity_number":13,...}`.
+    pub fn with_severity_number(self) -> Self {
+        Builder {
+            severity_number: true,
+            ..self
+        }
+    }
+
+    /// Returns a [`Builder`] that writes the fields as expected by the Datadog log pipeline,
+    /// so the logs are ingested without remap processors: `level` is renamed to `status`,
+    /// with the `debug`, `info`, `warning` and `error` values, the trace context set by [`Builder::with_trace_context`]
+    /// is written as `dd.trace_id` and `dd.span_id`, and the unified service tags are added as the static fields
+    /// `service`, `env` and `version`, from the `DD_SERVICE`, `DD_ENV` and `DD_VERSION` environment variables if they are set,
+    /// with the `json` feature.
+    ///
+    /// Example: `{"dd.span_id":"2","dd.trace_id":"1","env":"prod","message":"hello","service":"api","status":"warning",...}`.
+    #[cfg_attr(not(feature = "json"), allow(unused_mut))]
+    pub fn with_datadog_fields(mut self) -> Self {
+        #[cfg(feature = "json")]
+        for (key, var) in [
+            ("service", "DD_SERVICE"),
+            ("env", "DD_ENV"),
+            ("version", "DD_VERSION"),
+        ] {
+            if let Ok(value) = std::env::var(var) {
+                self = self.with_static_field(key, value);
+            }
+        }
+        Builder {
+            platform: Platform::Datadog,
+            ..self
+        }
+    }
+
+    /// Returns a [`Builder`] with a given trace context function, that is called for every record written,
+    /// such as to read the current span of a tracing library. The trace id and the span id of the returned context
+    /// are written as `trace_id` and `span_id` hexadecimal strings, or as `dd.trace_id` and `dd.span_id`
+    /// with [`Builder::with_datadog_fields`].
+    pub fn with_trace_context(self, f: TraceContextFn) -> Self {
+        Builder {
+            trace_context: Some(f),
+            ..self
+        }
+    }
+
+    /// Returns a [`Builder`] with a given record filter, that is called with the metadata and the fields
+    /// of every record enabled by the level filters, before writing it. The record is dropped if it returns false.
+    /// The fields are collected into a map for the filter, as passed to [`Writer::write_log`].
+    ///
+    /// Example:
+    /// ```rust
+    /// use log::kv::Key;
+    /// use structured_logger::Builder;
+    ///
+    /// // suppress the health-check access logs.
+    /// Builder::with_level("info")
+    ///     .with_filter(Box::new(|_, fields| {
+    ///         fields.get(&Key::from("path")).map(|v| v.to_string()) != Some("/healthz".to_string())
+    ///     }))
+    ///     .init();
+    /// ```
+    pub fn with_filter(self, filter: RecordFilter) -> Self {
+        Builder {
+            record_filter: Some(filter),
+            ..self
+        }
+    }
+
+    /// Returns a [`Builder`] with a given sampler, that decides whether to write every record enabled
+    /// by the level filters and the record filter, with its metadata and fields. See the [`sampler`] module.
+    pub fn with_sampler(self, sampler: Box<dyn sampler::Sampler>) -> Self {
+        Builder {
+            sampler: Some(sampler),
+            ..self
+        }
+    }
+
+    /// Returns a [`Builder`] that writes the byte-string values, such as [`bytes::Bytes`], with a given encoding,
+    /// the default is [`BytesEncoding::Lossy`](bytes::BytesEncoding::Lossy). See the [`bytes`] module.
+    /// The encoding is process-wide, it is only installed by [`Builder::init`] and [`Builder::try_init`].
+    pub fn with_bytes_encoding(self, encoding: bytes::BytesEncoding) -> Self {
+        Builder {
+            bytes_encoding: encoding,
+            ..self
+        }
+    }
+
+    /// Returns a [`Builder`] that writes the float values with a given format, such as the non-finite floats
+    /// as strings instead of `null`, or rounded to a fixed number of decimals. See the [`float`] module.
+    /// The format is process-wide, it is only installed by [`Builder::init`] and [`Builder::try_init`].
+    pub fn with_float_format(self, format: float::FloatFormat) -> Self {
+        Builder {
+            float_format: format,
+            ..self
+        }
+    }
+
+    /// Returns a [`Builder`] that writes the integers above [`integer::MAX_SAFE_INTEGER`], such as snowflake IDs,
+    /// as decimal strings, so the JavaScript parsers don't corrupt them. See the [`integer`] module.
+    /// The option is process-wide, it is only installed by [`Builder::init`] and [`Builder::try_init`].
+    pub fn with_big_integers_as_strings(self) -> Self {
+        Builder {
+            big_integers_as_strings: true,
+            ..self
+        }
+    }
+
+    /// Returns a [`Builder`] that logs the statistics of the logger every `interval`,
+    /// such as the number of records written and dropped, to the [`stats::STATS_TARGET`] target.
+    /// See the [`stats`] module.
+    pub fn with_stats(self, interval: Duration) -> Self {
+        Builder {
+            stats_interval: Some(interval),
+            ..self
+        }
+    }
+
+    /// Returns a [`Builder`] that adds the length and the dropped records of a queue to the statistics,
+    /// with the [`QueueMetrics`](metrics::QueueMetrics) of a buffering writer, see [`Builder::with_stats`].
+    /// You can call this method multiple times in order to add multiple queues.
+    #[cfg(feature = "json")]
+    pub fn with_stats_queue(mut self, name: &str, metrics: metrics::QueueMetrics) -> Self {
+        self.stats_queues.push((name.to_string(), metrics));
+        self
+    }
+
+    /// Returns a [`Builder`] with a given failure handler, that is called with the message of every
+    /// internal logging failure reported by [`log_failure`], instead of writing it to stderr.
+    /// It can count, forward, or silence the failures, but it should not log with the [`log`] crate.
+    pub fn with_failure_handler(self, handler: Box<dyn Fn(&str) + Send + Sync>) -> Self {
+        Builder {
+            failure: FailureHandler::Custom(handler),
+            ..self
+        }
+    }
+
+    /// Returns a [`Builder`] that writes the internal logging failures reported by [`log_failure`]
+    /// to a given output stream, the default is stderr.
+    pub fn with_failure_output(self, output: FailureOutput) -> Self {
+        Builder {
+            failure: FailureHandler::Output(output),
+            ..self
+        }
+    }
+
+    /// Returns a [`Builder`] that logs the panics without capturing a backtrace when `enabled` is false,
+    /// only the message, the location and the thread. Capturing a backtrace is expensive,
+    /// and it may leak the symbols of the binary to the logs.
+    /// The backtrace is also skipped when the `RUST_BACKTRACE` environment variable is `0`.
+    #[cfg(feature = "log-panic")]
+    pub fn with_panic_backtrace(self, enabled: bool) -> Self {
+        Builder {
+            panic_backtrace: enabled,
+            ..self
+        }
     }
 
     /// Initialize the logger for [`log`] crate.
@@ -198,31 +1039,323 @@ impl Builder {
     /// [`init`]: fn.init.html
     /// [crate level documentation]: index.html
     pub fn try_init(self) -> Result<(), SetLoggerError> {
-        let logger = Box::new(Logger {
-            filter: self.filter,
+        #[cfg(feature = "log-panic")]
+        let panic_backtrace = self.panic_backtrace;
+        let bytes_encoding = self.bytes_encoding;
+        let float_format = self.float_format;
+        let big_integers_as_strings = self.big_integers_as_strings;
+        #[cfg(feature = "json")]
+        let resource = self.resource.clone();
+        let (logger, failure) = self.into_logger();
+        let max_level = logger.max_level();
+        // the logger lives for the rest of the program, it is kept to shut down its writers.
+        let logger: &'static Logger = Box::leak(Box::new(logger));
+        log::set_logger(logger)?;
+        let _ = LOGGER.set(logger);
+        *FAILURE_HANDLER.write() = failure;
+        bytes::set_encoding(bytes_encoding);
+        float::set_format(float_format);
+        integer::set_big_as_string(big_integers_as_strings);
+        log::set_max_level(max_level);
+        #[cfg(feature = "json")]
+        if let Some(resource) = resource {
+            Resource::set_global(resource);
+        }
+
+        #[cfg(feature = "log-panic")]
+        {
+            PANIC_BACKTRACE.store(panic_backtrace, Ordering::Relaxed);
+            std::panic::set_hook(Box::new(log_panic));
+        }
+        Ok(())
+    }
+
+    /// Builds the [`Logger`] without installing it as the global logger of the [`log`] crate,
+    /// to embed it in another logging facade, or to test it.
+    /// The failure handler, the byte-string, float and integer formats, and the panic hook are only installed
+    /// by [`Builder::init`] and [`Builder::try_init`].
+    pub fn build(self) -> Logger {
+        self.into_logger().0
+    }
+
+    fn into_logger(self) -> (Logger, FailureHandler) {
+        #[cfg(feature = "json")]
+        let stats_queues = self.stats_queues;
+        let stats = self.stats_interval.map(|interval| {
+            Stats::new(
+                interval,
+                #[cfg(feature = "json")]
+                stats_queues,
+            )
+        });
+        let core = Core {
+            filter: TargetFilter::new(self.filter, self.target_levels),
             default_writer: self.default_writer,
             writers: self
                 .writers
                 .into_iter()
                 .map(|(t, w)| (InnerTarget::from(t), w))
                 .collect(),
-        });
-        log::set_boxed_logger(logger)?;
-        log::set_max_level(self.filter);
+            routes: Arc::new(ArcSwap::from_pointee(Vec::new())),
+            #[cfg(feature = "json")]
+            field_writers: self.field_writers.into_boxed_slice(),
+            #[cfg(feature = "json")]
+            schemas: self
+                .schemas
+                .into_iter()
+                .map(|(t, s)| (InnerTarget::from(t), s))
+                .collect(),
+            quarantine: self.quarantine,
+            #[cfg(feature = "json")]
+            remap: self.remap,
+            fields: self.fields,
+            clock: self.clock,
+            last_timestamp: self.monotonic.then(|| AtomicU64::new(0)),
+            platform: self.platform,
+            severity_number: self.severity_number,
+            trace_context: self.trace_context,
+            record_filter: self.record_filter,
+            sampler: self.sampler,
+            stats,
+            shut_down: AtomicBool::new(false),
+        };
+        let logger = Logger {
+            core: Arc::new(core),
+            statics: Arc::new(self.statics),
+        };
+        if let Some(ref stats) = logger.core.stats {
+            let core = Arc::downgrade(&logger.core);
+            let statics = logger.statics.clone();
+            let interval = stats.interval;
+            if let Err(err) = thread::Builder::new()
+                .name("structured-logger-stats".to_string())
+                .spawn(move || log_stats_periodically(core, statics, interval))
+            {
+                log_failure(format!("Logger failed to start the stats thread: {}", err).as_str());
+            }
+        }
+        (logger, self.failure)
+    }
+}
 
-        #[cfg(feature = "log-panic")]
-        std::panic::set_hook(Box::new(log_panic));
-        Ok(())
+/// Flushes and shuts down the writers of the logger initialized by [`Builder::init`], see [`Writer::shutdown`].
+/// The logs written after are dropped. Call it at the end of `main`, or before the process exits,
+/// so the file and network writers can close cleanly.
+pub fn shutdown() {
+    if let Some(logger) = LOGGER.get() {
+        logger.shutdown();
     }
 }
 
+/// Returns whether the writers of the logger initialized by [`Builder::init`] are healthy, see [`Logger::healthy`].
+/// It returns false if the logger is not initialized.
+///
+/// Example:
+/// ```rust
+/// use structured_logger::Builder;
+///
+/// Builder::with_level("info").init();
+/// // in the handler of a readiness probe:
+/// let status = if structured_logger::healthy() { 200 } else { 503 };
+/// assert_eq!(200, status);
+/// ```
+pub fn healthy() -> bool {
+    LOGGER.get().is_some_and(|logger| logger.healthy())
+}
+
+// The system clock isn't available on wasm32-unknown-unknown, the JavaScript clock is used instead.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+#[inline]
+fn since_unix_epoch() -> Duration {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("system time before Unix epoch")
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+#[inline]
+fn since_unix_epoch() -> Duration {
+    Duration::from_micros((js_sys::Date::now() * 1000.0) as u64)
+}
+
 /// Returns the current unix timestamp in milliseconds.
 #[inline]
 pub fn unix_ms() -> u64 {
-    let ts = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("system time before Unix epoch");
-    ts.as_millis() as u64
+    since_unix_epoch().as_millis() as u64
+}
+
+/// Returns the current unix timestamp in microseconds.
+#[inline]
+pub fn unix_us() -> u64 {
+    since_unix_epoch().as_micros() as u64
+}
+
+/// Returns the OpenTelemetry severity number of a level: 1 for `TRACE`, 5 for `DEBUG`, 9 for `INFO`,
+/// 13 for `WARN` and 17 for `ERROR`, the first number of the range of each level.
+pub fn severity_number(level: Level) -> u8 {
+    match level {
+        Level::Trace => 1,
+        Level::Debug => 5,
+        Level::Info => 9,
+        Level::Warn => 13,
+        Level::Error => 17,
+    }
+}
+
+/// Returns the current unix timestamp in nanoseconds.
+#[inline]
+pub fn unix_ns() -> u64 {
+    since_unix_epoch().as_nanos() as u64
+}
+
+/// Returns `ts`, or the `last` timestamp plus 1 if `ts` is before it, and stores the result as the `last` timestamp.
+fn monotonic_timestamp(last: &AtomicU64, ts: u64) -> u64 {
+    let mut prev = last.load(Ordering::Relaxed);
+    loop {
+        let next = if ts < prev { prev + 1 } else { ts };
+        match last.compare_exchange_weak(prev, next, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => return next,
+            Err(actual) => prev = actual,
+        }
+    }
+}
+
+// How the built-in fields are named and formatted, for the log pipeline of a platform.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Platform {
+    Default,
+    Gcp,
+    Datadog,
+}
+
+impl Platform {
+    // The key and the value of the level field.
+    fn level(self, level: Level) -> (&'static str, &'static str) {
+        match (self, level) {
+            (Platform::Default, _) => ("level", level.as_str()),
+            (Platform::Gcp, Level::Error) => ("severity", "ERROR"),
+            (Platform::Gcp, Level::Warn) => ("severity", "WARNING"),
+            (Platform::Gcp, Level::Info) => ("severity", "INFO"),
+            (Platform::Gcp, Level::Debug | Level::Trace) => ("severity", "DEBUG"),
+            (Platform::Datadog, Level::Error) => ("status", "error"),
+            (Platform::Datadog, Level::Warn) => ("status", "warning"),
+            (Platform::Datadog, Level::Info) => ("status", "info"),
+            (Platform::Datadog, Level::Debug | Level::Trace) => ("status", "debug"),
+        }
+    }
+
+    // The keys and the values of the trace id and span id fields.
+    fn trace_ids(self, ctx: TraceContext) -> [(&'static str, String); 2] {
+        match self {
+            // Datadog uses the lower 64 bits of the trace id, in decimal.
+            Platform::Datadog => [
+                ("dd.trace_id", (ctx.trace_id as u64).to_string()),
+                ("dd.span_id", ctx.span_id.to_string()),
+            ],
+            _ => [
+                ("trace_id", format!("{:032x}", ctx.trace_id)),
+                ("span_id", format!("{:016x}", ctx.span_id)),
+            ],
+        }
+    }
+}
+
+// A unix timestamp in milliseconds formatted as an RFC 3339 UTC string, `2023-03-25T11:59:52.127Z`.
+pub(crate) struct Rfc3339([u8; 24]);
+
+impl Rfc3339 {
+    pub(crate) fn from_unix_ms(ms: u64) -> Self {
+        let secs = ms / 1000;
+        let (hour, min, sec) = (secs / 3600 % 24, secs / 60 % 60, secs % 60);
+
+        // the civil date of the days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+        let z = secs / 86400 + 719468;
+        let era = z / 146097;
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + u64::from(month <= 2);
+
+        let mut buf = *b"0000-00-00T00:00:00.000Z";
+        let mut put = |at: usize, width: usize, mut n: u64| {
+            for i in (at..at + width).rev() {
+                buf[i] = b'0' + (n % 10) as u8;
+                n /= 10;
+            }
+        };
+        put(0, 4, year);
+        put(5, 2, month);
+        put(8, 2, day);
+        put(11, 2, hour);
+        put(14, 2, min);
+        put(17, 2, sec);
+        put(20, 3, ms % 1000);
+        Rfc3339(buf)
+    }
+
+    pub(crate) fn as_str(&self) -> &str {
+        // only ASCII digits and separators are written.
+        std::str::from_utf8(&self.0).unwrap_or_default()
+    }
+}
+
+/// Returns the current unix timestamp in milliseconds from a cached clock,
+/// which is updated every millisecond by a background ticker thread, started on the first call.
+/// It is cheaper than [`unix_ms`] as it doesn't call the system clock, but it may lag by a few milliseconds.
+/// The ticker parks itself after 10 milliseconds without a call, so an idle logger doesn't wake up a CPU
+/// every millisecond, and the next call reads the system clock and wakes it up.
+#[inline]
+pub fn coarse_unix_ms() -> u64 {
+    const IDLE_TICKS: u32 = 10;
+    static TICKER: OnceLock<Option<thread::Thread>> = OnceLock::new();
+    static NOW_MS: AtomicU64 = AtomicU64::new(0);
+    static READ: AtomicBool = AtomicBool::new(false);
+    static PARKED: AtomicBool = AtomicBool::new(false);
+
+    let ticker = TICKER.get_or_init(|| {
+        NOW_MS.store(unix_ms(), Ordering::Relaxed);
+        let res = thread::Builder::new()
+            .name("structured-logger-clock".to_string())
+            .spawn(|| {
+                let mut idle = 0;
+                loop {
+                    thread::sleep(Duration::from_millis(1));
+                    NOW_MS.store(unix_ms(), Ordering::Relaxed);
+                    if READ.swap(false, Ordering::Relaxed) {
+                        idle = 0;
+                        continue;
+                    }
+                    idle += 1;
+                    if idle >= IDLE_TICKS {
+                        idle = 0;
+                        PARKED.store(true, Ordering::Release);
+                        while PARKED.load(Ordering::Acquire) {
+                            thread::park();
+                        }
+                    }
+                }
+            });
+        res.ok().map(|handle| handle.thread().clone())
+    });
+    // the threads are not supported on some targets, such as wasm.
+    let ticker = match ticker {
+        Some(ticker) => ticker,
+        None => return unix_ms(),
+    };
+    // only the first call of a tick writes the shared flag.
+    if !READ.load(Ordering::Relaxed) {
+        READ.store(true, Ordering::Relaxed);
+    }
+    if PARKED.load(Ordering::Acquire) {
+        NOW_MS.fetch_max(unix_ms(), Ordering::Relaxed);
+        if PARKED.swap(false, Ordering::AcqRel) {
+            ticker.unpark();
+        }
+    }
+    NOW_MS.load(Ordering::Relaxed)
 }
 
 /// Returns the log level from the environment variables: `LOG`, `LOG_LEVEL`, `RUST_LOG`, `TRACE` or `DEBUG`.
@@ -245,84 +1378,597 @@ pub fn get_env_level() -> LevelFilter {
     }
 }
 
-struct Logger {
-    filter: LevelFilter,
+// The format of the records written to a `LOG_OUTPUT` destination, see `Builder::from_env`.
+#[cfg(feature = "json")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EnvFormat {
+    Json,
+    Logfmt,
+    Pretty,
+    Ecs,
+}
+
+#[cfg(feature = "json")]
+impl EnvFormat {
+    fn from(format: &str) -> Result<Self, io::Error> {
+        match format.trim().to_ascii_lowercase().as_str() {
+            "" | "json" => Ok(EnvFormat::Json),
+            "logfmt" => Ok(EnvFormat::Logfmt),
+            "pretty" => Ok(EnvFormat::Pretty),
+            "ecs" => Ok(EnvFormat::Ecs),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid LOG_FORMAT: {:?}", format),
+            )),
+        }
+    }
+
+    // Returns the writer of the format, the pretty format is colored if `terminal` is true.
+    fn writer<W: Write + Send + Sync + 'static>(self, w: W, terminal: bool) -> Box<dyn Writer> {
+        match self {
+            EnvFormat::Json => json::new_writer(w),
+            EnvFormat::Logfmt => logfmt::new_writer(w),
+            EnvFormat::Pretty => pretty::new_writer(w, terminal),
+            EnvFormat::Ecs => ecs::new_writer(json::new_writer(w)),
+        }
+    }
+}
+
+// Returns the writer of a `LOG_OUTPUT` destination and a `LOG_FORMAT` format, see `Builder::from_env`.
+#[cfg(feature = "json")]
+pub(crate) fn env_writer(output: &str, format: &str) -> Result<Box<dyn Writer>, io::Error> {
+    use std::io::IsTerminal;
+
+    let format = EnvFormat::from(format)?;
+    match output.trim() {
+        "" | "stderr" => Ok(format.writer(io::stderr(), io::stderr().is_terminal())),
+        "stdout" => Ok(format.writer(io::stdout(), io::stdout().is_terminal())),
+        "null" => Ok(format.writer(io::sink(), false)),
+        output => match output.strip_prefix("file:") {
+            Some(path) if !path.is_empty() => {
+                Ok(format.writer(json::FileOptions::new().open_file(path)?, false))
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid LOG_OUTPUT: {:?}", output),
+            )),
+        },
+    }
+}
+
+thread_local! {
+    // the formatted message of the record being logged, reused across records.
+    static MSG_BUF: RefCell<String> = RefCell::new(String::with_capacity(256));
+}
+
+// The keys of the built-in fields of a record.
+#[cfg(feature = "json")]
+const BUILTIN_KEYS: [&str; 7] = [
+    "target",
+    "message",
+    "level",
+    "module",
+    "file",
+    "line",
+    "timestamp",
+];
+
+// How the fields of a record are passed to the writers.
+#[derive(Debug, Clone, Copy)]
+enum FieldsMode {
+    Streaming,
+    Sorted,
+}
+
+/// The logger built by a [`Builder`], that implements [`log::Log`].
+/// It is usually installed as the global logger by [`Builder::init`], or built by [`Builder::build`].
+///
+/// A built logger is independent of the global logger: a library can run its own logger,
+/// such as a dedicated audit pipeline, alongside the global one, and log to it with [`Logger::log_record`].
+/// It is cheap to clone, the clones share the same writers.
+#[derive(Clone)]
+pub struct Logger {
+    core: Arc<Core>,
+    // the static fields of the logger, and the preset fields of a child logger.
+    statics: Arc<StaticFields>,
+}
+
+/// A handle to add or remove the target writers of a [`Logger`] at runtime, see [`Logger::routes`].
+/// It can be cloned and sent to other threads.
+#[derive(Clone)]
+pub struct RouteHandle(Arc<ArcSwap<Vec<Arc<DynamicRoute>>>>);
+
+// A target writer added at runtime by a `RouteHandle`.
+struct DynamicRoute {
+    target: InnerTarget,
+    writer: Box<dyn Writer>,
+}
+
+impl RouteHandle {
+    /// Adds a `writer` for the logs of the given `targets` pattern, like [`Builder::with_target_writer`],
+    /// such as for a plugin loaded after the logger is initialized.
+    /// The routes added at runtime are tested in the order they were added,
+    /// after the field writers and before the target writers of the builder.
+    pub fn add(&self, targets: &str, writer: Box<dyn Writer>) {
+        let route = Arc::new(DynamicRoute {
+            target: InnerTarget::from(Target::from(targets)),
+            writer,
+        });
+        self.0.rcu(|routes| {
+            let mut routes = Vec::clone(routes);
+            routes.push(route.clone());
+            routes
+        });
+    }
+
+    /// Removes the writers added for the given `targets` pattern, and flushes them.
+    /// Returns false if no writer was added for the pattern.
+    pub fn remove(&self, targets: &str) -> Result<bool, io::Error> {
+        let pattern = InnerTarget::from(Target::from(targets)).to_string();
+        let prev = self.0.rcu(|routes| {
+            routes
+                .iter()
+                .filter(|r| r.target.to_string() != pattern)
+                .cloned()
+                .collect::<Vec<_>>()
+        });
+        let mut removed = false;
+        for route in prev.iter().filter(|r| r.target.to_string() == pattern) {
+            removed = true;
+            route.writer.flush()?;
+        }
+        Ok(removed)
+    }
+
+    /// Returns the target patterns of the writers added at runtime, in the order they are tested.
+    pub fn targets(&self) -> Vec<String> {
+        self.0.load().iter().map(|r| r.target.to_string()).collect()
+    }
+}
+
+struct Core {
+    filter: TargetFilter,
     default_writer: Box<dyn Writer>,
     writers: Box<[(InnerTarget, Box<dyn Writer>)]>,
+    // the target writers added at runtime, see `RouteHandle`.
+    routes: Arc<ArcSwap<Vec<Arc<DynamicRoute>>>>,
+    #[cfg(feature = "json")]
+    field_writers: Box<[FieldRoute]>,
+    #[cfg(feature = "json")]
+    schemas: Box<[(InnerTarget, Schema)]>,
+    quarantine: Option<Box<dyn Writer>>,
+    #[cfg(feature = "json")]
+    remap: Option<Remap>,
+    fields: FieldsMode,
+    clock: Box<dyn Clock>,
+    // the timestamp of the previous record, if the timestamps are monotonic.
+    last_timestamp: Option<AtomicU64>,
+    platform: Platform,
+    severity_number: bool,
+    trace_context: Option<TraceContextFn>,
+    record_filter: Option<RecordFilter>,
+    sampler: Option<Box<dyn sampler::Sampler>>,
+    stats: Option<Stats>,
+    shut_down: AtomicBool,
+}
+
+static LOGGER: OnceLock<&'static Logger> = OnceLock::new();
+
+thread_local! {
+    // the logger set by `Logger::scope` on the current thread.
+    static SCOPE: RefCell<Option<Logger>> = const { RefCell::new(None) };
 }
 
 impl Logger {
-    fn get_writer(&self, target: &str) -> &dyn Writer {
+    /// Returns the global logger initialized by [`Builder::init`], to create child loggers from it.
+    pub fn global() -> Option<&'static Logger> {
+        LOGGER.get().copied()
+    }
+
+    /// Returns the most verbose level enabled for any target, as set by [`log::set_max_level`] on init.
+    pub fn max_level(&self) -> LevelFilter {
+        self.core.filter.max_level()
+    }
+
+    /// Returns the level filter of a given target.
+    pub fn level(&self, target: &str) -> LevelFilter {
+        self.core.filter.level(target)
+    }
+
+    /// Writes a record if it is enabled by the level filters, and returns the error of the writer
+    /// instead of reporting it by [`log_failure`]. The records are dropped after [`Logger::shutdown`].
+    pub fn log_record(&self, record: &Record) -> Result<(), io::Error> {
+        if self.core.enabled(record.metadata()) && !self.core.shut_down.load(Ordering::Relaxed) {
+            let res = self.core.try_log(record, &self.statics);
+            if let Some(ref stats) = self.core.stats {
+                stats.on_write(&res);
+            }
+            return res;
+        }
+        Ok(())
+    }
+
+    /// Returns a child logger whose records always include the given fields,
+    /// in addition to the static fields and the preset fields of this logger.
+    /// The child shares the writers and the level filters of this logger.
+    /// The built-in keys (`target`, `message`, `level`, ...) are ignored, and a field overrides a parent field with the same key.
+    ///
+    /// Example:
+    /// ```rust
+    /// use structured_logger::Builder;
+    ///
+    /// let logger = Builder::with_level("info").build();
+    /// let db = logger.with_fields([("component", "db"), ("pool", "primary")]);
+    /// db.scope(|| {
+    ///     // {"component":"db","level":"INFO","message":"connected","pool":"primary",...}
+    ///     log::info!("connected");
+    /// });
+    /// ```
+    #[cfg(feature = "json")]
+    pub fn with_fields<I, K, V>(&self, fields: I) -> Logger
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: serde::Serialize,
+    {
+        let mut statics = StaticFields::clone(&self.statics);
+        for (key, value) in fields {
+            let key = key.as_ref();
+            if !BUILTIN_KEYS.contains(&key) {
+                let value = serde_json::to_value(value).unwrap_or(serde_json::Value::Null);
+                statics.insert(key, value);
+            }
+        }
+        Logger {
+            core: self.core.clone(),
+            statics: Arc::new(statics),
+        }
+    }
+
+    /// Calls `f`, and writes the records logged by the global logger on the current thread within `f`
+    /// with this logger instead, such as the records of a child logger created by [`Logger::with_fields`].
+    /// The scopes can be nested, the innermost logger writes the records.
+    pub fn scope<R>(&self, f: impl FnOnce() -> R) -> R {
+        struct Restore(Option<Logger>);
+
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                let prev = self.0.take();
+                let _ = SCOPE.try_with(|cell| *cell.borrow_mut() = prev);
+            }
+        }
+
+        let prev = SCOPE.with(|cell| cell.borrow_mut().replace(self.clone()));
+        let _restore = Restore(prev);
+        f()
+    }
+
+    /// Flushes and shuts down the writers of the logger, see [`Writer::shutdown`].
+    /// The logs written after are dropped, by this logger and all its clones.
+    pub fn shutdown(&self) {
+        self.core.shutdown()
+    }
+
+    /// Returns whether all the writers of the logger are healthy, see [`Writer::healthy`],
+    /// and the logger is not shut down. It can be reported by a readiness probe.
+    pub fn healthy(&self) -> bool {
+        let mut healthy = !self.core.shut_down.load(Ordering::Relaxed);
+        self.core.each_writer(|w| healthy = healthy && w.healthy());
+        healthy
+    }
+
+    /// Returns a handle to add or remove target writers at runtime, shared by this logger and all its clones
+    /// and child loggers. The global logger's handle is `Logger::global().map(Logger::routes)`.
+    ///
+    /// Example:
+    /// ```rust
+    /// use structured_logger::{json::new_writer, Builder, Logger};
+    ///
+    /// Builder::with_level("info").init();
+    ///
+    /// // a plugin loaded later claims its own log destination.
+    /// let routes = Logger::global().map(Logger::routes).unwrap();
+    /// routes.add("my_plugin*", new_writer(std::io::stdout()));
+    /// log::info!(target: "my_plugin::db", "written to stdout");
+    /// routes.remove("my_plugin*").unwrap();
+    /// ```
+    pub fn routes(&self) -> RouteHandle {
+        RouteHandle(self.core.routes.clone())
+    }
+}
+
+impl Core {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.level(metadata.target()) >= metadata.level()
+    }
+
+    fn all_writers(&self) -> impl Iterator<Item = &dyn Writer> {
+        #[cfg(feature = "json")]
+        let field_writers = self.field_writers.iter().map(|f| f.2.as_ref());
+        #[cfg(not(feature = "json"))]
+        let field_writers = std::iter::empty();
+        field_writers
+            .chain(self.writers.iter().map(|t| t.1.as_ref()))
+            .chain(self.quarantine.as_deref())
+            .chain(std::iter::once(self.default_writer.as_ref()))
+    }
+
+    // Calls `f` with every writer, including the writers added at runtime.
+    fn each_writer(&self, mut f: impl FnMut(&dyn Writer)) {
+        for route in self.routes.load().iter() {
+            f(route.writer.as_ref());
+        }
+        for w in self.all_writers() {
+            f(w);
+        }
+    }
+
+    fn shutdown(&self) {
+        if self.shut_down.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        self.each_writer(|w| {
+            if let Err(err) = w.shutdown() {
+                log_failure(format!("Logger failed to shut down: {}", err).as_str());
+            }
+        });
+    }
+
+    // Returns the first route added at runtime that matches the target.
+    fn get_route(&self, target: &str) -> Option<Arc<DynamicRoute>> {
+        let routes = self.routes.load();
+        routes.iter().find(|r| r.target.test(target)).cloned()
+    }
+
+    // Returns the writer of the record: the first matched field writer, the route added at runtime,
+    // the first matched target writer, or the default writer.
+    fn get_writer<'a>(
+        &'a self,
+        record: &Record,
+        route: Option<&'a DynamicRoute>,
+    ) -> &'a dyn Writer {
+        #[cfg(feature = "json")]
+        if !self.field_writers.is_empty() {
+            let kvs = record.key_values();
+            for (key, value, writer) in self.field_writers.iter() {
+                if let Some(v) = kvs.get(Key::from(key.as_ref())) {
+                    if field_value_eq(&v, value) {
+                        return writer.as_ref();
+                    }
+                }
+            }
+        }
+
+        if let Some(route) = route {
+            return route.writer.as_ref();
+        }
+
+        let target = record.target();
         for t in self.writers.iter() {
             if t.0.test(target) {
                 return t.1.as_ref();
             }
         }
 
-        self.default_writer.as_ref()
+        self.default_writer.as_ref()
+    }
+
+    fn try_log(&self, record: &Record, statics: &StaticFields) -> Result<(), io::Error> {
+        let args = record.args();
+        if let Some(msg) = args.as_str() {
+            return self.write_record(record, msg, statics);
+        }
+
+        MSG_BUF.with(|cell| match cell.try_borrow_mut() {
+            Ok(mut msg) => {
+                msg.clear();
+                let _ = fmt::write(&mut *msg, *args);
+                let res = self.write_record(record, &msg, statics);
+                if msg.capacity() > pool::BufferPool::global().max_retained_capacity() {
+                    *msg = String::with_capacity(256);
+                }
+                res
+            }
+            // re-entrant logging while writing, such as from a `Display` implementation.
+            Err(_) => self.write_record(record, &args.to_string(), statics),
+        })
     }
 
-    fn try_log(&self, record: &Record) -> Result<(), io::Error> {
+    fn write_record(
+        &self,
+        record: &Record,
+        msg: &str,
+        statics: &StaticFields,
+    ) -> Result<(), io::Error> {
         let kvs = record.key_values();
-        let mut visitor = KeyValueVisitor(BTreeMap::new());
-        let _ = kvs.visit(&mut visitor);
-
-        visitor
-            .0
-            .insert(Key::from("target"), Value::from(record.target()));
-
-        let args = record.args();
-        let msg: String;
-        if let Some(msg) = args.as_str() {
-            visitor.0.insert(Key::from("message"), Value::from(msg));
-        } else {
-            msg = args.to_string();
-            visitor.0.insert(Key::from("message"), Value::from(&msg));
+        #[cfg(feature = "json")]
+        let schema_error = self
+            .schemas
+            .iter()
+            .find(|(t, _)| t.test(record.target()))
+            .and_then(|(_, schema)| schema.validate(kvs).err());
+        #[cfg(not(feature = "json"))]
+        let schema_error: Option<String> = None;
+        let mut timestamp = self.clock.unix_ms();
+        if let Some(ref last) = self.last_timestamp {
+            timestamp = monotonic_timestamp(last, timestamp);
         }
+        let time = (self.platform == Platform::Gcp).then(|| Rfc3339::from_unix_ms(timestamp));
+        let trace_ids = self
+            .trace_context
+            .as_ref()
+            .and_then(|f| f())
+            .map(|ctx| self.platform.trace_ids(ctx));
+
+        let mut builtins: SmallVec<[(Key, Value); 9]> = SmallVec::new();
+        builtins.push((Key::from("target"), Value::from(record.target())));
+        builtins.push((Key::from("message"), Value::from(msg)));
 
         let level = record.level();
-        visitor
-            .0
-            .insert(Key::from("level"), Value::from(level.as_str()));
+        let (key, value) = self.platform.level(level);
+        builtins.push((Key::from(key), Value::from(value)));
+        if self.severity_number {
+            builtins.push((
+                Key::from("severity_number"),
+                Value::from(severity_number(level)),
+            ));
+        }
 
         if level <= Level::Warn {
             if let Some(val) = record.module_path() {
-                visitor.0.insert(Key::from("module"), Value::from(val));
+                builtins.push((Key::from("module"), Value::from(val)));
             }
             if let Some(val) = record.file() {
-                visitor.0.insert(Key::from("file"), Value::from(val));
+                builtins.push((Key::from("file"), Value::from(val)));
             }
             if let Some(val) = record.line() {
-                visitor.0.insert(Key::from("line"), Value::from(val));
+                builtins.push((Key::from("line"), Value::from(val)));
             }
         }
 
-        visitor
-            .0
-            .insert(Key::from("timestamp"), Value::from(unix_ms()));
-        self.get_writer(record.target()).write_log(&visitor.0)?;
-        Ok(())
+        if let Some(ref err) = schema_error {
+            builtins.push((Key::from("schema_error"), Value::from(err.as_str())));
+        }
+
+        if let Some(ref ids) = trace_ids {
+            for (key, value) in ids {
+                builtins.push((Key::from(*key), Value::from(value.as_str())));
+            }
+        }
+
+        match time {
+            Some(ref time) => builtins.push((Key::from("time"), Value::from(time.as_str()))),
+            None => builtins.push((Key::from("timestamp"), Value::from(timestamp))),
+        }
+
+        // the fields collected into a map by the record filter, reused by the remap.
+        let mut map = None;
+        if let Some(ref filter) = self.record_filter {
+            let fields = map.insert(Fields::new(statics, kvs, &builtins, false).to_map());
+            if !filter(record.metadata(), fields) {
+                return Ok(());
+            }
+        }
+
+        if let Some(ref sampler) = self.sampler {
+            let fields = Fields::new(statics, kvs, &builtins, false);
+            if !sampler.sample(record.metadata(), &fields) {
+                return Ok(());
+            }
+        }
+
+        let route = self.get_route(record.target());
+        let writer = match (&schema_error, &self.quarantine) {
+            (Some(_), Some(quarantine)) => quarantine.as_ref(),
+            _ => self.get_writer(record, route.as_deref()),
+        };
+        #[cfg(feature = "json")]
+        if let Some(ref remap) = self.remap {
+            let fields = map.unwrap_or_else(|| Fields::new(statics, kvs, &builtins, false).to_map());
+            return remap.write(writer, &fields);
+        }
+        match self.fields {
+            FieldsMode::Streaming => {
+                writer.write_fields(&Fields::new(statics, kvs, &builtins, false))
+            }
+            FieldsMode::Sorted => writer.write_fields(&Fields::new(statics, kvs, &builtins, true)),
+        }
+    }
+}
+
+// Logs the statistics of a logger every `interval`, until the logger is dropped or shut down.
+fn log_stats_periodically(core: Weak<Core>, statics: Arc<StaticFields>, interval: Duration) {
+    let mut last_dropped = Vec::new();
+    loop {
+        thread::sleep(interval);
+        let core = match core.upgrade() {
+            Some(core) => core,
+            None => return,
+        };
+        if core.shut_down.load(Ordering::Relaxed) {
+            return;
+        }
+        let stats = match core.stats {
+            Some(ref stats) => stats,
+            None => return,
+        };
+        let kvs = stats.take(&mut last_dropped);
+        let record = Record::builder()
+            .args(format_args!("logger statistics"))
+            .level(Level::Info)
+            .target(stats::STATS_TARGET)
+            .key_values(&kvs)
+            .build();
+        // the statistics records are not counted.
+        if core.enabled(record.metadata()) {
+            if let Err(err) = core.try_log(&record, &statics) {
+                log_failure(format!("Logger failed to log the statistics: {}", err).as_str());
+            }
+        }
     }
 }
 
-unsafe impl Sync for Logger {}
-unsafe impl Send for Logger {}
+impl fmt::Debug for Logger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let core = &self.core;
+        let writers: Vec<String> = core.writers.iter().map(|(t, _)| t.to_string()).collect();
+        let mut d = f.debug_struct("Logger");
+        d.field("filter", &core.filter).field("writers", &writers);
+        #[cfg(feature = "json")]
+        {
+            let field_writers: Vec<String> = core
+                .field_writers
+                .iter()
+                .map(|(k, v, _)| format!("{}={}", k, v))
+                .collect();
+            let schemas: Vec<_> = core
+                .schemas
+                .iter()
+                .map(|(t, s)| (t.to_string(), s))
+                .collect();
+            d.field("field_writers", &field_writers)
+                .field("schemas", &schemas);
+        }
+        d.field("static_fields", &self.statics)
+            .field("fields", &core.fields)
+            .field("monotonic_timestamp", &core.last_timestamp.is_some())
+            .field("platform", &core.platform)
+            .field("trace_context", &core.trace_context.is_some())
+            .field("record_filter", &core.record_filter.is_some())
+            .field("sampler", &core.sampler.is_some())
+            .field("shut_down", &core.shut_down.load(Ordering::Relaxed))
+            .finish()
+    }
+}
 
 impl log::Log for Logger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        self.filter >= metadata.level()
+        self.core.enabled(metadata)
     }
 
     fn log(&self, record: &Record) {
-        if self.enabled(record.metadata()) {
-            if let Err(err) = self.try_log(record) {
-                // should never happen, but if it does, we log it.
-                log_failure(format!("Logger failed to log: {}", err).as_str());
-            }
+        // the scoped logger of the current thread, if any, writes the record instead.
+        let scoped = SCOPE
+            .try_with(|cell| cell.try_borrow().ok().and_then(|s| s.clone()))
+            .ok()
+            .flatten();
+        let res = match scoped {
+            Some(logger) => logger.log_record(record),
+            None => self.log_record(record),
+        };
+        if let Err(err) = res {
+            // should never happen, but if it does, we log it.
+            log_failure(format!("Logger failed to log: {}", err).as_str());
         }
     }
 
-    fn flush(&self) {}
+    fn flush(&self) {
+        self.core.each_writer(|w| {
+            if let Err(err) = w.flush() {
+                log_failure(format!("Logger failed to flush: {}", err).as_str());
+            }
+        });
+    }
 }
 
 struct Target {
@@ -359,6 +2005,18 @@ struct InnerTarget {
     items: Box<[Box<str>]>,
 }
 
+// Formats the target pattern, such as `api*,db`.
+impl fmt::Display for InnerTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.all {
+            return f.write_str("*");
+        }
+        let items = self.items.iter().map(|i| i.to_string());
+        let prefix = self.prefix.iter().map(|p| format!("{}*", p));
+        f.write_str(&items.chain(prefix).collect::<Vec<_>>().join(","))
+    }
+}
+
 impl InnerTarget {
     fn from(t: Target) -> Self {
         InnerTarget {
@@ -382,49 +2040,178 @@ impl InnerTarget {
     }
 }
 
-struct KeyValueVisitor<'kvs>(BTreeMap<Key<'kvs>, Value<'kvs>>);
+// The level filters by target, precomputed so that `enabled` is a lookup without allocation.
+#[derive(Debug)]
+struct TargetFilter {
+    default: LevelFilter,
+    // sorted by target for binary search.
+    exact: Box<[(Box<str>, LevelFilter)]>,
+    // sorted by descending length, so the longest matched prefix wins.
+    prefixes: Box<[(Box<str>, LevelFilter)]>,
+}
+
+impl TargetFilter {
+    fn new(default: LevelFilter, target_levels: Vec<(Target, LevelFilter)>) -> Self {
+        let mut default = default;
+        let mut exact: Vec<(Box<str>, LevelFilter)> = Vec::new();
+        let mut prefixes: Vec<(Box<str>, LevelFilter)> = Vec::new();
+        // the later filters override the earlier ones for the same target.
+        for (target, level) in target_levels {
+            if target.all {
+                default = level;
+            }
+            for t in target.items {
+                exact.retain(|(k, _)| k.as_ref() != t);
+                exact.push((t.into_boxed_str(), level));
+            }
+            for p in target.prefix {
+                prefixes.retain(|(k, _)| k.as_ref() != p);
+                prefixes.push((p.into_boxed_str(), level));
+            }
+        }
+        exact.sort_by(|a, b| a.0.cmp(&b.0));
+        prefixes.sort_by_key(|p| std::cmp::Reverse(p.0.len()));
+        TargetFilter {
+            default,
+            exact: exact.into_boxed_slice(),
+            prefixes: prefixes.into_boxed_slice(),
+        }
+    }
 
-impl<'kvs> Visitor<'kvs> for KeyValueVisitor<'kvs> {
     #[inline]
-    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), Error> {
-        self.0.insert(key, value);
-        Ok(())
+    fn level(&self, target: &str) -> LevelFilter {
+        if self.exact.is_empty() && self.prefixes.is_empty() {
+            return self.default;
+        }
+        if let Ok(i) = self.exact.binary_search_by(|(k, _)| k.as_ref().cmp(target)) {
+            return self.exact[i].1;
+        }
+        for (p, level) in self.prefixes.iter() {
+            if target.starts_with(p.as_ref()) {
+                return *level;
+            }
+        }
+        self.default
+    }
+
+    // The most verbose level of all filters, for `log::set_max_level`.
+    fn max_level(&self) -> LevelFilter {
+        self.exact
+            .iter()
+            .chain(self.prefixes.iter())
+            .map(|(_, level)| *level)
+            .fold(self.default, Ord::max)
     }
 }
 
+/// The output stream of the internal logging failures, see [`Builder::with_failure_output`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureOutput {
+    /// Writes the failures to stderr, the default.
+    Stderr,
+    /// Writes the failures to stdout.
+    Stdout,
+}
+
+enum FailureHandler {
+    Output(FailureOutput),
+    Custom(Box<dyn Fn(&str) + Send + Sync>),
+}
+
+static FAILURE_HANDLER: RwLock<FailureHandler> =
+    const_rwlock(FailureHandler::Output(FailureOutput::Stderr));
+
 /// A fallback logging function that is used in case of logging failure in [`Writer`] implementation.
-/// It will write failure information in JSON to `stderr`.
+/// It will write failure information in JSON to `stderr`,
+/// or pass it to the handler set by [`Builder::with_failure_handler`] or [`Builder::with_failure_output`].
 pub fn log_failure(msg: &str) {
-    match serde_json::to_string(msg) {
-        Ok(msg) => {
-            eprintln!(
-                "{{\"level\":\"ERROR\",\"message\":{},\"target\":\"structured_logger\",\"timestamp\":{}}}",
-                &msg,
-                unix_ms()
-            );
-        }
-        Err(err) => {
-            // should never happen
-            panic!("log_failure serialize error: {}", err)
+    match *FAILURE_HANDLER.read() {
+        FailureHandler::Output(FailureOutput::Stderr) => write_failure(io::stderr().lock(), msg),
+        FailureHandler::Output(FailureOutput::Stdout) => write_failure(io::stdout().lock(), msg),
+        FailureHandler::Custom(ref handler) => handler(msg),
+    }
+}
+
+fn write_failure(mut w: impl Write, msg: &str) {
+    // write to the file descriptor directly, bypassing the `eprintln!` capture.
+    let _ = writeln!(
+        w,
+        "{{\"level\":\"ERROR\",\"message\":\"{}\",\"target\":\"structured_logger\",\"timestamp\":{}}}",
+        JsonStr(msg),
+        unix_ms()
+    );
+}
+
+// A string escaped as the content of a JSON string, without depending on `serde_json`.
+struct JsonStr<'a>(&'a str);
+
+impl fmt::Display for JsonStr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut start = 0;
+        for (i, c) in self.0.char_indices() {
+            let escaped = match c {
+                '"' => "\\\"",
+                '\\' => "\\\\",
+                '\n' => "\\n",
+                '\r' => "\\r",
+                '\t' => "\\t",
+                '\u{8}' => "\\b",
+                '\u{c}' => "\\f",
+                c if c < ' ' => "",
+                _ => continue,
+            };
+            f.write_str(&self.0[start..i])?;
+            if escaped.is_empty() {
+                write!(f, "\\u{:04x}", c as u32)?;
+            } else {
+                f.write_str(escaped)?;
+            }
+            start = i + c.len_utf8();
         }
+        f.write_str(&self.0[start..])
     }
 }
 
+// Whether the panic hook captures a backtrace, see `Builder::with_panic_backtrace`.
+#[cfg(feature = "log-panic")]
+static PANIC_BACKTRACE: AtomicBool = AtomicBool::new(true);
+
 /// Panic hook that logs the panic using [`log::error!`].
 #[cfg(feature = "log-panic")]
-fn log_panic(info: &std::panic::PanicInfo<'_>) {
+fn log_panic(info: &std::panic::PanicHookInfo<'_>) {
     use std::backtrace::Backtrace;
-    use std::thread;
 
     let mut record = log::Record::builder();
     let thread = thread::current();
     let thread_name = thread.name().unwrap_or("unnamed");
-    let backtrace = Backtrace::force_capture();
+    let thread_id = thread.id();
+    let backtrace = (PANIC_BACKTRACE.load(Ordering::Relaxed)
+        && std::env::var_os("RUST_BACKTRACE").as_deref() != Some(std::ffi::OsStr::new("0")))
+    .then(Backtrace::force_capture);
 
-    let key_values = [
-        ("backtrace", Value::capture_display(&backtrace)),
+    let mut key_values = vec![
         ("thread_name", Value::from(thread_name)),
+        ("thread_id", Value::from_debug(&thread_id)),
     ];
+    if let Some(ref backtrace) = backtrace {
+        key_values.push(("backtrace", Value::from_display(backtrace)));
+    }
+    let payload = info
+        .payload()
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| info.payload().downcast_ref::<String>().map(String::as_str));
+    if let Some(payload) = payload {
+        key_values.push(("panic.payload", Value::from(payload)));
+    }
+    if let Some(location) = info.location() {
+        key_values.push(("location.file", Value::from(location.file())));
+        key_values.push(("location.line", Value::from(location.line())));
+        key_values.push(("location.column", Value::from(location.column())));
+        let _ = record
+            .file(Some(location.file()))
+            .line(Some(location.line()));
+    };
     let key_values = key_values.as_slice();
 
     let _ = record
@@ -432,20 +2219,16 @@ fn log_panic(info: &std::panic::PanicInfo<'_>) {
         .target("panic")
         .key_values(&key_values);
 
-    if let Some(location) = info.location() {
-        let _ = record
-            .file(Some(location.file()))
-            .line(Some(location.line()));
-    };
-
     log::logger().log(
         &record
             .args(format_args!("thread '{thread_name}' {info}"))
             .build(),
     );
+    // the process may exit after the panic, write the buffered records.
+    log::logger().flush();
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "json"))]
 mod tests {
     use super::*;
     use gag::BufferRedirect;
@@ -458,6 +2241,243 @@ mod tests {
         assert!(now > 1670123456789_u64);
     }
 
+    #[test]
+    fn unix_us_ns_works() {
+        let ms = unix_ms();
+        let us = unix_us();
+        let ns = unix_ns();
+        assert!(us / 1000 >= ms && us / 1000 <= unix_ms());
+        assert!(ns / 1000 >= us && ns / 1000 <= unix_us());
+    }
+
+    #[test]
+    fn monotonic_timestamp_works() {
+        let last = AtomicU64::new(0);
+        assert_eq!(100, monotonic_timestamp(&last, 100));
+        assert_eq!(100, monotonic_timestamp(&last, 100));
+        // the clock steps backwards.
+        assert_eq!(101, monotonic_timestamp(&last, 90));
+        assert_eq!(102, monotonic_timestamp(&last, 95));
+        assert_eq!(110, monotonic_timestamp(&last, 110));
+    }
+
+    #[test]
+    fn rfc3339_works() {
+        assert_eq!(
+            "1970-01-01T00:00:00.000Z",
+            Rfc3339::from_unix_ms(0).as_str()
+        );
+        assert_eq!(
+            "2023-03-25T11:59:52.127Z",
+            Rfc3339::from_unix_ms(1679745592127).as_str()
+        );
+        assert_eq!(
+            "2024-02-29T23:59:59.999Z",
+            Rfc3339::from_unix_ms(1709251199999).as_str()
+        );
+    }
+
+    #[test]
+    fn datadog_fields_works() {
+        use log::Log;
+
+        let lines = std::sync::Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let w = || {
+            let lines = lines.clone();
+            fn_writer(move |value| {
+                lines.lock().push(serde_json::to_string(value)?);
+                Ok(())
+            })
+        };
+        let trace = || -> TraceContextFn {
+            Box::new(|| {
+                Some(TraceContext {
+                    trace_id: 1 << 64 | 5,
+                    span_id: 7,
+                })
+            })
+        };
+        let log = |logger: &Logger, level: Level| {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("hello"))
+                    .level(level)
+                    .target("app")
+                    .build(),
+            )
+        };
+
+        std::env::set_var("DD_SERVICE", "checkout");
+        let logger = Builder::with_level("info")
+            .with_default_writer(w())
+            .with_trace_context(trace())
+            .with_datadog_fields()
+            .build();
+        std::env::remove_var("DD_SERVICE");
+        log(&logger, Level::Warn);
+        log(&logger, Level::Info);
+
+        let logger = Builder::with_level("info")
+            .with_default_writer(w())
+            .with_trace_context(trace())
+            .build();
+        log(&logger, Level::Info);
+
+        let values: Vec<value::Value> = lines
+            .lock()
+            .iter()
+            .map(|l| de::from_str(l).unwrap())
+            .collect();
+        assert_eq!("warning", values[0]["status"]);
+        assert_eq!("info", values[1]["status"]);
+        assert!(values[0].get("level").is_none());
+        assert_eq!("checkout", values[0]["service"]);
+        assert_eq!("5", values[0]["dd.trace_id"]);
+        assert_eq!("7", values[0]["dd.span_id"]);
+
+        assert_eq!("INFO", values[2]["level"]);
+        assert_eq!("00000000000000010000000000000005", values[2]["trace_id"]);
+        assert_eq!("0000000000000007", values[2]["span_id"]);
+        assert!(values[2].get("service").is_none());
+    }
+
+    #[test]
+    fn severity_number_works() {
+        use log::Log;
+
+        let lines = std::sync::Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let w = {
+            let lines = lines.clone();
+            fn_writer(move |value| {
+                lines.lock().push(serde_json::to_string(value)?);
+                Ok(())
+            })
+        };
+        let logger = Builder::with_level("trace")
+            .with_default_writer(w)
+            .with_datadog_fields()
+            .with_severity_number()
+            .build();
+        for level in [
+            Level::Error,
+            Level::Warn,
+            Level::Info,
+            Level::Debug,
+            Level::Trace,
+        ] {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("hello"))
+                    .level(level)
+                    .target("app")
+                    .build(),
+            );
+        }
+
+        let numbers: Vec<u64> = lines
+            .lock()
+            .iter()
+            .map(|line| {
+                let value: value::Value = de::from_str(line).unwrap();
+                value["severity_number"].as_u64().unwrap()
+            })
+            .collect();
+        assert_eq!(vec![17, 13, 9, 5, 1], numbers);
+    }
+
+    #[test]
+    fn gcp_fields_works() {
+        use log::Log;
+
+        let lines = std::sync::Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let w = {
+            let lines = lines.clone();
+            fn_writer(move |value| {
+                lines.lock().push(serde_json::to_string(value)?);
+                Ok(())
+            })
+        };
+        let logger = Builder::with_level("trace")
+            .with_default_writer(w)
+            .with_gcp_fields()
+            .build();
+        for level in [
+            Level::Error,
+            Level::Warn,
+            Level::Info,
+            Level::Debug,
+            Level::Trace,
+        ] {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("hello"))
+                    .level(level)
+                    .target("app")
+                    .build(),
+            );
+        }
+
+        let severities: Vec<String> = lines
+            .lock()
+            .iter()
+            .map(|line| {
+                let value: value::Value = de::from_str(line).unwrap();
+                assert_eq!("hello", value["message"]);
+                assert!(value.get("level").is_none());
+                assert!(value.get("timestamp").is_none());
+                let time = value["time"].as_str().unwrap();
+                assert_eq!(24, time.len());
+                assert!(time.ends_with('Z'));
+                value["severity"].as_str().unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(
+            vec!["ERROR", "WARNING", "INFO", "DEBUG", "DEBUG"],
+            severities
+        );
+    }
+
+    #[test]
+    fn coarse_unix_ms_works() {
+        // the ticker may be delayed by the scheduler of a busy machine.
+        const TOLERANCE_MS: u64 = 100;
+        let now = unix_ms();
+        let coarse = coarse_unix_ms();
+        assert!(coarse + TOLERANCE_MS >= now && coarse <= unix_ms());
+        thread::sleep(Duration::from_millis(20));
+        let coarse2 = coarse_unix_ms();
+        assert!(coarse2 > coarse);
+
+        // the idle ticker is parked, and woken up by the next call.
+        thread::sleep(Duration::from_millis(50));
+        let now = unix_ms();
+        let coarse = coarse_unix_ms();
+        assert!(coarse + TOLERANCE_MS >= now && coarse > coarse2);
+        thread::sleep(Duration::from_millis(5));
+        assert!(coarse_unix_ms() > coarse);
+    }
+
+    #[test]
+    fn fn_writer_works() {
+        let lines = std::sync::Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let w = {
+            let lines = lines.clone();
+            fn_writer(move |value| {
+                lines.lock().push(serde_json::to_string(value)?);
+                Ok(())
+            })
+        };
+
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("message"), Value::from("hello"));
+        w.write_log(&value).unwrap();
+        w.flush().unwrap();
+        assert_eq!(vec![r#"{"message":"hello"}"#.to_string()], *lines.lock());
+
+        let w = fn_writer(|_| Err(io::Error::other("closed")));
+        assert_eq!("closed", w.write_log(&value).unwrap_err().to_string());
+    }
+
     #[test]
     fn get_env_level_works() {
         assert_eq!(Level::Info, get_env_level());
@@ -483,6 +2503,38 @@ mod tests {
         env::remove_var("DEBUG");
     }
 
+    #[test]
+    fn env_writer_works() {
+        for (output, format) in [
+            ("", ""),
+            ("stderr", "logfmt"),
+            (" stdout ", "Pretty"),
+            ("null", "ecs"),
+        ] {
+            assert!(env_writer(output, format).is_ok());
+        }
+        for (output, format) in [("syslog", ""), ("file:", ""), ("stdout:", ""), ("", "xml")] {
+            let err = env_writer(output, format).err().unwrap();
+            assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+        }
+
+        let dir = env::temp_dir().join("structured-logger-env");
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("message"), Value::from("hello"));
+        for (format, expected) in [
+            ("json", "{\"message\":\"hello\"}\n"),
+            ("logfmt", "message=hello\n"),
+            ("ecs", "{\"ecs.version\":\"8.11.0\",\"message\":\"hello\"}\n"),
+        ] {
+            let path = dir.join(format!("{}.log", format));
+            let _ = std::fs::remove_file(&path);
+            let w = env_writer(&format!("file:{}", path.display()), format).unwrap();
+            w.write_log(&value).unwrap();
+            w.flush().unwrap();
+            assert_eq!(expected, std::fs::read_to_string(&path).unwrap());
+        }
+    }
+
     #[test]
     fn target_works() {
         let target = InnerTarget::from(Target::from("*"));
@@ -511,6 +2563,612 @@ mod tests {
         assert!(target.test("error"));
     }
 
+    #[test]
+    fn target_filter_works() {
+        let filter = TargetFilter::new(LevelFilter::Info, Vec::new());
+        assert_eq!(LevelFilter::Info, filter.level("api"));
+        assert_eq!(LevelFilter::Info, filter.max_level());
+
+        let filter = TargetFilter::new(
+            LevelFilter::Info,
+            vec![
+                (Target::from("db*, api"), LevelFilter::Warn),
+                (Target::from("db::pool*"), LevelFilter::Off),
+                (Target::from("api,http"), LevelFilter::Debug),
+                (Target::from("db::pool::conn"), LevelFilter::Trace),
+            ],
+        );
+        assert_eq!(LevelFilter::Info, filter.level(""));
+        assert_eq!(LevelFilter::Info, filter.level("ap"));
+        assert_eq!(LevelFilter::Debug, filter.level("api"));
+        assert_eq!(LevelFilter::Info, filter.level("api::v1"));
+        assert_eq!(LevelFilter::Debug, filter.level("http"));
+        assert_eq!(LevelFilter::Warn, filter.level("db"));
+        assert_eq!(LevelFilter::Warn, filter.level("db::query"));
+        assert_eq!(LevelFilter::Off, filter.level("db::pool"));
+        assert_eq!(LevelFilter::Off, filter.level("db::pool::idle"));
+        assert_eq!(LevelFilter::Trace, filter.level("db::pool::conn"));
+        assert_eq!(LevelFilter::Trace, filter.max_level());
+
+        let filter = TargetFilter::new(
+            LevelFilter::Info,
+            vec![(Target::from("*"), LevelFilter::Error)],
+        );
+        assert_eq!(LevelFilter::Error, filter.level("api"));
+        assert_eq!(LevelFilter::Error, filter.max_level());
+    }
+
+    #[test]
+    fn logger_build_works() {
+        use log::Log;
+
+        let lines = std::sync::Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let w = {
+            let lines = lines.clone();
+            fn_writer(move |value| {
+                lines.lock().push(serde_json::to_string(value)?);
+                Ok(())
+            })
+        };
+        let logger = Builder::with_level("info")
+            .with_target_writer("api*,db", w)
+            .with_target_level("db", "warn")
+            .with_static_field("service", "web")
+            .with_streaming()
+            .build();
+        assert_eq!(LevelFilter::Info, logger.max_level());
+        assert_eq!(LevelFilter::Warn, logger.level("db"));
+        assert_eq!(
+            r#"Logger { filter: TargetFilter { default: Info, exact: [("db", Warn)], prefixes: [] }, writers: ["db,api*"], field_writers: [], schemas: [], static_fields: {"service": String("web")}, fields: Streaming, monotonic_timestamp: false, platform: Default, trace_context: false, record_filter: false, sampler: false, shut_down: false }"#,
+            format!("{:?}", logger)
+        );
+
+        logger.log(
+            &Record::builder()
+                .args(format_args!("hello"))
+                .level(Level::Info)
+                .target("api")
+                .build(),
+        );
+        logger.log(
+            &Record::builder()
+                .args(format_args!("filtered"))
+                .level(Level::Info)
+                .target("db")
+                .build(),
+        );
+        let lines = lines.lock();
+        assert_eq!(1, lines.len());
+        assert!(lines[0].starts_with(
+            r#"{"level":"INFO","message":"hello","service":"web","target":"api","timestamp":"#
+        ));
+    }
+
+    #[test]
+    fn healthy_works() {
+        // a destination that fails while `full` is true, such as a full disk.
+        struct Disk(Arc<AtomicBool>);
+
+        impl Write for Disk {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                if self.0.load(Ordering::Relaxed) {
+                    return Err(io::Error::other("no space left on device"));
+                }
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let full = Arc::new(AtomicBool::new(false));
+        let logger = Builder::with_level("info")
+            .with_default_writer(json::new_writer(Disk(full.clone())))
+            .build();
+        let record = Record::builder()
+            .args(format_args!("hello"))
+            .level(Level::Info)
+            .target("api")
+            .build();
+        assert!(logger.healthy());
+
+        full.store(true, Ordering::Relaxed);
+        assert!(logger.log_record(&record).is_err());
+        assert!(!logger.healthy());
+
+        full.store(false, Ordering::Relaxed);
+        logger.log_record(&record).unwrap();
+        assert!(logger.healthy());
+
+        logger.shutdown();
+        assert!(!logger.healthy());
+    }
+
+    #[test]
+    fn stats_works() {
+        let stats = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let w = {
+            let stats = stats.clone();
+            fn_writer(move |value| {
+                let field = |key: &str| value.get(&Key::from(key)).and_then(|v| v.to_u64());
+                if value
+                    .get(&Key::from("message"))
+                    .and_then(|v| v.to_borrowed_str())
+                    == Some("fail")
+                {
+                    return Err(io::Error::other("failed"));
+                }
+                if let (Some(records), Some(errors)) = (field("records"), field("errors")) {
+                    stats.lock().push((records, errors));
+                }
+                Ok(())
+            })
+        };
+        let logger = Builder::with_level("info")
+            .with_default_writer(w)
+            .with_stats(Duration::from_millis(20))
+            .build();
+        for msg in ["hello", "world", "fail"] {
+            let _ = logger.log_record(
+                &Record::builder()
+                    .args(format_args!("{}", msg))
+                    .level(Level::Info)
+                    .target("api")
+                    .build(),
+            );
+        }
+        thread::sleep(Duration::from_millis(100));
+        logger.shutdown();
+
+        let stats = stats.lock();
+        assert!(!stats.is_empty());
+        assert_eq!(2, stats.iter().map(|s| s.0).sum::<u64>());
+        assert_eq!(1, stats.iter().map(|s| s.1).sum::<u64>());
+    }
+
+    #[test]
+    fn logger_instances_works() {
+        let count = std::sync::Arc::new(AtomicU64::new(0));
+        let w = {
+            let count = count.clone();
+            fn_writer(move |_| {
+                count.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            })
+        };
+        let audit = Builder::with_level("info")
+            .with_default_writer(w)
+            .with_target_writer("broken", fn_writer(|_| Err(io::Error::other("broken"))))
+            .build();
+        let clone = audit.clone();
+        for logger in [&audit, &clone] {
+            logger
+                .log_record(
+                    &Record::builder()
+                        .args(format_args!("hello"))
+                        .level(Level::Info)
+                        .target("audit")
+                        .build(),
+                )
+                .unwrap();
+        }
+        assert_eq!(2, count.load(Ordering::Relaxed));
+
+        let err = audit
+            .log_record(
+                &Record::builder()
+                    .args(format_args!("hello"))
+                    .level(Level::Info)
+                    .target("broken")
+                    .build(),
+            )
+            .unwrap_err();
+        assert_eq!("broken", err.to_string());
+
+        clone.shutdown();
+        audit
+            .log_record(
+                &Record::builder()
+                    .args(format_args!("dropped"))
+                    .level(Level::Info)
+                    .target("audit")
+                    .build(),
+            )
+            .unwrap();
+        assert_eq!(2, count.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn child_logger_works() {
+        use log::Log;
+
+        let lines = std::sync::Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let w = {
+            let lines = lines.clone();
+            fn_writer(move |value| {
+                lines.lock().push(serde_json::to_string(value)?);
+                Ok(())
+            })
+        };
+        let logger = Builder::with_level("info")
+            .with_default_writer(w)
+            .with_static_field("service", "web")
+            .build();
+        let child = logger.with_fields([("component", "db"), ("level", "ignored")]);
+        let grandchild = child.with_fields([("component", "pool")]);
+        let log = |logger: &Logger| {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("hello"))
+                    .level(Level::Info)
+                    .target("app")
+                    .build(),
+            )
+        };
+
+        log(&child);
+        log(&logger);
+        child.scope(|| {
+            log(&logger);
+            grandchild.scope(|| log(&logger));
+            log(&logger);
+        });
+        log(&logger);
+
+        let fields: Vec<String> = lines
+            .lock()
+            .iter()
+            .map(|line| {
+                let value: value::Value = de::from_str(line).unwrap();
+                assert_eq!("INFO", value["level"]);
+                assert_eq!("web", value["service"]);
+                value["component"].as_str().unwrap_or_default().to_string()
+            })
+            .collect();
+        assert_eq!(vec!["db", "", "db", "pool", "db", ""], fields);
+    }
+
+    #[test]
+    fn record_filter_works() {
+        use log::Log;
+
+        let count = std::sync::Arc::new(AtomicU64::new(0));
+        let w = {
+            let count = count.clone();
+            fn_writer(move |_| {
+                count.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            })
+        };
+        let logger = Builder::with_level("info")
+            .with_default_writer(w)
+            .with_filter(Box::new(|metadata, fields| {
+                metadata.target() != "api"
+                    || fields.get(&Key::from("path")).map(|v| v.to_string())
+                        != Some("/healthz".to_string())
+            }))
+            .with_streaming()
+            .build();
+
+        for (target, path) in [("api", "/healthz"), ("api", "/hello"), ("db", "/healthz")] {
+            let kvs = [("path", Value::from(path))];
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("hello"))
+                    .level(Level::Info)
+                    .target(target)
+                    .key_values(&kvs)
+                    .build(),
+            );
+        }
+        assert_eq!(2, count.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn sampler_works() {
+        use log::Log;
+
+        // keeps the errors, and the records of the traces with an error.
+        struct ErrorTraceSampler(parking_lot::Mutex<Vec<String>>);
+
+        impl sampler::Sampler for ErrorTraceSampler {
+            fn sample(&self, metadata: &Metadata, fields: &Fields) -> bool {
+                let trace_id = fields.to_sorted().get("trace_id").map(|v| v.to_string());
+                let mut traces = self.0.lock();
+                match trace_id {
+                    Some(id) if metadata.level() == Level::Error => {
+                        traces.push(id);
+                        true
+                    }
+                    Some(id) => traces.contains(&id),
+                    None => metadata.level() == Level::Error,
+                }
+            }
+        }
+
+        let lines = std::sync::Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let w = {
+            let lines = lines.clone();
+            fn_writer(move |value| {
+                lines.lock().push(value[&Key::from("message")].to_string());
+                Ok(())
+            })
+        };
+        let logger = Builder::with_level("info")
+            .with_default_writer(w)
+            .with_sampler(Box::new(ErrorTraceSampler(parking_lot::Mutex::new(
+                Vec::new(),
+            ))))
+            .with_streaming()
+            .build();
+
+        for (level, trace_id, message) in [
+            (Level::Info, "a", "a1"),
+            (Level::Error, "a", "a2"),
+            (Level::Info, "a", "a3"),
+            (Level::Info, "b", "b1"),
+        ] {
+            let kvs = [("trace_id", Value::from(trace_id))];
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(level)
+                    .key_values(&kvs)
+                    .build(),
+            );
+        }
+        assert_eq!(vec!["a2", "a3"], *lines.lock());
+    }
+
+    #[test]
+    fn remap_works() {
+        use log::Log;
+
+        let remap = Remap::new()
+            .with_rename("message", "msg")
+            .with_move("method", "http.request.method")
+            .with_move("service", "service.name")
+            .with_default("env", "production")
+            .with_drop("timestamp");
+        // the remap applies to the built-in and static fields, in every fields mode and with a record filter.
+        let builders = [
+            Builder::with_level("info"),
+            Builder::with_level("info").with_streaming(),
+            Builder::with_level("info").with_filter(Box::new(|_, fields| {
+                fields.get(&Key::from("method")).is_some()
+            })),
+        ];
+        for builder in builders {
+            let capture = test::Capture::new();
+            let logger = builder
+                .with_default_writer(capture.writer())
+                .with_static_field("service", "api")
+                .with_remap(remap.clone())
+                .build();
+
+            let kvs = [
+                ("method", Value::from("GET")),
+                ("env", Value::from("staging")),
+            ];
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("hello"))
+                    .level(Level::Info)
+                    .target("api")
+                    .key_values(&kvs)
+                    .build(),
+            );
+            let records = capture.records();
+            assert_eq!(1, records.len());
+            assert_eq!(
+                r#"{"env":"staging","http":{"request":{"method":"GET"}},"level":"INFO","msg":"hello","service":{"name":"api"},"target":"api"}"#,
+                records[0].to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn routes_works() {
+        use log::Log;
+
+        let counter = |count: &std::sync::Arc<AtomicU64>| {
+            let count = count.clone();
+            fn_writer(move |_| {
+                count.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            })
+        };
+        let default_count = std::sync::Arc::new(AtomicU64::new(0));
+        let plugin_count = std::sync::Arc::new(AtomicU64::new(0));
+        let logger = Builder::with_level("info")
+            .with_default_writer(counter(&default_count))
+            .with_target_writer("*", counter(&default_count))
+            .build();
+        let log = |target: &str| {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("hello"))
+                    .level(Level::Info)
+                    .target(target)
+                    .build(),
+            )
+        };
+
+        let routes = logger.routes();
+        routes.add("plugin*", counter(&plugin_count));
+        assert_eq!(vec!["plugin*"], routes.targets());
+        log("plugin::db");
+        log("api");
+        assert_eq!(1, plugin_count.load(Ordering::Relaxed));
+        assert_eq!(1, default_count.load(Ordering::Relaxed));
+
+        assert!(routes.remove("plugin*").unwrap());
+        assert!(!routes.remove("plugin*").unwrap());
+        log("plugin::db");
+        assert_eq!(1, plugin_count.load(Ordering::Relaxed));
+        assert_eq!(2, default_count.load(Ordering::Relaxed));
+        assert!(logger.healthy());
+    }
+
+    #[test]
+    fn field_writer_works() {
+        use log::Log;
+
+        let counter = |count: &std::sync::Arc<AtomicU64>| {
+            let count = count.clone();
+            fn_writer(move |_| {
+                count.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            })
+        };
+        let audit = std::sync::Arc::new(AtomicU64::new(0));
+        let acme = std::sync::Arc::new(AtomicU64::new(0));
+        let api = std::sync::Arc::new(AtomicU64::new(0));
+        let default = std::sync::Arc::new(AtomicU64::new(0));
+        let logger = Builder::with_level("info")
+            .with_default_writer(counter(&default))
+            .with_target_writer("api", counter(&api))
+            .with_field_writer("audit", true, counter(&audit))
+            .with_field_writer("tenant", "acme", counter(&acme))
+            .build();
+
+        let cases = [
+            ("api", ("audit", Value::from(true))),
+            ("db", ("audit", Value::from(true))),
+            ("db", ("audit", Value::from(false))),
+            ("api", ("tenant", Value::from("acme"))),
+            ("api", ("tenant", Value::from("other"))),
+        ];
+        for (target, kv) in cases {
+            let kvs = [kv];
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("hello"))
+                    .level(Level::Info)
+                    .target(target)
+                    .key_values(&kvs)
+                    .build(),
+            );
+        }
+        assert_eq!(2, audit.load(Ordering::Relaxed));
+        assert_eq!(1, acme.load(Ordering::Relaxed));
+        assert_eq!(1, api.load(Ordering::Relaxed));
+        assert_eq!(1, default.load(Ordering::Relaxed));
+        assert!(
+            format!("{:?}", logger).contains(r#"field_writers: ["audit=true", "tenant=\"acme\""]"#)
+        );
+
+        // the field writers are tested before the routes added at runtime.
+        let route = std::sync::Arc::new(AtomicU64::new(0));
+        logger.routes().add("api", counter(&route));
+        let kvs = [("audit", Value::from(true))];
+        logger.log(
+            &Record::builder()
+                .args(format_args!("hello"))
+                .level(Level::Info)
+                .target("api")
+                .key_values(&kvs)
+                .build(),
+        );
+        logger.log(
+            &Record::builder()
+                .args(format_args!("hello"))
+                .level(Level::Info)
+                .target("api")
+                .build(),
+        );
+        assert_eq!(3, audit.load(Ordering::Relaxed));
+        assert_eq!(1, route.load(Ordering::Relaxed));
+        assert_eq!(1, api.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn field_value_eq_works() {
+        let ip = std::net::Ipv4Addr::LOCALHOST;
+        let cases = [
+            (Value::from("acme"), serde_json::json!("acme"), true),
+            (Value::from("other"), serde_json::json!("acme"), false),
+            (
+                Value::from_display(&ip),
+                serde_json::json!("127.0.0.1"),
+                true,
+            ),
+            (Value::from(true), serde_json::json!(true), true),
+            (Value::from(true), serde_json::json!("true"), false),
+            (Value::from(42u8), serde_json::json!(42), true),
+            (Value::from(-42i64), serde_json::json!(-42), true),
+            (Value::from(42i64), serde_json::json!(-42), false),
+            (Value::from(1.5f64), serde_json::json!(1.5), true),
+            (Value::from(1i64), serde_json::json!(1.0), false),
+            (Value::from("1"), serde_json::json!(1), false),
+            (Value::from("acme"), serde_json::json!(null), false),
+        ];
+        for (v, expected, eq) in cases {
+            assert_eq!(eq, field_value_eq(&v, &expected), "{} == {}", v, expected);
+        }
+    }
+
+    #[test]
+    fn schema_works() {
+        use log::Log;
+        use schema::FieldType;
+
+        let lines = std::sync::Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let quarantined = std::sync::Arc::new(AtomicU64::new(0));
+        let w = {
+            let lines = lines.clone();
+            fn_writer(move |value| {
+                lines.lock().push(serde_json::to_string(value)?);
+                Ok(())
+            })
+        };
+        let quarantine = {
+            let quarantined = quarantined.clone();
+            fn_writer(move |value| {
+                assert!(value.contains_key(&Key::from("schema_error")));
+                quarantined.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            })
+        };
+        let schema = Schema::new().with_field("status", FieldType::Number);
+        let logger = Builder::with_level("info")
+            .with_default_writer(w)
+            .with_schema("api*", schema.clone())
+            .build();
+
+        let log = |logger: &Logger, target: &str, status: Value| {
+            let kvs = [("status", status)];
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("hello"))
+                    .level(Level::Info)
+                    .target(target)
+                    .key_values(&kvs)
+                    .build(),
+            );
+        };
+        log(&logger, "api", Value::from(200));
+        log(&logger, "api::v1", Value::from("200"));
+        log(&logger, "db", Value::from("200"));
+        {
+            let lines = lines.lock();
+            assert_eq!(3, lines.len());
+            assert!(!lines[0].contains("schema_error"));
+            assert!(lines[1].contains(r#""schema_error":"field `status` is not a Number""#));
+            assert!(!lines[2].contains("schema_error"));
+        }
+
+        let logger = Builder::with_level("info")
+            .with_default_writer(fn_writer(|_| Ok(())))
+            .with_schema("api*", schema)
+            .with_schema_quarantine(quarantine)
+            .build();
+        log(&logger, "api", Value::from(200));
+        log(&logger, "api", Value::from("200"));
+        assert_eq!(1, quarantined.load(Ordering::Relaxed));
+    }
+
     #[test]
     fn log_failure_works() {
         let cases: Vec<&str> = vec!["", "\"", "hello", "\"hello >", "hello\n", "hello\r"];
@@ -0,0 +1,421 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Lock-free Writer Implementation
+//!
+//! A [`Writer`] implementation that logs structured values in JSON format
+//! to a file, stderr, stdout, or any other destination, through a lock-free ring buffer.
+//! Unlike the [`json`](crate::json) writer, which serializes all threads through a mutex,
+//! the logging threads only push the encoded records into a bounded lock-free MPSC ring ([`crossbeam_queue::ArrayQueue`]),
+//! and a dedicated flusher thread writes them into the underlying `std::io::Write` instance.
+//! It is intended for workloads where many threads log concurrently and the mutex contention shows up in profiles.
+//!
+//! To create a `Box<dyn Writer>` use the [`new_writer`] function or the [`LockFreeBuilder`].
+//! The returned [`FlusherGuard`] must be kept alive, dropping it flushes the remaining records and stops the flusher.
+//!
+//! This module requires the `crossbeam` feature.
+//!
+//! Example:
+//! ```rust,ignore
+//! use structured_logger::{lock_free::new_writer, Builder};
+//!
+//! fn main() {
+//!     let (writer, _guard) = new_writer(std::io::stdout());
+//!     Builder::with_level("info")
+//!         .with_default_writer(writer)
+//!         .init();
+//!
+//!     log::info!("hello world");
+//! }
+//! ```
+//!
+//! [`crossbeam_queue::ArrayQueue`]: https://docs.rs/crossbeam-queue/latest/crossbeam_queue/struct.ArrayQueue.html
+//!
+
+use crossbeam_queue::ArrayQueue;
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    sync::mpsc::{self, Receiver},
+    sync::Arc,
+    thread::{self, Thread},
+    time::{Duration, Instant},
+};
+
+use crate::json::encode_owned;
+use crate::metrics::{QueueCounters, QueueMetrics};
+use crate::pool::BufferPool;
+use crate::{log_failure, Fields, Key, Value, Writer};
+
+/// The default capacity of the ring buffer, in records.
+pub const DEFAULT_RING_CAPACITY: usize = 128_000;
+
+/// The maximum number of records coalesced into a single write.
+const BATCH_SIZE: usize = 128;
+
+/// How long the idle flusher thread sleeps before checking the ring again.
+const IDLE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// How long [`Writer::flush`] waits for the flusher thread to flush the queued records.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long the [`FlusherGuard`] waits for the flusher thread to flush the remaining records.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A builder to configure and create a lock-free writer.
+pub struct LockFreeBuilder {
+    capacity: usize,
+    lossy: bool,
+    thread_name: String,
+}
+
+impl Default for LockFreeBuilder {
+    fn default() -> Self {
+        LockFreeBuilder {
+            capacity: DEFAULT_RING_CAPACITY,
+            lossy: true,
+            thread_name: "structured-logger-flusher".to_string(),
+        }
+    }
+}
+
+impl LockFreeBuilder {
+    /// Sets the maximum number of records that can be buffered in the ring.
+    pub fn with_capacity(self, capacity: usize) -> Self {
+        LockFreeBuilder {
+            capacity: capacity.max(1),
+            ..self
+        }
+    }
+
+    /// Sets whether the writer drops records when the ring is full (`true`, the default),
+    /// or spins the logging thread until the flusher catches up (`false`).
+    pub fn with_lossy(self, lossy: bool) -> Self {
+        LockFreeBuilder { lossy, ..self }
+    }
+
+    /// Sets the name of the flusher thread.
+    pub fn with_thread_name(self, name: &str) -> Self {
+        LockFreeBuilder {
+            thread_name: name.to_string(),
+            ..self
+        }
+    }
+
+    /// Spawns the flusher thread for the given std::io::Write instance,
+    /// returns a `Box<dyn Writer>` instance and its [`FlusherGuard`].
+    pub fn finish<W: Write + Send + 'static>(self, w: W) -> (Box<dyn Writer>, FlusherGuard) {
+        let (writer, guard) = LockFreeWriter::new(self, w);
+        (Box::new(writer), guard)
+    }
+}
+
+struct Shared {
+    ring: ArrayQueue<Vec<u8>>,
+    counters: Arc<QueueCounters>,
+    closed: AtomicBool,
+    // whether the flusher thread is parked, or about to park.
+    sleeping: AtomicBool,
+    flush_requested: AtomicU64,
+    flushed: AtomicU64,
+}
+
+/// A Writer implementation that writes logs in JSON format through a lock-free ring buffer.
+pub struct LockFreeWriter {
+    shared: Arc<Shared>,
+    flusher: Thread,
+    lossy: bool,
+}
+
+impl LockFreeWriter {
+    fn new<W: Write + Send + 'static>(cfg: LockFreeBuilder, w: W) -> (Self, FlusherGuard) {
+        let shared = Arc::new(Shared {
+            ring: ArrayQueue::new(cfg.capacity),
+            counters: Arc::new(QueueCounters::default()),
+            closed: AtomicBool::new(false),
+            sleeping: AtomicBool::new(false),
+            flush_requested: AtomicU64::new(0),
+            flushed: AtomicU64::new(0),
+        });
+        let (done_tx, done_rx) = mpsc::channel();
+        let flusher = Flusher {
+            w,
+            shared: shared.clone(),
+        };
+        let handle = thread::Builder::new()
+            .name(cfg.thread_name)
+            .spawn(move || {
+                flusher.run();
+                let _ = done_tx.send(());
+            })
+            .expect("failed to spawn the lock-free writer thread");
+
+        let guard = FlusherGuard {
+            shared: shared.clone(),
+            flusher: handle.thread().clone(),
+            done: done_rx,
+            handle: Some(handle),
+        };
+        (
+            LockFreeWriter {
+                shared,
+                flusher: guard.flusher.clone(),
+                lossy: cfg.lossy,
+            },
+            guard,
+        )
+    }
+
+    fn push(&self, mut buf: Vec<u8>) -> Result<(), io::Error> {
+        let shared = &self.shared;
+        loop {
+            if shared.closed.load(Ordering::SeqCst) {
+                BufferPool::global().put(buf);
+                return Err(flusher_stopped());
+            }
+            // count the record before pushing, the flusher may pop it immediately.
+            shared.counters.on_push();
+            match shared.ring.push(buf) {
+                Ok(()) => break,
+                Err(rejected) => {
+                    shared.counters.on_pop(1);
+                    if self.lossy {
+                        shared.counters.on_drop();
+                        BufferPool::global().put(rejected);
+                        return Ok(());
+                    }
+                    buf = rejected;
+                    self.flusher.unpark();
+                    thread::yield_now();
+                }
+            }
+        }
+
+        if shared.sleeping.load(Ordering::SeqCst) {
+            self.flusher.unpark();
+        }
+        Ok(())
+    }
+}
+
+/// Implements Writer trait for LockFreeWriter.
+impl Writer for LockFreeWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        self.push(encode_owned(value)?)
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        self.push(encode_owned(fields)?)
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        let shared = &self.shared;
+        let seq = shared.flush_requested.fetch_add(1, Ordering::SeqCst) + 1;
+        self.flusher.unpark();
+
+        let deadline = Instant::now() + FLUSH_TIMEOUT;
+        while shared.flushed.load(Ordering::SeqCst) < seq {
+            if shared.closed.load(Ordering::SeqCst) {
+                return Err(flusher_stopped());
+            }
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "lock-free writer flush timed out",
+                ));
+            }
+            thread::sleep(Duration::from_micros(100));
+        }
+        Ok(())
+    }
+
+    fn shutdown(&self) -> Result<(), io::Error> {
+        self.flush()?;
+        self.shared.closed.store(true, Ordering::SeqCst);
+        self.flusher.unpark();
+        Ok(())
+    }
+}
+
+/// A guard that flushes the remaining records and stops the flusher thread when dropped.
+///
+/// It should be held until the end of `main`, for example with `let _guard = ...;`.
+/// Note that `let _ = ...;` drops the guard immediately.
+#[must_use = "dropping the guard stops the lock-free writer"]
+pub struct FlusherGuard {
+    shared: Arc<Shared>,
+    flusher: Thread,
+    done: Receiver<()>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl FlusherGuard {
+    /// Returns a [`QueueMetrics`] handle to read the ring depth and drop counters of the writer.
+    pub fn metrics(&self) -> QueueMetrics {
+        QueueMetrics(self.shared.counters.clone())
+    }
+}
+
+impl Drop for FlusherGuard {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::SeqCst);
+        self.flusher.unpark();
+
+        match self.done.recv_timeout(SHUTDOWN_TIMEOUT) {
+            Ok(()) | Err(mpsc::RecvTimeoutError::Disconnected) => {
+                if let Some(handle) = self.handle.take() {
+                    let _ = handle.join();
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                log_failure("FlusherGuard failed to flush logs: timed out waiting for the flusher");
+            }
+        }
+    }
+}
+
+struct Flusher<W: Write> {
+    w: W,
+    shared: Arc<Shared>,
+}
+
+impl<W: Write> Flusher<W> {
+    fn run(mut self) {
+        let mut batch = BufferPool::global().get();
+        loop {
+            let requested = self.shared.flush_requested.load(Ordering::SeqCst);
+            let closed = self.shared.closed.load(Ordering::SeqCst);
+            let written = self.drain(&mut batch);
+            if written > 0 || requested > self.shared.flushed.load(Ordering::SeqCst) {
+                if let Err(err) = self.w.flush() {
+                    log_failure(format!("LockFreeWriter failed to flush logs: {}", err).as_str());
+                }
+                self.shared.flushed.store(requested, Ordering::SeqCst);
+            }
+            // the records pushed before the guard was dropped are drained above.
+            if closed {
+                BufferPool::global().put(batch);
+                return;
+            }
+
+            if written == 0 {
+                self.shared.sleeping.store(true, Ordering::SeqCst);
+                // check again after announcing the sleep, so a concurrent push can't be missed.
+                if self.shared.ring.is_empty()
+                    && !self.shared.closed.load(Ordering::SeqCst)
+                    && self.shared.flush_requested.load(Ordering::SeqCst) == requested
+                {
+                    thread::park_timeout(IDLE_TIMEOUT);
+                }
+                self.shared.sleeping.store(false, Ordering::SeqCst);
+            }
+        }
+    }
+
+    // Writes the queued records in batches, returns the number of records written.
+    fn drain(&mut self, batch: &mut Vec<u8>) -> usize {
+        let pool = BufferPool::global();
+        let mut written = 0;
+        loop {
+            let mut n = 0;
+            while n < BATCH_SIZE {
+                match self.shared.ring.pop() {
+                    Some(record) => {
+                        batch.extend_from_slice(&record);
+                        pool.put(record);
+                        n += 1;
+                    }
+                    None => break,
+                }
+            }
+            if n == 0 {
+                return written;
+            }
+
+            self.shared.counters.on_pop(n);
+            if let Err(err) = self.w.write_all(batch) {
+                // should never happen, but if it does, we log it.
+                log_failure(format!("LockFreeWriter failed to write log: {}", err).as_str());
+            }
+            batch.clear();
+            written += n;
+        }
+    }
+}
+
+fn flusher_stopped() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        "lock-free writer flusher has stopped",
+    )
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the LockFreeWriter for a given std::io::Write instance,
+/// using the default configuration.
+pub fn new_writer<W: Write + Send + 'static>(w: W) -> (Box<dyn Writer>, FlusherGuard) {
+    LockFreeBuilder::default().finish(w)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn lock_free_works() {
+        let buf = SharedBuf::default();
+        let cfg = LockFreeBuilder::default()
+            .with_capacity(16)
+            .with_lossy(false);
+        let (writer, guard) = LockFreeWriter::new(cfg, buf.clone());
+        let writer = Arc::new(writer);
+
+        let threads: Vec<_> = (0..8_u64)
+            .map(|t| {
+                let writer = writer.clone();
+                thread::spawn(move || {
+                    for i in 0..100_u64 {
+                        let mut value = BTreeMap::new();
+                        value.insert(Key::from("thread"), Value::from(t));
+                        value.insert(Key::from("index"), Value::from(i));
+                        writer.write_log(&value).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        writer.flush().unwrap();
+        assert_eq!(
+            800,
+            String::from_utf8(buf.0.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .count()
+        );
+
+        let metrics = guard.metrics();
+        drop(guard);
+        assert_eq!(0, metrics.queue_len());
+        assert_eq!(0, metrics.dropped());
+
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("index"), Value::from(0_u64));
+        assert!(writer.write_log(&value).is_err());
+    }
+}
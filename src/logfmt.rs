@@ -0,0 +1,146 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Logfmt Writer Implementation
+//!
+//! A [`Writer`] implementation that logs the records in the [logfmt](https://brandur.org/logfmt) format,
+//! one line of `key=value` pairs sorted by key per record, as read by Heroku, Loki or Grafana:
+//! `level=INFO message="hello world" method=GET status=200 target=api timestamp=1679745592127`.
+//!
+//! The values are written as in JSON, with the float, integer and byte-string formats of the logger,
+//! and quoted with JSON escapes if they are empty or contain whitespace, `=`, `"` or control characters.
+//! The whitespace, `=` and `"` in the keys are replaced by `_`.
+//!
+//! To create a `Box<dyn Writer>` use the [`new_writer`] function.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{logfmt, Builder};
+//!
+//! fn main() {
+//!     Builder::with_level("info")
+//!         .with_default_writer(logfmt::new_writer(std::io::stdout()))
+//!         .init();
+//!
+//!     // level=INFO message="hello world" method=GET target=rust_out timestamp=1679745592127
+//!     log::info!(method = "GET"; "hello world");
+//! }
+//! ```
+//!
+
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{self, Write},
+};
+
+use crate::fields::FieldMap;
+use crate::metrics::Health;
+use crate::{Fields, JsonStr, Key, Value, Writer};
+
+/// A Writer implementation that writes logs in the logfmt format.
+pub struct LogfmtWriter<W: Write + Sync + Send + 'static> {
+    w: Mutex<W>,
+    health: Health,
+}
+
+impl<W: Write + Sync + Send + 'static> LogfmtWriter<W> {
+    /// Creates a new LogfmtWriter instance.
+    pub fn new(w: W) -> Self {
+        LogfmtWriter {
+            w: Mutex::new(w),
+            health: Health::default(),
+        }
+    }
+
+    fn write_record(&self, record: &impl Serialize) -> Result<(), io::Error> {
+        let mut line = String::with_capacity(128);
+        format(&mut line, record)?;
+        line.push('\n');
+        self.health.track(self.w.lock().write_all(line.as_bytes()))
+    }
+}
+
+// Formats a record as `key=value` pairs, sorted by key as the record is collected into a JSON object.
+fn format(line: &mut String, record: &impl Serialize) -> Result<(), io::Error> {
+    let object = match serde_json::to_value(record)? {
+        serde_json::Value::Object(object) => object,
+        _ => return Ok(()),
+    };
+    for (i, (key, value)) in object.iter().enumerate() {
+        if i > 0 {
+            line.push(' ');
+        }
+        line.extend(key.chars().map(|c| match c {
+            c if c.is_whitespace() || c == '=' || c == '"' => '_',
+            c => c,
+        }));
+        line.push('=');
+        match value {
+            serde_json::Value::String(s) => write_str(line, s),
+            value => write_str(line, &value.to_string()),
+        }
+    }
+    Ok(())
+}
+
+fn write_str(line: &mut String, s: &str) {
+    if s.is_empty()
+        || s.contains(|c: char| c.is_whitespace() || c.is_control() || c == '=' || c == '"')
+    {
+        let _ = write!(line, "\"{}\"", JsonStr(s));
+    } else {
+        line.push_str(s);
+    }
+}
+
+/// Implements Writer trait for LogfmtWriter.
+impl<W: Write + Sync + Send + 'static> Writer for LogfmtWriter<W> {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        self.write_record(&FieldMap(value))
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        self.write_record(fields)
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        self.health.track(self.w.lock().flush())
+    }
+
+    fn healthy(&self) -> bool {
+        self.health.healthy()
+    }
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the LogfmtWriter for a given std::io::Write instance.
+pub fn new_writer<W: Write + Sync + Send + 'static>(w: W) -> Box<dyn Writer> {
+    Box::new(LogfmtWriter::new(w))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logfmt_writer_works() {
+        let map: BTreeMap<&str, u32> = vec![("a", 1)].into_iter().collect();
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("level"), Value::from("INFO"));
+        value.insert(Key::from("message"), Value::from("hello \"world\"\n"));
+        value.insert(Key::from("method"), Value::from("GET"));
+        value.insert(Key::from("empty"), Value::from(""));
+        value.insert(Key::from("status"), Value::from(200));
+        value.insert(Key::from("user id"), Value::from_serde(&map));
+        value.insert(Key::from("timestamp"), Value::from(1679745592127_u64));
+
+        let mut line = String::new();
+        format(&mut line, &FieldMap(&value)).unwrap();
+        assert_eq!(
+            r#"empty="" level=INFO message="hello \"world\"\n" method=GET status=200 timestamp=1679745592127 user_id="{\"a\":1}""#,
+            line
+        );
+    }
+}
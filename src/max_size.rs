@@ -0,0 +1,311 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Maximum Record Size Writer
+//!
+//! A [`Writer`] wrapper that caps the size of the records, in bytes of JSON, for the sinks that reject
+//! the big payloads, such as a UDP datagram, a Kafka message or a CloudWatch event.
+//! The records under the limit are written unchanged. An oversized record is handled by its [`Oversize`] policy:
+//! * [`Oversize::Truncate`], the default, truncates the largest values until the record fits,
+//!   and adds a `"truncated":true` field. A truncated value is written as a string,
+//!   the level, `target`, `timestamp` and `time` fields are never truncated;
+//! * [`Oversize::Summary`] replaces the record by a summary with its level, `target`, timestamp,
+//!   the beginning of its `message`, its size as `record_size`, and `"truncated":true`.
+//!
+//! A record that can't fit by truncation is replaced by the summary.
+//! The wrapped writer receives the records as a map, see [`Writer::write_log`].
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{json, max_size, Builder};
+//!
+//! fn main() {
+//!     let writer = max_size::new_writer(256, max_size::Oversize::Truncate, json::new_writer(std::io::stdout()));
+//!     Builder::with_level("info")
+//!         .with_default_writer(writer)
+//!         .init();
+//!
+//!     // {"body":"xxxx...","level":"INFO","message":"request","target":"rust_out","timestamp":1679745592127,"truncated":true}
+//!     log::info!(body = "x".repeat(1000).as_str(); "request");
+//! }
+//! ```
+//!
+
+use std::{collections::BTreeMap, io};
+
+use crate::fields::FieldMap;
+use crate::route::LEVEL_KEYS;
+use crate::{Fields, Key, Value, Writer};
+
+// The fields that are never truncated, with the level keys, and kept by the summary.
+const KEPT_KEYS: [&str; 3] = ["target", "timestamp", "time"];
+
+// The maximum size of the beginning of the message written in a summary, in bytes of JSON.
+const SUMMARY_MESSAGE_SIZE: usize = 256;
+
+/// The policy of a [`MaxSizeWriter`] for the oversized records.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Oversize {
+    /// Truncates the largest values of the record until it fits, see the [module level documentation](self).
+    #[default]
+    Truncate,
+    /// Replaces the record by a summary, see the [module level documentation](self).
+    Summary,
+}
+
+/// A Writer implementation that caps the size of the records written to a wrapped writer.
+pub struct MaxSizeWriter {
+    max_bytes: usize,
+    oversize: Oversize,
+    inner: Box<dyn Writer>,
+}
+
+impl MaxSizeWriter {
+    /// Creates a new MaxSizeWriter instance that writes the records of at most `max_bytes` bytes of JSON to `w`,
+    /// the oversized records are handled by `oversize`.
+    pub fn new(max_bytes: usize, oversize: Oversize, w: Box<dyn Writer>) -> Self {
+        MaxSizeWriter {
+            max_bytes,
+            oversize,
+            inner: w,
+        }
+    }
+
+    // Returns the truncated values that make the record fit, or `None` if it can't fit.
+    fn truncate(
+        &self,
+        value: &BTreeMap<Key, Value>,
+        json: &serde_json::Map<String, serde_json::Value>,
+        size: usize,
+    ) -> Option<BTreeMap<String, String>> {
+        let marker = r#","truncated":true"#.len();
+        let mut size = size + marker;
+        let mut truncated: BTreeMap<String, String> = BTreeMap::new();
+        while size > self.max_bytes {
+            // the largest value that can be truncated, with its size in bytes of JSON.
+            let (key, len) = json
+                .iter()
+                .filter(|(key, _)| {
+                    value.contains_key(&Key::from(key.as_str()))
+                        && !LEVEL_KEYS.contains(&key.as_str())
+                        && !KEPT_KEYS.contains(&key.as_str())
+                })
+                .map(|(key, v)| {
+                    let len = match truncated.get(key) {
+                        Some(s) => json_str_len(s),
+                        None => v.to_string().len(),
+                    };
+                    (key, len)
+                })
+                .max_by_key(|(_, len)| *len)?;
+            if len <= 2 {
+                return None;
+            }
+
+            let target = len.saturating_sub(size - self.max_bytes).max(2);
+            let s = match truncated.remove(key) {
+                Some(s) => s,
+                None => match json[key] {
+                    serde_json::Value::String(ref s) => s.clone(),
+                    ref v => v.to_string(),
+                },
+            };
+            let s = truncate_json_str(&s, target);
+            size = size - len + json_str_len(&s);
+            truncated.insert(key.clone(), s);
+        }
+        Some(truncated)
+    }
+
+    fn write_summary(&self, value: &BTreeMap<Key, Value>, size: usize) -> Result<(), io::Error> {
+        let mut summary: BTreeMap<Key, Value> = value
+            .iter()
+            .filter(|(key, _)| {
+                LEVEL_KEYS.contains(&key.as_str()) || KEPT_KEYS.contains(&key.as_str())
+            })
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        let message = value
+            .get(&Key::from("message"))
+            .map(|message| truncate_json_str(&message.to_string(), SUMMARY_MESSAGE_SIZE));
+        if let Some(ref message) = message {
+            summary.insert(Key::from("message"), Value::from(message.as_str()));
+        }
+        summary.insert(Key::from("record_size"), Value::from(size));
+        summary.insert(Key::from("truncated"), Value::from(true));
+        self.inner.write_log(&summary)
+    }
+}
+
+/// Implements Writer trait for MaxSizeWriter.
+impl Writer for MaxSizeWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        let json = serde_json::to_string(&FieldMap(value))?;
+        let size = json.len();
+        if size <= self.max_bytes {
+            return self.inner.write_log(value);
+        }
+
+        if self.oversize == Oversize::Truncate {
+            let json: serde_json::Map<String, serde_json::Value> = serde_json::from_str(&json)?;
+            if let Some(truncated) = self.truncate(value, &json, size) {
+                let mut value = value.clone();
+                for (key, s) in truncated.iter() {
+                    value.insert(Key::from(key.as_str()), Value::from(s.as_str()));
+                }
+                value.insert(Key::from("truncated"), Value::from(true));
+                return self.inner.write_log(&value);
+            }
+        }
+        self.write_summary(value, size)
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        self.write_log(&fields.to_map())
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        self.inner.flush()
+    }
+
+    fn shutdown(&self) -> Result<(), io::Error> {
+        self.inner.shutdown()
+    }
+
+    fn healthy(&self) -> bool {
+        self.inner.healthy()
+    }
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the MaxSizeWriter
+/// that writes the records of at most `max_bytes` bytes of JSON to `w`.
+pub fn new_writer(max_bytes: usize, oversize: Oversize, w: Box<dyn Writer>) -> Box<dyn Writer> {
+    Box::new(MaxSizeWriter::new(max_bytes, oversize, w))
+}
+
+// Returns the size of `c` escaped in a JSON string.
+fn json_char_len(c: char) -> usize {
+    match c {
+        '"' | '\\' | '\n' | '\r' | '\t' | '\u{08}' | '\u{0c}' => 2,
+        c if (c as u32) < 0x20 => 6,
+        c => c.len_utf8(),
+    }
+}
+
+// Returns the size of `s` as a JSON string, with the quotes.
+fn json_str_len(s: &str) -> usize {
+    s.chars().map(json_char_len).sum::<usize>() + 2
+}
+
+// Returns the longest prefix of `s` whose size as a JSON string is at most `max` bytes, with the quotes.
+fn truncate_json_str(s: &str, max: usize) -> String {
+    let mut len = 2;
+    let mut end = 0;
+    for (i, c) in s.char_indices() {
+        len += json_char_len(c);
+        if len > max {
+            break;
+        }
+        end = i + c.len_utf8();
+    }
+    s[..end].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fn_writer;
+    use std::sync::{Arc, Mutex};
+
+    // Writes a record with a given policy, and returns the written JSON line.
+    fn write(max_bytes: usize, oversize: Oversize, value: &BTreeMap<Key, Value>) -> String {
+        let line = Arc::new(Mutex::new(String::new()));
+        let w = {
+            let line = line.clone();
+            new_writer(
+                max_bytes,
+                oversize,
+                fn_writer(move |value| {
+                    *line.lock().unwrap() = serde_json::to_string(&FieldMap(value)).unwrap();
+                    Ok(())
+                }),
+            )
+        };
+        w.write_log(value).unwrap();
+        let line = line.lock().unwrap().clone();
+        line
+    }
+
+    fn request(body: &str) -> BTreeMap<Key<'_>, Value<'_>> {
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("level"), Value::from("INFO"));
+        value.insert(Key::from("message"), Value::from("request"));
+        value.insert(Key::from("target"), Value::from("api"));
+        value.insert(Key::from("timestamp"), Value::from(1679745592127u64));
+        value.insert(Key::from("body"), Value::from(body));
+        value.insert(Key::from("user"), Value::from("u1"));
+        value
+    }
+
+    #[test]
+    fn max_size_writer_works() {
+        let body = "a\"bc".repeat(50);
+        let line = write(1024, Oversize::Truncate, &request(&body));
+        assert!(line.contains(&serde_json::to_string(&body).unwrap()));
+        assert!(!line.contains("truncated"));
+    }
+
+    #[test]
+    fn max_size_truncate_works() {
+        let line = write(128, Oversize::Truncate, &request(&"a\"bc".repeat(50)));
+        assert_eq!(
+            r#"{"body":"a\"bca\"bca","level":"INFO","message":"request","target":"api","timestamp":1679745592127,"truncated":true,"user":"u1"}"#,
+            line
+        );
+        assert!(line.len() <= 128);
+    }
+
+    #[test]
+    fn max_size_summary_works() {
+        let summary = r#"{"level":"INFO","message":"request","record_size":349,"target":"api","timestamp":1679745592127,"truncated":true}"#;
+        let body = "a\"bc".repeat(50);
+        let record = request(&body);
+        assert_eq!(summary, write(128, Oversize::Summary, &record));
+        // the record can't fit by truncation.
+        assert_eq!(summary, write(64, Oversize::Truncate, &record));
+    }
+
+    #[test]
+    fn max_size_nested_works() {
+        // an oversized nested value is truncated as a JSON string.
+        let items: Vec<u32> = (0..100).collect();
+        let nested = serde_json::json!({"items": items, "user": {"id": "u1"}});
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("level"), Value::from("INFO"));
+        value.insert(Key::from("message"), Value::from("batch"));
+        value.insert(Key::from("batch"), Value::from_serde(&nested));
+        let line = write(96, Oversize::Truncate, &value);
+        assert!(line.len() <= 96);
+        let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+        let batch = json["batch"].as_str().unwrap();
+        assert!(batch.starts_with(r#"{"items":[0,1,2,"#));
+        assert_eq!(Some(true), json["truncated"].as_bool());
+        assert_eq!(Some("batch"), json["message"].as_str());
+    }
+
+    #[test]
+    fn max_size_kept_keys_works() {
+        // the level, target and timestamp are never truncated, even if they are the largest values.
+        let target = "t".repeat(100);
+        let message = "m".repeat(50);
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("level"), Value::from("INFO"));
+        value.insert(Key::from("target"), Value::from(target.as_str()));
+        value.insert(Key::from("message"), Value::from(message.as_str()));
+        let line = write(180, Oversize::Truncate, &value);
+        let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(Some(target.as_str()), json["target"].as_str());
+        assert!(json["message"].as_str().unwrap().len() < 50);
+        assert!(line.len() <= 180);
+    }
+}
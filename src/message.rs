@@ -0,0 +1,128 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Message Key Writer
+//!
+//! A [`Writer`] wrapper that writes the `message` field of the records with another key, such as `msg`
+//! for `pino` on stdout, while the other writers, such as an ECS file writer, keep the `message` key.
+//! The key can vary by target by wrapping the target writers, see
+//! [`Builder::with_target_writer`](crate::Builder::with_target_writer).
+//!
+//! The records without a `message` field are written unchanged.
+//! The wrapped writer receives the records as a map, see [`Writer::write_log`].
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{json, message, Builder};
+//!
+//! fn main() {
+//!     Builder::with_level("info")
+//!         .with_target_writer("api*", message::new_writer("msg", json::new_writer(std::io::stdout())))
+//!         .init();
+//!
+//!     // {"level":"INFO","msg":"hello world","target":"api","timestamp":1679745592127}
+//!     log::info!(target: "api", "hello world");
+//! }
+//! ```
+//!
+
+use std::{collections::BTreeMap, io};
+
+use crate::{Fields, Key, Value, Writer};
+
+/// A Writer implementation that writes the message of the records with a given key to a wrapped writer.
+pub struct MessageWriter {
+    key: Box<str>,
+    inner: Box<dyn Writer>,
+}
+
+impl MessageWriter {
+    /// Creates a new MessageWriter instance that writes the message of the records with the given key to `w`.
+    pub fn new(key: &str, w: Box<dyn Writer>) -> Self {
+        MessageWriter {
+            key: key.into(),
+            inner: w,
+        }
+    }
+}
+
+/// Implements Writer trait for MessageWriter.
+impl Writer for MessageWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        let message_key = Key::from("message");
+        if self.key.as_ref() == "message" || !value.contains_key(&message_key) {
+            return self.inner.write_log(value);
+        }
+
+        let mut value = value.clone();
+        if let Some(message) = value.remove(&message_key) {
+            value.insert(Key::from(self.key.as_ref()), message);
+        }
+        self.inner.write_log(&value)
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        if self.key.as_ref() == "message" {
+            return self.inner.write_fields(fields);
+        }
+        self.write_log(&fields.to_map())
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        self.inner.flush()
+    }
+
+    fn shutdown(&self) -> Result<(), io::Error> {
+        self.inner.shutdown()
+    }
+
+    fn healthy(&self) -> bool {
+        self.inner.healthy()
+    }
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the MessageWriter
+/// that writes the message of the records with the given key to `w`.
+pub fn new_writer(key: &str, w: Box<dyn Writer>) -> Box<dyn Writer> {
+    Box::new(MessageWriter::new(key, w))
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+    use crate::fn_writer;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn message_writer_works() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let with_key = |key| {
+            let lines = lines.clone();
+            new_writer(
+                key,
+                fn_writer(move |value| {
+                    let json = serde_json::to_string(&crate::fields::FieldMap(value)).unwrap();
+                    lines.lock().unwrap().push(json);
+                    Ok(())
+                }),
+            )
+        };
+
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("level"), Value::from("INFO"));
+        value.insert(Key::from("message"), Value::from("hello"));
+        with_key("msg").write_log(&value).unwrap();
+        with_key("message").write_log(&value).unwrap();
+        value.remove(&Key::from("message"));
+        with_key("msg").write_log(&value).unwrap();
+
+        assert_eq!(
+            vec![
+                r#"{"level":"INFO","msg":"hello"}"#,
+                r#"{"level":"INFO","message":"hello"}"#,
+                r#"{"level":"INFO"}"#,
+            ],
+            *lines.lock().unwrap()
+        );
+    }
+}
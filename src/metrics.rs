@@ -0,0 +1,244 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Writer Metrics
+//!
+//! Queue depth and drop counters of the buffering writers,
+//! so you can alert before a slow destination silently drops logs.
+//!
+//! Get a [`QueueMetrics`] handle from [`AsyncJSONWriter::metrics`](crate::async_json::AsyncJSONWriter::metrics)
+//! or [`WorkerGuard::metrics`](crate::non_blocking::WorkerGuard::metrics) before registering the writer.
+//!
+
+use log::Level;
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+#[derive(Default)]
+pub(crate) struct QueueCounters {
+    len: AtomicUsize,
+    high_water_mark: AtomicUsize,
+    dropped: AtomicU64,
+    // the dropped records of each level, indexed by `Level as usize - 1`.
+    dropped_levels: [AtomicU64; 5],
+    write_timeouts: AtomicU64,
+    pub(crate) health: Health,
+}
+
+impl QueueCounters {
+    pub(crate) fn on_push(&self) {
+        let len = self.len.fetch_add(1, Ordering::Relaxed) + 1;
+        self.high_water_mark.fetch_max(len, Ordering::Relaxed);
+    }
+
+    pub(crate) fn on_pop(&self, n: usize) {
+        self.len.fetch_sub(n, Ordering::Relaxed);
+    }
+
+    pub(crate) fn on_drop(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[cfg_attr(not(any(feature = "tokio", feature = "futures")), allow(dead_code))]
+    pub(crate) fn on_drop_level(&self, level: Level) {
+        self.on_drop();
+        self.dropped_levels[level as usize - 1].fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    pub(crate) fn on_write_timeout(&self) {
+        self.write_timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// The health of a writer, whether its last write to the destination succeeded, see [`Writer::healthy`](crate::Writer::healthy).
+#[derive(Default)]
+pub(crate) struct Health {
+    failing: AtomicBool,
+}
+
+impl Health {
+    pub(crate) fn set(&self, healthy: bool) {
+        self.failing.store(!healthy, Ordering::Relaxed);
+    }
+
+    /// Sets the health from the result of a write, and returns it.
+    pub(crate) fn track<T, E>(&self, res: Result<T, E>) -> Result<T, E> {
+        self.set(res.is_ok());
+        res
+    }
+
+    pub(crate) fn healthy(&self) -> bool {
+        !self.failing.load(Ordering::Relaxed)
+    }
+}
+
+/// A cloneable handle to read the queue metrics of a writer.
+#[derive(Clone)]
+pub struct QueueMetrics(pub(crate) Arc<QueueCounters>);
+
+impl QueueMetrics {
+    /// Returns the number of records waiting in the queue.
+    pub fn queue_len(&self) -> usize {
+        self.0.len.load(Ordering::Relaxed)
+    }
+
+    /// Returns the maximum number of records that have been waiting in the queue at the same time.
+    pub fn high_water_mark(&self) -> usize {
+        self.0.high_water_mark.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of records dropped because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.0.dropped()
+    }
+
+    /// Returns the number of records of a given level dropped because the queue was full.
+    /// The records without a level are counted as INFO.
+    pub fn dropped_by_level(&self, level: Level) -> u64 {
+        self.0.dropped_levels[level as usize - 1].load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of writes to the underlying writer that timed out.
+    pub fn write_timeouts(&self) -> u64 {
+        self.0.write_timeouts.load(Ordering::Relaxed)
+    }
+}
+
+impl fmt::Debug for QueueMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QueueMetrics")
+            .field("queue_len", &self.queue_len())
+            .field("high_water_mark", &self.high_water_mark())
+            .field("dropped", &self.dropped())
+            .field("write_timeouts", &self.write_timeouts())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::non_blocking::NonBlockingBuilder;
+    use crate::{Key, Value};
+    use std::{collections::BTreeMap, io, io::Write, sync::Mutex, thread};
+
+    #[test]
+    fn queue_counters_works() {
+        let metrics = QueueMetrics(Arc::new(QueueCounters::default()));
+        let counters = &metrics.0;
+        counters.on_push();
+        counters.on_push();
+        counters.on_push();
+        counters.on_pop(2);
+        assert_eq!(1, metrics.queue_len());
+        assert_eq!(3, metrics.high_water_mark());
+
+        counters.on_drop();
+        counters.on_drop_level(Level::Warn);
+        counters.on_drop_level(Level::Warn);
+        counters.on_drop_level(Level::Trace);
+        assert_eq!(4, metrics.dropped());
+        assert_eq!(2, metrics.dropped_by_level(Level::Warn));
+        assert_eq!(1, metrics.dropped_by_level(Level::Trace));
+        assert_eq!(0, metrics.dropped_by_level(Level::Error));
+
+        counters.on_write_timeout();
+        assert_eq!(1, metrics.write_timeouts());
+        assert_eq!(
+            "QueueMetrics { queue_len: 1, high_water_mark: 3, dropped: 4, write_timeouts: 1 }",
+            format!("{:?}", metrics)
+        );
+    }
+
+    #[test]
+    fn health_works() {
+        let health = Health::default();
+        assert!(health.healthy());
+        assert!(health.track(Err::<(), _>("failed")).is_err());
+        assert!(!health.healthy());
+        assert!(health.track(Ok::<_, ()>(())).is_ok());
+        assert!(health.healthy());
+    }
+
+    // A writer that fails while `failing` is set, and blocks while `stalled` is locked.
+    struct FailingBuf {
+        failing: Arc<AtomicBool>,
+        stalled: Arc<Mutex<()>>,
+    }
+
+    impl Write for FailingBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let _stalled = self.stalled.lock().unwrap();
+            if self.failing.load(Ordering::Relaxed) {
+                return Err(io::Error::other("failed"));
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn writer_metrics_works() {
+        let failing = Arc::new(AtomicBool::new(false));
+        let stalled = Arc::new(Mutex::new(()));
+        let (writer, guard) = NonBlockingBuilder::default()
+            .with_buffered_lines_limit(1)
+            .finish(FailingBuf {
+                failing: failing.clone(),
+                stalled: stalled.clone(),
+            });
+        let metrics = guard.metrics();
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("message"), Value::from("hello"));
+
+        // the written records.
+        for _ in 0..10 {
+            writer.write_log(&value).unwrap();
+            writer.flush().unwrap();
+        }
+        assert_eq!(0, metrics.queue_len());
+        assert_eq!(1, metrics.high_water_mark());
+        assert_eq!(0, metrics.dropped());
+        assert!(writer.healthy());
+
+        // the failed records.
+        failing.store(true, Ordering::Relaxed);
+        writer.write_log(&value).unwrap();
+        writer.flush().unwrap();
+        assert!(!writer.healthy());
+        failing.store(false, Ordering::Relaxed);
+        writer.write_log(&value).unwrap();
+        writer.flush().unwrap();
+        assert!(writer.healthy());
+
+        // the dropped records, while the worker is stalled in a write.
+        let lock = stalled.lock().unwrap();
+        writer.write_log(&value).unwrap();
+        while metrics.queue_len() > 0 {
+            thread::yield_now();
+        }
+        // the first record fills the channel, the others are dropped.
+        for _ in 0..5 {
+            writer.write_log(&value).unwrap();
+        }
+        assert_eq!(1, metrics.queue_len());
+        assert_eq!(4, metrics.dropped());
+        drop(lock);
+        writer.flush().unwrap();
+        assert_eq!(0, metrics.queue_len());
+        assert_eq!(4, metrics.dropped());
+    }
+}
@@ -0,0 +1,248 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Writer Middleware
+//!
+//! The [`WriterExt`] trait adds decorators to any [`Writer`], so the cross-cutting behaviors,
+//! such as enrichment or filtering, compose around an existing writer without a bespoke writer type:
+//! * [`WriterExt::map_records`] changes the records before they are written, such as to rename or remove keys;
+//! * [`WriterExt::filtered`] writes only the records that match a predicate;
+//! * [`WriterExt::with_fields`] adds fields to the records of this writer only, with the `json` feature.
+//!
+//! The decorators are writers too, so they can be chained, and [`WriterExt::boxed`] returns a `Box<dyn Writer>`
+//! for the [`Builder`](crate::Builder), or for the wrapper writers, such as the [`timestamp`](crate::timestamp),
+//! [`message`](crate::message) or [`dedup`](crate::dedup) writers.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{json, Builder, WriterExt};
+//!
+//! fn main() {
+//!     let writer = json::new_writer(std::io::stdout())
+//!         .filtered(|record| record.keys().all(|key| key.as_str() != "password"))
+//!         .with_fields([("region", "eu-west-1")])
+//!         .boxed();
+//!     Builder::with_level("info")
+//!         .with_default_writer(writer)
+//!         .init();
+//!
+//!     // {"level":"INFO","message":"hello world","region":"eu-west-1","target":"rust_out","timestamp":1679745592127}
+//!     log::info!("hello world");
+//! }
+//! ```
+//!
+
+use std::{collections::BTreeMap, io};
+
+use crate::{Fields, Key, Value, Writer};
+
+/// An extension trait to decorate a [`Writer`], see the [module level documentation](self).
+pub trait WriterExt: Writer + Sized + 'static {
+    /// Returns a writer that calls `f` to change each record before it is written by this writer.
+    /// The values inserted by `f` must be static, such as `Value::from("redacted")`.
+    fn map_records<F>(self, f: F) -> MapRecords<Self, F>
+    where
+        F: Fn(&mut BTreeMap<Key, Value>) + Send + Sync + 'static,
+    {
+        MapRecords { inner: self, f }
+    }
+
+    /// Returns a writer that writes only the records for which `predicate` returns true.
+    fn filtered<P>(self, predicate: P) -> Filtered<Self, P>
+    where
+        P: Fn(&BTreeMap<Key, Value>) -> bool + Send + Sync + 'static,
+    {
+        Filtered {
+            inner: self,
+            predicate,
+        }
+    }
+
+    /// Returns a writer that adds the given fields to each record written by this writer.
+    /// The values are serialized once, and a record key-value with the same key takes precedence.
+    #[cfg(feature = "json")]
+    fn with_fields<I, K, V>(self, fields: I) -> WithFields<Self>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: serde::Serialize,
+    {
+        WithFields {
+            inner: self,
+            fields: fields
+                .into_iter()
+                .map(|(k, v)| {
+                    let v = serde_json::to_value(v).unwrap_or(serde_json::Value::Null);
+                    (k.into(), v)
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns this writer as a `Box<dyn Writer>`.
+    fn boxed(self) -> Box<dyn Writer> {
+        Box::new(self)
+    }
+}
+
+impl<W: Writer + 'static> WriterExt for W {}
+
+/// A Writer implementation that changes the records before they are written, see [`WriterExt::map_records`].
+pub struct MapRecords<W, F> {
+    inner: W,
+    f: F,
+}
+
+/// Implements Writer trait for MapRecords.
+impl<W, F> Writer for MapRecords<W, F>
+where
+    W: Writer,
+    F: Fn(&mut BTreeMap<Key, Value>) + Send + Sync,
+{
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        let mut value = value.clone();
+        (self.f)(&mut value);
+        self.inner.write_log(&value)
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        self.write_log(&fields.to_map())
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        self.inner.flush()
+    }
+
+    fn shutdown(&self) -> Result<(), io::Error> {
+        self.inner.shutdown()
+    }
+
+    fn healthy(&self) -> bool {
+        self.inner.healthy()
+    }
+}
+
+/// A Writer implementation that writes only the records that match a predicate, see [`WriterExt::filtered`].
+pub struct Filtered<W, P> {
+    inner: W,
+    predicate: P,
+}
+
+/// Implements Writer trait for Filtered.
+impl<W, P> Writer for Filtered<W, P>
+where
+    W: Writer,
+    P: Fn(&BTreeMap<Key, Value>) -> bool + Send + Sync,
+{
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        if (self.predicate)(value) {
+            return self.inner.write_log(value);
+        }
+        Ok(())
+    }
+
+    // the fields are passed on as is, so the wrapped writer keeps streaming them.
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        if (self.predicate)(&fields.to_map()) {
+            return self.inner.write_fields(fields);
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        self.inner.flush()
+    }
+
+    fn shutdown(&self) -> Result<(), io::Error> {
+        self.inner.shutdown()
+    }
+
+    fn healthy(&self) -> bool {
+        self.inner.healthy()
+    }
+}
+
+/// A Writer implementation that adds fields to the records, see [`WriterExt::with_fields`].
+#[cfg(feature = "json")]
+pub struct WithFields<W> {
+    inner: W,
+    fields: Vec<(String, serde_json::Value)>,
+}
+
+/// Implements Writer trait for WithFields.
+#[cfg(feature = "json")]
+impl<W: Writer> Writer for WithFields<W> {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        let mut value = value.clone();
+        for (k, v) in &self.fields {
+            value
+                .entry(Key::from(k.as_str()))
+                .or_insert_with(|| Value::from_serde(v));
+        }
+        self.inner.write_log(&value)
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        self.write_log(&fields.to_map())
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        self.inner.flush()
+    }
+
+    fn shutdown(&self) -> Result<(), io::Error> {
+        self.inner.shutdown()
+    }
+
+    fn healthy(&self) -> bool {
+        self.inner.healthy()
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+    use crate::fn_writer;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn middleware_works() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let w = {
+            let lines = lines.clone();
+            fn_writer(move |value| {
+                let json = serde_json::to_string(&crate::fields::FieldMap(value)).unwrap();
+                lines.lock().unwrap().push(json);
+                Ok(())
+            })
+        }
+        .map_records(|record| {
+            if record.remove(&Key::from("password")).is_some() {
+                record.insert(Key::from("redacted"), Value::from(true));
+            }
+        })
+        .filtered(|record| {
+            record.get(&Key::from("level")).map(|v| v.to_string()) != Some("DEBUG".to_string())
+        })
+        .with_fields([("region", "eu-west-1"), ("message", "ignored")])
+        .boxed();
+
+        for (level, password) in [("DEBUG", None), ("INFO", Some("secret")), ("WARN", None)] {
+            let mut value = BTreeMap::new();
+            value.insert(Key::from("level"), Value::from(level));
+            value.insert(Key::from("message"), Value::from("hello"));
+            if let Some(password) = password {
+                value.insert(Key::from("password"), Value::from(password));
+            }
+            w.write_log(&value).unwrap();
+        }
+
+        assert_eq!(
+            vec![
+                r#"{"level":"INFO","message":"hello","redacted":true,"region":"eu-west-1"}"#,
+                r#"{"level":"WARN","message":"hello","region":"eu-west-1"}"#,
+            ],
+            *lines.lock().unwrap()
+        );
+    }
+}
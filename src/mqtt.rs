@@ -0,0 +1,286 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # MQTT Writer Implementation
+//!
+//! A [`Writer`] implementation that publishes structured values in JSON format to an MQTT broker,
+//! one message per record, with the [`rumqttc`](https://docs.rs/rumqttc) client.
+//! The topic of a message is rendered from [`PublishOptions::topic`], whose `{key}` placeholders
+//! are replaced by the values of the record fields, such as `"devices/{device_id}/logs/{level}"`.
+//!
+//! The connection is driven by a background thread, which reconnects to the broker when the connection is lost,
+//! and stops when the writer is dropped. A record is queued without blocking the log call,
+//! and fails with an error if the queue of [`PublishOptions::capacity`] messages is full.
+//! To create a `Box<dyn Writer>` use the [`new_writer`] function.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{mqtt, Builder};
+//!
+//! fn main() {
+//!     let client = mqtt::MqttOptions::new("device-42", "127.0.0.1", 1883);
+//!     let opts = mqtt::PublishOptions {
+//!         topic: "devices/{device_id}/logs/{level}".to_string(),
+//!         qos: mqtt::QoS::AtLeastOnce,
+//!         ..Default::default()
+//!     };
+//!     Builder::with_level("info")
+//!         .with_static_field("device_id", "42")
+//!         .with_default_writer(mqtt::new_writer(client, opts).unwrap())
+//!         .init();
+//!
+//!     log::info!("booted");
+//! }
+//! ```
+//!
+
+use log::kv::{Key, Value};
+use serde_json::Map;
+use std::{collections::BTreeMap, io, sync::Arc, thread, time::Duration};
+
+pub use rumqttc::{MqttOptions, QoS};
+
+use crate::fields::FieldMap;
+use crate::metrics::Health;
+use crate::{log_failure, Fields, Writer};
+
+/// The options of the messages published by a [`MqttWriter`].
+#[derive(Debug, Clone)]
+pub struct PublishOptions {
+    /// The topic template, the default is `"logs/{target}"`.
+    /// A `{key}` placeholder is replaced by the value of the field, or `unknown` if the record has none,
+    /// with the `/`, `+` and `#` characters of the value replaced by `_`.
+    pub topic: String,
+    /// The quality of service of the messages, the default is [`QoS::AtMostOnce`].
+    pub qos: QoS,
+    /// Whether the messages are retained by the broker, the default is false.
+    pub retain: bool,
+    /// The maximum number of messages queued for the connection, the default is 1024.
+    pub capacity: usize,
+}
+
+impl Default for PublishOptions {
+    fn default() -> Self {
+        PublishOptions {
+            topic: "logs/{target}".to_string(),
+            qos: QoS::AtMostOnce,
+            retain: false,
+            capacity: 1024,
+        }
+    }
+}
+
+// A part of a topic template.
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Text(String),
+    Field(String),
+}
+
+/// A Writer implementation that publishes logs in JSON format to an MQTT broker.
+pub struct MqttWriter {
+    client: rumqttc::Client,
+    topic: Vec<Segment>,
+    qos: QoS,
+    retain: bool,
+    // whether the connection is established.
+    health: Arc<Health>,
+}
+
+impl MqttWriter {
+    /// Creates a new MqttWriter instance that connects to the broker of the client options.
+    /// It starts the background thread that drives the connection.
+    pub fn new(client: MqttOptions, opts: PublishOptions) -> Result<Self, io::Error> {
+        let (client, mut connection) = rumqttc::Client::new(client, opts.capacity.max(1));
+        let health = Arc::new(Health::default());
+        let connection_health = health.clone();
+        thread::Builder::new()
+            .name("structured-logger-mqtt".to_string())
+            .spawn(move || {
+                // the iteration ends when the client is dropped, it reconnects after an error.
+                for event in connection.iter() {
+                    if let Err(err) = connection_health.track(event) {
+                        log_failure(format!("MqttWriter connection failed: {}", err).as_str());
+                        thread::sleep(Duration::from_secs(1));
+                    }
+                }
+            })?;
+        Ok(MqttWriter {
+            client,
+            topic: parse_topic(&opts.topic),
+            qos: opts.qos,
+            retain: opts.retain,
+            health,
+        })
+    }
+
+    fn publish(&self, record: Map<String, serde_json::Value>) -> Result<(), io::Error> {
+        let topic = render_topic(&self.topic, &record);
+        let payload = serde_json::to_vec(&record)?;
+        self.client
+            .try_publish(topic, self.qos, self.retain, payload)
+            .map_err(io::Error::other)
+    }
+}
+
+fn parse_topic(template: &str) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        match rest[start..].find('}') {
+            Some(end) => {
+                if start > 0 {
+                    segments.push(Segment::Text(rest[..start].to_string()));
+                }
+                segments.push(Segment::Field(rest[start + 1..start + end].to_string()));
+                rest = &rest[start + end + 1..];
+            }
+            None => break,
+        }
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Text(rest.to_string()));
+    }
+    segments
+}
+
+fn render_topic(segments: &[Segment], record: &Map<String, serde_json::Value>) -> String {
+    let mut topic = String::new();
+    for segment in segments {
+        match segment {
+            Segment::Text(text) => topic.push_str(text),
+            Segment::Field(key) => {
+                let value = match record.get(key) {
+                    Some(serde_json::Value::String(s)) if !s.is_empty() => s.clone(),
+                    None | Some(serde_json::Value::Null) | Some(serde_json::Value::String(_)) => {
+                        "unknown".to_string()
+                    }
+                    Some(value) => value.to_string(),
+                };
+                // a field value is a single topic level without wildcards.
+                topic.extend(value.chars().map(|c| match c {
+                    '/' | '+' | '#' | '\0' => '_',
+                    c => c,
+                }));
+            }
+        }
+    }
+    topic
+}
+
+fn to_object(value: serde_json::Value) -> Map<String, serde_json::Value> {
+    match value {
+        serde_json::Value::Object(map) => map,
+        _ => Map::new(),
+    }
+}
+
+/// Implements Writer trait for MqttWriter.
+impl Writer for MqttWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        self.publish(to_object(serde_json::to_value(FieldMap(value))?))
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        self.publish(to_object(serde_json::to_value(fields)?))
+    }
+
+    fn healthy(&self) -> bool {
+        self.health.healthy()
+    }
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the MqttWriter for the given client options.
+pub fn new_writer(client: MqttOptions, opts: PublishOptions) -> Result<Box<dyn Writer>, io::Error> {
+    Ok(Box::new(MqttWriter::new(client, opts)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    // Reads a MQTT packet, returns its fixed header byte and its body.
+    fn read_packet(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+        let mut byte = [0_u8; 1];
+        stream.read_exact(&mut byte).unwrap();
+        let header = byte[0];
+        let (mut len, mut shift) = (0_usize, 0);
+        loop {
+            stream.read_exact(&mut byte).unwrap();
+            len |= ((byte[0] & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0_u8; len];
+        stream.read_exact(&mut body).unwrap();
+        (header, body)
+    }
+
+    #[test]
+    fn parse_topic_works() {
+        assert_eq!(
+            vec![
+                Segment::Text("devices/".to_string()),
+                Segment::Field("device_id".to_string()),
+                Segment::Text("/logs/".to_string()),
+                Segment::Field("level".to_string()),
+            ],
+            parse_topic("devices/{device_id}/logs/{level}")
+        );
+        assert_eq!(
+            vec![Segment::Text("logs/{broken".to_string())],
+            parse_topic("logs/{broken")
+        );
+
+        let mut record = Map::new();
+        record.insert("target".to_string(), "app::db".into());
+        record.insert("id".to_string(), 7.into());
+        record.insert("path".to_string(), "a/b+#".into());
+        let segments = parse_topic("{target}/{id}/{path}/{missing}");
+        assert_eq!("app::db/7/a_b__/unknown", render_topic(&segments, &record));
+    }
+
+    #[test]
+    fn mqtt_writer_works() {
+        let broker = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = broker.local_addr().unwrap().port();
+        let opts = PublishOptions {
+            topic: "devices/{device_id}/logs".to_string(),
+            qos: QoS::AtLeastOnce,
+            ..Default::default()
+        };
+        let writer = MqttWriter::new(MqttOptions::new("test", "127.0.0.1", port), opts).unwrap();
+
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("message"), Value::from("booted"));
+        value.insert(Key::from("device_id"), Value::from("d42"));
+        writer.write_log(&value).unwrap();
+
+        let (mut stream, _) = broker.accept().unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let (header, _) = read_packet(&mut stream);
+        assert_eq!(0x10, header, "CONNECT");
+        stream.write_all(&[0x20, 0x02, 0x00, 0x00]).unwrap();
+
+        let (header, body) = read_packet(&mut stream);
+        assert_eq!(0x32, header, "PUBLISH with QoS 1");
+        let topic_len = u16::from_be_bytes([body[0], body[1]]) as usize;
+        let topic = std::str::from_utf8(&body[2..2 + topic_len]).unwrap();
+        assert_eq!("devices/d42/logs", topic);
+        let pkid = &body[2 + topic_len..4 + topic_len];
+        let msg: serde_json::Value = serde_json::from_slice(&body[4 + topic_len..]).unwrap();
+        assert_eq!(
+            serde_json::json!({"device_id": "d42", "message": "booted"}),
+            msg
+        );
+        stream.write_all(&[0x40, 0x02, pkid[0], pkid[1]]).unwrap();
+        // stops the connection before the broker closes it.
+        drop(writer);
+    }
+}
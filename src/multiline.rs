@@ -0,0 +1,175 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Multi-line Values Writer
+//!
+//! A [`Writer`] wrapper that applies a [`Multiline`] policy to the message and the string values
+//! that contain newlines, such as a stack trace pasted into a message. The JSON writers escape a newline
+//! as `\n`, which is valid JSON, but some downstream line-based parsers still split the records on it:
+//! * [`Multiline::Escape`], the default, writes the values unchanged;
+//! * [`Multiline::Marker`] replaces each line break with a visible marker, such as `" ⏎ "`;
+//! * [`Multiline::Lines`] splits a multi-line message into a `message_lines` array of its lines,
+//!   the `message` is its first line, and a multi-line value into an array of its lines.
+//!
+//! The `\n` and `\r\n` line breaks are handled, a trailing line break is removed.
+//! The wrapped writer receives the records as a map, see [`Writer::write_log`].
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{json, multiline, Builder};
+//!
+//! fn main() {
+//!     let writer = multiline::new_writer(multiline::Multiline::Lines, json::new_writer(std::io::stdout()));
+//!     Builder::with_level("info")
+//!         .with_default_writer(writer)
+//!         .init();
+//!
+//!     // {"level":"ERROR","message":"panic: boom","message_lines":["panic: boom","  at main.rs:3"],...}
+//!     log::error!("panic: boom\n  at main.rs:3");
+//! }
+//! ```
+//!
+
+use std::{collections::BTreeMap, io};
+
+use crate::{Fields, Key, Value, Writer};
+
+/// The policy of a [`MultilineWriter`] for the values that contain newlines.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Multiline {
+    /// Writes the values unchanged, the JSON writers escape the newlines as `\n`.
+    #[default]
+    Escape,
+    /// Replaces each line break with the given marker.
+    Marker(String),
+    /// Splits the message into a `message_lines` array, and the values into arrays of their lines.
+    Lines,
+}
+
+/// A Writer implementation that applies a [`Multiline`] policy to the records written to a wrapped writer.
+pub struct MultilineWriter {
+    policy: Multiline,
+    inner: Box<dyn Writer>,
+}
+
+impl MultilineWriter {
+    /// Creates a new MultilineWriter instance that applies `policy` to the records written to `w`.
+    pub fn new(policy: Multiline, w: Box<dyn Writer>) -> Self {
+        MultilineWriter { policy, inner: w }
+    }
+}
+
+/// Implements Writer trait for MultilineWriter.
+impl Writer for MultilineWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        let multiline: Vec<(&Key, Vec<&str>)> = match self.policy {
+            Multiline::Escape => Vec::new(),
+            _ => value
+                .iter()
+                .filter_map(|(key, value)| value.to_borrowed_str().map(|s| (key, s)))
+                .filter(|(_, s)| s.contains('\n'))
+                .map(|(key, s)| (key, s.lines().collect()))
+                .collect(),
+        };
+        if multiline.is_empty() {
+            return self.inner.write_log(value);
+        }
+
+        let mut value = value.clone();
+        match self.policy {
+            Multiline::Marker(ref marker) => {
+                let joined: Vec<(&Key, String)> = multiline
+                    .iter()
+                    .map(|(key, lines)| (*key, lines.join(marker)))
+                    .collect();
+                for (key, s) in joined.iter() {
+                    value.insert((*key).clone(), Value::from(s.as_str()));
+                }
+                self.inner.write_log(&value)
+            }
+            _ => {
+                for (key, lines) in multiline.iter() {
+                    if key.as_str() == "message" {
+                        value.insert(Key::from("message_lines"), Value::from_serde(lines));
+                        let first = lines.first().copied().unwrap_or_default();
+                        value.insert((*key).clone(), Value::from(first));
+                    } else {
+                        value.insert((*key).clone(), Value::from_serde(lines));
+                    }
+                }
+                self.inner.write_log(&value)
+            }
+        }
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        if self.policy == Multiline::Escape {
+            return self.inner.write_fields(fields);
+        }
+        self.write_log(&fields.to_map())
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        self.inner.flush()
+    }
+
+    fn shutdown(&self) -> Result<(), io::Error> {
+        self.inner.shutdown()
+    }
+
+    fn healthy(&self) -> bool {
+        self.inner.healthy()
+    }
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the MultilineWriter
+/// that applies `policy` to the records written to `w`.
+pub fn new_writer(policy: Multiline, w: Box<dyn Writer>) -> Box<dyn Writer> {
+    Box::new(MultilineWriter::new(policy, w))
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+    use crate::fn_writer;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn multiline_writer_works() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let with_policy = |policy| {
+            let lines = lines.clone();
+            new_writer(
+                policy,
+                fn_writer(move |value| {
+                    let json = serde_json::to_string(&crate::fields::FieldMap(value)).unwrap();
+                    lines.lock().unwrap().push(json);
+                    Ok(())
+                }),
+            )
+        };
+
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("level"), Value::from("ERROR"));
+        value.insert(
+            Key::from("message"),
+            Value::from("boom\r\n  at main.rs:3\n"),
+        );
+        value.insert(Key::from("query"), Value::from("SELECT 1\nFROM t"));
+        value.insert(Key::from("user"), Value::from("u1"));
+        with_policy(Multiline::Escape).write_log(&value).unwrap();
+        with_policy(Multiline::Marker(" | ".to_string()))
+            .write_log(&value)
+            .unwrap();
+        with_policy(Multiline::Lines).write_log(&value).unwrap();
+
+        assert_eq!(
+            vec![
+                r#"{"level":"ERROR","message":"boom\r\n  at main.rs:3\n","query":"SELECT 1\nFROM t","user":"u1"}"#,
+                r#"{"level":"ERROR","message":"boom |   at main.rs:3","query":"SELECT 1 | FROM t","user":"u1"}"#,
+                r#"{"level":"ERROR","message":"boom","message_lines":["boom","  at main.rs:3"],"query":["SELECT 1","FROM t"],"user":"u1"}"#,
+            ],
+            *lines.lock().unwrap()
+        );
+    }
+}
@@ -0,0 +1,582 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # NATS Writer Implementation
+//!
+//! A [`Writer`] implementation that publishes structured values asynchronous in JSON format
+//! to a NATS subject, one message per record, with the [`async_nats`](https://docs.rs/async-nats) client.
+//!
+//! Encoded records are buffered in a bounded queue, like the [`async_json`](crate::async_json) writer,
+//! and published in batches by a single long-lived task per writer, which is spawned on the current tokio runtime
+//! by the first log call. Each batch is flushed to the server before the next one.
+//!
+//! With [`NatsOptions::jetstream`], the records are published to the JetStream stream of the subject,
+//! and the records that are not acknowledged are published again, with an exponential backoff,
+//! up to [`NatsOptions::max_retries`] times. Without it, the records are published at most once.
+//! The client reconnects to the server on its own, and buffers the messages meanwhile.
+//! To create a `Box<dyn Writer>` use the [`new_writer`] function.
+//!
+//! Example:
+//! ```rust,no_run
+//! use structured_logger::{nats, Builder};
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     let client = nats::ConnectOptions::new()
+//!         .retry_on_initial_connect()
+//!         .connect("127.0.0.1:4222")
+//!         .await
+//!         .unwrap();
+//!     let opts = nats::NatsOptions {
+//!         subject: "logs.orders".to_string(),
+//!         jetstream: true,
+//!         ..Default::default()
+//!     };
+//!     Builder::with_level("info")
+//!         .with_default_writer(nats::new_writer(client, opts))
+//!         .init();
+//!
+//!     log::info!("order placed");
+//! }
+//! ```
+//!
+
+use async_nats::{jetstream, Subject};
+use bytes::Bytes;
+use log::Level;
+use std::{
+    collections::BTreeMap,
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::runtime::Handle;
+
+use crate::async_json::{DEFAULT_BATCH_SIZE, DEFAULT_QUEUE_CAPACITY, DEFAULT_SHUTDOWN_TIMEOUT};
+use crate::json::encode_owned;
+use crate::metrics::QueueMetrics;
+use crate::queue::Queue;
+use crate::retry::Backoff;
+use crate::{log_failure, Fields, Key, Value, Writer};
+
+pub use crate::queue::{AdaptiveBatching, BackpressurePolicy};
+pub use async_nats::{Client, ConnectOptions};
+
+/// How long [`Writer::flush`] waits for the queued records to be published.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+// The first delay before publishing the records that are not acknowledged again.
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// The options to create a [`NatsWriter`].
+#[derive(Debug, Clone)]
+pub struct NatsOptions {
+    /// The subject of the messages, the default is `"logs"`.
+    pub subject: String,
+    /// Whether the records are published to JetStream and acknowledged, the default is false.
+    /// A stream must capture the subject.
+    pub jetstream: bool,
+    /// The maximum number of records that can be buffered in the queue.
+    pub capacity: usize,
+    /// The policy to apply when the queue is full.
+    pub policy: BackpressurePolicy,
+    /// The maximum number of records published in a batch.
+    pub batch_size: usize,
+    /// How long to wait for more records before publishing a batch that is not full.
+    /// Zero (the default) publishes the queued records immediately.
+    pub batch_interval: Duration,
+    /// The adaptive batching, which sizes the batches from the rate of the records instead of
+    /// the `batch_size` and `batch_interval`, the default is `None`. See [`AdaptiveBatching`].
+    pub adaptive: Option<AdaptiveBatching>,
+    /// How many times the records that are not acknowledged by JetStream are published again, the default is 10.
+    pub max_retries: usize,
+    /// The maximum delay between the retries, the default is 5 seconds.
+    /// The delays are doubled from 100 ms, with jitter, see [`Backoff`].
+    pub max_retry_delay: Duration,
+}
+
+impl Default for NatsOptions {
+    fn default() -> Self {
+        NatsOptions {
+            subject: "logs".to_string(),
+            jetstream: false,
+            capacity: DEFAULT_QUEUE_CAPACITY,
+            policy: BackpressurePolicy::default(),
+            batch_size: DEFAULT_BATCH_SIZE,
+            batch_interval: Duration::ZERO,
+            adaptive: None,
+            max_retries: 10,
+            max_retry_delay: Duration::from_secs(5),
+        }
+    }
+}
+
+struct Shared {
+    client: Client,
+    jetstream: Option<jetstream::Context>,
+    subject: Subject,
+    queue: Queue,
+    max_retries: usize,
+    max_retry_delay: Duration,
+    // whether the consumer task is publishing popped records.
+    busy: AtomicBool,
+}
+
+/// A Writer implementation that publishes logs asynchronous in JSON format to NATS.
+pub struct NatsWriter {
+    shared: Arc<Shared>,
+    // whether the consumer task has been spawned.
+    started: AtomicBool,
+}
+
+impl NatsWriter {
+    /// Creates a new NatsWriter instance with a connected client.
+    pub fn new(client: Client, opts: NatsOptions) -> Self {
+        let jetstream = if opts.jetstream {
+            Some(jetstream::new(client.clone()))
+        } else {
+            None
+        };
+        NatsWriter {
+            shared: Arc::new(Shared {
+                client,
+                jetstream,
+                subject: Subject::from(opts.subject),
+                queue: Queue::new(
+                    opts.capacity,
+                    opts.policy,
+                    opts.batch_size,
+                    opts.batch_interval,
+                )
+                .with_adaptive(opts.adaptive),
+                max_retries: opts.max_retries,
+                max_retry_delay: opts.max_retry_delay,
+                busy: AtomicBool::new(false),
+            }),
+            started: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the number of records dropped because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.shared.queue.dropped()
+    }
+
+    /// Returns a [`QueueMetrics`] handle to read the queue depth and drop counters.
+    pub fn metrics(&self) -> QueueMetrics {
+        self.shared.queue.metrics()
+    }
+
+    fn enqueue(&self, mut buf: Vec<u8>, level: Option<Level>) -> Result<(), io::Error> {
+        let queue = &self.shared.queue;
+        if queue.is_closed() {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "NatsWriter has been shut down",
+            ));
+        }
+        // a message is a JSON object, without the line break.
+        if buf.last() == Some(&b'\n') {
+            buf.pop();
+        }
+        if !queue.push(buf, level) {
+            return Ok(());
+        }
+
+        if !self.started.swap(true, Ordering::SeqCst) {
+            Handle::current().spawn(consume(self.shared.clone()));
+        }
+        Ok(())
+    }
+
+    // Waits for the queued records to be published, from a synchronous context.
+    fn wait_idle(&self, timeout: Duration) -> Result<(), io::Error> {
+        let deadline = Instant::now() + timeout;
+        let shared = &self.shared;
+        while shared.queue.len() > 0 || shared.busy.load(Ordering::SeqCst) {
+            if !self.started.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "NatsWriter flush timed out",
+                ));
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        Ok(())
+    }
+}
+
+/// Implements Writer trait for NatsWriter.
+impl Writer for NatsWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        self.enqueue(encode_owned(value)?, self.shared.queue.level_of(value))
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        self.enqueue(encode_owned(fields)?, self.shared.queue.level_of(fields))
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        self.wait_idle(FLUSH_TIMEOUT)
+    }
+
+    fn shutdown(&self) -> Result<(), io::Error> {
+        self.shared.queue.close();
+        self.wait_idle(DEFAULT_SHUTDOWN_TIMEOUT)
+    }
+
+    fn healthy(&self) -> bool {
+        self.shared.queue.counters().health.healthy()
+    }
+}
+
+impl Drop for NatsWriter {
+    fn drop(&mut self) {
+        // no more records will be pushed, let the consumer task drain the queue and exit.
+        self.shared.queue.close();
+    }
+}
+
+// The long-lived consumer task of a writer, it exits when the queue is closed and drained.
+async fn consume(shared: Arc<Shared>) {
+    let queue = &shared.queue;
+    let mut records = Vec::with_capacity(queue.batch_size);
+    loop {
+        queue.wait().await;
+        // wait for more records if the batch is not full.
+        if let Some(delay) = queue.batch_delay() {
+            tokio::time::sleep(delay).await;
+        }
+
+        loop {
+            // marked busy before popping, so a flush never sees an empty queue with unpublished records.
+            shared.busy.store(true, Ordering::SeqCst);
+            if queue.pop_records(&mut records) == 0 {
+                shared.busy.store(false, Ordering::SeqCst);
+                break;
+            }
+            let mut batch: Vec<Bytes> = records.drain(..).map(Bytes::from).collect();
+            publish_batch(&shared, &mut batch).await;
+            shared.busy.store(false, Ordering::SeqCst);
+        }
+        if queue.is_closed() {
+            return;
+        }
+    }
+}
+
+// Publishes a batch of records, and publishes again the records that JetStream didn't acknowledge.
+async fn publish_batch(shared: &Shared, batch: &mut Vec<Bytes>) {
+    let health = &shared.queue.counters().health;
+    let js = match shared.jetstream {
+        Some(ref js) => js,
+        None => {
+            let mut healthy = true;
+            for record in batch.drain(..) {
+                if let Err(err) = shared.client.publish(shared.subject.clone(), record).await {
+                    healthy = false;
+                    log_failure(format!("NatsWriter failed to publish log: {}", err).as_str());
+                }
+            }
+            if let Err(err) = shared.client.flush().await {
+                healthy = false;
+                log_failure(format!("NatsWriter failed to flush logs: {}", err).as_str());
+            }
+            health.set(healthy);
+            return;
+        }
+    };
+
+    let backoff = Backoff {
+        initial: RETRY_DELAY,
+        max: shared.max_retry_delay,
+        max_attempts: shared.max_retries.saturating_add(1).min(u32::MAX as usize) as u32,
+        jitter: true,
+    };
+    let mut delay = backoff.delay(1);
+    for retry in 0..=shared.max_retries {
+        if retry > 0 {
+            tokio::time::sleep(delay).await;
+            delay = backoff.delay(retry as u32 + 1);
+        }
+        match health.track(publish_acked(js, &shared.subject, batch).await) {
+            Ok(()) => return,
+            Err(err) if retry == shared.max_retries => {
+                log_failure(
+                    format!(
+                        "NatsWriter dropped {} logs not acknowledged: {}",
+                        batch.len(),
+                        err
+                    )
+                    .as_str(),
+                );
+            }
+            Err(err) => {
+                log_failure(
+                    format!(
+                        "NatsWriter failed to publish {} logs, retrying in {:?}: {}",
+                        batch.len(),
+                        delay,
+                        err
+                    )
+                    .as_str(),
+                );
+            }
+        }
+    }
+}
+
+// Publishes the records to JetStream and waits for the acknowledgments.
+// The records that are not acknowledged are kept in the batch.
+async fn publish_acked(
+    js: &jetstream::Context,
+    subject: &Subject,
+    batch: &mut Vec<Bytes>,
+) -> Result<(), jetstream::context::PublishError> {
+    let mut acks = Vec::with_capacity(batch.len());
+    for record in batch.iter() {
+        acks.push(js.publish(subject.clone(), record.clone()).await);
+    }
+
+    let mut results = Vec::with_capacity(acks.len());
+    for ack in acks {
+        results.push(match ack {
+            Ok(ack) => ack.await.map(|_| ()),
+            Err(err) => Err(err),
+        });
+    }
+
+    let mut last_err = None;
+    let mut results = results.into_iter();
+    batch.retain(|_| match results.next() {
+        Some(Err(err)) => {
+            last_err = Some(err);
+            true
+        }
+        _ => false,
+    });
+    match last_err {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the NatsWriter for a given client.
+pub fn new_writer(client: Client, opts: NatsOptions) -> Box<dyn Writer> {
+    Box::new(NatsWriter::new(client, opts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    // A minimal NATS server that acknowledges the JetStream messages, except the first `nacks` ones
+    // which are answered with an error, it returns the subjects and the payloads of the published messages.
+    async fn serve(listener: TcpListener, n: usize, mut nacks: usize) -> Vec<(String, String)> {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (r, mut w) = stream.into_split();
+        let mut r = BufReader::new(r);
+        w.write_all(b"INFO {\"server_id\":\"test\",\"version\":\"2.10.0\",\"proto\":1,\"headers\":true,\"max_payload\":1048576}\r\n")
+            .await
+            .unwrap();
+
+        let mut published = Vec::new();
+        let mut sid = String::new();
+        let mut seq = 0;
+        while published.len() < n {
+            let mut line = String::new();
+            if r.read_line(&mut line).await.unwrap() == 0 {
+                break;
+            }
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            match parts.first().copied() {
+                Some("PING") => w.write_all(b"PONG\r\n").await.unwrap(),
+                Some("SUB") => sid = parts[parts.len() - 1].to_string(),
+                Some("PUB") | Some("HPUB") => {
+                    // PUB <subject> [reply] <size>, HPUB <subject> [reply] <header size> <total size>
+                    let size: usize = parts[parts.len() - 1].parse().unwrap();
+                    let header_size = if parts[0] == "HPUB" {
+                        parts[parts.len() - 2].parse().unwrap()
+                    } else {
+                        0
+                    };
+                    let mut payload = vec![0_u8; size + 2];
+                    r.read_exact(&mut payload).await.unwrap();
+                    let payload = String::from_utf8(payload[header_size..size].to_vec()).unwrap();
+                    let reply_len = if parts[0] == "HPUB" { 5 } else { 4 };
+                    if parts.len() == reply_len {
+                        let ack = if nacks > 0 {
+                            nacks -= 1;
+                            r#"{"error":{"code":503,"err_code":10077,"description":"maximum messages exceeded"}}"#.to_string()
+                        } else {
+                            seq += 1;
+                            format!("{{\"stream\":\"LOGS\",\"seq\":{}}}", seq)
+                        };
+                        let msg = format!("MSG {} {} {}\r\n{}\r\n", parts[2], sid, ack.len(), ack);
+                        w.write_all(msg.as_bytes()).await.unwrap();
+                    }
+                    published.push((parts[1].to_string(), payload));
+                }
+                _ => {}
+            }
+        }
+        published
+    }
+
+    async fn publish(jetstream: bool) -> Vec<(String, String)> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve(listener, 2, 0));
+
+        let client = async_nats::connect(addr.to_string()).await.unwrap();
+        let opts = NatsOptions {
+            subject: "logs.test".to_string(),
+            jetstream,
+            ..Default::default()
+        };
+        let writer = NatsWriter::new(client, opts);
+        for i in 0..2 {
+            let mut value = BTreeMap::new();
+            value.insert(Key::from("message"), Value::from("hello"));
+            value.insert(Key::from("i"), Value::from(i));
+            writer.write_log(&value).unwrap();
+        }
+
+        let published = server.await.unwrap();
+        tokio::task::spawn_blocking(move || writer.flush())
+            .await
+            .unwrap()
+            .unwrap();
+        published
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn nats_writer_works() {
+        let expected = vec![
+            (
+                "logs.test".to_string(),
+                r#"{"i":0,"message":"hello"}"#.to_string(),
+            ),
+            (
+                "logs.test".to_string(),
+                r#"{"i":1,"message":"hello"}"#.to_string(),
+            ),
+        ];
+        assert_eq!(expected, publish(false).await);
+        assert_eq!(expected, publish(true).await);
+    }
+
+    fn write(writer: &NatsWriter, i: i64) -> Result<(), io::Error> {
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("i"), Value::from(i));
+        writer.write_log(&value)
+    }
+
+    fn payloads(published: Vec<(String, String)>) -> Vec<String> {
+        published.into_iter().map(|(_, payload)| payload).collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn nats_writer_retry_works() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve(listener, 4, 3));
+
+        let client = async_nats::connect(addr.to_string()).await.unwrap();
+        let writer = Arc::new(NatsWriter::new(
+            client,
+            NatsOptions {
+                jetstream: true,
+                max_retries: 1,
+                max_retry_delay: Duration::from_millis(10),
+                ..Default::default()
+            },
+        ));
+        let flush = |writer: &Arc<NatsWriter>| {
+            let writer = writer.clone();
+            tokio::task::spawn_blocking(move || writer.flush())
+        };
+
+        // the record is not acknowledged after a retry, and is dropped.
+        write(&writer, 0).unwrap();
+        flush(&writer).await.unwrap().unwrap();
+        assert!(!writer.healthy());
+        // the record is acknowledged by the retry.
+        write(&writer, 1).unwrap();
+        flush(&writer).await.unwrap().unwrap();
+        assert!(writer.healthy());
+
+        assert_eq!(
+            vec![r#"{"i":0}"#, r#"{"i":0}"#, r#"{"i":1}"#, r#"{"i":1}"#],
+            payloads(server.await.unwrap())
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn nats_writer_full_works() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve(listener, 1, 0));
+
+        let client = async_nats::connect(addr.to_string()).await.unwrap();
+        let writer = NatsWriter::new(
+            client,
+            NatsOptions {
+                capacity: 1,
+                ..Default::default()
+            },
+        );
+        // the consumer task can't run before the current thread yields,
+        // the first record fills the queue, the others are dropped.
+        for i in 0..3 {
+            write(&writer, i).unwrap();
+        }
+        assert_eq!(2, writer.dropped());
+        assert_eq!(1, writer.metrics().queue_len());
+
+        assert_eq!(vec![r#"{"i":0}"#], payloads(server.await.unwrap()));
+        assert_eq!(2, writer.dropped());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn nats_writer_shutdown_works() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve(listener, 3, 0));
+
+        let client = async_nats::connect(addr.to_string()).await.unwrap();
+        let writer = Arc::new(NatsWriter::new(
+            client,
+            NatsOptions {
+                batch_interval: Duration::from_millis(50),
+                ..Default::default()
+            },
+        ));
+        for i in 0..3 {
+            write(&writer, i).unwrap();
+        }
+        // the queued records are published before the shutdown returns.
+        let w = writer.clone();
+        tokio::task::spawn_blocking(move || w.shutdown())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(0, writer.metrics().queue_len());
+        assert_eq!(
+            vec![r#"{"i":0}"#, r#"{"i":1}"#, r#"{"i":2}"#],
+            payloads(server.await.unwrap())
+        );
+
+        let err = write(&writer, 3).unwrap_err();
+        assert_eq!(io::ErrorKind::BrokenPipe, err.kind());
+    }
+}
@@ -0,0 +1,485 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Non-blocking Writer Implementation
+//!
+//! A [`Writer`] implementation that logs structured values in JSON format
+//! to a file, stderr, stdout, or any other destination, with the IO performed on a dedicated OS thread.
+//! It is inspired by [`tracing-appender`], and it doesn't require an async runtime.
+//!
+//! Records are encoded on the logging thread (the borrowed key-values can't outlive the log call),
+//! and the encoded bytes are sent over a bounded channel to the worker thread that writes them
+//! into the underlying `std::io::Write` instance.
+//! To create a `Box<dyn Writer>` use the [`non_blocking`] function or the [`NonBlockingBuilder`].
+//! The [`stdout`] and [`stderr`] functions create console writers that never block the logging thread,
+//! even if the pipe or the terminal is full, such as with a stalled `kubectl logs` reader:
+//! the records are dropped while the channel is full, and the drop count is reported when the worker catches up.
+//! The returned [`WorkerGuard`] must be kept alive, dropping it flushes the remaining records and stops the worker.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{non_blocking::non_blocking, Builder};
+//!
+//! fn main() {
+//!     let (writer, _guard) = non_blocking(std::io::stdout());
+//!     Builder::with_level("info")
+//!         .with_default_writer(writer)
+//!         .init();
+//!
+//!     log::info!("hello world");
+//! }
+//! ```
+//!
+//! [`tracing-appender`]: https://crates.io/crates/tracing-appender
+//!
+
+use std::{
+    collections::BTreeMap,
+    io,
+    io::Write,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use crate::json::encode_owned;
+use crate::metrics::{QueueCounters, QueueMetrics};
+use crate::pool::BufferPool;
+use crate::{log_failure, unix_ms, Fields, Key, Value, Writer};
+
+/// The default maximum number of records that can be buffered in the channel.
+pub const DEFAULT_BUFFERED_LINES_LIMIT: usize = 128_000;
+
+/// How long the [`WorkerGuard`] waits for the worker thread to flush the remaining records.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long [`Writer::flush`] waits for the worker thread to flush the queued records.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+enum Msg {
+    Record(Vec<u8>),
+    Flush(mpsc::Sender<io::Result<()>>),
+    Shutdown,
+}
+
+/// A builder to configure and create a non-blocking writer.
+pub struct NonBlockingBuilder {
+    buffered_lines_limit: usize,
+    lossy: bool,
+    report_dropped: bool,
+    thread_name: String,
+}
+
+impl Default for NonBlockingBuilder {
+    fn default() -> Self {
+        NonBlockingBuilder {
+            buffered_lines_limit: DEFAULT_BUFFERED_LINES_LIMIT,
+            lossy: true,
+            report_dropped: false,
+            thread_name: "structured-logger".to_string(),
+        }
+    }
+}
+
+impl NonBlockingBuilder {
+    /// Sets the maximum number of records that can be buffered before the channel is full.
+    pub fn with_buffered_lines_limit(self, limit: usize) -> Self {
+        NonBlockingBuilder {
+            buffered_lines_limit: limit.max(1),
+            ..self
+        }
+    }
+
+    /// Sets whether the writer drops records when the channel is full (`true`, the default),
+    /// or blocks the logging thread until the worker catches up (`false`).
+    pub fn with_lossy(self, lossy: bool) -> Self {
+        NonBlockingBuilder { lossy, ..self }
+    }
+
+    /// Sets whether the worker writes a record with the number of the dropped records,
+    /// after the records that were queued before the drops are written. The default is false.
+    ///
+    /// The record is a warning of the `structured_logger` target with a `dropped` field, such as:
+    /// `{"dropped":42,"level":"WARN","message":"dropped 42 logs because the writer was full","target":"structured_logger","timestamp":1679745592127}`.
+    pub fn with_report_dropped(self, report_dropped: bool) -> Self {
+        NonBlockingBuilder {
+            report_dropped,
+            ..self
+        }
+    }
+
+    /// Sets the name of the worker thread.
+    pub fn with_thread_name(self, name: &str) -> Self {
+        NonBlockingBuilder {
+            thread_name: name.to_string(),
+            ..self
+        }
+    }
+
+    /// Spawns the worker thread for the given std::io::Write instance,
+    /// returns a `Box<dyn Writer>` instance and its [`WorkerGuard`].
+    pub fn finish<W: Write + Send + 'static>(self, w: W) -> (Box<dyn Writer>, WorkerGuard) {
+        let (writer, guard) = NonBlockingWriter::new(self, w);
+        (Box::new(writer), guard)
+    }
+}
+
+/// A Writer implementation that writes logs in JSON format on a dedicated worker thread.
+pub struct NonBlockingWriter {
+    sender: SyncSender<Msg>,
+    lossy: bool,
+    counters: Arc<QueueCounters>,
+}
+
+impl NonBlockingWriter {
+    fn new<W: Write + Send + 'static>(cfg: NonBlockingBuilder, w: W) -> (Self, WorkerGuard) {
+        let (sender, receiver) = mpsc::sync_channel(cfg.buffered_lines_limit);
+        let (done_tx, done_rx) = mpsc::channel();
+        let counters = Arc::new(QueueCounters::default());
+        let worker = Worker {
+            w,
+            receiver,
+            counters: counters.clone(),
+            report_dropped: cfg.report_dropped,
+            reported: 0,
+        };
+        let handle = thread::Builder::new()
+            .name(cfg.thread_name)
+            .spawn(move || {
+                worker.run();
+                let _ = done_tx.send(());
+            })
+            .expect("failed to spawn the non-blocking writer thread");
+
+        let guard = WorkerGuard {
+            sender: sender.clone(),
+            done: done_rx,
+            handle: Some(handle),
+            counters: counters.clone(),
+        };
+        (
+            NonBlockingWriter {
+                sender,
+                lossy: cfg.lossy,
+                counters,
+            },
+            guard,
+        )
+    }
+}
+
+/// Implements Writer trait for NonBlockingWriter.
+impl Writer for NonBlockingWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        self.send(encode_owned(value)?)
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        self.send(encode_owned(fields)?)
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        let (tx, rx) = mpsc::channel();
+        self.sender
+            .send(Msg::Flush(tx))
+            .map_err(|_| worker_stopped())?;
+        match rx.recv_timeout(FLUSH_TIMEOUT) {
+            Ok(res) => res,
+            Err(RecvTimeoutError::Timeout) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "non-blocking writer flush timed out",
+            )),
+            Err(RecvTimeoutError::Disconnected) => Err(worker_stopped()),
+        }
+    }
+
+    fn shutdown(&self) -> Result<(), io::Error> {
+        self.flush()?;
+        // the worker stops after the flushed records, the guard has nothing left to wait for.
+        let _ = self.sender.send(Msg::Shutdown);
+        Ok(())
+    }
+
+    fn healthy(&self) -> bool {
+        self.counters.health.healthy()
+    }
+}
+
+impl NonBlockingWriter {
+    fn send(&self, buf: Vec<u8>) -> Result<(), io::Error> {
+        // count the record before sending, the worker may pop it immediately.
+        self.counters.on_push();
+        let sent = if self.lossy {
+            match self.sender.try_send(Msg::Record(buf)) {
+                Ok(()) => Ok(()),
+                Err(TrySendError::Full(msg)) => {
+                    if let Msg::Record(buf) = msg {
+                        BufferPool::global().put(buf);
+                    }
+                    self.counters.on_pop(1);
+                    self.counters.on_drop();
+                    return Ok(());
+                }
+                Err(TrySendError::Disconnected(_)) => Err(()),
+            }
+        } else {
+            self.sender.send(Msg::Record(buf)).map_err(|_| ())
+        };
+
+        sent.map_err(|_| {
+            self.counters.on_pop(1);
+            worker_stopped()
+        })
+    }
+}
+
+/// A guard that flushes the remaining records and stops the worker thread when dropped.
+///
+/// It should be held until the end of `main`, for example with `let _guard = ...;`.
+/// Note that `let _ = ...;` drops the guard immediately.
+#[must_use = "dropping the guard stops the non-blocking writer"]
+pub struct WorkerGuard {
+    sender: SyncSender<Msg>,
+    done: Receiver<()>,
+    handle: Option<thread::JoinHandle<()>>,
+    counters: Arc<QueueCounters>,
+}
+
+impl WorkerGuard {
+    /// Returns a [`QueueMetrics`] handle to read the channel depth and drop counters of the writer.
+    pub fn metrics(&self) -> QueueMetrics {
+        QueueMetrics(self.counters.clone())
+    }
+}
+
+impl Drop for WorkerGuard {
+    fn drop(&mut self) {
+        if self.sender.send(Msg::Shutdown).is_err() {
+            // the worker has already stopped.
+            return;
+        }
+
+        match self.done.recv_timeout(SHUTDOWN_TIMEOUT) {
+            Ok(()) | Err(RecvTimeoutError::Disconnected) => {
+                if let Some(handle) = self.handle.take() {
+                    let _ = handle.join();
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                log_failure("WorkerGuard failed to flush logs: timed out waiting for the worker");
+            }
+        }
+    }
+}
+
+struct Worker<W: Write> {
+    w: W,
+    receiver: Receiver<Msg>,
+    counters: Arc<QueueCounters>,
+    report_dropped: bool,
+    // the number of dropped records already reported.
+    reported: u64,
+}
+
+impl<W: Write> Worker<W> {
+    fn run(mut self) {
+        while let Ok(msg) = self.receiver.recv() {
+            let mut shutdown = self.handle(msg);
+            // drain the records that are already queued before flushing.
+            while !shutdown {
+                match self.receiver.try_recv() {
+                    Ok(msg) => shutdown = self.handle(msg),
+                    Err(_) => break,
+                }
+            }
+            if self.report_dropped {
+                self.report_dropped();
+            }
+            if let Err(err) = self.w.flush() {
+                log_failure(format!("NonBlockingWriter failed to flush logs: {}", err).as_str());
+            }
+            if shutdown {
+                return;
+            }
+        }
+    }
+
+    // Writes a record with the number of records dropped since the last report.
+    fn report_dropped(&mut self) {
+        let dropped = self.counters.dropped();
+        let n = dropped - self.reported;
+        if n == 0 {
+            return;
+        }
+        self.reported = dropped;
+        if let Err(err) = writeln!(
+            self.w,
+            "{{\"dropped\":{},\"level\":\"WARN\",\"message\":\"dropped {} logs because the writer was full\",\"target\":\"structured_logger\",\"timestamp\":{}}}",
+            n,
+            n,
+            unix_ms()
+        ) {
+            log_failure(format!("NonBlockingWriter failed to write log: {}", err).as_str());
+        }
+    }
+
+    // Returns true if the worker should stop.
+    fn handle(&mut self, msg: Msg) -> bool {
+        match msg {
+            Msg::Record(buf) => {
+                self.counters.on_pop(1);
+                if let Err(err) = self.counters.health.track(self.w.write_all(&buf)) {
+                    // should never happen, but if it does, we log it.
+                    log_failure(format!("NonBlockingWriter failed to write log: {}", err).as_str());
+                }
+                BufferPool::global().put(buf);
+                false
+            }
+            Msg::Flush(ack) => {
+                let _ = ack.send(self.w.flush());
+                false
+            }
+            Msg::Shutdown => true,
+        }
+    }
+}
+
+fn worker_stopped() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        "non-blocking writer worker has stopped",
+    )
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the NonBlockingWriter for a given std::io::Write instance,
+/// using the default configuration.
+pub fn non_blocking<W: Write + Send + 'static>(w: W) -> (Box<dyn Writer>, WorkerGuard) {
+    NonBlockingBuilder::default().finish(w)
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the NonBlockingWriter for stdout, which never blocks the logging thread:
+/// the records are dropped while the channel is full, and the drop count is reported later.
+pub fn stdout() -> (Box<dyn Writer>, WorkerGuard) {
+    NonBlockingBuilder::default()
+        .with_report_dropped(true)
+        .with_thread_name("structured-logger-stdout")
+        .finish(io::stdout())
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the NonBlockingWriter for stderr, which never blocks the logging thread:
+/// the records are dropped while the channel is full, and the drop count is reported later.
+pub fn stderr() -> (Box<dyn Writer>, WorkerGuard) {
+    NonBlockingBuilder::default()
+        .with_report_dropped(true)
+        .with_thread_name("structured-logger-stderr")
+        .finish(io::stderr())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn non_blocking_works() {
+        let buf = SharedBuf::default();
+        let (writer, guard) = NonBlockingBuilder::default()
+            .with_lossy(false)
+            .finish(buf.clone());
+
+        for i in 0..100_u64 {
+            let mut value = BTreeMap::new();
+            value.insert(Key::from("message"), Value::from("hello"));
+            value.insert(Key::from("index"), Value::from(i));
+            writer.write_log(&value).unwrap();
+        }
+        let metrics = guard.metrics();
+        drop(guard);
+        assert_eq!(0, metrics.queue_len());
+        assert!(metrics.high_water_mark() >= 1);
+        assert_eq!(0, metrics.dropped());
+
+        let output = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(100, lines.len());
+        for (i, line) in lines.iter().enumerate() {
+            assert_eq!(format!("{{\"index\":{},\"message\":\"hello\"}}", i), *line);
+        }
+
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("message"), Value::from("hello"));
+        assert!(writer.write_log(&value).is_err());
+    }
+
+    // A writer that blocks its first write until it is released.
+    struct StalledBuf {
+        buf: SharedBuf,
+        stalled: Option<(mpsc::Sender<()>, mpsc::Receiver<()>)>,
+    }
+
+    impl Write for StalledBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if let Some((entered, release)) = self.stalled.take() {
+                entered.send(()).unwrap();
+                release.recv().unwrap();
+            }
+            self.buf.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn report_dropped_works() {
+        let buf = SharedBuf::default();
+        let (entered_tx, entered_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel();
+        let (writer, guard) = NonBlockingBuilder::default()
+            .with_buffered_lines_limit(1)
+            .with_report_dropped(true)
+            .finish(StalledBuf {
+                buf: buf.clone(),
+                stalled: Some((entered_tx, release_rx)),
+            });
+
+        for i in 0..6_u64 {
+            let mut value = BTreeMap::new();
+            value.insert(Key::from("index"), Value::from(i));
+            writer.write_log(&value).unwrap();
+            if i == 0 {
+                // the worker is stalled in the write of the first record.
+                entered_rx.recv().unwrap();
+            }
+        }
+        let metrics = guard.metrics();
+        assert_eq!(4, metrics.dropped());
+        release_tx.send(()).unwrap();
+        drop(guard);
+
+        let output = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(3, lines.len());
+        assert_eq!("{\"index\":0}", lines[0]);
+        assert_eq!("{\"index\":1}", lines[1]);
+        let report: serde_json::Value = serde_json::from_str(lines[2]).unwrap();
+        assert_eq!(4, report["dropped"]);
+        assert_eq!("WARN", report["level"]);
+        assert_eq!("structured_logger", report["target"]);
+    }
+}
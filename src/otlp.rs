@@ -0,0 +1,788 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # OTLP Writer Implementation
+//!
+//! A [`Writer`] implementation that exports the records to an OpenTelemetry collector, or any OTLP backend,
+//! with the [OTLP/HTTP](https://opentelemetry.io/docs/specs/otlp/#otlphttp) protocol in the binary protobuf
+//! encoding, with the [`ureq`](https://docs.rs/ureq) client. The collector's gRPC receiver is not supported,
+//! the endpoint is its HTTP receiver, on the port 4318 by default.
+//!
+//! A record is exported as a `LogRecord`:
+//! * the `message` is the body;
+//! * the `level` (or `severity` or `status`) is the severity text, and is mapped to the severity number,
+//!   see [`severity_number`](crate::severity_number);
+//! * the `timestamp`, in milliseconds, is the time of the record;
+//! * the `trace_id` and `span_id` hexadecimal strings, such as written by [`Builder::with_trace_context`](crate::Builder::with_trace_context),
+//!   are the trace context of the record;
+//! * the `module`, `file` and `line` are the `code.namespace`, `code.filepath` and `code.lineno` attributes;
+//! * the other fields are the attributes, with their JSON types.
+//!
+//! The resource attributes, such as `service.name`, are sent once per request, see [`OtlpOptions::resource`].
+//! They are not sent again as the attributes of the records, when [`Builder::with_resource`](crate::Builder::with_resource)
+//! adds them to every record.
+//!
+//! Records are encoded on the logging thread, and sent over a bounded channel to a worker thread that
+//! exports them in batches of [`OtlpOptions::batch_size`] records, or every [`OtlpOptions::flush_interval`].
+//! A batch that fails with a network error or a retryable status, 429, 502, 503 or 504, is retried
+//! with the [`OtlpOptions::backoff`], then dropped and reported to the failure handler.
+//! The records are dropped when the channel is full, see [`OtlpWriter::dropped`].
+//! To create a `Box<dyn Writer>` use the [`new_writer`] function.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{otlp, Builder, Resource};
+//!
+//! fn main() {
+//!     let opts = otlp::OtlpOptions {
+//!         endpoint: "http://127.0.0.1:4318/v1/logs".to_string(),
+//!         ..Default::default()
+//!     };
+//!     let resource = Resource::new()
+//!         .with_service_name("checkout")
+//!         .with_deployment_environment("production");
+//!     Builder::with_level("info")
+//!         .with_resource(resource)
+//!         .with_default_writer(otlp::new_writer(opts))
+//!         .init();
+//!
+//!     log::info!(order = 42; "hello world");
+//! }
+//! ```
+//!
+
+use std::{
+    collections::BTreeMap,
+    io,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::fields::FieldMap;
+use crate::metrics::{QueueCounters, QueueMetrics};
+use crate::retry::Backoff;
+use crate::route::{parse_level, LEVEL_KEYS};
+use crate::{log_failure, severity_number, unix_ns, Fields, Key, Resource, Value, Writer};
+
+/// How long [`Writer::flush`] waits for the worker thread to export the queued records.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The options of an [`OtlpWriter`].
+#[derive(Debug, Clone)]
+pub struct OtlpOptions {
+    /// The URL of the logs endpoint, the default is `"http://localhost:4318/v1/logs"`.
+    pub endpoint: String,
+    /// The headers of the requests, such as the API key of a backend, the default is empty.
+    pub headers: Vec<(String, String)>,
+    /// The resource attributes, such as `service.name`, `service.namespace` or `host.name`,
+    /// the default is `None`, for the [`Resource::global`] set by [`Builder::with_resource`](crate::Builder::with_resource).
+    pub resource: Option<Resource>,
+    /// The maximum number of records exported by a request, the default is 512.
+    pub batch_size: usize,
+    /// How long the records wait for a batch to fill before they are exported, the default is 1 second.
+    pub flush_interval: Duration,
+    /// The maximum number of records that can be buffered in the channel, the default is 2048.
+    pub capacity: usize,
+    /// The retries of a failed batch, see [`Backoff`].
+    pub backoff: Backoff,
+    /// The timeout of a request, the default is 10 seconds.
+    pub timeout: Duration,
+}
+
+impl Default for OtlpOptions {
+    fn default() -> Self {
+        OtlpOptions {
+            endpoint: "http://localhost:4318/v1/logs".to_string(),
+            headers: Vec::new(),
+            resource: None,
+            batch_size: 512,
+            flush_interval: Duration::from_secs(1),
+            capacity: 2048,
+            backoff: Backoff::default(),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+enum Msg {
+    // an encoded `LogRecord` message.
+    Record(Vec<u8>),
+    Flush(mpsc::Sender<io::Result<()>>),
+    Shutdown,
+}
+
+/// A Writer implementation that exports logs to an OTLP endpoint on a dedicated worker thread.
+pub struct OtlpWriter {
+    resource: Option<Resource>,
+    sender: SyncSender<Msg>,
+    counters: Arc<QueueCounters>,
+}
+
+impl OtlpWriter {
+    /// Creates a new OtlpWriter instance, and starts its worker thread,
+    /// which stops when the writer is dropped.
+    pub fn new(opts: OtlpOptions) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(opts.capacity.max(1));
+        let counters = Arc::new(QueueCounters::default());
+        let agent: ureq::Agent = ureq::Agent::config_builder()
+            .http_status_as_error(false)
+            .timeout_global(Some(opts.timeout))
+            .build()
+            .into();
+        let mut scope = Vec::new();
+        put_str(&mut scope, 1, env!("CARGO_PKG_NAME"));
+        put_str(&mut scope, 2, env!("CARGO_PKG_VERSION"));
+        let resource = opts.resource.clone();
+        let worker = Worker {
+            agent,
+            opts,
+            scope,
+            receiver,
+            counters: counters.clone(),
+            batch: Vec::new(),
+            records: 0,
+        };
+        thread::Builder::new()
+            .name("structured-logger-otlp".to_string())
+            .spawn(move || worker.run())
+            .expect("failed to spawn the OTLP writer thread");
+        OtlpWriter {
+            resource,
+            sender,
+            counters,
+        }
+    }
+
+    /// Returns the number of records dropped because the channel was full.
+    pub fn dropped(&self) -> u64 {
+        self.counters.dropped()
+    }
+
+    /// Returns a [`QueueMetrics`] handle to read the channel depth and drop counters.
+    pub fn metrics(&self) -> QueueMetrics {
+        QueueMetrics(self.counters.clone())
+    }
+
+    fn send(&self, buf: Vec<u8>) -> Result<(), io::Error> {
+        // count the record before sending, the worker may pop it immediately.
+        self.counters.on_push();
+        match self.sender.try_send(Msg::Record(buf)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                self.counters.on_pop(1);
+                self.counters.on_drop();
+                Ok(())
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                self.counters.on_pop(1);
+                Err(worker_stopped())
+            }
+        }
+    }
+}
+
+/// Implements Writer trait for OtlpWriter.
+impl Writer for OtlpWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        let record = serde_json::to_value(FieldMap(value))?;
+        let resource = self.resource.as_ref().or_else(|| Resource::global());
+        self.send(encode_record(record, resource))
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        self.write_log(&fields.to_map())
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        let (tx, rx) = mpsc::channel();
+        self.sender
+            .send(Msg::Flush(tx))
+            .map_err(|_| worker_stopped())?;
+        match rx.recv_timeout(FLUSH_TIMEOUT) {
+            Ok(res) => res,
+            Err(RecvTimeoutError::Timeout) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "OtlpWriter flush timed out",
+            )),
+            Err(RecvTimeoutError::Disconnected) => Err(worker_stopped()),
+        }
+    }
+
+    fn shutdown(&self) -> Result<(), io::Error> {
+        self.flush()?;
+        let _ = self.sender.send(Msg::Shutdown);
+        Ok(())
+    }
+
+    fn healthy(&self) -> bool {
+        self.counters.health.healthy()
+    }
+}
+
+// Encodes a record as a `LogRecord` message, without the attributes of the resource.
+fn encode_record(record: serde_json::Value, resource: Option<&Resource>) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(256);
+    let fields = match record {
+        serde_json::Value::Object(fields) => fields,
+        _ => return buf,
+    };
+
+    // time_unix_nano
+    if let Some(ms) = fields.get("timestamp").and_then(|v| v.as_u64()) {
+        put_fixed64(&mut buf, 1, ms.saturating_mul(1_000_000));
+    }
+    if let Some(level) = LEVEL_KEYS
+        .iter()
+        .find_map(|key| fields.get(*key).and_then(|v| v.as_str()))
+    {
+        // severity_number and severity_text
+        if let Some(level) = parse_level(level) {
+            put_varint_field(&mut buf, 2, severity_number(level) as u64);
+        }
+        put_str(&mut buf, 3, level);
+    }
+    // body
+    if let Some(message) = fields.get("message") {
+        put_message(&mut buf, 5, |buf| put_any_value(buf, message));
+    }
+    for (key, value) in &fields {
+        let key = match key.as_str() {
+            "timestamp" | "message" | "severity_number" | "trace_id" | "span_id" => continue,
+            key if LEVEL_KEYS.contains(&key) => continue,
+            key if resource.and_then(|r| r.get(key)).is_some() => continue,
+            "module" => "code.namespace",
+            "file" => "code.filepath",
+            "line" => "code.lineno",
+            key => key,
+        };
+        // attributes
+        put_message(&mut buf, 6, |buf| {
+            put_str(buf, 1, key);
+            put_message(buf, 2, |buf| put_any_value(buf, value));
+        });
+    }
+    // trace_id and span_id
+    for (key, field, len) in [("trace_id", 9, 16), ("span_id", 10, 8)] {
+        if let Some(id) = fields
+            .get(key)
+            .and_then(|v| v.as_str())
+            .and_then(|v| decode_hex(v, len))
+        {
+            put_bytes(&mut buf, field, &id);
+        }
+    }
+    // observed_time_unix_nano
+    put_fixed64(&mut buf, 11, unix_ns());
+    buf
+}
+
+// Encodes a JSON value as an `AnyValue` message.
+fn put_any_value(buf: &mut Vec<u8>, value: &serde_json::Value) {
+    match value {
+        serde_json::Value::Null => {}
+        serde_json::Value::String(v) => put_str(buf, 1, v),
+        serde_json::Value::Bool(v) => put_varint_field(buf, 2, *v as u64),
+        serde_json::Value::Number(v) => match v.as_i64() {
+            Some(v) => put_varint_field(buf, 3, v as u64),
+            None => match v.as_f64() {
+                Some(v) => {
+                    put_tag(buf, 4, 1);
+                    buf.extend_from_slice(&v.to_bits().to_le_bytes());
+                }
+                // a u64 that doesn't fit in an int_value.
+                None => put_str(buf, 1, &v.to_string()),
+            },
+        },
+        serde_json::Value::Array(values) => put_message(buf, 5, |buf| {
+            for value in values {
+                put_message(buf, 1, |buf| put_any_value(buf, value));
+            }
+        }),
+        serde_json::Value::Object(fields) => put_message(buf, 6, |buf| {
+            for (key, value) in fields {
+                put_message(buf, 1, |buf| {
+                    put_str(buf, 1, key);
+                    put_message(buf, 2, |buf| put_any_value(buf, value));
+                });
+            }
+        }),
+    }
+}
+
+fn decode_hex(s: &str, len: usize) -> Option<Vec<u8>> {
+    if s.len() != len * 2 {
+        return None;
+    }
+    (0..len)
+        .map(|i| u8::from_str_radix(s.get(i * 2..i * 2 + 2)?, 16).ok())
+        .collect()
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push((v as u8 & 0x7f) | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+fn put_tag(buf: &mut Vec<u8>, field: u32, wire_type: u8) {
+    put_varint(buf, ((field as u64) << 3) | wire_type as u64);
+}
+
+fn put_varint_field(buf: &mut Vec<u8>, field: u32, v: u64) {
+    put_tag(buf, field, 0);
+    put_varint(buf, v);
+}
+
+fn put_fixed64(buf: &mut Vec<u8>, field: u32, v: u64) {
+    put_tag(buf, field, 1);
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_bytes(buf: &mut Vec<u8>, field: u32, v: &[u8]) {
+    put_tag(buf, field, 2);
+    put_varint(buf, v.len() as u64);
+    buf.extend_from_slice(v);
+}
+
+fn put_str(buf: &mut Vec<u8>, field: u32, v: &str) {
+    put_bytes(buf, field, v.as_bytes());
+}
+
+// Encodes the message written by `f` as a length-delimited field.
+fn put_message(buf: &mut Vec<u8>, field: u32, f: impl FnOnce(&mut Vec<u8>)) {
+    let mut msg = Vec::new();
+    f(&mut msg);
+    put_bytes(buf, field, &msg);
+}
+
+struct Worker {
+    agent: ureq::Agent,
+    opts: OtlpOptions,
+    // the encoded `InstrumentationScope` message.
+    scope: Vec<u8>,
+    receiver: Receiver<Msg>,
+    counters: Arc<QueueCounters>,
+    // the `log_records` fields of the `ScopeLogs` message of the current batch.
+    batch: Vec<u8>,
+    records: usize,
+}
+
+impl Worker {
+    fn run(mut self) {
+        // when the current batch must be exported, `None` if it is empty.
+        let mut deadline: Option<Instant> = None;
+        loop {
+            let msg = match deadline {
+                None => self
+                    .receiver
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected),
+                Some(deadline) => self
+                    .receiver
+                    .recv_timeout(deadline.saturating_duration_since(Instant::now())),
+            };
+            match msg {
+                Ok(Msg::Record(buf)) => {
+                    self.counters.on_pop(1);
+                    put_bytes(&mut self.batch, 2, &buf);
+                    self.records += 1;
+                    if self.records >= self.opts.batch_size {
+                        let _ = self.export();
+                        deadline = None;
+                    } else if deadline.is_none() {
+                        deadline = Some(Instant::now() + self.opts.flush_interval);
+                    }
+                }
+                Ok(Msg::Flush(ack)) => {
+                    let _ = ack.send(self.export());
+                    deadline = None;
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    let _ = self.export();
+                    deadline = None;
+                }
+                Ok(Msg::Shutdown) | Err(RecvTimeoutError::Disconnected) => {
+                    let _ = self.export();
+                    return;
+                }
+            }
+        }
+    }
+
+    // Exports the current batch, retries it on the transient errors, and drops it after the last retry.
+    fn export(&mut self) -> Result<(), io::Error> {
+        if self.records == 0 {
+            return Ok(());
+        }
+
+        // ExportLogsServiceRequest { resource_logs: [ResourceLogs { resource, scope_logs: [ScopeLogs { scope, log_records }] }] }
+        let mut body = Vec::with_capacity(self.batch.len() + 128);
+        let resource = self.opts.resource.as_ref().or_else(|| Resource::global());
+        put_message(&mut body, 1, |buf| {
+            put_message(buf, 1, |buf| {
+                for (key, value) in resource.map(|r| r.attributes()).unwrap_or_default() {
+                    put_message(buf, 1, |buf| {
+                        put_str(buf, 1, key);
+                        put_message(buf, 2, |buf| put_str(buf, 1, value));
+                    });
+                }
+            });
+            put_message(buf, 2, |buf| {
+                put_bytes(buf, 1, &self.scope);
+                buf.extend_from_slice(&self.batch);
+            });
+        });
+
+        let mut attempts = 0;
+        let res = loop {
+            attempts += 1;
+            match self.post(&body) {
+                Ok(()) => break Ok(()),
+                Err((_, true)) if self.opts.backoff.can_retry(attempts) => {
+                    self.counters.health.set(false);
+                    thread::sleep(self.opts.backoff.delay(attempts));
+                }
+                Err((err, _)) => {
+                    log_failure(
+                        format!("OtlpWriter dropped {} logs: {}", self.records, err).as_str(),
+                    );
+                    break Err(err);
+                }
+            }
+        };
+        self.batch.clear();
+        self.records = 0;
+        self.counters.health.track(res)
+    }
+
+    // Returns the error, and whether it is transient.
+    fn post(&self, body: &[u8]) -> Result<(), (io::Error, bool)> {
+        let mut req = self
+            .agent
+            .post(&self.opts.endpoint)
+            .header("Content-Type", "application/x-protobuf");
+        for (name, value) in &self.opts.headers {
+            req = req.header(name, value);
+        }
+
+        let res = req
+            .send(body)
+            .map_err(|err| (io::Error::other(err), true))?;
+        let status = res.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        Err((
+            io::Error::other(format!("OTLP endpoint returned {}", status)),
+            matches!(status.as_u16(), 429 | 502 | 503 | 504),
+        ))
+    }
+}
+
+fn worker_stopped() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "OtlpWriter worker has stopped")
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the OtlpWriter.
+pub fn new_writer(opts: OtlpOptions) -> Box<dyn Writer> {
+    Box::new(OtlpWriter::new(opts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    // A minimal HTTP server that replies with the given statuses, it returns the bodies of the requests.
+    fn serve(listener: TcpListener, statuses: Vec<u16>) -> Vec<Vec<u8>> {
+        let mut bodies = Vec::new();
+        for status in statuses {
+            let (stream, _) = listener.accept().unwrap();
+            let mut w = stream.try_clone().unwrap();
+            let mut r = BufReader::new(stream);
+            let mut len = 0;
+            loop {
+                let mut line = String::new();
+                r.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some(v) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    len = v.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0_u8; len];
+            r.read_exact(&mut body).unwrap();
+            w.write_all(
+                format!(
+                    "HTTP/1.1 {} X\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+            bodies.push(body);
+        }
+        bodies
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Field {
+        Varint(u64),
+        Fixed64(u64),
+        Bytes(Vec<u8>),
+    }
+
+    // Decodes the fields of a protobuf message.
+    fn decode(mut buf: &[u8]) -> Vec<(u64, Field)> {
+        fn varint(buf: &mut &[u8]) -> u64 {
+            let mut v = 0;
+            for shift in (0..64).step_by(7) {
+                let b = buf[0];
+                *buf = &buf[1..];
+                v |= ((b & 0x7f) as u64) << shift;
+                if b < 0x80 {
+                    break;
+                }
+            }
+            v
+        }
+
+        let mut fields = Vec::new();
+        while !buf.is_empty() {
+            let tag = varint(&mut buf);
+            let field = match tag & 7 {
+                0 => Field::Varint(varint(&mut buf)),
+                1 => {
+                    let (v, rest) = buf.split_at(8);
+                    buf = rest;
+                    Field::Fixed64(u64::from_le_bytes(v.try_into().unwrap()))
+                }
+                2 => {
+                    let len = varint(&mut buf) as usize;
+                    let (v, rest) = buf.split_at(len);
+                    buf = rest;
+                    Field::Bytes(v.to_vec())
+                }
+                _ => panic!("unexpected wire type"),
+            };
+            fields.push((tag >> 3, field));
+        }
+        fields
+    }
+
+    fn bytes(fields: &[(u64, Field)], n: u64) -> Vec<&[u8]> {
+        fields
+            .iter()
+            .filter_map(|(i, f)| match f {
+                Field::Bytes(v) if *i == n => Some(v.as_slice()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn otlp_writer_works() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}/v1/logs", listener.local_addr().unwrap());
+        let server = thread::spawn(move || serve(listener, vec![503, 200]));
+
+        let writer = OtlpWriter::new(OtlpOptions {
+            endpoint,
+            resource: Some(Resource::new().with_service_name("checkout")),
+            batch_size: 2,
+            flush_interval: Duration::from_secs(60),
+            backoff: Backoff {
+                initial: Duration::from_millis(10),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        for i in 0..2 {
+            let mut value = BTreeMap::new();
+            value.insert(Key::from("level"), Value::from("WARN"));
+            value.insert(Key::from("message"), Value::from("hello"));
+            value.insert(Key::from("timestamp"), Value::from(1679745592127_u64));
+            value.insert(
+                Key::from("trace_id"),
+                Value::from("4bf92f3577b34da6a3ce929d0e0e4736"),
+            );
+            value.insert(Key::from("service.name"), Value::from("checkout"));
+            value.insert(Key::from("i"), Value::from(i));
+            writer.write_log(&value).unwrap();
+        }
+        writer.flush().unwrap();
+
+        let bodies = server.join().unwrap();
+        assert_eq!(2, bodies.len(), "the failed batch is retried");
+        assert_eq!(bodies[0], bodies[1]);
+
+        let request = decode(&bodies[1]);
+        let resource_logs = decode(bytes(&request, 1)[0]);
+        let resource = decode(bytes(&resource_logs, 1)[0]);
+        let attribute = decode(bytes(&resource, 1)[0]);
+        assert_eq!(vec![b"service.name".as_slice()], bytes(&attribute, 1));
+        assert_eq!(
+            vec![(1, Field::Bytes(b"checkout".to_vec()))],
+            decode(bytes(&attribute, 2)[0])
+        );
+
+        let scope_logs = decode(bytes(&resource_logs, 2)[0]);
+        let scope = decode(bytes(&scope_logs, 1)[0]);
+        assert_eq!(vec![b"structured-logger".as_slice()], bytes(&scope, 1));
+        let records = bytes(&scope_logs, 2);
+        assert_eq!(2, records.len());
+
+        let record = decode(records[1]);
+        assert_eq!((1, Field::Fixed64(1679745592127000000)), record[0]);
+        assert_eq!((2, Field::Varint(13)), record[1]);
+        assert_eq!((3, Field::Bytes(b"WARN".to_vec())), record[2]);
+        assert_eq!(
+            vec![(1, Field::Bytes(b"hello".to_vec()))],
+            decode(bytes(&record, 5)[0])
+        );
+        assert_eq!(
+            1,
+            bytes(&record, 6).len(),
+            "the resource attributes are not repeated"
+        );
+        let attribute = decode(bytes(&record, 6)[0]);
+        assert_eq!(vec![b"i".as_slice()], bytes(&attribute, 1));
+        assert_eq!(vec![(3, Field::Varint(1))], decode(bytes(&attribute, 2)[0]));
+        assert_eq!(
+            vec![decode_hex("4bf92f3577b34da6a3ce929d0e0e4736", 16)
+                .unwrap()
+                .as_slice()],
+            bytes(&record, 9)
+        );
+        assert!(matches!(record.last(), Some((11, Field::Fixed64(_)))));
+        assert_eq!(0, writer.dropped());
+    }
+
+    fn write(writer: &OtlpWriter, i: i64) -> Result<(), io::Error> {
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("i"), Value::from(i));
+        writer.write_log(&value)
+    }
+
+    // Returns the values of the `i` attribute of the records of an `ExportLogsServiceRequest`.
+    fn record_ids(body: &[u8]) -> Vec<u64> {
+        let request = decode(body);
+        let resource_logs = decode(bytes(&request, 1)[0]);
+        let scope_logs = decode(bytes(&resource_logs, 2)[0]);
+        bytes(&scope_logs, 2)
+            .into_iter()
+            .map(|record| {
+                let record = decode(record);
+                let attribute = decode(bytes(&record, 6)[0]);
+                match decode(bytes(&attribute, 2)[0]).pop() {
+                    Some((3, Field::Varint(i))) => i,
+                    v => panic!("unexpected attribute value {:?}", v),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn otlp_writer_drop_works() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}/v1/logs", listener.local_addr().unwrap());
+        let server = thread::spawn(move || serve(listener, vec![400, 503, 429, 200]));
+
+        let writer = OtlpWriter::new(OtlpOptions {
+            endpoint,
+            flush_interval: Duration::from_secs(60),
+            backoff: Backoff {
+                initial: Duration::from_millis(10),
+                max_attempts: 2,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        // a client error is not retried.
+        write(&writer, 0).unwrap();
+        assert!(writer.flush().is_err());
+        assert!(!writer.healthy());
+        // a transient error is retried, then the batch is dropped.
+        write(&writer, 1).unwrap();
+        assert!(writer.flush().is_err());
+        assert!(!writer.healthy());
+        // the next batch doesn't contain the dropped records.
+        write(&writer, 2).unwrap();
+        writer.flush().unwrap();
+        assert!(writer.healthy());
+
+        let bodies = server.join().unwrap();
+        let ids: Vec<Vec<u64>> = bodies.iter().map(|body| record_ids(body)).collect();
+        assert_eq!(vec![vec![0], vec![1], vec![1], vec![2]], ids);
+    }
+
+    #[test]
+    fn otlp_writer_full_works() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}/v1/logs", listener.local_addr().unwrap());
+        let (release, released) = mpsc::channel::<()>();
+        let server = thread::spawn(move || {
+            released.recv().unwrap();
+            serve(listener, vec![200, 200])
+        });
+
+        let writer = OtlpWriter::new(OtlpOptions {
+            endpoint,
+            batch_size: 1,
+            capacity: 1,
+            ..Default::default()
+        });
+        let metrics = writer.metrics();
+        // the worker is blocked by the server in the export of the first record.
+        write(&writer, 0).unwrap();
+        while metrics.queue_len() > 0 {
+            thread::yield_now();
+        }
+        // the second record fills the channel, the others are dropped.
+        for i in 1..5 {
+            write(&writer, i).unwrap();
+        }
+        assert_eq!(1, metrics.queue_len());
+        assert_eq!(3, writer.dropped());
+        release.send(()).unwrap();
+        writer.flush().unwrap();
+
+        let bodies = server.join().unwrap();
+        let ids: Vec<Vec<u64>> = bodies.iter().map(|body| record_ids(body)).collect();
+        assert_eq!(vec![vec![0], vec![1]], ids);
+        assert_eq!(3, writer.dropped());
+    }
+
+    #[test]
+    fn otlp_writer_shutdown_works() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}/v1/logs", listener.local_addr().unwrap());
+        let server = thread::spawn(move || serve(listener, vec![200]));
+
+        let writer = OtlpWriter::new(OtlpOptions {
+            endpoint,
+            flush_interval: Duration::from_secs(60),
+            ..Default::default()
+        });
+        for i in 0..3 {
+            write(&writer, i).unwrap();
+        }
+        // the queued records are exported before the worker stops.
+        writer.shutdown().unwrap();
+        let bodies = server.join().unwrap();
+        assert_eq!(vec![0, 1, 2], record_ids(&bodies[0]));
+
+        // the writer fails once the worker has stopped.
+        while write(&writer, 3).is_ok() {
+            thread::yield_now();
+        }
+        assert!(writer.flush().is_err());
+    }
+}
@@ -0,0 +1,144 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Buffer Pool
+//!
+//! A pool of reusable buffers for the encoded records, shared by all writers.
+//! The buffering writers ([`async_json`](crate::async_json), [`non_blocking`](crate::non_blocking), ...)
+//! encode every record into a buffer taken from the [`BufferPool::global`] pool,
+//! and return it to the pool once the record has been written,
+//! so the allocation and growth of the buffers is amortized across records.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::pool::BufferPool;
+//!
+//! // retain up to 4096 buffers of up to 16 KiB each.
+//! BufferPool::global().set_max_buffers(4096);
+//! BufferPool::global().set_max_retained_capacity(16 * 1024);
+//! ```
+//!
+
+use parking_lot::{const_mutex, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The default maximum number of buffers retained by a pool.
+pub const DEFAULT_MAX_BUFFERS: usize = 1024;
+
+/// The default maximum capacity of a retained buffer, larger buffers are dropped.
+pub const DEFAULT_MAX_RETAINED_CAPACITY: usize = 64 * 1024;
+
+/// The initial capacity of a new buffer.
+const INITIAL_CAPACITY: usize = 256;
+
+static GLOBAL: BufferPool = BufferPool::new(DEFAULT_MAX_BUFFERS, DEFAULT_MAX_RETAINED_CAPACITY);
+
+/// A pool of reusable `Vec<u8>` buffers.
+pub struct BufferPool {
+    buffers: Mutex<Vec<Vec<u8>>>,
+    max_buffers: AtomicUsize,
+    max_retained_capacity: AtomicUsize,
+}
+
+impl BufferPool {
+    /// Creates a new BufferPool instance that retains up to `max_buffers` buffers
+    /// with a capacity of up to `max_retained_capacity` bytes.
+    pub const fn new(max_buffers: usize, max_retained_capacity: usize) -> Self {
+        BufferPool {
+            buffers: const_mutex(Vec::new()),
+            max_buffers: AtomicUsize::new(max_buffers),
+            max_retained_capacity: AtomicUsize::new(max_retained_capacity),
+        }
+    }
+
+    /// Returns the pool shared by the writers of this crate.
+    pub fn global() -> &'static BufferPool {
+        &GLOBAL
+    }
+
+    /// Sets the maximum number of retained buffers, the extra buffers are dropped.
+    pub fn set_max_buffers(&self, max_buffers: usize) {
+        self.max_buffers.store(max_buffers, Ordering::Relaxed);
+        self.buffers.lock().truncate(max_buffers);
+    }
+
+    /// Sets the maximum capacity of a retained buffer, the larger buffers are dropped.
+    pub fn set_max_retained_capacity(&self, max_retained_capacity: usize) {
+        self.max_retained_capacity
+            .store(max_retained_capacity, Ordering::Relaxed);
+        self.buffers
+            .lock()
+            .retain(|buf| buf.capacity() <= max_retained_capacity);
+    }
+
+    /// Returns the maximum capacity of a retained buffer.
+    pub fn max_retained_capacity(&self) -> usize {
+        self.max_retained_capacity.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of buffers in the pool.
+    pub fn len(&self) -> usize {
+        self.buffers.lock().len()
+    }
+
+    /// Returns true if there is no buffer in the pool.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Takes an empty buffer from the pool, or allocates a new one.
+    pub fn get(&self) -> Vec<u8> {
+        self.buffers
+            .lock()
+            .pop()
+            .unwrap_or_else(|| Vec::with_capacity(INITIAL_CAPACITY))
+    }
+
+    /// Returns a buffer to the pool, it is dropped if it is too large or the pool is full.
+    pub fn put(&self, mut buf: Vec<u8>) {
+        if buf.capacity() > self.max_retained_capacity() {
+            return;
+        }
+        buf.clear();
+        let mut buffers = self.buffers.lock();
+        if buffers.len() < self.max_buffers.load(Ordering::Relaxed) {
+            buffers.push(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffer_pool_works() {
+        let pool = BufferPool::new(2, 1024);
+        assert!(pool.is_empty());
+
+        let mut buf = pool.get();
+        assert_eq!(INITIAL_CAPACITY, buf.capacity());
+        buf.extend_from_slice(b"hello");
+        let ptr = buf.as_ptr();
+        pool.put(buf);
+        assert_eq!(1, pool.len());
+
+        let buf = pool.get();
+        assert!(buf.is_empty());
+        assert_eq!(ptr, buf.as_ptr());
+
+        // too large to be retained.
+        pool.put(Vec::with_capacity(2048));
+        assert!(pool.is_empty());
+
+        pool.put(buf);
+        pool.put(Vec::new());
+        pool.put(Vec::new());
+        assert_eq!(2, pool.len());
+
+        pool.set_max_buffers(1);
+        assert_eq!(1, pool.len());
+        pool.set_max_retained_capacity(0);
+        assert!(pool.is_empty());
+    }
+}
@@ -0,0 +1,386 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # PostgreSQL Writer Implementation
+//!
+//! A [`Writer`] implementation that inserts structured values asynchronous into a PostgreSQL table,
+//! with the [`tokio_postgres`](https://docs.rs/tokio-postgres) client.
+//! The `timestamp`, `level`, `target` and `message` fields are stored in their own columns,
+//! and the other fields in a JSONB `attributes` column, in a table such as:
+//! ```sql
+//! CREATE TABLE logs (
+//!     timestamp  timestamptz NOT NULL,
+//!     level      text,
+//!     target     text,
+//!     message    text,
+//!     attributes jsonb NOT NULL
+//! );
+//! ```
+//!
+//! Records are buffered in a bounded queue, the [`BackpressurePolicy`] decides what happens when it is full,
+//! and inserted in batches with `COPY ... FROM STDIN` by a single long-lived task per writer,
+//! which is spawned on the current tokio runtime by the first log call.
+//! A batch that fails to be inserted is dropped and reported to the failure handler.
+//! To create a `Box<dyn Writer>` use the [`new_writer`] function.
+//!
+//! Example:
+//! ```rust,no_run
+//! use structured_logger::{postgres, Builder};
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     let (client, connection) = postgres::connect("host=localhost user=app", postgres::NoTls)
+//!         .await
+//!         .unwrap();
+//!     tokio::spawn(connection);
+//!
+//!     Builder::with_level("info")
+//!         .with_default_writer(postgres::new_writer(client, postgres::PostgresOptions::default()))
+//!         .init();
+//!
+//!     log::info!("order placed");
+//! }
+//! ```
+//!
+
+use bytes::Bytes;
+use futures_util::SinkExt;
+use log::Level;
+use serde_json::Map;
+use std::{
+    collections::BTreeMap,
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::runtime::Handle;
+
+use crate::async_json::{DEFAULT_BATCH_SIZE, DEFAULT_QUEUE_CAPACITY, DEFAULT_SHUTDOWN_TIMEOUT};
+use crate::fields::FieldMap;
+use crate::metrics::QueueMetrics;
+use crate::pool::BufferPool;
+use crate::queue::Queue;
+use crate::{log_failure, unix_ms, Fields, Key, Rfc3339, Value, Writer};
+
+pub use crate::queue::{AdaptiveBatching, BackpressurePolicy};
+pub use tokio_postgres::{connect, Client, NoTls};
+
+/// How long [`Writer::flush`] waits for the queued records to be inserted.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The options to create a [`PostgresWriter`].
+#[derive(Debug, Clone)]
+pub struct PostgresOptions {
+    /// The table of the records, optionally qualified by its schema such as `"audit.logs"`, the default is `"logs"`.
+    /// The names separated by `.` are quoted as identifiers in the `COPY` statement, so they are case-sensitive.
+    pub table: String,
+    /// The maximum number of records that can be buffered in the queue.
+    pub capacity: usize,
+    /// The policy to apply when the queue is full.
+    pub policy: BackpressurePolicy,
+    /// The maximum number of records inserted by a `COPY` statement.
+    pub batch_size: usize,
+    /// How long to wait for more records before inserting a batch that is not full, the default is 100 milliseconds.
+    pub batch_interval: Duration,
+    /// The adaptive batching, which sizes the batches from the rate of the records instead of
+    /// the `batch_size` and `batch_interval`, the default is `None`. See [`AdaptiveBatching`].
+    pub adaptive: Option<AdaptiveBatching>,
+}
+
+impl Default for PostgresOptions {
+    fn default() -> Self {
+        PostgresOptions {
+            table: "logs".to_string(),
+            capacity: DEFAULT_QUEUE_CAPACITY,
+            policy: BackpressurePolicy::default(),
+            batch_size: DEFAULT_BATCH_SIZE,
+            batch_interval: Duration::from_millis(100),
+            adaptive: None,
+        }
+    }
+}
+
+struct Shared {
+    client: Client,
+    statement: String,
+    queue: Queue,
+    // whether the consumer task is inserting popped records.
+    busy: AtomicBool,
+}
+
+/// A Writer implementation that inserts logs asynchronous into a PostgreSQL table.
+pub struct PostgresWriter {
+    shared: Arc<Shared>,
+    // whether the consumer task has been spawned.
+    started: AtomicBool,
+}
+
+impl PostgresWriter {
+    /// Creates a new PostgresWriter instance with a connected client.
+    pub fn new(client: Client, opts: PostgresOptions) -> Self {
+        PostgresWriter {
+            shared: Arc::new(Shared {
+                client,
+                statement: format!(
+                    "COPY {} (timestamp, level, target, message, attributes) FROM STDIN",
+                    quote_table(&opts.table)
+                ),
+                queue: Queue::new(
+                    opts.capacity,
+                    opts.policy,
+                    opts.batch_size,
+                    opts.batch_interval,
+                )
+                .with_adaptive(opts.adaptive),
+                busy: AtomicBool::new(false),
+            }),
+            started: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the number of records dropped because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.shared.queue.dropped()
+    }
+
+    /// Returns a [`QueueMetrics`] handle to read the queue depth and drop counters.
+    pub fn metrics(&self) -> QueueMetrics {
+        self.shared.queue.metrics()
+    }
+
+    fn enqueue(&self, record: serde_json::Value, level: Option<Level>) -> Result<(), io::Error> {
+        let queue = &self.shared.queue;
+        if queue.is_closed() {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "PostgresWriter has been shut down",
+            ));
+        }
+        let mut buf = BufferPool::global().get();
+        if let serde_json::Value::Object(record) = record {
+            encode_row(&mut buf, record)?;
+        }
+        if !queue.push(buf, level) {
+            return Ok(());
+        }
+
+        if !self.started.swap(true, Ordering::SeqCst) {
+            Handle::current().spawn(consume(self.shared.clone()));
+        }
+        Ok(())
+    }
+
+    // Waits for the queued records to be inserted, from a synchronous context.
+    fn wait_idle(&self, timeout: Duration) -> Result<(), io::Error> {
+        let deadline = Instant::now() + timeout;
+        let shared = &self.shared;
+        while shared.queue.len() > 0 || shared.busy.load(Ordering::SeqCst) {
+            if !self.started.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "PostgresWriter flush timed out",
+                ));
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        Ok(())
+    }
+}
+
+// Encodes a record as a row of the text format of `COPY`.
+fn encode_row(
+    buf: &mut Vec<u8>,
+    mut record: Map<String, serde_json::Value>,
+) -> Result<(), io::Error> {
+    let timestamp = match record.remove("timestamp") {
+        Some(serde_json::Value::Number(ms)) => ms.as_u64().unwrap_or_else(unix_ms),
+        _ => unix_ms(),
+    };
+    // the `time` field of the GCP mode is the same timestamp.
+    if matches!(record.get("time"), Some(serde_json::Value::String(_))) {
+        record.remove("time");
+    }
+    buf.extend_from_slice(Rfc3339::from_unix_ms(timestamp).as_str().as_bytes());
+
+    for keys in [
+        &["level", "severity", "status"][..],
+        &["target"][..],
+        &["message"][..],
+    ] {
+        buf.push(b'\t');
+        match keys.iter().find_map(|key| match record.remove(*key) {
+            Some(serde_json::Value::String(s)) => Some(s),
+            _ => None,
+        }) {
+            Some(s) => escape(buf, &s),
+            None => buf.extend_from_slice(b"\\N"),
+        }
+    }
+
+    buf.push(b'\t');
+    escape(buf, &serde_json::to_string(&record)?);
+    buf.push(b'\n');
+    Ok(())
+}
+
+// Quotes the names of a table, optionally qualified by its schema, as SQL identifiers.
+fn quote_table(table: &str) -> String {
+    table
+        .split('.')
+        .map(|name| format!("\"{}\"", name.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+// Escapes a column value of the text format of `COPY`.
+fn escape(buf: &mut Vec<u8>, s: &str) {
+    for b in s.bytes() {
+        match b {
+            b'\\' => buf.extend_from_slice(b"\\\\"),
+            b'\t' => buf.extend_from_slice(b"\\t"),
+            b'\n' => buf.extend_from_slice(b"\\n"),
+            b'\r' => buf.extend_from_slice(b"\\r"),
+            b => buf.push(b),
+        }
+    }
+}
+
+/// Implements Writer trait for PostgresWriter.
+impl Writer for PostgresWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        self.enqueue(
+            serde_json::to_value(FieldMap(value))?,
+            self.shared.queue.level_of(value),
+        )
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        self.enqueue(
+            serde_json::to_value(fields)?,
+            self.shared.queue.level_of(fields),
+        )
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        self.wait_idle(FLUSH_TIMEOUT)
+    }
+
+    fn shutdown(&self) -> Result<(), io::Error> {
+        self.shared.queue.close();
+        self.wait_idle(DEFAULT_SHUTDOWN_TIMEOUT)
+    }
+
+    fn healthy(&self) -> bool {
+        self.shared.queue.counters().health.healthy()
+    }
+}
+
+impl Drop for PostgresWriter {
+    fn drop(&mut self) {
+        // no more records will be pushed, let the consumer task drain the queue and exit.
+        self.shared.queue.close();
+    }
+}
+
+// The long-lived consumer task of a writer, it exits when the queue is closed and drained.
+async fn consume(shared: Arc<Shared>) {
+    let queue = &shared.queue;
+    loop {
+        queue.wait().await;
+        // wait for more records if the batch is not full.
+        if let Some(delay) = queue.batch_delay() {
+            tokio::time::sleep(delay).await;
+        }
+
+        loop {
+            // marked busy before popping, so a flush never sees an empty queue with records not inserted.
+            shared.busy.store(true, Ordering::SeqCst);
+            let mut buf = Vec::new();
+            let n = queue.pop_batch(&mut buf);
+            if n == 0 {
+                shared.busy.store(false, Ordering::SeqCst);
+                break;
+            }
+            if let Err(err) = queue.counters().health.track(copy_in(&shared, buf).await) {
+                log_failure(
+                    format!("PostgresWriter failed to insert {} logs: {}", n, err).as_str(),
+                );
+            }
+            shared.busy.store(false, Ordering::SeqCst);
+        }
+        if queue.is_closed() {
+            return;
+        }
+    }
+}
+
+async fn copy_in(shared: &Shared, rows: Vec<u8>) -> Result<u64, tokio_postgres::Error> {
+    let sink = shared.client.copy_in(shared.statement.as_str()).await?;
+    let mut sink = std::pin::pin!(sink);
+    sink.send(Bytes::from(rows)).await?;
+    sink.finish().await
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the PostgresWriter for a given client.
+pub fn new_writer(client: Client, opts: PostgresOptions) -> Box<dyn Writer> {
+    Box::new(PostgresWriter::new(client, opts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quote_table_works() {
+        assert_eq!(r#""logs""#, quote_table("logs"));
+        assert_eq!(r#""audit"."Logs""#, quote_table("audit.Logs"));
+        assert_eq!(
+            r#""logs; DROP TABLE users; --""#,
+            quote_table("logs; DROP TABLE users; --")
+        );
+        assert_eq!(
+            r#""logs"" (a) FROM STDIN; --""#,
+            quote_table(r#"logs" (a) FROM STDIN; --"#)
+        );
+    }
+
+    #[test]
+    fn encode_row_works() {
+        let record = serde_json::json!({
+            "timestamp": 1679745592127_u64,
+            "level": "INFO",
+            "target": "app",
+            "message": "line 1\n\tline 2 \\ end",
+            "user": "tom",
+        });
+        let mut buf = Vec::new();
+        if let serde_json::Value::Object(record) = record {
+            encode_row(&mut buf, record).unwrap();
+        }
+        assert_eq!(
+            "2023-03-25T11:59:52.127Z\tINFO\tapp\tline 1\\n\\tline 2 \\\\ end\t{\"user\":\"tom\"}\n",
+            String::from_utf8(buf).unwrap()
+        );
+
+        let record = serde_json::json!({
+            "timestamp": 1679745592127_u64,
+            "severity": "WARNING",
+            "path": "C:\\logs",
+        });
+        let mut buf = Vec::new();
+        if let serde_json::Value::Object(record) = record {
+            encode_row(&mut buf, record).unwrap();
+        }
+        assert_eq!(
+            "2023-03-25T11:59:52.127Z\tWARNING\t\\N\t\\N\t{\"path\":\"C:\\\\\\\\logs\"}\n",
+            String::from_utf8(buf).unwrap()
+        );
+    }
+}
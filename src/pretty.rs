@@ -0,0 +1,343 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Pretty Writer Implementation
+//!
+//! A [`Writer`] implementation that logs the records as human-readable lines, for the developers reading
+//! the logs in a terminal, with the level colored by ANSI escape codes:
+//! `2023-03-25T11:59:52.127Z  INFO api: hello world method=GET path="/a b"`.
+//!
+//! The timestamp, the level, the target and the message are written first, then the other fields
+//! as `key=value` sorted by key. The strings are quoted if they are empty or contain whitespace, `=` or `"`.
+//! [`Builder::with_auto_format`](crate::Builder::with_auto_format) writes this format to stderr
+//! when it is a terminal, and JSON otherwise.
+//!
+//! The colors are set by a [`Theme`]: the color of each level, the style of the metadata,
+//! the timestamp and the target, and of the keys. The environment overrides the `color` argument
+//! of the writer, see <https://no-color.org> and <https://bixense.com/clicolors>:
+//! * `NO_COLOR`, if set and not empty, disables the colors;
+//! * `CLICOLOR_FORCE`, if set and not `0`, enables the colors, such as in the CI logs.
+//!
+//! On Windows, the virtual terminal processing of the console is enabled when a colored writer is created,
+//! so cmd and PowerShell interpret the escape codes. If the console doesn't support it, such as the legacy console
+//! before Windows 10, the colors are disabled instead of writing raw escape codes.
+//!
+//! To create a `Box<dyn Writer>` use the [`new_writer`] function.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{pretty, Builder};
+//!
+//! fn main() {
+//!     Builder::with_level("info")
+//!         .with_default_writer(pretty::new_writer(std::io::stderr(), true))
+//!         .init();
+//!
+//!     log::info!(method = "GET"; "hello world");
+//! }
+//! ```
+//!
+
+use parking_lot::Mutex;
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::OsStr,
+    fmt::Write as _,
+    io::{self, Write},
+};
+
+use crate::metrics::Health;
+use crate::{Fields, Key, Rfc3339, Value, Writer};
+
+const RESET: &str = "\x1b[0m";
+
+/// The ANSI styles of a [`PrettyWriter`], as SGR escape sequences such as `"\x1b[1;31m"` for bold red.
+/// An empty style writes the text unstyled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    /// The style of the ERROR level, the default is red.
+    pub error: &'static str,
+    /// The style of the WARN level, the default is yellow.
+    pub warn: &'static str,
+    /// The style of the INFO level, the default is green.
+    pub info: &'static str,
+    /// The style of the DEBUG level, the default is blue.
+    pub debug: &'static str,
+    /// The style of the TRACE level, the default is magenta.
+    pub trace: &'static str,
+    /// The style of the timestamp and the target, the default is dimmed.
+    pub metadata: &'static str,
+    /// The style of the keys of the other fields, the default is dimmed.
+    pub key: &'static str,
+    /// The style of the message, the default is unstyled.
+    pub message: &'static str,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            error: "\x1b[31m",
+            warn: "\x1b[33m",
+            info: "\x1b[32m",
+            debug: "\x1b[34m",
+            trace: "\x1b[35m",
+            metadata: "\x1b[2m",
+            key: "\x1b[2m",
+            message: "",
+        }
+    }
+}
+
+impl Theme {
+    // Returns the style of a level, such as `"ERROR"` or `"warn"`.
+    fn level(&self, level: &str) -> &'static str {
+        match level.to_ascii_uppercase().as_str() {
+            "ERROR" => self.error,
+            "WARN" | "WARNING" => self.warn,
+            "INFO" => self.info,
+            "DEBUG" => self.debug,
+            "TRACE" => self.trace,
+            _ => "",
+        }
+    }
+}
+
+/// A Writer implementation that writes logs as human-readable lines, optionally colored.
+pub struct PrettyWriter<W: Write + Sync + Send + 'static> {
+    w: Mutex<W>,
+    // the theme, or `None` if the colors are disabled.
+    theme: Option<Theme>,
+    health: Health,
+}
+
+impl<W: Write + Sync + Send + 'static> PrettyWriter<W> {
+    /// Creates a new PrettyWriter instance, that writes the ANSI colors of the default [`Theme`] if `color` is true,
+    /// unless overridden by the `NO_COLOR` or `CLICOLOR_FORCE` environment variables.
+    pub fn new(w: W, color: bool) -> Self {
+        let color = color_from_env(
+            color,
+            env::var_os("NO_COLOR").as_deref(),
+            env::var_os("CLICOLOR_FORCE").as_deref(),
+        );
+        PrettyWriter {
+            w: Mutex::new(w),
+            theme: (color && enable_ansi()).then(Theme::default),
+            health: Health::default(),
+        }
+    }
+
+    /// Returns the PrettyWriter with the given theme, if the colors are enabled.
+    pub fn with_theme(self, theme: Theme) -> Self {
+        PrettyWriter {
+            theme: self.theme.map(|_| theme),
+            ..self
+        }
+    }
+
+    fn write_record<'a, 'v: 'a>(
+        &self,
+        fields: impl Iterator<Item = (&'a Key<'v>, &'a Value<'v>)>,
+    ) -> Result<(), io::Error> {
+        let mut line = String::with_capacity(128);
+        self.format(&mut line, fields);
+        line.push('\n');
+        self.health.track(self.w.lock().write_all(line.as_bytes()))
+    }
+
+    fn format<'a, 'v: 'a>(
+        &self,
+        line: &mut String,
+        fields: impl Iterator<Item = (&'a Key<'v>, &'a Value<'v>)>,
+    ) {
+        let (mut timestamp, mut level, mut target, mut message) = (None, None, None, None);
+        let mut rest = Vec::new();
+        for (key, value) in fields {
+            match key.as_str() {
+                "timestamp" => timestamp = value.to_u64(),
+                "level" => level = value.to_borrowed_str(),
+                "target" => target = value.to_borrowed_str(),
+                "message" => message = Some(value),
+                _ => rest.push((key, value)),
+            }
+        }
+
+        let theme = self.theme.unwrap_or(PLAIN);
+        if let Some(ms) = timestamp {
+            paint(line, theme.metadata, Rfc3339::from_unix_ms(ms).as_str());
+            line.push(' ');
+        }
+        if let Some(level) = level {
+            paint(line, theme.level(level), &format!("{:>5}", level));
+            line.push(' ');
+        }
+        if let Some(target) = target {
+            paint(line, theme.metadata, target);
+            line.push_str(": ");
+        }
+        if let Some(message) = message {
+            paint(line, theme.message, &message.to_string());
+        }
+        for (key, value) in rest {
+            line.push(' ');
+            paint(line, theme.key, key.as_str());
+            line.push('=');
+            write_value(line, value);
+        }
+    }
+}
+
+// The theme without styles, when the colors are disabled.
+const PLAIN: Theme = Theme {
+    error: "",
+    warn: "",
+    info: "",
+    debug: "",
+    trace: "",
+    metadata: "",
+    key: "",
+    message: "",
+};
+
+fn paint(line: &mut String, style: &str, s: &str) {
+    if style.is_empty() {
+        line.push_str(s);
+    } else {
+        line.push_str(style);
+        line.push_str(s);
+        line.push_str(RESET);
+    }
+}
+
+// Returns whether the colors are enabled, `NO_COLOR` overrides `CLICOLOR_FORCE`, which overrides `color`.
+fn color_from_env(color: bool, no_color: Option<&OsStr>, force: Option<&OsStr>) -> bool {
+    if no_color.is_some_and(|v| !v.is_empty()) {
+        return false;
+    }
+    if force.is_some_and(|v| v != "0") {
+        return true;
+    }
+    color
+}
+
+// Enables the virtual terminal processing of the Windows console, once for the process.
+// Returns false if stdout or stderr is a console that doesn't support it, true if they are redirected.
+#[cfg(windows)]
+fn enable_ansi() -> bool {
+    use std::sync::OnceLock;
+    use windows_sys::Win32::System::Console::{
+        GetConsoleMode, GetStdHandle, SetConsoleMode, ENABLE_VIRTUAL_TERMINAL_PROCESSING,
+        STD_ERROR_HANDLE, STD_OUTPUT_HANDLE,
+    };
+
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| {
+        [STD_OUTPUT_HANDLE, STD_ERROR_HANDLE].into_iter().all(|id| {
+            // SAFETY: the mode is only read and written for a valid console handle.
+            unsafe {
+                let handle = GetStdHandle(id);
+                let mut mode = 0;
+                if GetConsoleMode(handle, &mut mode) == 0 {
+                    // not a console, the escape codes are written as is.
+                    return true;
+                }
+                mode & ENABLE_VIRTUAL_TERMINAL_PROCESSING != 0
+                    || SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) != 0
+            }
+        })
+    })
+}
+
+#[cfg(not(windows))]
+fn enable_ansi() -> bool {
+    true
+}
+
+// Writes a value, the strings are quoted if they are empty or contain whitespace, `=` or `"`.
+fn write_value(line: &mut String, value: &Value) {
+    match value.to_borrowed_str() {
+        Some(s)
+            if s.is_empty() || s.contains(|c: char| c.is_whitespace() || c == '=' || c == '"') =>
+        {
+            let _ = write!(line, "{:?}", s);
+        }
+        Some(s) => line.push_str(s),
+        None => {
+            let _ = write!(line, "{}", value);
+        }
+    }
+}
+
+/// Implements Writer trait for PrettyWriter.
+impl<W: Write + Sync + Send + 'static> Writer for PrettyWriter<W> {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        self.write_record(value.iter())
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        self.write_record(fields.to_sorted().iter())
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        self.health.track(self.w.lock().flush())
+    }
+
+    fn healthy(&self) -> bool {
+        self.health.healthy()
+    }
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the PrettyWriter for a given std::io::Write instance,
+/// that writes the ANSI colors if `color` is true.
+pub fn new_writer<W: Write + Sync + Send + 'static>(w: W, color: bool) -> Box<dyn Writer> {
+    Box::new(PrettyWriter::new(w, color))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pretty_writer_works() {
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("level"), Value::from("INFO"));
+        value.insert(Key::from("message"), Value::from("hello world"));
+        value.insert(Key::from("method"), Value::from("GET"));
+        value.insert(Key::from("path"), Value::from("/a b"));
+        value.insert(Key::from("status"), Value::from(200));
+        value.insert(Key::from("target"), Value::from("api"));
+        value.insert(Key::from("timestamp"), Value::from(1679745592127_u64));
+
+        let mut line = String::new();
+        PrettyWriter::new(io::sink(), false).format(&mut line, value.iter());
+        assert_eq!(
+            r#"2023-03-25T11:59:52.127Z  INFO api: hello world method=GET path="/a b" status=200"#,
+            line
+        );
+
+        let mut line = String::new();
+        PrettyWriter::new(io::sink(), true).format(&mut line, value.iter());
+        let theme = Theme {
+            info: "\x1b[1;32m",
+            metadata: "",
+            ..Theme::default()
+        };
+        let w = PrettyWriter {
+            theme: Some(theme),
+            ..PrettyWriter::new(io::sink(), false)
+        };
+        let mut line = String::new();
+        w.format(&mut line, value.iter());
+        assert!(line.starts_with(
+            "2023-03-25T11:59:52.127Z \x1b[1;32m INFO\x1b[0m api: hello world \x1b[2mmethod\x1b[0m=GET"
+        ));
+
+        let (empty, set) = (Some(OsStr::new("")), Some(OsStr::new("1")));
+        assert!(color_from_env(true, None, None));
+        assert!(color_from_env(true, empty, None));
+        assert!(!color_from_env(true, set, None));
+        assert!(!color_from_env(true, set, set));
+        assert!(color_from_env(false, None, set));
+        assert!(!color_from_env(false, None, Some(OsStr::new("0"))));
+    }
+}
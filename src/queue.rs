@@ -0,0 +1,546 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! A bounded queue of encoded records shared by the async writers.
+
+use event_listener::Event;
+use log::Level;
+use parking_lot::{Condvar, Mutex};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::atomic::{AtomicBool, Ordering},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::metrics::{QueueCounters, QueueMetrics};
+use crate::pool::BufferPool;
+use crate::route::{parse_level, LEVEL_KEYS};
+use crate::{Fields, Key, Value};
+
+/// The default maximum number of records that can be buffered in the queue.
+pub const DEFAULT_QUEUE_CAPACITY: usize = 128_000;
+
+/// The default maximum number of records coalesced into a single write.
+pub const DEFAULT_BATCH_SIZE: usize = 128;
+
+/// The policy to apply when the queue of an async writer is full.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BackpressurePolicy {
+    /// Blocks the logging thread until the queue has room.
+    /// Don't use it with a current-thread runtime, the queue can't be drained while the only thread is blocked.
+    Block,
+    /// Drops the new record, the default policy.
+    #[default]
+    DropNewest,
+    /// Drops the oldest queued record to make room for the new one.
+    DropOldest,
+    /// Drops the least severe records first: the oldest queued TRACE or DEBUG record, then INFO, then WARN,
+    /// or the new record if it is the least severe. The ERROR records are never dropped,
+    /// they are queued over the capacity when the queue is full of errors.
+    /// The records without a level are shed as INFO. See [`QueueMetrics::dropped_by_level`].
+    ShedBySeverity,
+}
+
+/// The adaptive batching of an async writer: the records are written immediately at a low rate,
+/// for a low latency, and the consumer waits for larger batches as the rate grows, during a burst.
+/// The batches are bounded by [`max_bytes`](AdaptiveBatching::max_bytes), and a record never waits
+/// longer than [`max_delay`](AdaptiveBatching::max_delay) for its batch to fill.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdaptiveBatching {
+    /// The maximum size in bytes of a batch, the default is 1 MiB. A larger record is written alone.
+    pub max_bytes: usize,
+    /// The maximum time the consumer waits for more records, the default is 50 milliseconds.
+    pub max_delay: Duration,
+}
+
+impl Default for AdaptiveBatching {
+    fn default() -> Self {
+        AdaptiveBatching {
+            max_bytes: 1024 * 1024,
+            max_delay: Duration::from_millis(50),
+        }
+    }
+}
+
+// The consumer waits for more records only if at least that many are expected within the maximum delay.
+const MIN_EXPECTED_RECORDS: f64 = 2.0;
+
+/// A record whose level is read to shed the least severe records, see [`BackpressurePolicy::ShedBySeverity`].
+pub(crate) trait RecordLevel {
+    fn record_level(&self) -> Option<Level>;
+}
+
+impl RecordLevel for BTreeMap<Key<'_>, Value<'_>> {
+    fn record_level(&self) -> Option<Level> {
+        LEVEL_KEYS.iter().find_map(|key| {
+            self.get(&Key::from(*key))
+                .and_then(|v| v.to_borrowed_str())
+                .and_then(parse_level)
+        })
+    }
+}
+
+impl RecordLevel for Fields<'_> {
+    fn record_level(&self) -> Option<Level> {
+        let mut level = None;
+        let _ = self.visit(&mut |key, value| {
+            if LEVEL_KEYS.contains(&key.as_str()) {
+                level = value.to_borrowed_str().and_then(parse_level);
+            }
+            Ok(())
+        });
+        level
+    }
+}
+
+// The queued records, and the number of queued records of each level, indexed by `Level as usize`.
+#[derive(Default)]
+struct Records {
+    queue: VecDeque<(Level, Vec<u8>)>,
+    levels: [usize; 6],
+    // the size of the queued records.
+    bytes: usize,
+    // the number of records pushed since the queue was created.
+    pushed: u64,
+}
+
+impl Records {
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    fn push_back(&mut self, level: Level, buf: Vec<u8>) {
+        self.levels[level as usize] += 1;
+        self.bytes += buf.len();
+        self.pushed += 1;
+        self.queue.push_back((level, buf));
+    }
+
+    fn pop_front(&mut self) -> Option<(Level, Vec<u8>)> {
+        let record = self.queue.pop_front()?;
+        self.levels[record.0 as usize] -= 1;
+        self.bytes -= record.1.len();
+        Some(record)
+    }
+
+    // Removes and returns the oldest record of a given level.
+    fn remove_oldest(&mut self, level: Level) -> Option<Vec<u8>> {
+        let i = self.queue.iter().position(|(l, _)| *l == level)?;
+        let (_, buf) = self.queue.remove(i)?;
+        self.levels[level as usize] -= 1;
+        self.bytes -= buf.len();
+        Some(buf)
+    }
+
+    // Returns the least severe level of the queued records.
+    fn least_severe(&self) -> Option<Level> {
+        Level::iter()
+            .filter(|l| self.levels[*l as usize] > 0)
+            .last()
+    }
+
+    fn drain(&mut self, n: usize) -> impl Iterator<Item = Vec<u8>> + '_ {
+        for (level, buf) in self.queue.range(..n) {
+            self.levels[*level as usize] -= 1;
+            self.bytes -= buf.len();
+        }
+        self.queue.drain(..n).map(|(_, buf)| buf)
+    }
+}
+
+// The arrival rate of the records, estimated by the consumer for the adaptive batching.
+struct Rate {
+    at: Instant,
+    pushed: u64,
+    per_sec: f64,
+}
+
+pub(crate) struct Queue {
+    records: Mutex<Records>,
+    not_full: Condvar,
+    not_empty: Event,
+    // whether records were pushed, or the queue closed, since the consumer last waited.
+    notified: AtomicBool,
+    capacity: usize,
+    policy: BackpressurePolicy,
+    pub(crate) batch_size: usize,
+    pub(crate) batch_interval: Duration,
+    adaptive: Option<AdaptiveBatching>,
+    // `None` until the first batch, the clock is only read with the adaptive batching.
+    rate: Mutex<Option<Rate>>,
+    counters: Arc<QueueCounters>,
+    closed: AtomicBool,
+}
+
+impl Queue {
+    pub(crate) fn new(
+        capacity: usize,
+        policy: BackpressurePolicy,
+        batch_size: usize,
+        batch_interval: Duration,
+    ) -> Self {
+        Queue {
+            records: Mutex::new(Records::default()),
+            not_full: Condvar::new(),
+            not_empty: Event::new(),
+            notified: AtomicBool::new(false),
+            capacity: capacity.max(1),
+            policy,
+            batch_size: batch_size.max(1),
+            batch_interval,
+            adaptive: None,
+            rate: Mutex::new(None),
+            counters: Arc::new(QueueCounters::default()),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    // Sets the adaptive batching, the `batch_size` and `batch_interval` are ignored if it is set.
+    pub(crate) fn with_adaptive(mut self, adaptive: Option<AdaptiveBatching>) -> Self {
+        self.adaptive = adaptive;
+        self
+    }
+
+    /// Returns the level of a record if the policy sheds by severity, without reading it otherwise.
+    pub(crate) fn level_of<T: RecordLevel + ?Sized>(&self, record: &T) -> Option<Level> {
+        if self.policy == BackpressurePolicy::ShedBySeverity {
+            return record.record_level();
+        }
+        None
+    }
+
+    // Returns true if the record is queued.
+    pub(crate) fn push(&self, buf: Vec<u8>, level: Option<Level>) -> bool {
+        let level = level.unwrap_or(Level::Info);
+        let mut records = self.records.lock();
+        while records.len() >= self.capacity {
+            if self.is_closed() {
+                return false;
+            }
+            match self.policy {
+                BackpressurePolicy::Block => self.not_full.wait(&mut records),
+                BackpressurePolicy::DropNewest => {
+                    self.counters.on_drop_level(level);
+                    drop(records);
+                    BufferPool::global().put(buf);
+                    return false;
+                }
+                BackpressurePolicy::DropOldest => {
+                    if let Some((oldest_level, oldest)) = records.pop_front() {
+                        BufferPool::global().put(oldest);
+                        self.counters.on_pop(1);
+                        self.counters.on_drop_level(oldest_level);
+                    }
+                }
+                BackpressurePolicy::ShedBySeverity => match records.least_severe() {
+                    Some(least) if least > level => {
+                        if let Some(shed) = records.remove_oldest(least) {
+                            BufferPool::global().put(shed);
+                        }
+                        self.counters.on_pop(1);
+                        self.counters.on_drop_level(least);
+                    }
+                    // the queue is full of errors.
+                    _ if level == Level::Error => break,
+                    _ => {
+                        self.counters.on_drop_level(level);
+                        drop(records);
+                        BufferPool::global().put(buf);
+                        return false;
+                    }
+                },
+            }
+        }
+        records.push_back(level, buf);
+        self.counters.on_push();
+        drop(records);
+        self.notify();
+        true
+    }
+
+    fn notify(&self) {
+        self.notified.store(true, Ordering::SeqCst);
+        self.not_empty.notify(1);
+    }
+
+    // Waits until records are pushed or the queue is closed, on any async runtime.
+    // A notification sent while nobody is waiting is stored, so it is never lost.
+    pub(crate) async fn wait(&self) {
+        loop {
+            if self.notified.swap(false, Ordering::SeqCst) {
+                return;
+            }
+            let listener = self.not_empty.listen();
+            // a notification may have been sent before the listener was registered.
+            if self.notified.swap(false, Ordering::SeqCst) {
+                return;
+            }
+            listener.await;
+        }
+    }
+
+    pub(crate) fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        // wake up the blocked producers and the consumer, they will see the closed flag.
+        self.not_full.notify_all();
+        self.notify();
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.records.lock().len()
+    }
+
+    pub(crate) fn dropped(&self) -> u64 {
+        self.counters.dropped()
+    }
+
+    pub(crate) fn counters(&self) -> &QueueCounters {
+        &self.counters
+    }
+
+    pub(crate) fn metrics(&self) -> QueueMetrics {
+        QueueMetrics(self.counters.clone())
+    }
+
+    // Returns how long the consumer waits for more records before popping a batch, `None` to pop it now.
+    pub(crate) fn batch_delay(&self) -> Option<Duration> {
+        let (queued, bytes, pushed) = {
+            let records = self.records.lock();
+            (records.len(), records.bytes, records.pushed)
+        };
+        if queued == 0 {
+            return None;
+        }
+        let adaptive = match self.adaptive {
+            Some(adaptive) => adaptive,
+            None => {
+                return (queued < self.batch_size && !self.batch_interval.is_zero())
+                    .then_some(self.batch_interval)
+            }
+        };
+
+        let per_sec = self.arrival_rate(pushed, adaptive.max_delay);
+        let max_delay = adaptive.max_delay.as_secs_f64();
+        if bytes >= adaptive.max_bytes || per_sec * max_delay < MIN_EXPECTED_RECORDS {
+            return None;
+        }
+        // the time to fill the batch with records of the average size.
+        let missing = (adaptive.max_bytes - bytes) as f64 / (bytes / queued).max(1) as f64;
+        Some(Duration::from_secs_f64((missing / per_sec).min(max_delay)))
+    }
+
+    // Updates the arrival rate of the records per second with the records pushed since the last batch.
+    // The last rate is forgotten after `window`, so the rate drops as soon as a burst ends.
+    fn arrival_rate(&self, pushed: u64, window: Duration) -> f64 {
+        let now = Instant::now();
+        let mut rate = self.rate.lock();
+        let rate = match *rate {
+            Some(ref mut rate) => rate,
+            None => {
+                *rate = Some(Rate {
+                    at: now,
+                    pushed,
+                    per_sec: 0.0,
+                });
+                return 0.0;
+            }
+        };
+        let elapsed = now.duration_since(rate.at).as_secs_f64();
+        if elapsed > 0.0 {
+            let current = (pushed - rate.pushed) as f64 / elapsed;
+            let weight = (elapsed / window.as_secs_f64()).min(1.0);
+            rate.per_sec = rate.per_sec * (1.0 - weight) + current * weight;
+            rate.at = now;
+            rate.pushed = pushed;
+        }
+        rate.per_sec
+    }
+
+    // Returns the number of records of the next batch.
+    fn batch_len(&self, records: &Records) -> usize {
+        match self.adaptive {
+            None => records.len().min(self.batch_size),
+            Some(adaptive) => {
+                let mut bytes = 0;
+                let n = records
+                    .queue
+                    .iter()
+                    .take_while(|(_, buf)| {
+                        bytes += buf.len();
+                        bytes <= adaptive.max_bytes
+                    })
+                    .count();
+                n.max(1).min(records.len())
+            }
+        }
+    }
+
+    // Pops the records of the next batch into `buf`, up to `batch_size` records, or `max_bytes` with the adaptive batching.
+    // Returns the number of records popped. The buffers of the popped records are returned to the pool.
+    pub(crate) fn pop_batch(&self, buf: &mut Vec<u8>) -> usize {
+        let pool = BufferPool::global();
+        let mut records = self.records.lock();
+        let n = self.batch_len(&records);
+        for record in records.drain(n) {
+            buf.extend_from_slice(&record);
+            pool.put(record);
+        }
+        self.counters.on_pop(n);
+        drop(records);
+        if n > 0 {
+            self.not_full.notify_all();
+        }
+        n
+    }
+
+    // Pops the records of the next batch into `records`, returns the number of records popped.
+    // The writers that send each record as its own message take the buffers of the records.
+    #[cfg(feature = "nats")]
+    pub(crate) fn pop_records(&self, records: &mut Vec<Vec<u8>>) -> usize {
+        let mut queued = self.records.lock();
+        let n = self.batch_len(&queued);
+        records.extend(queued.drain(n));
+        self.counters.on_pop(n);
+        drop(queued);
+        if n > 0 {
+            self.not_full.notify_all();
+        }
+        n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn pop_all(queue: &Queue) -> Vec<u8> {
+        let mut buf = Vec::new();
+        while queue.pop_batch(&mut buf) > 0 {}
+        buf
+    }
+
+    #[test]
+    fn queue_policy_works() {
+        let queue = Queue::new(2, BackpressurePolicy::DropNewest, 2, Duration::ZERO);
+        assert!(queue.push(b"1".to_vec(), None));
+        assert!(queue.push(b"2".to_vec(), None));
+        assert!(!queue.push(b"3".to_vec(), None));
+        assert_eq!(1, queue.dropped());
+        assert_eq!(b"12".to_vec(), pop_all(&queue));
+
+        let queue = Queue::new(2, BackpressurePolicy::DropOldest, 2, Duration::ZERO);
+        assert!(queue.push(b"1".to_vec(), None));
+        assert!(queue.push(b"2".to_vec(), None));
+        assert!(queue.push(b"3".to_vec(), None));
+        assert_eq!(1, queue.dropped());
+        let metrics = queue.metrics();
+        assert_eq!(2, metrics.queue_len());
+        assert_eq!(2, metrics.high_water_mark());
+        assert_eq!(b"23".to_vec(), pop_all(&queue));
+        assert_eq!(0, metrics.queue_len());
+        assert_eq!(2, metrics.high_water_mark());
+
+        queue.close();
+        assert!(queue.is_closed());
+    }
+
+    #[test]
+    fn queue_shed_by_severity_works() {
+        let queue = Queue::new(3, BackpressurePolicy::ShedBySeverity, 10, Duration::ZERO);
+        assert!(queue.push(b"d".to_vec(), Some(Level::Debug)));
+        assert!(queue.push(b"i".to_vec(), Some(Level::Info)));
+        assert!(queue.push(b"D".to_vec(), Some(Level::Debug)));
+        // the oldest DEBUG record, then the other one, then the INFO record are shed.
+        assert!(queue.push(b"e".to_vec(), Some(Level::Error)));
+        assert!(queue.push(b"w".to_vec(), Some(Level::Warn)));
+        assert!(!queue.push(b"t".to_vec(), Some(Level::Trace)));
+        assert!(queue.push(b"E".to_vec(), Some(Level::Error)));
+        assert_eq!(b"ewE".to_vec(), {
+            let mut buf = Vec::new();
+            queue.pop_batch(&mut buf);
+            buf
+        });
+
+        // the errors are queued over the capacity.
+        for _ in 0..4 {
+            assert!(queue.push(b"e".to_vec(), Some(Level::Error)));
+        }
+        assert!(!queue.push(b"w".to_vec(), Some(Level::Warn)));
+        assert_eq!(4, queue.len());
+
+        let metrics = queue.metrics();
+        assert_eq!(5, metrics.dropped());
+        assert_eq!(0, metrics.dropped_by_level(Level::Error));
+        assert_eq!(2, metrics.dropped_by_level(Level::Debug));
+        assert_eq!(1, metrics.dropped_by_level(Level::Trace));
+        assert_eq!(1, metrics.dropped_by_level(Level::Info));
+        assert_eq!(1, metrics.dropped_by_level(Level::Warn));
+    }
+
+    #[test]
+    fn queue_batch_works() {
+        let queue = Queue::new(10, BackpressurePolicy::Block, 2, Duration::ZERO);
+        for i in 0..5 {
+            assert!(queue.push(format!("{}", i).into_bytes(), None));
+        }
+        let mut buf = Vec::new();
+        assert_eq!(2, queue.pop_batch(&mut buf));
+        assert_eq!(b"01".to_vec(), buf);
+        assert_eq!(2, queue.pop_batch(&mut buf));
+        assert_eq!(1, queue.pop_batch(&mut buf));
+        assert_eq!(0, queue.pop_batch(&mut buf));
+        assert_eq!(b"01234".to_vec(), buf);
+    }
+
+    #[test]
+    fn queue_adaptive_batching_works() {
+        let adaptive = AdaptiveBatching {
+            max_bytes: 4,
+            max_delay: Duration::from_millis(50),
+        };
+        let queue = Queue::new(1000, BackpressurePolicy::Block, 2, Duration::ZERO)
+            .with_adaptive(Some(adaptive));
+        for record in ["ab", "cd", "efg", "hijkl"] {
+            assert!(queue.push(record.as_bytes().to_vec(), None));
+        }
+        // the batches are bounded by the size, a larger record is written alone.
+        let mut buf = Vec::new();
+        assert_eq!(2, queue.pop_batch(&mut buf));
+        assert_eq!(1, queue.pop_batch(&mut buf));
+        assert_eq!(1, queue.pop_batch(&mut buf));
+        assert_eq!(b"abcdefghijkl".to_vec(), buf);
+
+        let queue = Queue::new(100_000, BackpressurePolicy::Block, 2, Duration::ZERO)
+            .with_adaptive(Some(AdaptiveBatching {
+                max_bytes: 1024 * 1024,
+                ..adaptive
+            }));
+        assert!(queue.push(b"1".to_vec(), None));
+        assert_eq!(
+            None,
+            queue.batch_delay(),
+            "the first record is written immediately"
+        );
+        pop_all(&queue);
+
+        // a burst, the consumer waits for a larger batch.
+        thread::sleep(Duration::from_millis(10));
+        for _ in 0..1000 {
+            assert!(queue.push(b"1".to_vec(), None));
+        }
+        let delay = queue.batch_delay().unwrap();
+        assert!(delay > Duration::ZERO && delay <= adaptive.max_delay);
+        pop_all(&queue);
+
+        // the rate drops as soon as the burst ends.
+        thread::sleep(Duration::from_millis(100));
+        assert!(queue.push(b"1".to_vec(), None));
+        assert_eq!(None, queue.batch_delay());
+    }
+}
@@ -0,0 +1,287 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Rate Limiting Writer
+//!
+//! A [`Writer`] wrapper that limits the records written per second with a token bucket per target,
+//! or per value of a chosen field, such as `tenant_id`, to protect the downstream pipelines from log storms.
+//!
+//! A bucket holds up to [`RateLimitOptions::burst`] records, and is refilled with
+//! [`RateLimitOptions::per_second`] records every second. The records over the limit are dropped,
+//! and counted: a notice with the number of the suppressed records is written before the next record
+//! of the same key that is within the limit, at most once every [`RateLimitOptions::notice_interval`],
+//! and when the writer is flushed or shut down. The notice is a warning of the `structured_logger` target, such as:
+//! `{"level":"WARN","message":"suppressed 42 logs over the rate limit","rate_limit_key":"db","suppressed":42,"target":"structured_logger","timestamp":1679745592127}`.
+//!
+//! To create a `Box<dyn Writer>` use the [`new_writer`] function.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{json, rate_limit, Builder};
+//!
+//! fn main() {
+//!     let opts = rate_limit::RateLimitOptions {
+//!         per_second: 100,
+//!         key: Some("tenant_id".to_string()),
+//!         ..Default::default()
+//!     };
+//!     Builder::with_level("info")
+//!         .with_default_writer(rate_limit::new_writer(opts, json::new_writer(std::io::stderr())))
+//!         .init();
+//!
+//!     for _ in 0..1000 {
+//!         log::error!(tenant_id = "acme"; "connection refused");
+//!     }
+//! }
+//! ```
+//!
+
+use parking_lot::Mutex;
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    time::{Duration, Instant},
+};
+
+use crate::{unix_ms, Fields, Key, Value, Writer};
+
+/// The options of a [`RateLimitWriter`].
+#[derive(Debug, Clone)]
+pub struct RateLimitOptions {
+    /// The number of records per second written for a key, the default is 100.
+    pub per_second: u32,
+    /// The number of records that can be written at once for a key, the default is 100.
+    pub burst: u32,
+    /// The field whose value is the key of the buckets, the default is `None`, the target of the records.
+    /// The records without the field share the bucket of the empty key.
+    pub key: Option<String>,
+    /// The minimum interval between two notices of the suppressed records of a key, the default is 10 seconds.
+    pub notice_interval: Duration,
+    /// The maximum number of buckets, the default is 10,000. When it is reached, the idle buckets are removed,
+    /// and the records of the new keys share the bucket of the empty key.
+    pub max_keys: usize,
+}
+
+impl Default for RateLimitOptions {
+    fn default() -> Self {
+        RateLimitOptions {
+            per_second: 100,
+            burst: 100,
+            key: None,
+            notice_interval: Duration::from_secs(10),
+            max_keys: 10_000,
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+    suppressed: u64,
+    noticed: Instant,
+}
+
+/// A Writer implementation that limits the records written per second to a wrapped writer.
+pub struct RateLimitWriter {
+    opts: RateLimitOptions,
+    buckets: Mutex<HashMap<String, Bucket>>,
+    inner: Box<dyn Writer>,
+}
+
+impl RateLimitWriter {
+    /// Creates a new RateLimitWriter instance that writes the records within the limits to `w`.
+    pub fn new(opts: RateLimitOptions, w: Box<dyn Writer>) -> Self {
+        RateLimitWriter {
+            opts,
+            buckets: Mutex::new(HashMap::new()),
+            inner: w,
+        }
+    }
+
+    fn key_field(&self) -> &str {
+        self.opts.key.as_deref().unwrap_or("target")
+    }
+
+    // Takes a token from the bucket of a key, returns `None` if the record is suppressed,
+    // or the key and the number of the suppressed records to notice before the record.
+    fn acquire(&self, key: &str) -> Option<Option<(String, u64)>> {
+        let now = Instant::now();
+        let rate = self.opts.per_second as f64;
+        let burst = self.opts.burst.max(1) as f64;
+        let mut buckets = self.buckets.lock();
+        if !buckets.contains_key(key) && buckets.len() >= self.opts.max_keys {
+            // removes the buckets that are full again, without suppressed records.
+            buckets.retain(|_, b| {
+                b.suppressed > 0
+                    || b.tokens + now.duration_since(b.refilled).as_secs_f64() * rate < burst
+            });
+        }
+        let key = if buckets.contains_key(key) || buckets.len() < self.opts.max_keys {
+            key
+        } else {
+            ""
+        };
+
+        let bucket = buckets.entry(key.to_string()).or_insert_with(|| Bucket {
+            tokens: burst,
+            refilled: now,
+            suppressed: 0,
+            noticed: now,
+        });
+        let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+        bucket.refilled = now;
+        if bucket.tokens < 1.0 {
+            bucket.suppressed += 1;
+            return None;
+        }
+        bucket.tokens -= 1.0;
+        if bucket.suppressed == 0 || now.duration_since(bucket.noticed) < self.opts.notice_interval
+        {
+            return Some(None);
+        }
+        bucket.noticed = now;
+        Some(Some((
+            key.to_string(),
+            std::mem::take(&mut bucket.suppressed),
+        )))
+    }
+
+    // Writes a record to the wrapped writer if it is within the limit of its key.
+    fn write_limited(
+        &self,
+        key: &str,
+        write: impl FnOnce(&dyn Writer) -> Result<(), io::Error>,
+    ) -> Result<(), io::Error> {
+        match self.acquire(key) {
+            None => Ok(()),
+            Some(notice) => {
+                if let Some((key, suppressed)) = notice {
+                    self.write_notice(&key, suppressed)?;
+                }
+                write(self.inner.as_ref())
+            }
+        }
+    }
+
+    fn write_notice(&self, key: &str, suppressed: u64) -> Result<(), io::Error> {
+        let message = format!("suppressed {} logs over the rate limit", suppressed);
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("level"), Value::from("WARN"));
+        value.insert(Key::from("message"), Value::from(message.as_str()));
+        value.insert(Key::from("rate_limit_key"), Value::from(key));
+        value.insert(Key::from("suppressed"), Value::from(suppressed));
+        value.insert(Key::from("target"), Value::from("structured_logger"));
+        value.insert(Key::from("timestamp"), Value::from(unix_ms()));
+        self.inner.write_log(&value)
+    }
+
+    // Writes the notices of all the keys with suppressed records.
+    fn write_notices(&self) -> Result<(), io::Error> {
+        let now = Instant::now();
+        let notices: Vec<(String, u64)> = self
+            .buckets
+            .lock()
+            .iter_mut()
+            .filter(|(_, b)| b.suppressed > 0)
+            .map(|(key, b)| {
+                b.noticed = now;
+                (key.clone(), std::mem::take(&mut b.suppressed))
+            })
+            .collect();
+        for (key, suppressed) in notices {
+            self.write_notice(&key, suppressed)?;
+        }
+        Ok(())
+    }
+}
+
+/// Implements Writer trait for RateLimitWriter.
+impl Writer for RateLimitWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        let key = value
+            .get(&Key::from(self.key_field()))
+            .map(|v| v.to_string())
+            .unwrap_or_default();
+        self.write_limited(&key, |w| w.write_log(value))
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        let field = self.key_field();
+        let mut key = String::new();
+        let _ = fields.visit(&mut |k, v| {
+            if k.as_str() == field {
+                key = v.to_string();
+            }
+            Ok(())
+        });
+        self.write_limited(&key, |w| w.write_fields(fields))
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        self.write_notices()?;
+        self.inner.flush()
+    }
+
+    fn shutdown(&self) -> Result<(), io::Error> {
+        self.write_notices()?;
+        self.inner.shutdown()
+    }
+
+    fn healthy(&self) -> bool {
+        self.inner.healthy()
+    }
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the RateLimitWriter that writes the records within the limits to `w`.
+pub fn new_writer(opts: RateLimitOptions, w: Box<dyn Writer>) -> Box<dyn Writer> {
+    Box::new(RateLimitWriter::new(opts, w))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fn_writer;
+    use std::sync::Arc;
+
+    #[test]
+    fn rate_limit_works() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let collected = lines.clone();
+        let opts = RateLimitOptions {
+            per_second: 1,
+            burst: 2,
+            key: Some("tenant".to_string()),
+            notice_interval: Duration::ZERO,
+            ..Default::default()
+        };
+        let w = new_writer(
+            opts,
+            fn_writer(move |value| {
+                let field = |key: &str| {
+                    value
+                        .get(&Key::from(key))
+                        .map(|v| v.to_string())
+                        .unwrap_or_default()
+                };
+                collected
+                    .lock()
+                    .push(format!("{}:{}", field("tenant"), field("suppressed")));
+                Ok(())
+            }),
+        );
+
+        for tenant in ["a", "a", "a", "a", "b"] {
+            let mut value = BTreeMap::new();
+            value.insert(Key::from("tenant"), Value::from(tenant));
+            w.write_log(&value).unwrap();
+        }
+        assert_eq!(vec!["a:", "a:", "b:"], *lines.lock());
+
+        // the notice of the suppressed records is written on flush.
+        w.flush().unwrap();
+        assert_eq!(":2", lines.lock()[3]);
+        w.flush().unwrap();
+        assert_eq!(4, lines.lock().len());
+    }
+}
@@ -0,0 +1,283 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Redis Streams Writer Implementation
+//!
+//! A [`Writer`] implementation that appends structured values to a Redis stream with `XADD`,
+//! one stream entry per record, with the [`redis`](https://docs.rs/redis) client.
+//! The entry is either the JSON record in a single field, or the fields of the record, see [`EntryFormat`].
+//! The stream is trimmed to [`RedisOptions::max_len`] entries by every `XADD`, so it works as a bounded buffer.
+//!
+//! The connection is opened by the first log call, and opened again by the next one after an error.
+//! To create a `Box<dyn Writer>` use the [`new_writer`] function.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{redis, Builder};
+//!
+//! fn main() {
+//!     let opts = redis::RedisOptions {
+//!         stream: "logs:orders".to_string(),
+//!         max_len: Some(10_000),
+//!         ..Default::default()
+//!     };
+//!     Builder::with_level("info")
+//!         .with_default_writer(redis::new_writer("redis://127.0.0.1/", opts).unwrap())
+//!         .init();
+//!
+//!     log::info!("order placed");
+//! }
+//! ```
+//!
+
+use ::redis::{Client, Cmd, Connection, IntoConnectionInfo};
+use log::kv::{Key, Value};
+use parking_lot::Mutex;
+use std::{collections::BTreeMap, io, time::Duration};
+
+use crate::fields::FieldMap;
+use crate::metrics::Health;
+use crate::{Fields, Writer};
+
+/// How a record is stored in a stream entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryFormat {
+    /// The JSON record in a single field of the given name.
+    Json(String),
+    /// Each field of the record in a field of the entry, the values other than strings are encoded in JSON.
+    Fields,
+}
+
+impl Default for EntryFormat {
+    fn default() -> Self {
+        EntryFormat::Json("record".to_string())
+    }
+}
+
+/// The options of a [`RedisWriter`].
+#[derive(Debug, Clone)]
+pub struct RedisOptions {
+    /// The key of the stream, the default is `"logs"`.
+    pub stream: String,
+    /// The format of the entries, the default is the JSON record in the `record` field.
+    pub format: EntryFormat,
+    /// The maximum length of the stream, the default is 100,000 entries. `None` doesn't trim the stream.
+    pub max_len: Option<usize>,
+    /// Whether the stream is trimmed approximately with `MAXLEN ~`, which is much more efficient,
+    /// the stream may be a bit longer than the maximum length. The default is true.
+    pub approximate: bool,
+    /// The timeout of the connection and of the commands, the default is 1 second.
+    pub timeout: Duration,
+}
+
+impl Default for RedisOptions {
+    fn default() -> Self {
+        RedisOptions {
+            stream: "logs".to_string(),
+            format: EntryFormat::default(),
+            max_len: Some(100_000),
+            approximate: true,
+            timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+/// A Writer implementation that appends logs to a Redis stream.
+pub struct RedisWriter {
+    client: Client,
+    conn: Mutex<Option<Connection>>,
+    opts: RedisOptions,
+    health: Health,
+}
+
+impl RedisWriter {
+    /// Creates a new RedisWriter instance for a given Redis URL, such as `"redis://127.0.0.1/"`.
+    /// It doesn't connect until the first record is written.
+    pub fn new<T: IntoConnectionInfo>(url: T, opts: RedisOptions) -> Result<Self, io::Error> {
+        Ok(RedisWriter {
+            client: Client::open(url).map_err(io::Error::other)?,
+            conn: Mutex::new(None),
+            opts,
+            health: Health::default(),
+        })
+    }
+
+    fn xadd(&self, entry: Vec<(String, String)>) -> Result<(), io::Error> {
+        self.health.track(self.send(entry))
+    }
+
+    fn send(&self, entry: Vec<(String, String)>) -> Result<(), io::Error> {
+        let mut cmd = Cmd::new();
+        cmd.arg("XADD").arg(&self.opts.stream);
+        if let Some(max_len) = self.opts.max_len {
+            cmd.arg("MAXLEN");
+            if self.opts.approximate {
+                cmd.arg("~");
+            }
+            cmd.arg(max_len);
+        }
+        cmd.arg("*");
+        for (field, value) in entry {
+            cmd.arg(field).arg(value);
+        }
+
+        let mut conn = self.conn.lock();
+        if conn.is_none() {
+            let c = self
+                .client
+                .get_connection_with_timeout(self.opts.timeout)
+                .map_err(io::Error::other)?;
+            c.set_read_timeout(Some(self.opts.timeout))
+                .and_then(|_| c.set_write_timeout(Some(self.opts.timeout)))
+                .map_err(io::Error::other)?;
+            *conn = Some(c);
+        }
+        let res = cmd.query::<::redis::Value>(conn.as_mut().unwrap());
+        if let Err(ref err) = res {
+            if err.is_io_error() || err.is_connection_dropped() || err.is_timeout() {
+                // opens a new connection for the next record.
+                *conn = None;
+            }
+        }
+        res.map(|_| ()).map_err(io::Error::other)
+    }
+
+    fn entry(&self, record: serde_json::Value) -> Vec<(String, String)> {
+        match self.opts.format {
+            EntryFormat::Json(ref field) => vec![(field.clone(), record.to_string())],
+            EntryFormat::Fields => match record {
+                serde_json::Value::Object(map) => map
+                    .into_iter()
+                    .filter(|(_, value)| !value.is_null())
+                    .map(|(key, value)| match value {
+                        serde_json::Value::String(s) => (key, s),
+                        value => (key, value.to_string()),
+                    })
+                    .collect(),
+                _ => Vec::new(),
+            },
+        }
+    }
+}
+
+/// Implements Writer trait for RedisWriter.
+impl Writer for RedisWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        self.xadd(self.entry(serde_json::to_value(FieldMap(value))?))
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        self.xadd(self.entry(serde_json::to_value(fields)?))
+    }
+
+    fn healthy(&self) -> bool {
+        self.health.healthy()
+    }
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the RedisWriter for a given Redis URL.
+pub fn new_writer<T: IntoConnectionInfo>(
+    url: T,
+    opts: RedisOptions,
+) -> Result<Box<dyn Writer>, io::Error> {
+    Ok(Box::new(RedisWriter::new(url, opts)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    // A minimal Redis server that replies to the commands of the connections until `n` XADD commands,
+    // it returns the arguments of the XADD commands.
+    fn serve(listener: TcpListener, n: usize) -> Vec<Vec<String>> {
+        let mut commands = Vec::new();
+        while commands.len() < n {
+            let (stream, _) = listener.accept().unwrap();
+            let mut w = stream.try_clone().unwrap();
+            let mut r = BufReader::new(stream);
+            let mut line = String::new();
+            while commands.len() < n && r.read_line(&mut line).unwrap() > 0 {
+                let argc: usize = line.trim_end()[1..].parse().unwrap();
+                let mut args = Vec::with_capacity(argc);
+                for _ in 0..argc {
+                    line.clear();
+                    r.read_line(&mut line).unwrap();
+                    let len: usize = line.trim_end()[1..].parse().unwrap();
+                    let mut arg = vec![0_u8; len + 2];
+                    r.read_exact(&mut arg).unwrap();
+                    arg.truncate(len);
+                    args.push(String::from_utf8(arg).unwrap());
+                }
+                line.clear();
+                if args[0] == "XADD" {
+                    w.write_all(b"$3\r\n1-0\r\n").unwrap();
+                    commands.push(args);
+                } else {
+                    w.write_all(b"+OK\r\n").unwrap();
+                }
+            }
+        }
+        commands
+    }
+
+    fn record() -> BTreeMap<Key<'static>, Value<'static>> {
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("message"), Value::from("order placed"));
+        value.insert(Key::from("count"), Value::from(3_u64));
+        value
+    }
+
+    #[test]
+    fn redis_writer_works() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("redis://{}/", listener.local_addr().unwrap());
+        let server = thread::spawn(move || serve(listener, 2));
+
+        let writer = RedisWriter::new(url.as_str(), RedisOptions::default()).unwrap();
+        writer.write_log(&record()).unwrap();
+        // closes the connection, the server serves the next one.
+        drop(writer);
+
+        let opts = RedisOptions {
+            stream: "logs:orders".to_string(),
+            format: EntryFormat::Fields,
+            max_len: Some(10),
+            approximate: false,
+            ..Default::default()
+        };
+        let writer = RedisWriter::new(url.as_str(), opts).unwrap();
+        writer.write_log(&record()).unwrap();
+
+        let commands = server.join().unwrap();
+        assert_eq!(
+            vec![
+                "XADD",
+                "logs",
+                "MAXLEN",
+                "~",
+                "100000",
+                "*",
+                "record",
+                r#"{"count":3,"message":"order placed"}"#,
+            ],
+            commands[0]
+        );
+        assert_eq!(
+            vec![
+                "XADD",
+                "logs:orders",
+                "MAXLEN",
+                "10",
+                "*",
+                "count",
+                "3",
+                "message",
+                "order placed",
+            ],
+            commands[1]
+        );
+    }
+}
@@ -0,0 +1,464 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Field Rewrite Rules
+//!
+//! A [`Remap`] is a list of declarative [`Rule`]s that rewrite the fields of the records, in order,
+//! to adapt the output to a corporate schema without a custom writer:
+//! * [`Rule::Rename`] renames a field, such as `a.b` to `c`;
+//! * [`Rule::Move`] moves a field into a nested object, such as `method` to `http.request.method`;
+//! * [`Rule::Default`] sets a field if the record has none;
+//! * [`Rule::Drop`] removes the fields whose keys match a pattern, such as `debug_*`.
+//!
+//! The source of a rule is a path: a field whose key is the whole path, such as `service.name`,
+//! or else the dot-separated keys of the nested objects, such as `a.b` for `{"a":{"b":1}}`.
+//! A [`Rule::Rename`] and a [`Rule::Default`] write a top-level key, a [`Rule::Move`] writes the nested objects of its path.
+//! A pattern of [`Rule::Drop`] matches the top-level keys, with `*` as a wildcard.
+//! Each rule applies to the fields rewritten by the previous rules, and a rule that writes an existing field replaces it,
+//! so the last of the conflicting rules wins.
+//!
+//! The rules apply to all the writers with [`Builder::with_remap`](crate::Builder::with_remap),
+//! or to one writer with [`new_writer`]. They can be loaded from a JSON config file with [`Remap::from_file`]:
+//! ```json
+//! [
+//!   { "op": "rename", "from": "msg", "to": "message" },
+//!   { "op": "move", "from": "method", "to": "http.request.method" },
+//!   { "op": "default", "key": "env", "value": "production" },
+//!   { "op": "drop", "pattern": "debug_*" }
+//! ]
+//! ```
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{json, remap::Remap, Builder};
+//!
+//! fn main() {
+//!     let remap = Remap::new()
+//!         .with_rename("user.id", "user_id")
+//!         .with_move("method", "http.request.method")
+//!         .with_default("env", "production")
+//!         .with_drop("debug_*");
+//!     Builder::with_level("info")
+//!         .with_remap(remap)
+//!         .with_default_writer(json::new_writer(std::io::stdout()))
+//!         .init();
+//!
+//!     let user = serde_json::json!({"id": "u1", "name": "Alice"});
+//!     // {"env":"production","http":{"request":{"method":"GET"}},"level":"INFO","message":"request","user":{"name":"Alice"},"user_id":"u1",...}
+//!     log::info!(method = "GET", user:serde = user, debug_peer = "10.0.0.1"; "request");
+//! }
+//! ```
+//!
+
+use serde::Deserialize;
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+use crate::fields::FieldMap;
+use crate::{Fields, Key, Value, Writer};
+
+/// A field rewrite rule, see the [module level documentation](self).
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Rule {
+    /// Renames the field at the path `from` to the top-level key `to`.
+    Rename {
+        /// The path of the field.
+        from: String,
+        /// The new key of the field.
+        to: String,
+    },
+    /// Moves the field at the path `from` to the nested objects of the path `to`.
+    Move {
+        /// The path of the field.
+        from: String,
+        /// The dot-separated keys of the nested objects and of the field.
+        to: String,
+    },
+    /// Sets the top-level key `key` to `value` if the record has no field at the path `key`.
+    Default {
+        /// The path of the field.
+        key: String,
+        /// The default value.
+        value: serde_json::Value,
+    },
+    /// Removes the top-level fields whose keys match `pattern`, with `*` as a wildcard.
+    Drop {
+        /// The pattern of the keys, such as `debug_*` or `*_secret`.
+        pattern: String,
+    },
+}
+
+/// A list of field rewrite rules, see the [module level documentation](self).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Remap {
+    rules: Vec<Rule>,
+}
+
+impl Remap {
+    /// Creates a new Remap instance without rules.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the Remap with a [`Rule::Rename`] rule.
+    pub fn with_rename(self, from: &str, to: &str) -> Self {
+        self.with_rule(Rule::Rename {
+            from: from.to_string(),
+            to: to.to_string(),
+        })
+    }
+
+    /// Returns the Remap with a [`Rule::Move`] rule.
+    pub fn with_move(self, from: &str, to: &str) -> Self {
+        self.with_rule(Rule::Move {
+            from: from.to_string(),
+            to: to.to_string(),
+        })
+    }
+
+    /// Returns the Remap with a [`Rule::Default`] rule.
+    pub fn with_default<T: serde::Serialize>(self, key: &str, value: T) -> Self {
+        self.with_rule(Rule::Default {
+            key: key.to_string(),
+            value: serde_json::to_value(value).unwrap_or(serde_json::Value::Null),
+        })
+    }
+
+    /// Returns the Remap with a [`Rule::Drop`] rule.
+    pub fn with_drop(self, pattern: &str) -> Self {
+        self.with_rule(Rule::Drop {
+            pattern: pattern.to_string(),
+        })
+    }
+
+    /// Returns the Remap with a rule.
+    pub fn with_rule(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Returns the rules, in the order they apply.
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Returns a Remap with the rules of a JSON array, see the [module level documentation](self).
+    pub fn from_json(json: &str) -> Result<Self, io::Error> {
+        let rules: Vec<Rule> = serde_json::from_str(json).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid remap rules: {}", err),
+            )
+        })?;
+        Ok(Remap { rules })
+    }
+
+    /// Returns a Remap with the rules of a JSON config file, see [`Remap::from_json`].
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, io::Error> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+
+    /// Applies the rules to the fields of a record.
+    pub fn apply(&self, fields: &mut serde_json::Map<String, serde_json::Value>) {
+        for rule in self.rules.iter() {
+            match rule {
+                Rule::Rename { from, to } => {
+                    if let Some(value) = take(fields, from) {
+                        fields.insert(to.clone(), value);
+                    }
+                }
+                Rule::Move { from, to } => {
+                    if let Some(value) = take(fields, from) {
+                        insert_nested(fields, to, value);
+                    }
+                }
+                Rule::Default { key, value } => {
+                    if get(fields, key).is_none() {
+                        fields.insert(key.clone(), value.clone());
+                    }
+                }
+                Rule::Drop { pattern } => fields.retain(|key, _| !matches_pattern(pattern, key)),
+            }
+        }
+    }
+
+    // Writes a record rewritten by the rules to `w`, the unchanged fields keep their original values.
+    pub(crate) fn write(
+        &self,
+        w: &dyn Writer,
+        value: &BTreeMap<Key, Value>,
+    ) -> Result<(), io::Error> {
+        let fields = match serde_json::to_value(FieldMap(value))? {
+            serde_json::Value::Object(fields) => fields,
+            _ => return w.write_log(value),
+        };
+        let mut remapped = fields.clone();
+        self.apply(&mut remapped);
+        if remapped == fields {
+            return w.write_log(value);
+        }
+
+        let mut rewritten = BTreeMap::new();
+        for (key, json) in remapped.iter() {
+            let original = value
+                .get_key_value(&Key::from(key.as_str()))
+                .filter(|_| fields.get(key) == Some(json));
+            match original {
+                Some((key, value)) => rewritten.insert(key.clone(), value.clone()),
+                None => rewritten.insert(Key::from(key.as_str()), Value::from_serde(json)),
+            };
+        }
+        w.write_log(&rewritten)
+    }
+}
+
+// Returns the field at `path`, a top-level key or the keys of the nested objects.
+fn get<'a>(
+    fields: &'a serde_json::Map<String, serde_json::Value>,
+    path: &str,
+) -> Option<&'a serde_json::Value> {
+    if let Some(value) = fields.get(path) {
+        return Some(value);
+    }
+    let (first, rest) = path.split_once('.')?;
+    get(fields.get(first)?.as_object()?, rest)
+}
+
+// Removes the field at `path`, a top-level key or the keys of the nested objects.
+fn take(
+    fields: &mut serde_json::Map<String, serde_json::Value>,
+    path: &str,
+) -> Option<serde_json::Value> {
+    if let Some(value) = fields.remove(path) {
+        return Some(value);
+    }
+    let (first, rest) = path.split_once('.')?;
+    take(fields.get_mut(first)?.as_object_mut()?, rest)
+}
+
+// Inserts `value` at the dot-separated `path`, creating the nested objects,
+// a field that is not an object on the path is replaced.
+fn insert_nested(
+    fields: &mut serde_json::Map<String, serde_json::Value>,
+    path: &str,
+    value: serde_json::Value,
+) {
+    match path.split_once('.') {
+        None => {
+            fields.insert(path.to_string(), value);
+        }
+        Some((first, rest)) => {
+            let nested = fields
+                .entry(first)
+                .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()));
+            if !nested.is_object() {
+                *nested = serde_json::Value::Object(serde_json::Map::new());
+            }
+            if let serde_json::Value::Object(nested) = nested {
+                insert_nested(nested, rest, value);
+            }
+        }
+    }
+}
+
+// Returns true if `key` matches `pattern`, with `*` as a wildcard of any characters.
+fn matches_pattern(pattern: &str, key: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let mut rest = match key.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let parts: Vec<&str> = parts.collect();
+    let last = match parts.last() {
+        Some(last) => *last,
+        // no wildcard.
+        None => return rest.is_empty(),
+    };
+    for part in parts[..parts.len() - 1].iter() {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// A Writer implementation that rewrites the fields of the records written to a wrapped writer with a [`Remap`].
+pub struct RemapWriter {
+    remap: Remap,
+    inner: Box<dyn Writer>,
+}
+
+impl RemapWriter {
+    /// Creates a new RemapWriter instance that rewrites the records written to `w` with `remap`.
+    pub fn new(remap: Remap, w: Box<dyn Writer>) -> Self {
+        RemapWriter { remap, inner: w }
+    }
+}
+
+/// Implements Writer trait for RemapWriter.
+impl Writer for RemapWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        self.remap.write(self.inner.as_ref(), value)
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        self.write_log(&fields.to_map())
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        self.inner.flush()
+    }
+
+    fn shutdown(&self) -> Result<(), io::Error> {
+        self.inner.shutdown()
+    }
+
+    fn healthy(&self) -> bool {
+        self.inner.healthy()
+    }
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the RemapWriter
+/// that rewrites the records written to `w` with `remap`.
+pub fn new_writer(remap: Remap, w: Box<dyn Writer>) -> Box<dyn Writer> {
+    Box::new(RemapWriter::new(remap, w))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fn_writer;
+    use std::sync::{Arc, Mutex};
+
+    fn apply(remap: &Remap, fields: serde_json::Value) -> serde_json::Value {
+        let mut fields = match fields {
+            serde_json::Value::Object(fields) => fields,
+            _ => unreachable!(),
+        };
+        remap.apply(&mut fields);
+        serde_json::Value::Object(fields)
+    }
+
+    #[test]
+    fn remap_works() {
+        assert!(matches_pattern("debug_*", "debug_peer"));
+        assert!(matches_pattern("*_secret", "api_secret"));
+        assert!(matches_pattern("a*b*c", "a1b2c"));
+        assert!(matches_pattern("user", "user"));
+        assert!(!matches_pattern("user", "user_id"));
+        assert!(!matches_pattern("a*b*c", "a1c2b"));
+
+        let from_json = Remap::from_json(
+            r#"[
+                {"op": "rename", "from": "user.id", "to": "user_id"},
+                {"op": "move", "from": "method", "to": "http.request.method"},
+                {"op": "default", "key": "env", "value": "production"},
+                {"op": "drop", "pattern": "debug_*"}
+            ]"#,
+        )
+        .unwrap();
+        let remap = Remap::new()
+            .with_rename("user.id", "user_id")
+            .with_move("method", "http.request.method")
+            .with_default("env", "production")
+            .with_drop("debug_*");
+        assert_eq!(remap, from_json);
+        assert!(Remap::from_json(r#"[{"op": "upper", "key": "a"}]"#).is_err());
+
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let w = {
+            let lines = lines.clone();
+            new_writer(
+                remap,
+                fn_writer(move |value| {
+                    let json = serde_json::to_string(&FieldMap(value)).unwrap();
+                    lines.lock().unwrap().push(json);
+                    Ok(())
+                }),
+            )
+        };
+
+        let user = serde_json::json!({"id": "u1", "name": "Alice"});
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("env"), Value::from("staging"));
+        value.insert(Key::from("level"), Value::from("INFO"));
+        w.write_log(&value).unwrap();
+        value.remove(&Key::from("env"));
+        value.insert(Key::from("method"), Value::from("GET"));
+        value.insert(Key::from("user"), Value::from_serde(&user));
+        value.insert(Key::from("debug_peer"), Value::from("10.0.0.1"));
+        w.write_log(&value).unwrap();
+
+        assert_eq!(
+            vec![
+                r#"{"env":"staging","level":"INFO"}"#,
+                r#"{"env":"production","http":{"request":{"method":"GET"}},"level":"INFO","user":{"name":"Alice"},"user_id":"u1"}"#,
+            ],
+            *lines.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn chained_rules_works() {
+        // each rule applies to the fields rewritten by the previous rules.
+        let remap = Remap::new()
+            .with_rename("msg", "text")
+            .with_move("text", "log.message")
+            .with_default("log.message", "none")
+            .with_drop("log");
+        assert_eq!(
+            serde_json::json!({"level": "INFO"}),
+            apply(&remap, serde_json::json!({"level": "INFO", "msg": "hello"}))
+        );
+        let remap = Remap::new()
+            .with_rename("msg", "text")
+            .with_move("text", "log.message")
+            .with_default("log.message", "none");
+        assert_eq!(
+            serde_json::json!({"log": {"message": "hello"}}),
+            apply(&remap, serde_json::json!({"msg": "hello"}))
+        );
+        // a default writes a top-level key, see `Rule::Default`.
+        assert_eq!(
+            serde_json::json!({"log.message": "none"}),
+            apply(&remap, serde_json::json!({}))
+        );
+    }
+
+    #[test]
+    fn conflicting_rules_works() {
+        // a rule that writes an existing field replaces it, so the last rule wins.
+        let remap = Remap::new()
+            .with_rename("msg", "message")
+            .with_rename("text", "message");
+        assert_eq!(
+            serde_json::json!({"message": "from text"}),
+            apply(
+                &remap,
+                serde_json::json!({"message": "original", "msg": "from msg", "text": "from text"})
+            )
+        );
+        // a move through a field that is not an object replaces it.
+        let remap = Remap::new().with_move("method", "http.method");
+        assert_eq!(
+            serde_json::json!({"http": {"method": "GET"}}),
+            apply(&remap, serde_json::json!({"http": "1.1", "method": "GET"}))
+        );
+        // a default after a drop sets the dropped field.
+        let remap = Remap::new()
+            .with_drop("env")
+            .with_default("env", "production");
+        assert_eq!(
+            serde_json::json!({"env": "production"}),
+            apply(&remap, serde_json::json!({"env": "staging"}))
+        );
+        // a rule whose field is missing does nothing.
+        let remap = Remap::new().with_rename("missing", "message");
+        assert_eq!(
+            serde_json::json!({"message": "hello"}),
+            apply(&remap, serde_json::json!({"message": "hello"}))
+        );
+    }
+}
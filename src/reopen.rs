@@ -0,0 +1,262 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Reopening File Writer Implementation
+//!
+//! A [`Writer`] implementation that logs structured values in JSON format to a file at a given path,
+//! and opens the path again when the file is rotated by an external tool such as `logrotate`
+//! with the default `create` mode, which renames the file: without reopening, the logs would be written
+//! to the renamed file forever.
+//!
+//! The path is opened again on the next log call after:
+//! * a [`ReopenHandle::reopen`] call, such as from the `postrotate` script of `logrotate`
+//!   through a signal handler, see [`ReopenHandle::reopen_on_signals`] with the `signal` feature;
+//! * a check, every [`ReopenOptions::check_interval`], finds that the path doesn't exist anymore,
+//!   or that it is another file than the opened one, by comparing the device and inode numbers on Unix.
+//!
+//! To create a `Box<dyn Writer>` use the [`new_writer`] function.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{reopen, Builder};
+//!
+//! fn main() {
+//!     let path = std::env::temp_dir().join("app.log");
+//!     Builder::with_level("info")
+//!         .with_default_writer(reopen::new_writer(path, reopen::ReopenOptions::default()).unwrap())
+//!         .init();
+//!
+//!     log::info!("hello world");
+//! }
+//! ```
+//!
+
+use parking_lot::Mutex;
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::json::{with_encoded, FileOptions};
+use crate::metrics::Health;
+use crate::{log_failure, Fields, Key, Value, Writer};
+
+/// The options of a [`ReopenWriter`].
+#[derive(Debug, Clone)]
+pub struct ReopenOptions {
+    /// The options to open the file, the default is [`FileOptions::new`]. The buffer options are ignored.
+    pub file: FileOptions,
+    /// The interval to check whether the path still refers to the opened file, the default is 1 second.
+    /// `None` to reopen the file only on [`ReopenHandle::reopen`].
+    pub check_interval: Option<Duration>,
+}
+
+impl Default for ReopenOptions {
+    fn default() -> Self {
+        ReopenOptions {
+            file: FileOptions::new(),
+            check_interval: Some(Duration::from_secs(1)),
+        }
+    }
+}
+
+struct State {
+    file: File,
+    // when the path was last checked.
+    checked: Instant,
+}
+
+/// A Writer implementation that writes logs in JSON format to a file, and reopens it after a rotation.
+pub struct ReopenWriter {
+    path: PathBuf,
+    opts: ReopenOptions,
+    state: Mutex<State>,
+    reopen: Arc<AtomicBool>,
+    health: Health,
+}
+
+/// A handle to request a [`ReopenWriter`] to open its path again, it can be cloned and sent to other threads.
+#[derive(Debug, Clone)]
+pub struct ReopenHandle(Arc<AtomicBool>);
+
+impl ReopenHandle {
+    /// Requests the writer to open its path again, the file is reopened by the next log call.
+    pub fn reopen(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    /// Registers SIGHUP and SIGUSR1 handlers that request the writer to open its path again,
+    /// for the `postrotate` script of `logrotate`, such as `kill -HUP $(cat /run/app.pid)`.
+    /// The handlers replace the default action of the signals, which terminates the process.
+    ///
+    /// This method requires the `signal` feature, and is only available on Unix.
+    #[cfg(all(feature = "signal", unix))]
+    pub fn reopen_on_signals(&self) -> Result<(), io::Error> {
+        use signal_hook::consts::{SIGHUP, SIGUSR1};
+
+        for signal in [SIGHUP, SIGUSR1] {
+            signal_hook::flag::register(signal, self.0.clone())?;
+        }
+        Ok(())
+    }
+}
+
+impl ReopenWriter {
+    /// Creates a new ReopenWriter instance, and opens the file at a given path.
+    pub fn new<P: AsRef<Path>>(path: P, opts: ReopenOptions) -> Result<Self, io::Error> {
+        let path = path.as_ref().to_path_buf();
+        let file = opts.file.open_file(&path)?;
+        Ok(ReopenWriter {
+            path,
+            opts,
+            state: Mutex::new(State {
+                file,
+                checked: Instant::now(),
+            }),
+            reopen: Arc::new(AtomicBool::new(false)),
+            health: Health::default(),
+        })
+    }
+
+    /// Returns a [`ReopenHandle`] to request the writer to open its path again.
+    pub fn handle(&self) -> ReopenHandle {
+        ReopenHandle(self.reopen.clone())
+    }
+
+    fn write_line(&self, buf: &[u8]) -> Result<(), io::Error> {
+        let mut state = self.state.lock();
+        let mut reopen = self.reopen.swap(false, Ordering::AcqRel);
+        if let Some(interval) = self.opts.check_interval {
+            if state.checked.elapsed() >= interval {
+                state.checked = Instant::now();
+                reopen = reopen || is_rotated(&self.path, &state.file);
+            }
+        }
+        if reopen {
+            match self.opts.file.open_file(&self.path) {
+                Ok(file) => state.file = file,
+                // keeps writing to the opened file, and tries again on the next log call.
+                Err(err) => {
+                    self.reopen.store(true, Ordering::Release);
+                    log_failure(
+                        format!(
+                            "ReopenWriter failed to reopen {}: {}",
+                            self.path.display(),
+                            err
+                        )
+                        .as_str(),
+                    );
+                }
+            }
+        }
+        self.health.track(state.file.write_all(buf))
+    }
+}
+
+// Returns true if the path doesn't exist, or refers to another file than the opened one.
+pub(crate) fn is_rotated(path: &Path, file: &File) -> bool {
+    let meta = match fs::metadata(path) {
+        Ok(meta) => meta,
+        Err(err) => return err.kind() == io::ErrorKind::NotFound,
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        if let Ok(opened) = file.metadata() {
+            return meta.dev() != opened.dev() || meta.ino() != opened.ino();
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (file, meta);
+    false
+}
+
+/// Implements Writer trait for ReopenWriter.
+impl Writer for ReopenWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        with_encoded(value, |buf| self.write_line(buf))
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        with_encoded(fields, |buf| self.write_line(buf))
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        self.state.lock().file.flush()
+    }
+
+    fn healthy(&self) -> bool {
+        self.health.healthy()
+    }
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the ReopenWriter for the file at a given path.
+pub fn new_writer<P: AsRef<Path>>(
+    path: P,
+    opts: ReopenOptions,
+) -> Result<Box<dyn Writer>, io::Error> {
+    Ok(Box::new(ReopenWriter::new(path, opts)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the open files can't be renamed on Windows.
+    #[cfg(unix)]
+    #[test]
+    fn reopen_writer_works() {
+        let dir =
+            std::env::temp_dir().join(format!("structured-logger-reopen-{}", std::process::id()));
+        let path = dir.join("app.log");
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("message"), Value::from("hello"));
+
+        let opts = ReopenOptions {
+            check_interval: None,
+            ..Default::default()
+        };
+        let w = ReopenWriter::new(&path, opts).unwrap();
+        w.write_log(&value).unwrap();
+        fs::rename(&path, dir.join("app.log.1")).unwrap();
+        w.write_log(&value).unwrap();
+        assert_eq!(
+            2,
+            fs::read_to_string(dir.join("app.log.1"))
+                .unwrap()
+                .lines()
+                .count()
+        );
+        assert!(!path.exists());
+
+        w.handle().reopen();
+        w.write_log(&value).unwrap();
+        assert_eq!(1, fs::read_to_string(&path).unwrap().lines().count());
+
+        // the rotation is detected by the periodic check.
+        let opts = ReopenOptions {
+            check_interval: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        let w = ReopenWriter::new(&path, opts).unwrap();
+        fs::rename(&path, dir.join("app.log.2")).unwrap();
+        fs::write(&path, "").unwrap();
+        w.write_log(&value).unwrap();
+        assert_eq!(1, fs::read_to_string(&path).unwrap().lines().count());
+        assert_eq!(
+            1,
+            fs::read_to_string(dir.join("app.log.2"))
+                .unwrap()
+                .lines()
+                .count()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,157 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Resource Attributes
+//!
+//! A [`Resource`] declares the attributes of the entity that produces the logs, with the
+//! [OpenTelemetry semantic conventions](https://opentelemetry.io/docs/specs/semconv/resource/),
+//! such as `service.name`, `service.namespace`, `deployment.environment` or `host.name`.
+//! It is configured once with [`Builder::with_resource`](crate::Builder::with_resource),
+//! and each writer emits the attributes in the appropriate place:
+//! * the JSON writers write them as flat keys of every record, such as `"service.name":"checkout"`;
+//! * the [`ecs`](crate::ecs) writer writes `deployment.environment` as the ECS `service.environment` field,
+//!   the other attributes have the same names in ECS;
+//! * the [`otlp`](crate::otlp) writer, with the `otlp` feature, sends them as the OTLP resource of the records,
+//!   instead of the attributes of each record.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{json, Builder, Resource};
+//!
+//! fn main() {
+//!     let resource = Resource::new()
+//!         .with_service_name("checkout")
+//!         .with_deployment_environment("production");
+//!     Builder::with_level("info")
+//!         .with_resource(resource)
+//!         .with_default_writer(json::new_writer(std::io::stdout()))
+//!         .init();
+//!
+//!     // {"deployment.environment":"production","level":"INFO","message":"hello world","service.name":"checkout",...}
+//!     log::info!("hello world");
+//! }
+//! ```
+//!
+
+use std::{env, sync::OnceLock};
+
+// The resource of the global logger, set by `Builder::init`.
+static GLOBAL: OnceLock<Resource> = OnceLock::new();
+
+/// The resource attributes of the logs, see the [module level documentation](self).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Resource {
+    attributes: Vec<(String, String)>,
+}
+
+impl Resource {
+    /// Returns an empty Resource.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a Resource with the attributes of the standard OpenTelemetry environment variables:
+    /// `OTEL_RESOURCE_ATTRIBUTES`, such as `service.namespace=shop,deployment.environment=production`,
+    /// and `OTEL_SERVICE_NAME`, which takes precedence over a `service.name` in `OTEL_RESOURCE_ATTRIBUTES`.
+    pub fn from_env() -> Self {
+        let mut resource = Self::new();
+        if let Ok(attributes) = env::var("OTEL_RESOURCE_ATTRIBUTES") {
+            for attribute in attributes.split(',') {
+                if let Some((key, value)) = attribute.split_once('=') {
+                    if !key.trim().is_empty() {
+                        resource = resource.with_attribute(key.trim(), value.trim());
+                    }
+                }
+            }
+        }
+        match env::var("OTEL_SERVICE_NAME") {
+            Ok(name) if !name.is_empty() => resource.with_service_name(&name),
+            _ => resource,
+        }
+    }
+
+    /// Returns the resource of the global logger, set by [`Builder::with_resource`](crate::Builder::with_resource),
+    /// or `None` if the global logger is not initialized or has no resource.
+    pub fn global() -> Option<&'static Resource> {
+        GLOBAL.get()
+    }
+
+    pub(crate) fn set_global(resource: Resource) {
+        let _ = GLOBAL.set(resource);
+    }
+
+    /// Returns the Resource with the `service.name` attribute, the logical name of the service.
+    pub fn with_service_name(self, name: &str) -> Self {
+        self.with_attribute("service.name", name)
+    }
+
+    /// Returns the Resource with the `service.namespace` attribute, the group of services of `service.name`.
+    pub fn with_service_namespace(self, namespace: &str) -> Self {
+        self.with_attribute("service.namespace", namespace)
+    }
+
+    /// Returns the Resource with the `service.version` attribute.
+    pub fn with_service_version(self, version: &str) -> Self {
+        self.with_attribute("service.version", version)
+    }
+
+    /// Returns the Resource with the `deployment.environment` attribute, such as `"production"`.
+    pub fn with_deployment_environment(self, environment: &str) -> Self {
+        self.with_attribute("deployment.environment", environment)
+    }
+
+    /// Returns the Resource with the `host.name` attribute.
+    pub fn with_host_name(self, name: &str) -> Self {
+        self.with_attribute("host.name", name)
+    }
+
+    /// Returns the Resource with an attribute, it replaces the value of an attribute with the same key.
+    pub fn with_attribute(mut self, key: &str, value: &str) -> Self {
+        match self.attributes.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value.to_string(),
+            None => self.attributes.push((key.to_string(), value.to_string())),
+        }
+        self
+    }
+
+    /// Returns the value of an attribute.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns the attributes, in the order they were added.
+    pub fn attributes(&self) -> &[(String, String)] {
+        &self.attributes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resource_works() {
+        env::set_var(
+            "OTEL_RESOURCE_ATTRIBUTES",
+            "service.name=api, service.namespace=shop,invalid,=x",
+        );
+        env::set_var("OTEL_SERVICE_NAME", "checkout");
+        let resource = Resource::from_env().with_host_name("web-1");
+        env::remove_var("OTEL_RESOURCE_ATTRIBUTES");
+        env::remove_var("OTEL_SERVICE_NAME");
+
+        assert_eq!(
+            &[
+                ("service.name".to_string(), "checkout".to_string()),
+                ("service.namespace".to_string(), "shop".to_string()),
+                ("host.name".to_string(), "web-1".to_string()),
+            ],
+            resource.attributes()
+        );
+        assert_eq!(Some("shop"), resource.get("service.namespace"));
+        assert_eq!(None, resource.get("deployment.environment"));
+    }
+}
@@ -0,0 +1,388 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Retry Writer Implementation
+//!
+//! A [`Writer`] wrapper that retries the transient IO errors of the wrapped writer with backoff,
+//! then trips a circuit breaker that routes the records to a fallback writer,
+//! and periodically probes the wrapped writer until it recovers.
+//! Only the opening and the closing of the circuit are reported by [`log_failure`],
+//! instead of a failure line per failed record.
+//! To create a `Box<dyn Writer>` use the [`new_writer`] function.
+//!
+//! The delays between the retries are computed by a [`Backoff`], the exponential backoff with jitter
+//! shared by the network writers and the [`delivery`](crate::delivery) writer.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{json, retry, Builder};
+//!
+//! fn main() {
+//!     let file = std::fs::File::create(std::env::temp_dir().join("app.log")).unwrap();
+//!     Builder::with_level("info")
+//!         .with_default_writer(retry::new_writer(
+//!             json::new_writer(file),
+//!             Some(json::new_writer(std::io::stderr())),
+//!             retry::RetryOptions::default(),
+//!         ))
+//!         .init();
+//!
+//!     log::info!("hello world");
+//! }
+//! ```
+//!
+
+use parking_lot::Mutex;
+use std::{
+    collections::{hash_map::RandomState, BTreeMap},
+    hash::{BuildHasher, Hasher},
+    io,
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{log_failure, Fields, Key, Value, Writer};
+
+/// An exponential backoff with jitter: the delays between the attempts to deliver records to a destination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    /// The delay before the first retry, doubled on each retry, the default is 100 ms.
+    pub initial: Duration,
+    /// The maximum delay between two attempts, the default is 10 seconds.
+    pub max: Duration,
+    /// The maximum number of attempts, including the first one, the default is 5.
+    pub max_attempts: u32,
+    /// Whether the delays are randomized between half and all of the exponential delay,
+    /// so the writers of many processes don't retry in lockstep after an outage. The default is true.
+    pub jitter: bool,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(10),
+            max_attempts: 5,
+            jitter: true,
+        }
+    }
+}
+
+impl Backoff {
+    /// Returns the delay before a given retry, `1` for the first retry.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let factor = 1_u32
+            .checked_shl(retry.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        let delay = self.initial.saturating_mul(factor).min(self.max);
+        if !self.jitter {
+            return delay;
+        }
+        let half = delay / 2;
+        half + (delay - half).mul_f64(random_unit())
+    }
+
+    /// Returns true if a record can be retried after a given number of failed attempts.
+    pub fn can_retry(&self, attempts: u32) -> bool {
+        attempts < self.max_attempts
+    }
+}
+
+// Returns a pseudo-random number in `[0, 1)`, from the random keys of the standard library hasher.
+fn random_unit() -> f64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    (hasher.finish() >> 11) as f64 / (1_u64 << 53) as f64
+}
+
+/// The options of a [`RetryWriter`].
+#[derive(Debug, Clone)]
+pub struct RetryOptions {
+    /// How many times a transient error is retried before the circuit opens, the default is 3.
+    pub max_retries: u32,
+    /// The backoff before the first retry, doubled on each retry, the default is 10 ms.
+    /// The retries block the logging thread. The backoffs are randomized with jitter, see [`Backoff`].
+    pub initial_backoff: Duration,
+    /// The maximum backoff between retries, the default is 100 ms.
+    pub max_backoff: Duration,
+    /// How long the circuit stays open before the wrapped writer is probed again, the default is 5 seconds.
+    pub probe_interval: Duration,
+}
+
+impl Default for RetryOptions {
+    fn default() -> Self {
+        RetryOptions {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(100),
+            probe_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+/// A Writer implementation that retries the wrapped writer and falls back to another writer.
+pub struct RetryWriter {
+    inner: Box<dyn Writer>,
+    fallback: Option<Box<dyn Writer>>,
+    opts: RetryOptions,
+    // when the open circuit should probe the wrapped writer, `None` if the circuit is closed.
+    probe_at: Mutex<Option<Instant>>,
+    dropped: AtomicU64,
+}
+
+impl RetryWriter {
+    /// Creates a new RetryWriter instance that wraps `inner`, and routes the records to `fallback`
+    /// while the circuit is open. The records are dropped if there is no fallback writer.
+    pub fn new(
+        inner: Box<dyn Writer>,
+        fallback: Option<Box<dyn Writer>>,
+        opts: RetryOptions,
+    ) -> Self {
+        RetryWriter {
+            inner,
+            fallback,
+            opts,
+            probe_at: Mutex::new(None),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns true if the circuit is open, the records are routed to the fallback writer.
+    pub fn is_open(&self) -> bool {
+        self.probe_at.lock().is_some()
+    }
+
+    /// Returns the number of records dropped while the circuit was open and there is no fallback writer.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    fn write(&self, write: &dyn Fn(&dyn Writer) -> Result<(), io::Error>) -> Result<(), io::Error> {
+        let probe = {
+            let mut probe_at = self.probe_at.lock();
+            match *probe_at {
+                None => false,
+                Some(at) if Instant::now() >= at => {
+                    // only one record probes the wrapped writer at a time.
+                    *probe_at = Some(Instant::now() + self.opts.probe_interval);
+                    true
+                }
+                Some(_) => return self.write_fallback(write),
+            }
+        };
+
+        if probe {
+            if write(self.inner.as_ref()).is_ok() {
+                *self.probe_at.lock() = None;
+                log_failure("RetryWriter circuit closed: the writer has recovered");
+                return Ok(());
+            }
+            return self.write_fallback(write);
+        }
+
+        let backoff = Backoff {
+            initial: self.opts.initial_backoff,
+            max: self.opts.max_backoff,
+            max_attempts: self.opts.max_retries.saturating_add(1),
+            jitter: true,
+        };
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            match write(self.inner.as_ref()) {
+                Ok(()) => return Ok(()),
+                Err(err) if backoff.can_retry(attempts) && is_transient(&err) => {
+                    thread::sleep(backoff.delay(attempts));
+                }
+                Err(err) => {
+                    let mut probe_at = self.probe_at.lock();
+                    if probe_at.is_none() {
+                        *probe_at = Some(Instant::now() + self.opts.probe_interval);
+                        log_failure(format!("RetryWriter circuit opened: {}", err).as_str());
+                    }
+                    drop(probe_at);
+                    return self.write_fallback(write);
+                }
+            }
+        }
+    }
+
+    fn write_fallback(
+        &self,
+        write: &dyn Fn(&dyn Writer) -> Result<(), io::Error>,
+    ) -> Result<(), io::Error> {
+        match self.fallback {
+            Some(ref fallback) => write(fallback.as_ref()),
+            None => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Implements Writer trait for RetryWriter.
+impl Writer for RetryWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        self.write(&|w| w.write_log(value))
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        self.write(&|w| w.write_fields(fields))
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        if let Some(ref fallback) = self.fallback {
+            fallback.flush()?;
+        }
+        if self.is_open() {
+            return Ok(());
+        }
+        self.inner.flush()
+    }
+
+    fn shutdown(&self) -> Result<(), io::Error> {
+        let res = self.inner.shutdown();
+        if let Some(ref fallback) = self.fallback {
+            fallback.shutdown()?;
+        }
+        if self.is_open() {
+            return Ok(());
+        }
+        res
+    }
+
+    /// Returns false while the circuit is open, even if the records are written to the fallback writer.
+    fn healthy(&self) -> bool {
+        !self.is_open() && self.inner.healthy()
+    }
+}
+
+fn is_transient(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+    )
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the RetryWriter for a given writer and fallback writer.
+pub fn new_writer(
+    inner: Box<dyn Writer>,
+    fallback: Option<Box<dyn Writer>>,
+    opts: RetryOptions,
+) -> Box<dyn Writer> {
+    Box::new(RetryWriter::new(inner, fallback, opts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{atomic::AtomicU32, Arc};
+
+    // A writer that fails with the given error kinds, then succeeds.
+    struct FlakyWriter {
+        errors: Mutex<Vec<io::ErrorKind>>,
+        written: Arc<AtomicU32>,
+    }
+
+    impl Writer for FlakyWriter {
+        fn write_log(&self, _value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+            if let Some(kind) = self.errors.lock().pop() {
+                return Err(kind.into());
+            }
+            self.written.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    fn flaky(errors: Vec<io::ErrorKind>) -> (Box<dyn Writer>, Arc<AtomicU32>) {
+        let written = Arc::new(AtomicU32::new(0));
+        let w = FlakyWriter {
+            errors: Mutex::new(errors),
+            written: written.clone(),
+        };
+        (Box::new(w), written)
+    }
+
+    #[test]
+    fn backoff_works() {
+        let backoff = Backoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(1),
+            max_attempts: 3,
+            jitter: false,
+        };
+        let delays: Vec<u128> = (1..6).map(|r| backoff.delay(r).as_millis()).collect();
+        assert_eq!(vec![100, 200, 400, 800, 1000], delays);
+        assert_eq!(1000, backoff.delay(u32::MAX).as_millis());
+        assert!(backoff.can_retry(2));
+        assert!(!backoff.can_retry(3));
+
+        let backoff = Backoff {
+            jitter: true,
+            ..backoff
+        };
+        for retry in 1..6 {
+            let delay = backoff.delay(retry);
+            let max = Backoff {
+                jitter: false,
+                ..backoff
+            }
+            .delay(retry);
+            assert!(delay >= max / 2 && delay <= max);
+        }
+    }
+
+    #[test]
+    fn retry_writer_works() {
+        let opts = RetryOptions {
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(2),
+            probe_interval: Duration::from_millis(50),
+        };
+        let value = BTreeMap::new();
+
+        // the transient errors are retried.
+        let (inner, written) = flaky(vec![io::ErrorKind::TimedOut, io::ErrorKind::Interrupted]);
+        let (fallback, fallback_written) = flaky(vec![]);
+        let writer = RetryWriter::new(inner, Some(fallback), opts.clone());
+        writer.write_log(&value).unwrap();
+        assert_eq!(1, written.load(Ordering::Relaxed));
+        assert_eq!(0, fallback_written.load(Ordering::Relaxed));
+        assert!(!writer.is_open());
+
+        // the other errors open the circuit.
+        let (inner, written) = flaky(vec![io::ErrorKind::PermissionDenied]);
+        let (fallback, fallback_written) = flaky(vec![]);
+        let writer = RetryWriter::new(inner, Some(fallback), opts.clone());
+        writer.write_log(&value).unwrap();
+        writer.write_log(&value).unwrap();
+        assert!(writer.is_open());
+        assert!(!writer.healthy());
+        assert_eq!(0, written.load(Ordering::Relaxed));
+        assert_eq!(2, fallback_written.load(Ordering::Relaxed));
+
+        // the probe closes the circuit.
+        thread::sleep(Duration::from_millis(60));
+        writer.write_log(&value).unwrap();
+        assert!(!writer.is_open());
+        assert!(writer.healthy());
+        assert_eq!(1, written.load(Ordering::Relaxed));
+
+        // the records are dropped without fallback writer.
+        let (inner, _) = flaky(vec![io::ErrorKind::TimedOut; 3]);
+        let writer = RetryWriter::new(inner, None, opts);
+        writer.write_log(&value).unwrap();
+        writer.write_log(&value).unwrap();
+        assert!(writer.is_open());
+        assert_eq!(2, writer.dropped());
+    }
+}
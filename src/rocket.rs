@@ -0,0 +1,316 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Rocket Integration
+//!
+//! The [`init`] function installs the global logger configured by the `logger` table of a Rocket
+//! [`Figment`], such as the `Rocket.toml` file and the `ROCKET_` environment variables,
+//! and the [`AccessLog`] fairing logs the launch of Rocket and one structured record per HTTP request.
+//!
+//! The `logger` table has the following keys, all optional:
+//! * `level`, the level filter, such as `"info"`, the default is mapped from Rocket's `log_level`;
+//! * `output`, the destination of the logs, with the syntax of the `LOG_OUTPUT` environment variable
+//!   of [`Builder::from_env`], such as `"stdout"` or `"file:/var/log/app.log"`, the default is `"stderr"`;
+//! * `format`, the format of the logs, with the syntax of the `LOG_FORMAT` environment variable,
+//!   such as `"json"` (the default) or `"logfmt"`;
+//! * `targets`, the destinations of the records of some targets, in the same format,
+//!   such as `{ access = "file:/var/log/access.log" }` to write the access logs to their own file;
+//! * `target_levels`, the level filters of some targets, such as `{ "rocket::server" = "warn" }`.
+//!
+//! A record of a request has the `method`, the `path` without the query string, the `status` of the response,
+//! the `latency` as an [`Elapsed`](crate::Elapsed), and the `request_id` read from the request header
+//! set in [`AccessLogOptions::request_id_header`]. It is logged when the response is ready,
+//! with the `access` target by default.
+//!
+//! Rocket installs its own global logger in `rocket::build`, so [`init`] must be called before.
+//! Otherwise the fairing builds a [`Logger`] from the figment on ignition, for its own records only.
+//!
+//! Example:
+//! ```rust,no_run
+//! use structured_logger::rocket::{init, AccessLog};
+//!
+//! #[rocket::get("/")]
+//! fn index() -> &'static str {
+//!     "hello world"
+//! }
+//!
+//! #[rocket::launch]
+//! fn rocket() -> _ {
+//!     // [default.logger]
+//!     // output = "stdout"
+//!     // targets = { access = "file:/var/log/access.log" }
+//!     let figment = rocket::Config::figment();
+//!     init(&figment).unwrap();
+//!
+//!     // {"address":"127.0.0.1","level":"INFO","message":"rocket launched on 127.0.0.1:8000","port":8000,"profile":"debug","target":"rocket",...}
+//!     rocket::custom(figment)
+//!         .attach(AccessLog::default())
+//!         .mount("/", rocket::routes![index])
+//! }
+//! ```
+//!
+
+use ::rocket::{
+    fairing::{self, Fairing, Info, Kind},
+    figment::Figment,
+    Build, Data, Orbit, Request, Response, Rocket,
+};
+use log::{kv::Value, Level, LevelFilter, Record};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    io,
+    sync::{Arc, OnceLock},
+};
+
+use crate::access::Access;
+use crate::{env_writer, log_failure, start_timer, Builder, Logger, Stopwatch};
+
+pub use crate::access::AccessLogOptions;
+
+// The `logger` table of the figment, see the module level documentation.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct LoggerConfig {
+    level: Option<String>,
+    output: String,
+    format: String,
+    targets: BTreeMap<String, String>,
+    target_levels: BTreeMap<String, String>,
+}
+
+/// Returns a [`Builder`] configured by the `logger` table of `figment`, see the [module level documentation](self).
+///
+/// It returns an error if the configuration is invalid, or if a file can't be opened.
+pub fn builder(figment: &Figment) -> Result<Builder, io::Error> {
+    let config: LoggerConfig = match figment.extract_inner("logger") {
+        Ok(config) => config,
+        Err(err) if err.missing() => LoggerConfig::default(),
+        Err(err) => return Err(invalid(format!("invalid logger config: {}", err))),
+    };
+    let level = match config.level {
+        Some(level) => parse_level(&level)?,
+        None => {
+            let rocket = ::rocket::Config::try_from(figment)
+                .map_err(|err| invalid(format!("invalid rocket config: {}", err)))?;
+            match rocket.log_level {
+                ::rocket::config::LogLevel::Off => LevelFilter::Off,
+                ::rocket::config::LogLevel::Critical => LevelFilter::Warn,
+                ::rocket::config::LogLevel::Normal => LevelFilter::Info,
+                ::rocket::config::LogLevel::Debug => LevelFilter::Debug,
+            }
+        }
+    };
+
+    let mut builder = Builder::with_level(level.as_str())
+        .with_default_writer(env_writer(&config.output, &config.format)?);
+    for (targets, output) in &config.targets {
+        builder = builder.with_target_writer(targets, env_writer(output, &config.format)?);
+    }
+    for (targets, level) in &config.target_levels {
+        builder = builder.with_target_level(targets, parse_level(level)?.as_str());
+    }
+    Ok(builder)
+}
+
+/// Initializes the global logger configured by the `logger` table of `figment`,
+/// see the [module level documentation](self). It must be called before `rocket::build` or `rocket::custom`.
+///
+/// It returns an error if the configuration is invalid, or if the global logger is already initialized.
+pub fn init(figment: &Figment) -> Result<(), io::Error> {
+    builder(figment)?.try_init().map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("failed to initialize the logger: {}", err),
+        )
+    })
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, io::Error> {
+    level
+        .parse()
+        .map_err(|_| invalid(format!("invalid logger level: {:?}", level)))
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+/// A Rocket fairing that logs the launch of Rocket and the requests, see the [module level documentation](self).
+#[derive(Debug, Clone, Default)]
+pub struct AccessLog {
+    opts: AccessLogOptions,
+    // the options with the logger built on ignition, if there is no logger.
+    built: Arc<OnceLock<AccessLogOptions>>,
+}
+
+impl AccessLog {
+    /// Creates a new AccessLog instance with the given options.
+    pub fn new(opts: AccessLogOptions) -> Self {
+        AccessLog {
+            opts,
+            built: Arc::new(OnceLock::new()),
+        }
+    }
+
+    fn opts(&self) -> &AccessLogOptions {
+        self.built.get().unwrap_or(&self.opts)
+    }
+}
+
+// The stopwatch of a request, started when the request is received.
+struct RequestTimer(Stopwatch);
+
+#[::rocket::async_trait]
+impl Fairing for AccessLog {
+    fn info(&self) -> Info {
+        Info {
+            name: "Structured Logger",
+            kind: Kind::Ignite | Kind::Liftoff | Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        if self.opts.logger.is_some() || Logger::global().is_some() {
+            return Ok(rocket);
+        }
+
+        match builder(rocket.figment()) {
+            Ok(builder) => {
+                let _ = self.built.set(AccessLogOptions {
+                    logger: Some(builder.build()),
+                    ..self.opts.clone()
+                });
+                Ok(rocket)
+            }
+            Err(err) => {
+                log_failure(format!("failed to build the logger: {}", err).as_str());
+                Err(rocket)
+            }
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let opts = self.opts();
+        let config = rocket.config();
+        let address = config.address.to_string();
+        let profile = config.profile.as_str().as_str();
+        let kvs: [(&str, Value); 4] = [
+            ("address", Value::from(address.as_str())),
+            ("port", Value::from(config.port)),
+            ("profile", Value::from(profile)),
+            ("workers", Value::from(config.workers)),
+        ];
+        opts.write(
+            &Record::builder()
+                .args(format_args!(
+                    "rocket launched on {}:{}",
+                    address, config.port
+                ))
+                .level(Level::Info)
+                .target("rocket")
+                .key_values(&kvs.as_slice())
+                .build(),
+        );
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _data: &mut Data<'_>) {
+        req.local_cache(|| RequestTimer(start_timer()));
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let opts = self.opts();
+        let timer = req.local_cache(|| RequestTimer(start_timer()));
+        opts.log(&Access {
+            method: req.method().as_str(),
+            path: req.uri().path().as_str(),
+            request_id: req.headers().get_one(&opts.request_id_header),
+            status: Some(res.status().code),
+            error: None,
+            latency: timer.0.elapsed(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fn_writer, Key};
+    use ::rocket::{config::LogLevel, figment::providers::Serialized, http::Header};
+    use std::sync::Mutex;
+
+    #[::rocket::get("/hello")]
+    fn hello() -> &'static str {
+        "hello"
+    }
+
+    #[test]
+    fn builder_from_figment_works() {
+        let figment = Figment::from(::rocket::Config::debug_default());
+        assert_eq!(
+            LevelFilter::Info,
+            builder(&figment).unwrap().build().max_level()
+        );
+
+        let logger = serde_json::json!({
+            "level": "debug",
+            "output": "null",
+            "format": "logfmt",
+            "targets": { "access": "null" },
+            "target_levels": { "hyper": "warn" },
+        });
+        let figment = figment.merge(Serialized::default("logger", logger));
+        let logger = builder(&figment).unwrap().build();
+        assert_eq!(LevelFilter::Debug, logger.max_level());
+        assert_eq!(LevelFilter::Warn, logger.level("hyper"));
+
+        let figment = figment.merge(("logger.format", "xml"));
+        assert!(builder(&figment).is_err());
+    }
+
+    #[test]
+    fn access_log_fairing_works() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let logger = {
+            let lines = lines.clone();
+            Builder::with_level("info")
+                .with_default_writer(fn_writer(move |value| {
+                    let mut value = value.clone();
+                    value.remove(&Key::from("latency"));
+                    value.remove(&Key::from("timestamp"));
+                    value.remove(&Key::from("workers"));
+                    let json = serde_json::to_string(&crate::fields::FieldMap(&value)).unwrap();
+                    lines.lock().unwrap().push(json);
+                    Ok(())
+                }))
+                .build()
+        };
+        let fairing = AccessLog::new(AccessLogOptions {
+            logger: Some(logger),
+            ..Default::default()
+        });
+
+        let config = ::rocket::Config {
+            log_level: LogLevel::Off,
+            ..::rocket::Config::debug_default()
+        };
+        let rocket = ::rocket::custom(config)
+            .attach(fairing)
+            .mount("/", ::rocket::routes![hello]);
+        let client = ::rocket::local::blocking::Client::untracked(rocket).unwrap();
+        client
+            .get("/hello?token=secret")
+            .header(Header::new("x-request-id", "req-1"))
+            .dispatch();
+        client.post("/missing").dispatch();
+
+        assert_eq!(
+            vec![
+                r#"{"address":"127.0.0.1","level":"INFO","message":"rocket launched on 127.0.0.1:8000","port":8000,"profile":"debug","target":"rocket"}"#,
+                r#"{"level":"INFO","message":"GET /hello 200","method":"GET","path":"/hello","request_id":"req-1","status":200,"target":"access"}"#,
+                r#"{"level":"WARN","message":"POST /missing 404","method":"POST","path":"/missing","status":404,"target":"access"}"#,
+            ],
+            *lines.lock().unwrap()
+        );
+    }
+}
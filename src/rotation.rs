@@ -0,0 +1,451 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # File Rotation
+//!
+//! Rotates a log file by size or age: the file is renamed to `{path}.{unix_ms}`,
+//! compressed to `{path}.{unix_ms}.gz` with the `gzip` feature, and the oldest rotated files
+//! beyond the retention limit are removed. See [`RotationOptions`] and [`rotate_file`].
+//!
+//! A [`RotateHook`] set in [`RotationOptions::on_rotate`] is called with each completed file,
+//! after it is compressed, such as the [`s3`](crate::s3) uploader that archives the files to S3 or GCS
+//! with the `s3` feature.
+//!
+//! The [`AsyncRotatingFile`] is a tokio file sink that rotates itself without blocking the runtime:
+//! the rename, compression and retention are performed on [`tokio::task::spawn_blocking`].
+//! To create a `Box<dyn Writer>` with the [`async_json`](crate::async_json) writer use the [`new_async_writer`] function.
+//! They require the `tokio` feature.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{rotation, Builder};
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     let path = std::env::temp_dir().join("app.log");
+//!     let opts = rotation::RotationOptions {
+//!         max_size: Some(10 * 1024 * 1024),
+//!         max_files: Some(5),
+//!         ..Default::default()
+//!     };
+//!     Builder::with_level("info")
+//!         .with_default_writer(rotation::new_async_writer(path, opts).unwrap())
+//!         .init();
+//!
+//!     log::info!("hello world");
+//! }
+//! ```
+//!
+
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{log_failure, unix_ms};
+
+/// The default maximum size of a log file before it is rotated, 100 MiB.
+pub const DEFAULT_MAX_SIZE: u64 = 100 * 1024 * 1024;
+
+/// The default maximum number of rotated files to keep.
+pub const DEFAULT_MAX_FILES: usize = 10;
+
+/// A trait to process the completed files after a rotation, such as to archive them.
+pub trait RotateHook: Sync + Send + 'static {
+    /// Called with the path of a rotated file, after it is compressed. The hook may move or remove the file.
+    /// It runs on the thread that rotates the file, so a slow hook should hand the file to another thread.
+    fn on_rotate(&self, path: &Path) -> Result<(), io::Error>;
+}
+
+impl<F> RotateHook for F
+where
+    F: Fn(&Path) -> Result<(), io::Error> + Sync + Send + 'static,
+{
+    fn on_rotate(&self, path: &Path) -> Result<(), io::Error> {
+        self(path)
+    }
+}
+
+/// The options of the file rotation.
+#[derive(Clone)]
+pub struct RotationOptions {
+    /// The maximum size of the file before it is rotated, the default is [`DEFAULT_MAX_SIZE`].
+    /// `None` to not rotate by size.
+    pub max_size: Option<u64>,
+    /// The maximum age of the file before it is rotated, since it was opened, the default is `None`.
+    pub max_age: Option<Duration>,
+    /// The maximum number of rotated files to keep, the oldest are removed,
+    /// the default is [`DEFAULT_MAX_FILES`]. `None` to keep all files.
+    pub max_files: Option<usize>,
+    /// Whether the rotated files are compressed with gzip, the default is false.
+    #[cfg(feature = "gzip")]
+    pub compress: bool,
+    /// The hook called with each rotated file, the default is `None`.
+    /// Its failures are reported with the failure handler, and don't fail the rotation.
+    pub on_rotate: Option<Arc<dyn RotateHook>>,
+}
+
+impl fmt::Debug for RotationOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("RotationOptions");
+        s.field("max_size", &self.max_size)
+            .field("max_age", &self.max_age)
+            .field("max_files", &self.max_files);
+        #[cfg(feature = "gzip")]
+        s.field("compress", &self.compress);
+        s.field("on_rotate", &self.on_rotate.is_some()).finish()
+    }
+}
+
+impl Default for RotationOptions {
+    fn default() -> Self {
+        RotationOptions {
+            max_size: Some(DEFAULT_MAX_SIZE),
+            max_age: None,
+            max_files: Some(DEFAULT_MAX_FILES),
+            #[cfg(feature = "gzip")]
+            compress: false,
+            on_rotate: None,
+        }
+    }
+}
+
+impl RotationOptions {
+    #[cfg(all(feature = "tokio", not(target_family = "wasm")))]
+    fn should_rotate(&self, size: u64, opened_at: std::time::Instant) -> bool {
+        self.max_size.is_some_and(|max| size >= max)
+            || self.max_age.is_some_and(|max| opened_at.elapsed() >= max)
+    }
+}
+
+/// Rotates the file at a given path: renames it, compresses it if enabled, calls the [`RotateHook`] if any,
+/// and removes the oldest rotated files. It returns the path of the rotated file. It blocks, the file should be closed or flushed before,
+/// and reopened after by the caller.
+pub fn rotate_file(path: &Path, opts: &RotationOptions) -> Result<PathBuf, io::Error> {
+    // the next free timestamp, a file rotated in the same millisecond is not overwritten.
+    let mut ts = unix_ms();
+    let rotated = loop {
+        let mut rotated = path.as_os_str().to_owned();
+        rotated.push(format!(".{}", ts));
+        let rotated = PathBuf::from(rotated);
+        if !rotated.exists() {
+            break rotated;
+        }
+        ts += 1;
+    };
+    fs::rename(path, &rotated)?;
+
+    #[cfg(feature = "gzip")]
+    let rotated = if opts.compress {
+        compress_file(&rotated)?
+    } else {
+        rotated
+    };
+
+    if let Some(ref hook) = opts.on_rotate {
+        if let Err(err) = hook.on_rotate(&rotated) {
+            log_failure(
+                format!("failed to process the rotated file {:?}: {}", rotated, err).as_str(),
+            );
+        }
+    }
+
+    if let Some(max_files) = opts.max_files {
+        remove_oldest(path, max_files)?;
+    }
+    Ok(rotated)
+}
+
+#[cfg(feature = "gzip")]
+fn compress_file(path: &Path) -> Result<PathBuf, io::Error> {
+    use flate2::{write::GzEncoder, Compression};
+
+    let mut compressed = path.as_os_str().to_owned();
+    compressed.push(".gz");
+    let compressed = PathBuf::from(compressed);
+    let mut encoder = GzEncoder::new(fs::File::create(&compressed)?, Compression::default());
+    io::copy(&mut fs::File::open(path)?, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+    fs::remove_file(path)?;
+    Ok(compressed)
+}
+
+// Removes the oldest rotated files of a given path, `{path}.{unix_ms}[.gz]`, beyond `max_files`.
+fn remove_oldest(path: &Path, max_files: usize) -> Result<(), io::Error> {
+    let (dir, name) = match (path.parent(), path.file_name().and_then(|n| n.to_str())) {
+        (Some(dir), Some(name)) => (dir, name),
+        _ => return Ok(()),
+    };
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+
+    let mut rotated: Vec<(u64, PathBuf)> = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let ts = file_name
+            .to_str()
+            .and_then(|n| n.strip_prefix(name))
+            .and_then(|n| n.strip_prefix('.'))
+            .map(|n| n.trim_end_matches(".gz"))
+            .and_then(|n| n.parse::<u64>().ok());
+        if let Some(ts) = ts {
+            rotated.push((ts, entry.path()));
+        }
+    }
+    if rotated.len() > max_files {
+        rotated.sort();
+        for (_, path) in rotated.iter().take(rotated.len() - max_files) {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(feature = "tokio", not(target_family = "wasm")))]
+pub use async_file::{new_async_writer, AsyncRotatingFile};
+
+// The tokio file sink, tokio doesn't support the file system on wasm.
+#[cfg(all(feature = "tokio", not(target_family = "wasm")))]
+mod async_file {
+    use std::{
+        fs,
+        future::Future,
+        io,
+        path::PathBuf,
+        pin::Pin,
+        task::{ready, Context, Poll},
+        time::Instant,
+    };
+    use tokio::{fs::File, io::AsyncWrite, task::JoinHandle};
+
+    use super::{rotate_file, RotationOptions};
+    use crate::json::FileOptions;
+    use crate::{log_failure, Writer};
+
+    enum State {
+        Open(File),
+        // rotating the file and reopening it, or only reopening it after an error.
+        Opening(JoinHandle<io::Result<fs::File>>),
+        Closed,
+    }
+
+    /// A tokio file sink that rotates itself by size or age, see the [module level documentation](super).
+    /// A record is never split across files: the file is only rotated when the last write ended with a newline.
+    pub struct AsyncRotatingFile {
+        path: PathBuf,
+        opts: RotationOptions,
+        state: State,
+        size: u64,
+        opened_at: Instant,
+        // whether the last write ended with a newline.
+        line_start: bool,
+    }
+
+    impl AsyncRotatingFile {
+        /// Opens the file at a given path, with the default [`FileOptions`]:
+        /// the parent directories are created, and the logs are appended to the file.
+        /// The rotation of an existing file takes its current size into account.
+        pub fn open<P: Into<PathBuf>>(path: P, opts: RotationOptions) -> Result<Self, io::Error> {
+            let path = path.into();
+            let file = FileOptions::new().open_file(&path)?;
+            let size = file.metadata()?.len();
+            Ok(AsyncRotatingFile {
+                path,
+                opts,
+                state: State::Open(File::from_std(file)),
+                size,
+                opened_at: Instant::now(),
+                line_start: true,
+            })
+        }
+
+        fn start_opening(&mut self, rotate: bool) {
+            let path = self.path.clone();
+            let opts = self.opts.clone();
+            self.state = State::Opening(tokio::task::spawn_blocking(move || {
+                if rotate {
+                    if let Err(err) = rotate_file(&path, &opts) {
+                        log_failure(
+                            format!("AsyncRotatingFile failed to rotate: {}", err).as_str(),
+                        );
+                    }
+                }
+                FileOptions::new().open_file(&path)
+            }));
+        }
+
+        // Polls the pending rotation or reopening until the file is open.
+        fn poll_open(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<&mut File>> {
+            if let State::Opening(ref mut handle) = self.state {
+                let res = ready!(Pin::new(handle).poll(cx));
+                match res.map_err(io::Error::other).and_then(|res| res) {
+                    Ok(file) => {
+                        self.size = file.metadata().map(|m| m.len()).unwrap_or(0);
+                        self.opened_at = Instant::now();
+                        self.state = State::Open(File::from_std(file));
+                    }
+                    Err(err) => {
+                        self.state = State::Closed;
+                        return Poll::Ready(Err(err));
+                    }
+                }
+            }
+            match self.state {
+                State::Open(ref mut file) => Poll::Ready(Ok(file)),
+                _ => Poll::Ready(Err(io::Error::other("AsyncRotatingFile is closed"))),
+            }
+        }
+    }
+
+    impl AsyncWrite for AsyncRotatingFile {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<Result<usize, io::Error>> {
+            let this = self.get_mut();
+            if let State::Closed = this.state {
+                // retries to reopen the file after an error.
+                this.start_opening(false);
+            }
+            loop {
+                ready!(this.poll_open(cx))?;
+                if this.line_start
+                    && this.size > 0
+                    && this.opts.should_rotate(this.size, this.opened_at)
+                {
+                    if let State::Open(ref mut file) = this.state {
+                        ready!(Pin::new(file).poll_flush(cx))?;
+                    }
+                    this.start_opening(true);
+                    continue;
+                }
+
+                let file = ready!(this.poll_open(cx))?;
+                let n = ready!(Pin::new(file).poll_write(cx, buf))?;
+                if n > 0 {
+                    this.size += n as u64;
+                    this.line_start = buf[n - 1] == b'\n';
+                }
+                return Poll::Ready(Ok(n));
+            }
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+            let this = self.get_mut();
+            if let State::Closed = this.state {
+                return Poll::Ready(Ok(()));
+            }
+            let file = ready!(this.poll_open(cx))?;
+            Pin::new(file).poll_flush(cx)
+        }
+
+        fn poll_shutdown(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Result<(), io::Error>> {
+            let this = self.get_mut();
+            if let State::Closed = this.state {
+                return Poll::Ready(Ok(()));
+            }
+            let file = ready!(this.poll_open(cx))?;
+            Pin::new(file).poll_shutdown(cx)
+        }
+    }
+
+    /// Creates a new `Box<dyn Writer>` instance with the [`AsyncJSONWriter`](crate::async_json::AsyncJSONWriter)
+    /// for an [`AsyncRotatingFile`] at a given path.
+    pub fn new_async_writer<P: Into<PathBuf>>(
+        path: P,
+        opts: RotationOptions,
+    ) -> Result<Box<dyn Writer>, io::Error> {
+        Ok(crate::async_json::new_writer(AsyncRotatingFile::open(
+            path, opts,
+        )?))
+    }
+}
+
+#[cfg(all(test, any(feature = "tokio", feature = "gzip")))]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "tokio")]
+    fn rotated_files(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .filter(|n| n != "app.log")
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn async_rotating_file_works() {
+        use tokio::io::AsyncWriteExt;
+
+        let dir =
+            std::env::temp_dir().join(format!("structured-logger-rotation-{}", std::process::id()));
+        let path = dir.join("app.log");
+        let opts = RotationOptions {
+            max_size: Some(20),
+            max_files: Some(2),
+            ..Default::default()
+        };
+
+        let mut file = AsyncRotatingFile::open(&path, opts).unwrap();
+        for i in 0..4 {
+            // a record is never split across files.
+            file.write_all(format!("{{\"line\":{}", i).as_bytes())
+                .await
+                .unwrap();
+            file.write_all(b",\"padding\":true}\n").await.unwrap();
+            file.flush().await.unwrap();
+            tokio::time::sleep(Duration::from_millis(2)).await;
+        }
+        file.shutdown().await.unwrap();
+
+        assert_eq!(
+            "{\"line\":3,\"padding\":true}\n",
+            fs::read_to_string(&path).unwrap()
+        );
+        let rotated = rotated_files(&dir);
+        assert_eq!(2, rotated.len());
+        let last = fs::read_to_string(dir.join(&rotated[1])).unwrap();
+        assert_eq!("{\"line\":2,\"padding\":true}\n", last);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn rotate_file_compress_works() {
+        use std::io::Read;
+
+        let dir =
+            std::env::temp_dir().join(format!("structured-logger-gzip-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.log");
+        fs::write(&path, "{\"line\":1}\n").unwrap();
+        let opts = RotationOptions {
+            compress: true,
+            ..Default::default()
+        };
+
+        let rotated = rotate_file(&path, &opts).unwrap();
+        assert!(!path.exists());
+        assert!(rotated.to_str().unwrap().ends_with(".gz"));
+        let mut content = String::new();
+        flate2::read::GzDecoder::new(fs::File::open(&rotated).unwrap())
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!("{\"line\":1}\n", content);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,210 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Routing Writers
+//!
+//! [`Writer`] combinators to route the records to several writers:
+//! * [`tee`] writes every record to all the given writers;
+//! * [`min_level`] writes only the records of a given level or more severe to the wrapped writer.
+//!
+//! The level of a record is read from its `level` field, or from the `severity` or `status` field
+//! written by [`Builder::with_gcp_fields`](crate::Builder::with_gcp_fields)
+//! and [`Builder::with_datadog_fields`](crate::Builder::with_datadog_fields).
+//! See [`Builder::with_split_files`](crate::Builder::with_split_files) for the common layout
+//! of a combined file, an error file, and the errors on stderr.
+//!
+//! Example:
+//! ```rust
+//! use log::Level;
+//! use structured_logger::{json, route, Builder};
+//!
+//! fn main() {
+//!     let path = std::env::temp_dir().join("app.log");
+//!     Builder::with_level("info")
+//!         .with_default_writer(route::tee(vec![
+//!             json::new_file_writer(path).unwrap(),
+//!             route::min_level(Level::Error, json::new_writer(std::io::stderr())),
+//!         ]))
+//!         .init();
+//!
+//!     log::info!("written to the file");
+//!     log::error!("written to the file and stderr");
+//! }
+//! ```
+//!
+
+use log::Level;
+use std::{collections::BTreeMap, io, str::FromStr};
+
+use crate::{Fields, Key, Value, Writer};
+
+/// The fields that hold the level of a record, depending on the platform fields.
+pub(crate) const LEVEL_KEYS: [&str; 3] = ["level", "severity", "status"];
+
+/// Parses the level of a record, such as `"ERROR"`, `"WARNING"` or `"warn"`.
+pub(crate) fn parse_level(level: &str) -> Option<Level> {
+    match level {
+        "WARNING" | "warning" => Some(Level::Warn),
+        level => Level::from_str(level).ok(),
+    }
+}
+
+/// A Writer implementation that writes logs to several writers.
+pub struct TeeWriter(Vec<Box<dyn Writer>>);
+
+impl TeeWriter {
+    /// Creates a new TeeWriter instance that writes logs to all the given writers, in order.
+    pub fn new(writers: Vec<Box<dyn Writer>>) -> Self {
+        TeeWriter(writers)
+    }
+
+    // Calls `f` with every writer, even after an error, and returns the first error.
+    fn each(&self, f: impl Fn(&dyn Writer) -> Result<(), io::Error>) -> Result<(), io::Error> {
+        let mut res = Ok(());
+        for w in &self.0 {
+            if let Err(err) = f(w.as_ref()) {
+                if res.is_ok() {
+                    res = Err(err);
+                }
+            }
+        }
+        res
+    }
+}
+
+/// Implements Writer trait for TeeWriter.
+impl Writer for TeeWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        self.each(|w| w.write_log(value))
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        self.each(|w| w.write_fields(fields))
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        self.each(|w| w.flush())
+    }
+
+    fn shutdown(&self) -> Result<(), io::Error> {
+        self.each(|w| w.shutdown())
+    }
+
+    fn healthy(&self) -> bool {
+        self.0.iter().all(|w| w.healthy())
+    }
+}
+
+/// A Writer implementation that writes the logs of a given level or more severe to a wrapped writer.
+pub struct LevelWriter {
+    level: Level,
+    inner: Box<dyn Writer>,
+}
+
+impl LevelWriter {
+    /// Creates a new LevelWriter instance that writes the logs of `level` or more severe to `w`.
+    /// The records without a level are not written.
+    pub fn new(level: Level, w: Box<dyn Writer>) -> Self {
+        LevelWriter { level, inner: w }
+    }
+
+    fn is_enabled(&self, level: Option<&str>) -> bool {
+        level.and_then(parse_level).is_some_and(|l| l <= self.level)
+    }
+}
+
+/// Implements Writer trait for LevelWriter.
+impl Writer for LevelWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        let level = LEVEL_KEYS.iter().find_map(|key| {
+            value
+                .get(&Key::from(*key))
+                .and_then(|v| v.to_borrowed_str())
+        });
+        if self.is_enabled(level) {
+            return self.inner.write_log(value);
+        }
+        Ok(())
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        let mut enabled = false;
+        let _ = fields.visit(&mut |key, value| {
+            if LEVEL_KEYS.contains(&key.as_str()) {
+                enabled = self.is_enabled(value.to_borrowed_str());
+            }
+            Ok(())
+        });
+        if enabled {
+            return self.inner.write_fields(fields);
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        self.inner.flush()
+    }
+
+    fn shutdown(&self) -> Result<(), io::Error> {
+        self.inner.shutdown()
+    }
+
+    fn healthy(&self) -> bool {
+        self.inner.healthy()
+    }
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the TeeWriter for the given writers.
+pub fn tee(writers: Vec<Box<dyn Writer>>) -> Box<dyn Writer> {
+    Box::new(TeeWriter::new(writers))
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the LevelWriter
+/// that writes the logs of `level` or more severe to `w`.
+pub fn min_level(level: Level, w: Box<dyn Writer>) -> Box<dyn Writer> {
+    Box::new(LevelWriter::new(level, w))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fn_writer;
+    use std::sync::{Arc, Mutex};
+
+    fn collect(lines: &Arc<Mutex<Vec<String>>>) -> Box<dyn Writer> {
+        let lines = lines.clone();
+        fn_writer(move |value| {
+            let level = value
+                .get(&Key::from("level"))
+                .and_then(|v| v.to_borrowed_str())
+                .unwrap_or_default();
+            lines.lock().unwrap().push(level.to_string());
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn route_works() {
+        let all = Arc::new(Mutex::new(Vec::new()));
+        let warnings = Arc::new(Mutex::new(Vec::new()));
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let w = tee(vec![
+            collect(&all),
+            min_level(Level::Warn, collect(&warnings)),
+            min_level(Level::Error, collect(&errors)),
+        ]);
+
+        for level in ["DEBUG", "INFO", "WARN", "ERROR"] {
+            let mut value = BTreeMap::new();
+            value.insert(Key::from("level"), Value::from(level));
+            w.write_log(&value).unwrap();
+        }
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("severity"), Value::from("WARNING"));
+        w.write_log(&value).unwrap();
+
+        assert_eq!(5, all.lock().unwrap().len());
+        assert_eq!(vec!["WARN", "ERROR", ""], *warnings.lock().unwrap());
+        assert_eq!(vec!["ERROR"], *errors.lock().unwrap());
+    }
+}
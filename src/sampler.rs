@@ -0,0 +1,145 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Sampling
+//!
+//! A [`Sampler`] decides whether to write each record enabled by the level filters, with the metadata
+//! and all the fields of the record, including the static fields and the built-in keys such as `level` and `trace_id`.
+//! It is set with [`Builder::with_sampler`](crate::Builder::with_sampler), and is consulted after the
+//! record filter of [`Builder::with_filter`](crate::Builder::with_filter), before writing the record.
+//!
+//! A sampler can keep state across the records, such as the trace ids of the errors,
+//! to implement the sampling strategies of an organization. The sampler is called for every record,
+//! on the logging thread, so it should be fast and not log itself.
+//!
+//! Example: keep 1 in 10 of the records, but all the errors, and all the records of a trace after an error:
+//! ```rust
+//! use log::Metadata;
+//! use std::collections::HashSet;
+//! use std::sync::{atomic::{AtomicU64, Ordering}, Mutex};
+//! use structured_logger::{sampler::Sampler, Builder, Fields};
+//!
+//! #[derive(Default)]
+//! struct ErrorTraceSampler {
+//!     count: AtomicU64,
+//!     error_traces: Mutex<HashSet<String>>,
+//! }
+//!
+//! impl Sampler for ErrorTraceSampler {
+//!     fn sample(&self, metadata: &Metadata, fields: &Fields) -> bool {
+//!         let fields = fields.to_sorted();
+//!         let trace_id = fields.get("trace_id").map(|v| v.to_string());
+//!         if metadata.level() == log::Level::Error {
+//!             if let Some(trace_id) = trace_id {
+//!                 self.error_traces.lock().unwrap().insert(trace_id);
+//!             }
+//!             return true;
+//!         }
+//!         if trace_id.is_some_and(|id| self.error_traces.lock().unwrap().contains(&id)) {
+//!             return true;
+//!         }
+//!         self.count.fetch_add(1, Ordering::Relaxed) % 10 == 0
+//!     }
+//! }
+//!
+//! fn main() {
+//!     Builder::with_level("info")
+//!         .with_sampler(Box::new(ErrorTraceSampler::default()))
+//!         .init();
+//!
+//!     log::error!(trace_id = "4bf92f3577b34da6"; "payment failed");
+//!     // written, as the trace has an error.
+//!     log::info!(trace_id = "4bf92f3577b34da6"; "payment retried");
+//! }
+//! ```
+//!
+
+use log::Metadata;
+
+use crate::Fields;
+
+/// A trait to decide whether to write a record, see the [`sampler`](crate::sampler) module.
+pub trait Sampler: Sync + Send + 'static {
+    /// Returns true to write the record with the given metadata and fields, false to drop it.
+    fn sample(&self, metadata: &Metadata, fields: &Fields) -> bool;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fn_writer, Builder, TraceContext};
+    use log::{kv::Key, Level, Log, Record};
+    use std::sync::{Arc, Mutex};
+
+    // Keeps the warnings and the errors, and records the fields of every record it sees.
+    #[derive(Clone, Default)]
+    struct WarnSampler(Arc<Mutex<Vec<String>>>);
+
+    impl Sampler for WarnSampler {
+        fn sample(&self, metadata: &Metadata, fields: &Fields) -> bool {
+            let fields: Vec<String> = fields
+                .to_map()
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect();
+            self.0.lock().unwrap().push(fields.join(" "));
+            metadata.level() <= Level::Warn
+        }
+    }
+
+    #[test]
+    fn sampler_works() {
+        let sampler = WarnSampler::default();
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let w = {
+            let written = written.clone();
+            fn_writer(move |value| {
+                written
+                    .lock()
+                    .unwrap()
+                    .push(value[&Key::from("message")].to_string());
+                Ok(())
+            })
+        };
+        let logger = Builder::with_level("info")
+            .with_default_writer(w)
+            .with_static_field("service", "api")
+            .with_trace_context(Box::new(|| {
+                Some(TraceContext {
+                    trace_id: 1,
+                    span_id: 2,
+                })
+            }))
+            .with_clock(|| 1679745592127)
+            .with_sampler(Box::new(sampler.clone()))
+            .build();
+
+        for (level, message) in [
+            (Level::Info, "info"),
+            (Level::Warn, "warn"),
+            (Level::Debug, "debug"),
+            (Level::Error, "error"),
+        ] {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(level)
+                    .target("api")
+                    .build(),
+            );
+        }
+
+        // the dropped records never reach the writer.
+        assert_eq!(vec!["warn", "error"], *written.lock().unwrap());
+        // the records disabled by the level filters are not sampled.
+        let seen = sampler.0.lock().unwrap();
+        assert_eq!(3, seen.len());
+        // the sampler sees the built-in and the static fields.
+        assert_eq!(
+            r#"level=INFO message=info service="api" span_id=0000000000000002 target=api timestamp=1679745592127 trace_id=00000000000000000000000000000001"#,
+            seen[0]
+        );
+        assert!(seen[1].starts_with("level=WARN message=warn "));
+        assert!(seen[2].starts_with("level=ERROR message=error "));
+    }
+}
@@ -0,0 +1,346 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Key Sanitizing Writer
+//!
+//! A [`Writer`] wrapper that normalizes the keys of the records, and the keys of their nested objects,
+//! such as the keys of a user-controlled map captured with `:serde`, before they reach a downstream index
+//! whose mappings would break on them:
+//! * the control characters are removed;
+//! * the characters not allowed by [`SanitizeOptions::allowed`] are replaced with [`SanitizeOptions::replacement`],
+//!   or removed if it is `None`;
+//! * the keys are converted to lowercase or to snake_case with [`SanitizeOptions::case`].
+//!
+//! An empty key is written as `_`. When several keys of an object are normalized to the same key,
+//! such as `userId` and `user_id` in snake_case, the field whose key is already sanitized is written,
+//! or else the first one in key order, the others are dropped.
+//! The wrapped writer receives the records as a map, see [`Writer::write_log`].
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{json, sanitize, Builder};
+//!
+//! fn main() {
+//!     let opts = sanitize::SanitizeOptions {
+//!         case: sanitize::KeyCase::Snake,
+//!         ..Default::default()
+//!     };
+//!     Builder::with_level("info")
+//!         .with_default_writer(sanitize::new_writer(opts, json::new_writer(std::io::stdout())))
+//!         .init();
+//!
+//!     let headers = serde_json::json!({"Content-Type": "text/plain", "X-Bad\nKey": "1"});
+//!     // {"headers":{"content_type":"text/plain","x_bad_key":"1"},"level":"INFO","message":"request",...}
+//!     log::info!(headers:serde = headers; "request");
+//! }
+//! ```
+//!
+
+use std::{borrow::Cow, collections::BTreeMap, io};
+
+use crate::fields::is_primitive;
+use crate::{Fields, Key, Value, Writer};
+
+/// The case of the sanitized keys.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeyCase {
+    /// Keeps the case of the keys.
+    #[default]
+    Unchanged,
+    /// Converts the keys to lowercase.
+    Lower,
+    /// Converts the keys to snake_case, such as `userId` or `User-Id` to `user_id`.
+    Snake,
+}
+
+/// The options of a [`SanitizeWriter`].
+#[derive(Debug, Clone)]
+pub struct SanitizeOptions {
+    /// Returns true if a character is allowed in the keys, the default allows the ASCII letters and digits,
+    /// and `_`, `.`, `-` and `@`.
+    pub allowed: fn(char) -> bool,
+    /// The replacement of the characters that are not allowed, the default is `Some('_')`.
+    pub replacement: Option<char>,
+    /// The case of the keys, the default is [`KeyCase::Unchanged`].
+    pub case: KeyCase,
+}
+
+impl Default for SanitizeOptions {
+    fn default() -> Self {
+        SanitizeOptions {
+            allowed: is_default_allowed,
+            replacement: Some('_'),
+            case: KeyCase::Unchanged,
+        }
+    }
+}
+
+impl SanitizeOptions {
+    /// Returns the sanitized key, borrowed if it is unchanged.
+    pub fn sanitize<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        let unchanged = !key.is_empty()
+            && key.chars().all(|c| !c.is_control() && (self.allowed)(c))
+            && match self.case {
+                KeyCase::Unchanged => true,
+                KeyCase::Lower | KeyCase::Snake => !key.chars().any(char::is_uppercase),
+            }
+            && (self.case != KeyCase::Snake || !key.contains(['-', ' ']));
+        if unchanged {
+            return Cow::Borrowed(key);
+        }
+
+        let mut chars: Vec<char> = Vec::with_capacity(key.len());
+        for c in key.chars().filter(|c| !c.is_control()) {
+            if self.case == KeyCase::Snake && (c == '-' || c == ' ') {
+                chars.push('_');
+            } else if (self.allowed)(c) {
+                chars.push(c);
+            } else if let Some(r) = self.replacement {
+                chars.push(r);
+            }
+        }
+
+        let mut s = String::with_capacity(chars.len());
+        for (i, &c) in chars.iter().enumerate() {
+            match self.case {
+                KeyCase::Unchanged => s.push(c),
+                KeyCase::Lower => s.extend(c.to_lowercase()),
+                KeyCase::Snake => {
+                    if c.is_uppercase() && i > 0 {
+                        let prev = chars[i - 1];
+                        let next_lower = chars.get(i + 1).is_some_and(|c| c.is_lowercase());
+                        if prev.is_lowercase()
+                            || prev.is_ascii_digit()
+                            || (prev.is_uppercase() && next_lower)
+                        {
+                            s.push('_');
+                        }
+                    }
+                    s.extend(c.to_lowercase());
+                }
+            }
+        }
+        if s.is_empty() {
+            s.push('_');
+        }
+        Cow::Owned(s)
+    }
+
+    // Sanitizes the keys of the nested objects of `value`, returns true if a key changed.
+    fn sanitize_value(&self, value: &mut serde_json::Value) -> bool {
+        match value {
+            serde_json::Value::Object(map) => {
+                let mut changed = false;
+                for (_, v) in map.iter_mut() {
+                    changed |= self.sanitize_value(v);
+                }
+                if map
+                    .keys()
+                    .any(|k| matches!(self.sanitize(k), Cow::Owned(_)))
+                {
+                    let entries = std::mem::take(map);
+                    for (k, v) in entries {
+                        let key = self.sanitize(&k).into_owned();
+                        // a renamed key doesn't replace a field, see the module level documentation.
+                        if key == k || !map.contains_key(&key) {
+                            map.insert(key, v);
+                        }
+                    }
+                    changed = true;
+                }
+                changed
+            }
+            serde_json::Value::Array(values) => {
+                let mut changed = false;
+                for v in values.iter_mut() {
+                    changed |= self.sanitize_value(v);
+                }
+                changed
+            }
+            _ => false,
+        }
+    }
+}
+
+fn is_default_allowed(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-' | '@')
+}
+
+/// A Writer implementation that sanitizes the keys of the records written to a wrapped writer.
+pub struct SanitizeWriter {
+    opts: SanitizeOptions,
+    inner: Box<dyn Writer>,
+}
+
+impl SanitizeWriter {
+    /// Creates a new SanitizeWriter instance that sanitizes the keys of the records written to `w`.
+    pub fn new(opts: SanitizeOptions, w: Box<dyn Writer>) -> Self {
+        SanitizeWriter { opts, inner: w }
+    }
+}
+
+/// Implements Writer trait for SanitizeWriter.
+impl Writer for SanitizeWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        // the nested values whose keys changed.
+        let mut nested: Vec<(&Key, serde_json::Value)> = Vec::new();
+        for (key, value) in value.iter() {
+            if is_primitive(value) {
+                continue;
+            }
+            let mut json = serde_json::to_value(value)?;
+            if self.opts.sanitize_value(&mut json) {
+                nested.push((key, json));
+            }
+        }
+        let keys_changed = value
+            .keys()
+            .any(|key| matches!(self.opts.sanitize(key.as_str()), Cow::Owned(_)));
+        if nested.is_empty() && !keys_changed {
+            return self.inner.write_log(value);
+        }
+
+        let sanitized_keys: Vec<Cow<str>> = value
+            .keys()
+            .map(|key| self.opts.sanitize(key.as_str()))
+            .collect();
+        let mut sanitized = BTreeMap::new();
+        for ((key, value), sanitized_key) in value.iter().zip(sanitized_keys.iter()) {
+            let sanitized_key = Key::from(sanitized_key.as_ref());
+            // a renamed key doesn't replace a field, see the module level documentation.
+            if sanitized_key != *key && sanitized.contains_key(&sanitized_key) {
+                continue;
+            }
+            let value = match nested.iter().find(|(k, _)| *k == key) {
+                Some((_, json)) => Value::from_serde(json),
+                None => value.clone(),
+            };
+            sanitized.insert(sanitized_key, value);
+        }
+        self.inner.write_log(&sanitized)
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        self.write_log(&fields.to_map())
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        self.inner.flush()
+    }
+
+    fn shutdown(&self) -> Result<(), io::Error> {
+        self.inner.shutdown()
+    }
+
+    fn healthy(&self) -> bool {
+        self.inner.healthy()
+    }
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the SanitizeWriter
+/// that sanitizes the keys of the records written to `w`.
+pub fn new_writer(opts: SanitizeOptions, w: Box<dyn Writer>) -> Box<dyn Writer> {
+    Box::new(SanitizeWriter::new(opts, w))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fn_writer;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn sanitize_writer_works() {
+        let opts = SanitizeOptions::default();
+        assert_eq!("user_id", opts.sanitize("user_id"));
+        assert_eq!("ab", opts.sanitize("a\u{1b}\nb"));
+        assert_eq!("a_b", opts.sanitize("a b"));
+        assert_eq!("_", opts.sanitize("\n"));
+        let snake = SanitizeOptions {
+            case: KeyCase::Snake,
+            replacement: None,
+            ..Default::default()
+        };
+        assert_eq!("user_id", snake.sanitize("userId"));
+        assert_eq!("http_status_code", snake.sanitize("HTTPStatusCode"));
+        assert_eq!("content_type", snake.sanitize("Content-Type"));
+        assert_eq!("http.request.id", snake.sanitize("http.request.ID!"));
+        let lower = SanitizeOptions {
+            case: KeyCase::Lower,
+            ..Default::default()
+        };
+        assert_eq!("userid", lower.sanitize("UserId"));
+
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let w = {
+            let lines = lines.clone();
+            new_writer(
+                snake,
+                fn_writer(move |value| {
+                    let json = serde_json::to_string(&crate::fields::FieldMap(value)).unwrap();
+                    lines.lock().unwrap().push(json);
+                    Ok(())
+                }),
+            )
+        };
+
+        let headers = serde_json::json!({"Content-Type": "text/plain", "items": [{"Item\tId": 1}]});
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("level"), Value::from("INFO"));
+        value.insert(Key::from("message"), Value::from("request"));
+        w.write_log(&value).unwrap();
+        value.insert(Key::from("requestId"), Value::from("r1"));
+        value.insert(Key::from("headers"), Value::from_serde(&headers));
+        w.write_log(&value).unwrap();
+
+        assert_eq!(
+            vec![
+                r#"{"level":"INFO","message":"request"}"#,
+                r#"{"headers":{"content_type":"text/plain","items":[{"item_id":1}]},"level":"INFO","message":"request","request_id":"r1"}"#,
+            ],
+            *lines.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn sanitize_collision_works() {
+        let snake = SanitizeOptions {
+            case: KeyCase::Snake,
+            ..Default::default()
+        };
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let w = {
+            let lines = lines.clone();
+            new_writer(
+                snake,
+                fn_writer(move |value| {
+                    let json = serde_json::to_string(&crate::fields::FieldMap(value)).unwrap();
+                    lines.lock().unwrap().push(json);
+                    Ok(())
+                }),
+            )
+        };
+
+        // the key that is already sanitized wins, whatever its order.
+        let nested = serde_json::json!({"User-Id": "a", "user_id": "b", "userId": "c"});
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("User-Id"), Value::from("a"));
+        value.insert(Key::from("user_id"), Value::from("b"));
+        value.insert(Key::from("userId"), Value::from("c"));
+        value.insert(Key::from("nested"), Value::from_serde(&nested));
+        w.write_log(&value).unwrap();
+        // or else the first key in order.
+        let nested = serde_json::json!({"User-Id": "a", "userId": "c"});
+        value.remove(&Key::from("user_id"));
+        value.insert(Key::from("nested"), Value::from_serde(&nested));
+        w.write_log(&value).unwrap();
+
+        assert_eq!(
+            vec![
+                r#"{"nested":{"user_id":"b"},"user_id":"b"}"#,
+                r#"{"nested":{"user_id":"a"},"user_id":"a"}"#,
+            ],
+            *lines.lock().unwrap()
+        );
+    }
+}
@@ -0,0 +1,135 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Schema Validation
+//!
+//! The required key-values of the records of some targets, and their expected types,
+//! to enforce the logging conventions across a large codebase, see [`Builder::with_schema`](crate::Builder::with_schema).
+//! A record that violates its schema is annotated with a `schema_error` field,
+//! and written to the quarantine writer set by [`Builder::with_schema_quarantine`](crate::Builder::with_schema_quarantine), if any.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{schema::{FieldType, Schema}, Builder};
+//!
+//! fn main() {
+//!     Builder::with_level("info")
+//!         .with_schema(
+//!             "api*",
+//!             Schema::new()
+//!                 .with_field("method", FieldType::String)
+//!                 .with_field("path", FieldType::String)
+//!                 .with_field("status", FieldType::Number),
+//!         )
+//!         .init();
+//!
+//!     log::info!(target: "api", method = "GET", path = "/hello"; "");
+//!     // {"level":"INFO","message":"","method":"GET","path":"/hello","schema_error":"missing field `status`",...}
+//! }
+//! ```
+//!
+
+use log::kv::{Key, Source};
+
+/// The expected type of a field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldType {
+    /// Any value.
+    Any,
+    /// A string.
+    String,
+    /// An integer or a float.
+    Number,
+    /// A boolean.
+    Bool,
+    /// A sequence.
+    Array,
+    /// A map or a struct.
+    Object,
+}
+
+impl FieldType {
+    fn matches(&self, value: &serde_json::Value) -> bool {
+        match self {
+            FieldType::Any => true,
+            FieldType::String => value.is_string(),
+            FieldType::Number => value.is_number(),
+            FieldType::Bool => value.is_boolean(),
+            FieldType::Array => value.is_array(),
+            FieldType::Object => value.is_object(),
+        }
+    }
+}
+
+/// The required key-values of a record and their expected types.
+#[derive(Debug, Clone, Default)]
+pub struct Schema {
+    fields: Vec<(String, FieldType)>,
+}
+
+impl Schema {
+    /// Creates a new empty Schema instance.
+    pub fn new() -> Self {
+        Schema { fields: Vec::new() }
+    }
+
+    /// Returns a [`Schema`] that requires a given key-value with a given type.
+    pub fn with_field(mut self, key: &str, ty: FieldType) -> Self {
+        self.fields.push((key.to_string(), ty));
+        self
+    }
+
+    /// Validates the key-values of a record, and returns the first violation.
+    pub fn validate(&self, kvs: &dyn Source) -> Result<(), String> {
+        for (key, ty) in self.fields.iter() {
+            match kvs.get(Key::from(key.as_str())) {
+                None => return Err(format!("missing field `{}`", key)),
+                Some(value) if *ty != FieldType::Any => {
+                    let value = serde_json::to_value(value).unwrap_or(serde_json::Value::Null);
+                    if !ty.matches(&value) {
+                        return Err(format!("field `{}` is not a {:?}", key, ty));
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::kv::Value;
+
+    #[test]
+    fn schema_works() {
+        let schema = Schema::new()
+            .with_field("method", FieldType::String)
+            .with_field("status", FieldType::Number)
+            .with_field("user", FieldType::Any);
+
+        let kvs = [
+            ("method", Value::from("GET")),
+            ("status", Value::from(200)),
+            ("user", Value::from(1)),
+        ];
+        assert_eq!(Ok(()), schema.validate(&kvs));
+
+        let kvs = [("method", Value::from("GET")), ("user", Value::from(1))];
+        assert_eq!(
+            Err("missing field `status`".to_string()),
+            schema.validate(&kvs)
+        );
+
+        let kvs = [
+            ("method", Value::from("GET")),
+            ("status", Value::from("200")),
+            ("user", Value::from(1)),
+        ];
+        assert_eq!(
+            Err("field `status` is not a Number".to_string()),
+            schema.validate(&kvs)
+        );
+    }
+}
@@ -0,0 +1,388 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Sharded Writer Implementation
+//!
+//! A [`Writer`] implementation that logs structured values in JSON format
+//! to a separate buffer or file segment per OS thread, for extreme-throughput batch jobs.
+//! Every thread writes its records to its own segment, created by a factory on its first log,
+//! so the logging threads never contend with each other:
+//! the segment lock is only ever taken by other threads on [`Writer::flush`].
+//! The records of a thread are written in order, the segments can be merged by timestamp
+//! with [`merge_segments`], when rotating them or at the end of the job.
+//! The segments are buffered, and flushed when their thread exits, on [`Writer::flush`] and [`Writer::shutdown`].
+//! The thread-local destructors don't run for the main thread, and the global logger is never dropped,
+//! so call `log::logger().flush()` or [`shutdown`](crate::shutdown) before the process exits.
+//! The segments of a writer are removed from the threads when it is dropped.
+//!
+//! To create a `Box<dyn Writer>` use the [`new_writer`] or the [`new_file_writer`] function.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{sharded::new_file_writer, Builder};
+//!
+//! fn main() {
+//!     let dir = std::env::temp_dir();
+//!     // writes to "job.log.0", "job.log.1", ...
+//!     Builder::with_level("info")
+//!         .with_default_writer(new_file_writer(dir.join("job.log")))
+//!         .init();
+//!
+//!     log::info!("hello world");
+//!     log::logger().flush();
+//! }
+//! ```
+//!
+
+use parking_lot::Mutex;
+use serde::Deserialize;
+use std::{
+    cell::RefCell,
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap},
+    fs::File,
+    io::{self, BufRead, BufWriter, Write},
+    path::PathBuf,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    sync::{Arc, Weak},
+};
+
+use crate::json::with_encoded;
+use crate::{log_failure, Fields, Key, Value, Writer};
+
+static NEXT_WRITER_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // the segments of the current thread, by writer id, with the liveness of their writer.
+    static SEGMENTS: RefCell<Vec<(usize, Weak<()>, Segment)>> = const { RefCell::new(Vec::new()) };
+}
+
+trait Shard: Send + Sync {
+    fn write_line(&self, buf: &[u8]) -> io::Result<()>;
+    fn flush(&self) -> io::Result<()>;
+}
+
+impl<W: Write + Send> Shard for Mutex<BufWriter<W>> {
+    fn write_line(&self, buf: &[u8]) -> io::Result<()> {
+        self.lock().write_all(buf)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.lock().flush()
+    }
+}
+
+// The segment handle owned by a thread, it flushes the segment when the thread exits.
+struct Segment(Arc<dyn Shard>);
+
+impl Drop for Segment {
+    fn drop(&mut self) {
+        if let Err(err) = self.0.flush() {
+            log_failure(format!("ShardedWriter failed to flush segment: {}", err).as_str());
+        }
+    }
+}
+
+type Factory<W> = Box<dyn Fn(usize) -> io::Result<W> + Send + Sync>;
+
+/// A Writer implementation that writes logs in JSON format to a separate segment per thread.
+pub struct ShardedWriter<W: Write + Send + 'static> {
+    id: usize,
+    factory: Factory<W>,
+    next_segment: AtomicUsize,
+    // all segments, to flush them from any thread.
+    segments: Mutex<Vec<Arc<Mutex<BufWriter<W>>>>>,
+    // dropped with the writer, the segments of the threads are removed once it is gone.
+    alive: Arc<()>,
+    shut_down: AtomicBool,
+}
+
+impl<W: Write + Send + 'static> ShardedWriter<W> {
+    /// Creates a new ShardedWriter instance, `factory` is called with the segment index (0, 1, 2, ...)
+    /// to create the segment of a thread on its first log.
+    pub fn new<F>(factory: F) -> Self
+    where
+        F: Fn(usize) -> io::Result<W> + Send + Sync + 'static,
+    {
+        ShardedWriter {
+            id: NEXT_WRITER_ID.fetch_add(1, Ordering::Relaxed),
+            factory: Box::new(factory),
+            next_segment: AtomicUsize::new(0),
+            segments: Mutex::new(Vec::new()),
+            alive: Arc::new(()),
+            shut_down: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the number of segments created so far.
+    pub fn segments(&self) -> usize {
+        self.next_segment.load(Ordering::Relaxed)
+    }
+
+    fn write_line(&self, buf: &[u8]) -> Result<(), io::Error> {
+        if self.shut_down.load(Ordering::Relaxed) {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "ShardedWriter has been shut down",
+            ));
+        }
+
+        let res = SEGMENTS.try_with(|cell| {
+            let mut segments = cell
+                .try_borrow_mut()
+                .map_err(|_| io::Error::other("ShardedWriter segment already borrowed"))?;
+            if let Some((_, _, segment)) = segments.iter().find(|(id, _, _)| *id == self.id) {
+                return segment.0.write_line(buf);
+            }
+
+            // removes the segments of the dropped writers.
+            segments.retain(|(_, alive, _)| alive.strong_count() > 0);
+            let index = self.next_segment.fetch_add(1, Ordering::Relaxed);
+            let segment = Arc::new(Mutex::new(BufWriter::new((self.factory)(index)?)));
+            self.segments.lock().push(segment.clone());
+            segment.write_line(buf)?;
+            segments.push((self.id, Arc::downgrade(&self.alive), Segment(segment)));
+            Ok(())
+        });
+        match res {
+            Ok(res) => res,
+            // the thread is exiting and its segments are gone.
+            Err(_) => Err(io::Error::other(
+                "ShardedWriter segments have been destroyed",
+            )),
+        }
+    }
+}
+
+/// Implements Writer trait for ShardedWriter.
+impl<W: Write + Send + 'static> Writer for ShardedWriter<W> {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        with_encoded(value, |buf| self.write_line(buf))
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        with_encoded(fields, |buf| self.write_line(buf))
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        let mut segments = self.segments.lock();
+        let mut res = Ok(());
+        for segment in segments.iter() {
+            if let Err(err) = segment.lock().flush() {
+                res = Err(err);
+            }
+        }
+        // the segments of the exited threads are only referenced here.
+        segments.retain(|segment| Arc::strong_count(segment) > 1);
+        res
+    }
+
+    fn shutdown(&self) -> Result<(), io::Error> {
+        // the records written after are rejected, instead of being buffered in a segment that is never flushed.
+        self.shut_down.store(true, Ordering::Relaxed);
+        Writer::flush(self)
+    }
+}
+
+impl<W: Write + Send + 'static> Drop for ShardedWriter<W> {
+    fn drop(&mut self) {
+        if let Err(err) = Writer::flush(self) {
+            log_failure(format!("ShardedWriter failed to flush: {}", err).as_str());
+        }
+        // the segments of the other threads are removed by their next new segment, or when they exit.
+        let _ = SEGMENTS.try_with(|cell| {
+            if let Ok(mut segments) = cell.try_borrow_mut() {
+                segments.retain(|(id, _, _)| *id != self.id);
+            }
+        });
+    }
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the ShardedWriter for a given segment factory.
+pub fn new_writer<W, F>(factory: F) -> Box<dyn Writer>
+where
+    W: Write + Send + 'static,
+    F: Fn(usize) -> io::Result<W> + Send + Sync + 'static,
+{
+    Box::new(ShardedWriter::new(factory))
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the ShardedWriter
+/// that writes the segment of each thread to the file `{path}.{index}`.
+pub fn new_file_writer<P: Into<PathBuf>>(path: P) -> Box<dyn Writer> {
+    let path = path.into();
+    new_writer(move |index| {
+        let mut name = path.clone().into_os_string();
+        name.push(format!(".{}", index));
+        File::create(name)
+    })
+}
+
+#[derive(Deserialize)]
+struct Timestamp {
+    timestamp: Option<u64>,
+}
+
+/// Merges the JSON lines of the given segments into `out`, ordered by their `timestamp` field.
+/// The lines of a segment must be ordered, as they are written by a [`ShardedWriter`];
+/// a line without timestamp keeps its position after the previous line of its segment.
+pub fn merge_segments<R: BufRead, W: Write>(segments: Vec<R>, out: &mut W) -> io::Result<()> {
+    fn next_line<R: BufRead>(r: &mut R, last: u64) -> io::Result<Option<(u64, String)>> {
+        let mut line = String::new();
+        if r.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let ts = serde_json::from_str::<Timestamp>(&line)
+            .ok()
+            .and_then(|t| t.timestamp)
+            .unwrap_or(last);
+        Ok(Some((ts, line)))
+    }
+
+    let mut segments = segments;
+    let mut lines: Vec<Option<String>> = Vec::with_capacity(segments.len());
+    let mut heap = BinaryHeap::with_capacity(segments.len());
+    for (i, r) in segments.iter_mut().enumerate() {
+        match next_line(r, 0)? {
+            Some((ts, line)) => {
+                heap.push(Reverse((ts, i)));
+                lines.push(Some(line));
+            }
+            None => lines.push(None),
+        }
+    }
+
+    while let Some(Reverse((ts, i))) = heap.pop() {
+        if let Some(line) = lines[i].take() {
+            out.write_all(line.as_bytes())?;
+            if !line.ends_with('\n') {
+                out.write_all(b"\n")?;
+            }
+        }
+        if let Some((ts, line)) = next_line(&mut segments[i], ts)? {
+            heap.push(Reverse((ts, i)));
+            lines[i] = Some(line);
+        }
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sharded_writer_works() {
+        let bufs: Arc<Mutex<Vec<SharedBuf>>> = Arc::default();
+        let factory_bufs = bufs.clone();
+        let writer = Arc::new(ShardedWriter::new(move |_| {
+            let buf = SharedBuf::default();
+            factory_bufs.lock().push(buf.clone());
+            Ok(buf)
+        }));
+
+        let threads: Vec<_> = (0..4_u64)
+            .map(|t| {
+                let writer = writer.clone();
+                thread::spawn(move || {
+                    for i in 0..50_u64 {
+                        let mut value = BTreeMap::new();
+                        value.insert(Key::from("thread"), Value::from(t));
+                        value.insert(Key::from("timestamp"), Value::from(i * 4 + t));
+                        writer.write_log(&value).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        writer.flush().unwrap();
+        assert_eq!(4, writer.segments());
+
+        let segments: Vec<Vec<u8>> = bufs.lock().iter().map(|b| b.0.lock().clone()).collect();
+        for segment in segments.iter() {
+            let output = String::from_utf8(segment.clone()).unwrap();
+            let lines: Vec<&str> = output.lines().collect();
+            assert_eq!(50, lines.len());
+            let thread = &lines[0][..lines[0].find(',').unwrap()];
+            assert!(lines.iter().all(|l| l.starts_with(thread)));
+        }
+
+        let mut merged = Vec::new();
+        merge_segments(segments.iter().map(|s| s.as_slice()).collect(), &mut merged).unwrap();
+        let merged = String::from_utf8(merged).unwrap();
+        let lines: Vec<&str> = merged.lines().collect();
+        assert_eq!(200, lines.len());
+        for (i, line) in lines.iter().enumerate() {
+            assert!(line.ends_with(&format!("\"timestamp\":{}}}", i)));
+        }
+    }
+
+    fn thread_segments() -> Vec<usize> {
+        SEGMENTS.with(|cell| cell.borrow().iter().map(|(id, _, _)| *id).collect())
+    }
+
+    #[test]
+    fn sharded_writer_shutdown_works() {
+        let buf = SharedBuf::default();
+        let factory_buf = buf.clone();
+        let logger = crate::Builder::with_level("info")
+            .with_default_writer(new_writer(move |_| Ok(factory_buf.clone())))
+            .build();
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .target("job")
+            .build();
+        logger.log_record(&record).unwrap();
+        // the segment of the current thread is buffered, it isn't flushed at exit for the main thread.
+        assert!(buf.0.lock().is_empty());
+
+        logger.shutdown();
+        assert_eq!(1, buf.0.lock().iter().filter(|b| **b == b'\n').count());
+    }
+
+    #[test]
+    fn sharded_writer_drop_works() {
+        let buf = SharedBuf::default();
+        let factory_buf = buf.clone();
+        let writer = ShardedWriter::new(move |_| Ok(factory_buf.clone()));
+        let id = writer.id;
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("message"), Value::from("hello"));
+        writer.write_log(&value).unwrap();
+        assert!(thread_segments().contains(&id));
+
+        writer.shutdown().unwrap();
+        assert!(writer.write_log(&value).is_err());
+        drop(writer);
+        assert!(!thread_segments().contains(&id));
+        assert_eq!("{\"message\":\"hello\"}\n".as_bytes(), buf.0.lock().as_slice());
+
+        // the segments of a writer dropped by another thread are removed by the next new segment.
+        let writer = ShardedWriter::new(|_| Ok(SharedBuf::default()));
+        let id = writer.id;
+        writer.write_log(&value).unwrap();
+        thread::spawn(move || drop(writer)).join().unwrap();
+        assert!(thread_segments().contains(&id));
+        let other = ShardedWriter::new(|_| Ok(SharedBuf::default()));
+        other.write_log(&value).unwrap();
+        assert!(!thread_segments().contains(&id));
+        assert!(thread_segments().contains(&other.id));
+    }
+}
@@ -0,0 +1,245 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Shared File Writer Implementation
+//!
+//! A [`Writer`] implementation that logs structured values in JSON format to a file
+//! shared by several processes, such as the workers of a pre-fork server.
+//!
+//! The file is opened in append mode (`O_APPEND`), and every record is written with a single `write` call
+//! of the whole line, without a userspace buffer, so the records of the processes are never split
+//! or interleaved: the kernel appends each write atomically at the end of the file.
+//! A write that doesn't write the whole record, such as on a full disk, returns an error.
+//!
+//! With [`SharedFileOptions::rotation`], the file is rotated by size by the first process that finds it full,
+//! under an exclusive advisory lock (`flock`) of the file with [`SharedFileOptions::lock`],
+//! and the other processes open the new file when they find the rotated one full.
+//! Rotating a file that is open by other processes is only supported on Unix.
+//!
+//! To create a `Box<dyn Writer>` use the [`new_writer`] function.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{rotation::RotationOptions, shared_file, Builder};
+//!
+//! fn main() {
+//!     let path = std::env::temp_dir().join("workers.log");
+//!     let opts = shared_file::SharedFileOptions {
+//!         rotation: Some(RotationOptions {
+//!             max_size: Some(10 * 1024 * 1024),
+//!             ..Default::default()
+//!         }),
+//!         ..Default::default()
+//!     };
+//!     Builder::with_level("info")
+//!         .with_default_writer(shared_file::new_writer(path, opts).unwrap())
+//!         .init();
+//!
+//!     log::info!(pid = std::process::id(); "worker started");
+//! }
+//! ```
+//!
+
+use fs4::fs_std::FileExt;
+use parking_lot::Mutex;
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::json::{with_encoded, FileOptions};
+use crate::metrics::Health;
+use crate::reopen::is_rotated;
+use crate::rotation::{rotate_file, RotationOptions};
+use crate::{log_failure, Fields, Key, Value, Writer};
+
+/// The options of a [`SharedFileWriter`].
+#[derive(Debug, Clone)]
+pub struct SharedFileOptions {
+    /// The rotation of the file, the default is `None`, the file is not rotated.
+    /// The file is rotated by [`RotationOptions::max_size`] only, the `max_age` is ignored.
+    pub rotation: Option<RotationOptions>,
+    /// Whether the file is locked with an exclusive advisory lock while it is rotated,
+    /// so only one process rotates it. The default is true.
+    pub lock: bool,
+}
+
+impl Default for SharedFileOptions {
+    fn default() -> Self {
+        SharedFileOptions {
+            rotation: None,
+            lock: true,
+        }
+    }
+}
+
+/// A Writer implementation that appends logs in JSON format to a file shared by several processes.
+pub struct SharedFileWriter {
+    path: PathBuf,
+    opts: SharedFileOptions,
+    file: Mutex<File>,
+    health: Health,
+}
+
+impl SharedFileWriter {
+    /// Creates a new SharedFileWriter instance, and opens the file at a given path in append mode.
+    /// The parent directories are created.
+    pub fn new<P: AsRef<Path>>(path: P, opts: SharedFileOptions) -> Result<Self, io::Error> {
+        let path = path.as_ref().to_path_buf();
+        let file = FileOptions::new().open_file(&path)?;
+        Ok(SharedFileWriter {
+            path,
+            opts,
+            file: Mutex::new(file),
+            health: Health::default(),
+        })
+    }
+
+    fn write_line(&self, buf: &[u8]) -> Result<(), io::Error> {
+        self.health.track(self.write_record(buf))
+    }
+
+    fn write_record(&self, buf: &[u8]) -> Result<(), io::Error> {
+        let mut file = self.file.lock();
+        if let Some(ref rotation) = self.opts.rotation {
+            if let Err(err) = self.rotate(&mut file, rotation) {
+                log_failure(
+                    format!("SharedFileWriter failed to rotate the file: {}", err).as_str(),
+                );
+            }
+        }
+
+        // a single write, a retried partial write could be interleaved with the writes of the other processes.
+        let n = file.write(buf)?;
+        if n < buf.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                format!(
+                    "SharedFileWriter wrote {} of {} bytes of a log",
+                    n,
+                    buf.len()
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    // Rotates the file if it is full, or opens the new file if another process has rotated it.
+    fn rotate(&self, file: &mut File, rotation: &RotationOptions) -> Result<(), io::Error> {
+        let max_size = match rotation.max_size {
+            Some(max_size) => max_size,
+            None => return Ok(()),
+        };
+        // the size includes the records of the other processes.
+        if file.metadata()?.len() < max_size {
+            return Ok(());
+        }
+
+        if self.opts.lock {
+            // the lock of the opened file, it is released when the file is closed.
+            FileExt::lock_exclusive(file)?;
+        }
+        // another process may have rotated the file while this one was waiting for the lock.
+        let res = if is_rotated(&self.path, file) {
+            Ok(())
+        } else {
+            rotate_file(&self.path, rotation).map(|_| ())
+        };
+        match res.and_then(|_| FileOptions::new().open_file(&self.path)) {
+            Ok(new_file) => {
+                *file = new_file;
+                Ok(())
+            }
+            Err(err) => {
+                if self.opts.lock {
+                    let _ = FileExt::unlock(file);
+                }
+                Err(err)
+            }
+        }
+    }
+}
+
+/// Implements Writer trait for SharedFileWriter.
+impl Writer for SharedFileWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        with_encoded(value, |buf| self.write_line(buf))
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        with_encoded(fields, |buf| self.write_line(buf))
+    }
+
+    fn healthy(&self) -> bool {
+        self.health.healthy()
+    }
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the SharedFileWriter for the file at a given path.
+pub fn new_writer<P: AsRef<Path>>(
+    path: P,
+    opts: SharedFileOptions,
+) -> Result<Box<dyn Writer>, io::Error> {
+    Ok(Box::new(SharedFileWriter::new(path, opts)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, sync::Arc, thread};
+
+    // the open files can't be renamed on Windows.
+    #[cfg(unix)]
+    #[test]
+    fn shared_file_writer_works() {
+        let dir =
+            std::env::temp_dir().join(format!("structured-logger-shared-{}", std::process::id()));
+        let path = dir.join("workers.log");
+        let opts = SharedFileOptions {
+            rotation: Some(RotationOptions {
+                max_size: Some(4096),
+                max_files: None,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        // the writers open the file separately, as the processes do.
+        let handles: Vec<_> = (0..4_u64)
+            .map(|worker| {
+                let w = Arc::new(SharedFileWriter::new(&path, opts.clone()).unwrap());
+                thread::spawn(move || {
+                    let message = "x".repeat(64);
+                    for i in 0..500_u64 {
+                        let mut value = BTreeMap::new();
+                        value.insert(Key::from("worker"), Value::from(worker));
+                        value.insert(Key::from("i"), Value::from(i));
+                        value.insert(Key::from("message"), Value::from(message.as_str()));
+                        w.write_log(&value).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let mut files = 0;
+        let mut records = 0;
+        for entry in fs::read_dir(&dir).unwrap() {
+            let content = fs::read_to_string(entry.unwrap().path()).unwrap();
+            assert!(content.ends_with('\n'));
+            for line in content.lines() {
+                let record: serde_json::Value = serde_json::from_str(line).unwrap();
+                assert_eq!(64, record["message"].as_str().unwrap().len());
+                records += 1;
+            }
+            files += 1;
+        }
+        assert!(files > 1, "the file is rotated");
+        assert_eq!(2000, records);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
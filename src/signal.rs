@@ -0,0 +1,156 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Signal-triggered Shutdown
+//!
+//! Installs SIGTERM and SIGINT handlers (console control handlers on Windows) that
+//! [`shutdown`](crate::shutdown) the logger, flushing all writers and draining the async queues,
+//! before the signal terminates the process as it would without the handlers.
+//! So container shutdowns don't truncate the final log lines.
+//!
+//! On Unix, the signals are handled on a dedicated thread, then the default action is emulated.
+//! On Windows, the handler runs on the thread created by the system for the console event,
+//! then the default handler terminates the process.
+//!
+//! This module requires the `signal` feature.
+//!
+//! Example:
+//! ```rust,ignore
+//! use structured_logger::{async_json::new_writer, signal, Builder};
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     Builder::with_level("info")
+//!         .with_default_writer(new_writer(tokio::io::stdout()))
+//!         .init();
+//!     signal::install().unwrap();
+//!
+//!     log::info!("hello world");
+//! }
+//! ```
+//!
+
+use std::{io, sync::Mutex};
+
+static INSTALLED: Mutex<bool> = Mutex::new(false);
+
+/// Installs the signal handlers, it does nothing if they are already installed.
+/// It should be called after the logger is initialized.
+pub fn install() -> Result<(), io::Error> {
+    let mut installed = INSTALLED.lock().unwrap_or_else(|e| e.into_inner());
+    if !*installed {
+        imp::install()?;
+        *installed = true;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+mod imp {
+    use signal_hook::{
+        consts::{SIGINT, SIGTERM},
+        iterator::Signals,
+        low_level::emulate_default_handler,
+    };
+    use std::{io, os::raw::c_int, thread};
+
+    pub(super) fn install() -> Result<(), io::Error> {
+        let mut signals = Signals::new([SIGTERM, SIGINT])?;
+        thread::Builder::new()
+            .name("structured-logger-signal".to_string())
+            .spawn(move || {
+                on_signal(signals.forever(), crate::shutdown, |signal| {
+                    // restores the default action and raises the signal again.
+                    let _ = emulate_default_handler(signal);
+                })
+            })?;
+        Ok(())
+    }
+
+    // Shuts down the logger on the first signal, then raises it.
+    pub(super) fn on_signal(
+        mut signals: impl Iterator<Item = c_int>,
+        shutdown: impl FnOnce(),
+        raise: impl FnOnce(c_int),
+    ) {
+        if let Some(signal) = signals.next() {
+            shutdown();
+            raise(signal);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::io;
+    use windows_sys::Win32::{
+        Foundation::{BOOL, FALSE, TRUE},
+        System::Console::SetConsoleCtrlHandler,
+    };
+
+    unsafe extern "system" fn ctrl_handler(_ctrl_type: u32) -> BOOL {
+        crate::shutdown();
+        // let the next handler, the default one, terminate the process.
+        FALSE
+    }
+
+    pub(super) fn install() -> Result<(), io::Error> {
+        // SAFETY: the handler is a valid function for the lifetime of the process.
+        if unsafe { SetConsoleCtrlHandler(Some(ctrl_handler), TRUE) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod imp {
+    use std::io;
+
+    pub(super) fn install() -> Result<(), io::Error> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "signal handlers are not supported on this platform",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn install_works() {
+        install().unwrap();
+        // the handlers are installed once.
+        install().unwrap();
+        assert!(*INSTALLED.lock().unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn on_signal_works() {
+        use signal_hook::consts::{SIGINT, SIGTERM};
+        use std::cell::RefCell;
+
+        let calls = RefCell::new(Vec::new());
+        imp::on_signal(
+            vec![SIGTERM, SIGINT].into_iter(),
+            || calls.borrow_mut().push("shutdown".to_string()),
+            |signal| calls.borrow_mut().push(format!("raise {}", signal)),
+        );
+        // the logger is shut down before the first signal is raised, the next signals are ignored.
+        assert_eq!(
+            vec!["shutdown".to_string(), format!("raise {}", SIGTERM)],
+            *calls.borrow()
+        );
+
+        calls.borrow_mut().clear();
+        imp::on_signal(
+            std::iter::empty(),
+            || calls.borrow_mut().push("shutdown".to_string()),
+            |signal| calls.borrow_mut().push(format!("raise {}", signal)),
+        );
+        assert!(calls.borrow().is_empty());
+    }
+}
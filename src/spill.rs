@@ -0,0 +1,365 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Spill Writer Implementation
+//!
+//! A [`Writer`] wrapper for the network writers that spills the records to a bounded queue file on disk
+//! while the wrapped writer is unavailable, then replays them in order once it recovers,
+//! so a transient collector outage doesn't lose the logs.
+//!
+//! A record is spilled if the wrapped writer fails to write it, or is not [`healthy`](Writer::healthy),
+//! and while older records are waiting in the queue file, so the records are never reordered.
+//! A background thread tries to replay the queue every [`SpillOptions::replay_interval`],
+//! from the oldest record, and the file is truncated once it is replayed.
+//! The offset of the replayed records is stored next to the file, with the `.offset` extension,
+//! so the records left in the queue are replayed after a restart.
+//! The records are dropped when the file would exceed [`SpillOptions::max_bytes`].
+//!
+//! The records are spilled as JSON lines, and replayed to the wrapped writer as a map, see [`Writer::write_log`].
+//! To create a `Box<dyn Writer>` use the [`new_writer`] function.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{gelf, spill, Builder};
+//!
+//! fn main() {
+//!     let path = std::env::temp_dir().join("spill").join("gelf.queue");
+//!     let gelf = gelf::new_writer("127.0.0.1:12201", gelf::GelfOptions::default()).unwrap();
+//!     Builder::with_level("info")
+//!         .with_default_writer(spill::new_writer(gelf, path, spill::SpillOptions::default()).unwrap())
+//!         .init();
+//!
+//!     log::info!("hello world");
+//! }
+//! ```
+//!
+
+use parking_lot::Mutex;
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Weak,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::json::{with_encoded, write_json_line, Encode, FileOptions};
+use crate::{log_failure, Fields, Key, Value, Writer};
+
+// The maximum number of records replayed between two updates of the offset.
+const REPLAY_BATCH: usize = 256;
+
+/// The options of a [`SpillWriter`].
+#[derive(Debug, Clone)]
+pub struct SpillOptions {
+    /// The maximum size of the queue file, the records that would exceed it are dropped.
+    /// The default is 64 MiB.
+    pub max_bytes: u64,
+    /// How often the background thread tries to replay the queue, the default is 1 second.
+    pub replay_interval: Duration,
+}
+
+impl Default for SpillOptions {
+    fn default() -> Self {
+        SpillOptions {
+            max_bytes: 64 * 1024 * 1024,
+            replay_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+// The queue file, the records from `offset` to `len` are waiting to be replayed.
+struct Queue {
+    file: File,
+    len: u64,
+    offset: u64,
+}
+
+struct Inner {
+    writer: Box<dyn Writer>,
+    path: PathBuf,
+    offset_path: PathBuf,
+    max_bytes: u64,
+    queue: Mutex<Queue>,
+    // only one thread replays the queue at a time.
+    replaying: Mutex<()>,
+    dropped: AtomicU64,
+}
+
+impl Inner {
+    fn write<T: Encode + ?Sized>(
+        &self,
+        value: &T,
+        write: &dyn Fn(&dyn Writer) -> Result<(), io::Error>,
+    ) -> Result<(), io::Error> {
+        let waiting = {
+            let queue = self.queue.lock();
+            queue.offset < queue.len
+        };
+        if !waiting && self.writer.healthy() {
+            match write(self.writer.as_ref()) {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    log_failure(format!("SpillWriter spilling the logs to disk: {}", err).as_str())
+                }
+            }
+        }
+        with_encoded(value, |buf| self.spill(buf))
+    }
+
+    fn spill(&self, buf: &[u8]) -> Result<(), io::Error> {
+        let mut queue = self.queue.lock();
+        if queue.len + buf.len() as u64 > self.max_bytes {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        }
+        queue.file.write_all(buf)?;
+        queue.len += buf.len() as u64;
+        Ok(())
+    }
+
+    // Replays the queue to the wrapped writer, until it is empty or a record fails to be written.
+    fn replay(&self) -> Result<(), io::Error> {
+        let _replaying = self.replaying.lock();
+        loop {
+            let (offset, len) = {
+                let queue = self.queue.lock();
+                (queue.offset, queue.len)
+            };
+            if offset >= len {
+                return Ok(());
+            }
+
+            let mut next = offset;
+            let mut res = Ok(());
+            for line in self.read_batch(offset, len)? {
+                match write_json_line(self.writer.as_ref(), &line) {
+                    Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                        log_failure(format!("SpillWriter dropped a log: {}", err).as_str());
+                    }
+                    Err(err) => {
+                        res = Err(err);
+                        break;
+                    }
+                    Ok(()) => {}
+                }
+                next += line.len() as u64;
+            }
+            self.advance(next)?;
+            res?;
+        }
+    }
+
+    // Reads the next batch of records of the queue, from `offset` to at most `len`.
+    fn read_batch(&self, offset: u64, len: u64) -> Result<Vec<Vec<u8>>, io::Error> {
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut reader = BufReader::new(file.take(len - offset));
+        let mut lines = Vec::new();
+        while lines.len() < REPLAY_BATCH {
+            let mut line = Vec::new();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            lines.push(line);
+        }
+        Ok(lines)
+    }
+
+    // Marks the records before `offset` as replayed, and truncates the file if all of them are.
+    fn advance(&self, offset: u64) -> Result<(), io::Error> {
+        let mut queue = self.queue.lock();
+        if offset >= queue.len {
+            queue.file.set_len(0)?;
+            queue.len = 0;
+            queue.offset = 0;
+            log_failure("SpillWriter replayed the logs spilled to disk");
+        } else {
+            queue.offset = offset;
+        }
+        fs::write(&self.offset_path, queue.offset.to_string())
+    }
+}
+
+/// A Writer implementation that spills the records of a wrapped writer to disk while it is unavailable.
+pub struct SpillWriter {
+    inner: Arc<Inner>,
+}
+
+impl SpillWriter {
+    /// Creates a new SpillWriter instance that wraps `writer`, with the queue file at a given path,
+    /// whose parent directories are created. The records left in the file are replayed.
+    /// It starts the background thread that replays the queue, which stops when the writer is dropped.
+    pub fn new<P: AsRef<Path>>(
+        writer: Box<dyn Writer>,
+        path: P,
+        opts: SpillOptions,
+    ) -> Result<Self, io::Error> {
+        let path = path.as_ref().to_path_buf();
+        let offset_path = path.with_extension("offset");
+        let file = FileOptions::new().open_file(&path)?;
+        let len = file.metadata()?.len();
+        let offset = fs::read_to_string(&offset_path)
+            .ok()
+            .and_then(|s| s.trim().parse().ok())
+            .unwrap_or(0)
+            .min(len);
+        let inner = Arc::new(Inner {
+            writer,
+            path,
+            offset_path,
+            max_bytes: opts.max_bytes,
+            queue: Mutex::new(Queue { file, len, offset }),
+            replaying: Mutex::new(()),
+            dropped: AtomicU64::new(0),
+        });
+        let weak = Arc::downgrade(&inner);
+        thread::Builder::new()
+            .name("structured-logger-spill".to_string())
+            .spawn(move || replay_periodically(weak, opts.replay_interval))?;
+        Ok(SpillWriter { inner })
+    }
+
+    /// Returns the size in bytes of the records waiting in the queue file.
+    pub fn pending_bytes(&self) -> u64 {
+        let queue = self.inner.queue.lock();
+        queue.len - queue.offset
+    }
+
+    /// Returns the number of records dropped because the queue file was full, or invalid.
+    pub fn dropped(&self) -> u64 {
+        self.inner.dropped.load(Ordering::Relaxed)
+    }
+}
+
+fn replay_periodically(inner: Weak<Inner>, interval: Duration) {
+    loop {
+        thread::sleep(interval);
+        match inner.upgrade() {
+            // the wrapped writer is still unavailable, the queue is replayed on the next tick.
+            Some(inner) => {
+                let _ = inner.replay();
+            }
+            None => return,
+        }
+    }
+}
+
+/// Implements Writer trait for SpillWriter.
+impl Writer for SpillWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        self.inner.write(value, &|w| w.write_log(value))
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        self.inner.write(fields, &|w| w.write_fields(fields))
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        self.inner.writer.flush()
+    }
+
+    /// Tries to replay the queue once, the records that are not replayed are kept in the file for the next start.
+    fn shutdown(&self) -> Result<(), io::Error> {
+        if let Err(err) = self.inner.replay() {
+            log_failure(format!("SpillWriter failed to replay the logs: {}", err).as_str());
+        }
+        self.inner.writer.shutdown()
+    }
+
+    fn healthy(&self) -> bool {
+        self.inner.writer.healthy()
+    }
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the SpillWriter for a given writer and queue file path.
+pub fn new_writer<P: AsRef<Path>>(
+    writer: Box<dyn Writer>,
+    path: P,
+    opts: SpillOptions,
+) -> Result<Box<dyn Writer>, io::Error> {
+    Ok(Box::new(SpillWriter::new(writer, path, opts)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+
+    // A network writer that fails while `down` is true.
+    struct Collector {
+        down: Arc<AtomicBool>,
+        messages: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Writer for Collector {
+        fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+            if self.down.load(Ordering::Relaxed) {
+                return Err(io::ErrorKind::ConnectionRefused.into());
+            }
+            let message = value[&Key::from("message")].to_string();
+            self.messages.lock().push(message);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn spill_writer_works() {
+        let dir =
+            std::env::temp_dir().join(format!("structured-logger-spill-{}", std::process::id()));
+        let path = dir.join("collector.queue");
+        let _ = fs::remove_dir_all(&dir);
+        let down = Arc::new(AtomicBool::new(false));
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let collector = || {
+            Box::new(Collector {
+                down: down.clone(),
+                messages: messages.clone(),
+            })
+        };
+        let opts = SpillOptions {
+            max_bytes: 60,
+            replay_interval: Duration::from_millis(10),
+        };
+        let write = |w: &SpillWriter, message: &str| {
+            let mut value = BTreeMap::new();
+            value.insert(Key::from("message"), Value::from(message));
+            w.write_log(&value).unwrap();
+        };
+
+        let w = SpillWriter::new(collector(), &path, opts.clone()).unwrap();
+        write(&w, "a");
+        down.store(true, Ordering::Relaxed);
+        for message in ["b", "c", "d", "e"] {
+            write(&w, message);
+        }
+        // `{"message":"b"}\n` is 16 bytes, the 4th record is dropped.
+        assert_eq!(48, w.pending_bytes());
+        assert_eq!(1, w.dropped());
+
+        down.store(false, Ordering::Relaxed);
+        thread::sleep(Duration::from_millis(50));
+        write(&w, "f");
+        assert_eq!(0, w.pending_bytes());
+        assert_eq!(vec!["a", "b", "c", "d", "f"], *messages.lock());
+
+        // the records left in the queue are replayed after a restart.
+        down.store(true, Ordering::Relaxed);
+        write(&w, "g");
+        drop(w);
+        messages.lock().clear();
+        down.store(false, Ordering::Relaxed);
+        let w = SpillWriter::new(collector(), &path, opts).unwrap();
+        assert_eq!(16, w.pending_bytes());
+        w.shutdown().unwrap();
+        assert_eq!(0, w.pending_bytes());
+        assert_eq!(vec!["g"], *messages.lock());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
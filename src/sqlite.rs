@@ -0,0 +1,348 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # SQLite Writer Implementation
+//!
+//! A [`Writer`] implementation that appends structured values to a table of a local SQLite database,
+//! with the [`rusqlite`](https://docs.rs/rusqlite) crate, so the logs of a desktop or CLI application
+//! can be queried with SQL instead of searching rotated text files.
+//! The `timestamp` (in milliseconds), `level`, `target` and `message` fields are stored in their own columns,
+//! and the other fields in a JSON `attributes` column, in a table created if it doesn't exist:
+//! ```sql
+//! CREATE TABLE logs (
+//!     id         INTEGER PRIMARY KEY,
+//!     timestamp  INTEGER NOT NULL,
+//!     level      TEXT,
+//!     target     TEXT,
+//!     message    TEXT,
+//!     attributes TEXT NOT NULL
+//! );
+//! ```
+//!
+//! The table works as a ring: the rows beyond [`SqliteOptions::max_rows`], and the rows older than
+//! [`SqliteOptions::max_age`], are deleted every [`SqliteOptions::prune_interval`] records.
+//! To create a `Box<dyn Writer>` use the [`new_writer`] function.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{sqlite, Builder};
+//!
+//! fn main() {
+//!     let path = std::env::temp_dir().join("app-logs.db");
+//!     let opts = sqlite::SqliteOptions {
+//!         max_rows: Some(10_000),
+//!         ..Default::default()
+//!     };
+//!     Builder::with_level("info")
+//!         .with_default_writer(sqlite::new_writer(&path, opts).unwrap())
+//!         .init();
+//!
+//!     log::info!("started");
+//!     // sqlite3 app-logs.db "SELECT message FROM logs WHERE level = 'ERROR'"
+//! }
+//! ```
+//!
+
+use log::kv::{Key, Value};
+use parking_lot::Mutex;
+use rusqlite::{params, Connection};
+use serde_json::Map;
+use std::{collections::BTreeMap, io, path::Path, time::Duration};
+
+use crate::fields::FieldMap;
+use crate::metrics::Health;
+use crate::{unix_ms, Fields, Writer};
+
+/// The options of a [`SqliteWriter`].
+#[derive(Debug, Clone)]
+pub struct SqliteOptions {
+    /// The table of the records, optionally qualified by its schema such as `"main.logs"`, the default is `"logs"`.
+    /// The names separated by `.` are quoted as identifiers in the statements.
+    pub table: String,
+    /// The maximum number of rows kept, the default is 100,000. `None` keeps all the rows.
+    pub max_rows: Option<u64>,
+    /// The maximum age of the rows kept, the default is `None`, without an age limit.
+    pub max_age: Option<Duration>,
+    /// The number of records written between two prunings, the default is 1,000.
+    pub prune_interval: u64,
+}
+
+impl Default for SqliteOptions {
+    fn default() -> Self {
+        SqliteOptions {
+            table: "logs".to_string(),
+            max_rows: Some(100_000),
+            max_age: None,
+            prune_interval: 1000,
+        }
+    }
+}
+
+struct Inner {
+    conn: Connection,
+    // the number of records written since the last pruning.
+    written: u64,
+}
+
+/// A Writer implementation that appends logs to a SQLite table.
+pub struct SqliteWriter {
+    inner: Mutex<Inner>,
+    insert: String,
+    opts: SqliteOptions,
+    health: Health,
+}
+
+impl SqliteWriter {
+    /// Creates a new SqliteWriter instance for a given database file, which is created if it doesn't exist.
+    pub fn new<P: AsRef<Path>>(path: P, opts: SqliteOptions) -> Result<Self, io::Error> {
+        Self::with_connection(Connection::open(path).map_err(io::Error::other)?, opts)
+    }
+
+    /// Creates a new SqliteWriter instance with an opened connection, such as `Connection::open_in_memory()`.
+    /// The table is created if it doesn't exist.
+    pub fn with_connection(conn: Connection, opts: SqliteOptions) -> Result<Self, io::Error> {
+        // the WAL journal lets the logs be queried while they are written.
+        conn.pragma_update(None, "journal_mode", "WAL")
+            .and_then(|_| conn.pragma_update(None, "synchronous", "NORMAL"))
+            .and_then(|_| {
+                // the index is created in the schema of the table, with an unqualified table name.
+                let (schema, name) = match opts.table.rsplit_once('.') {
+                    Some((schema, name)) => (format!("{}.", quote(schema)), name),
+                    None => (String::new(), opts.table.as_str()),
+                };
+                conn.execute_batch(&format!(
+                    "CREATE TABLE IF NOT EXISTS {table} (
+                        id INTEGER PRIMARY KEY,
+                        timestamp INTEGER NOT NULL,
+                        level TEXT,
+                        target TEXT,
+                        message TEXT,
+                        attributes TEXT NOT NULL
+                    );
+                    CREATE INDEX IF NOT EXISTS {schema}{index} ON {name} (timestamp);",
+                    table = quote_table(&opts.table),
+                    index = quote(&format!("{}_timestamp", name)),
+                    name = quote(name),
+                ))
+            })
+            .map_err(io::Error::other)?;
+        Ok(SqliteWriter {
+            inner: Mutex::new(Inner { conn, written: 0 }),
+            insert: format!(
+                "INSERT INTO {} (timestamp, level, target, message, attributes) VALUES (?1, ?2, ?3, ?4, ?5)",
+                quote_table(&opts.table)
+            ),
+            opts,
+            health: Health::default(),
+        })
+    }
+
+    fn insert(&self, record: serde_json::Value) -> Result<(), io::Error> {
+        let mut record = match record {
+            serde_json::Value::Object(record) => record,
+            _ => Map::new(),
+        };
+        // SQLite integers are signed.
+        let timestamp = match record.remove("timestamp") {
+            Some(serde_json::Value::Number(ms)) => ms.as_u64().unwrap_or_else(unix_ms),
+            _ => unix_ms(),
+        } as i64;
+        // the `time` field of the GCP mode is the same timestamp.
+        if matches!(record.get("time"), Some(serde_json::Value::String(_))) {
+            record.remove("time");
+        }
+        let level = take_str(&mut record, &["level", "severity", "status"]);
+        let target = take_str(&mut record, &["target"]);
+        let message = take_str(&mut record, &["message"]);
+        let attributes = serde_json::to_string(&record)?;
+
+        let mut inner = self.inner.lock();
+        self.health
+            .track(
+                inner
+                    .conn
+                    .prepare_cached(&self.insert)
+                    .and_then(|mut stmt| {
+                        stmt.execute(params![timestamp, level, target, message, attributes])
+                    }),
+            )
+            .map_err(io::Error::other)?;
+
+        inner.written += 1;
+        if inner.written >= self.opts.prune_interval {
+            inner.written = 0;
+            self.prune(&inner.conn).map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+
+    // Deletes the rows beyond the maximum number of rows, and the rows older than the maximum age.
+    fn prune(&self, conn: &Connection) -> rusqlite::Result<()> {
+        if let Some(max_rows) = self.opts.max_rows {
+            conn.execute(
+                &format!(
+                    "DELETE FROM {table} WHERE id <= (SELECT MAX(id) FROM {table}) - ?1",
+                    table = quote_table(&self.opts.table)
+                ),
+                params![max_rows.min(i64::MAX as u64) as i64],
+            )?;
+        }
+        if let Some(max_age) = self.opts.max_age {
+            let oldest = unix_ms().saturating_sub(max_age.as_millis() as u64);
+            conn.execute(
+                &format!(
+                    "DELETE FROM {} WHERE timestamp < ?1",
+                    quote_table(&self.opts.table)
+                ),
+                params![oldest as i64],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Quotes the names of a table, optionally qualified by its schema, as SQL identifiers.
+fn quote_table(table: &str) -> String {
+    table.split('.').map(quote).collect::<Vec<_>>().join(".")
+}
+
+// Quotes a name as an SQL identifier.
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+// Removes the first string field of the given keys from the record.
+fn take_str(record: &mut Map<String, serde_json::Value>, keys: &[&str]) -> Option<String> {
+    keys.iter().find_map(|key| match record.remove(*key) {
+        Some(serde_json::Value::String(s)) => Some(s),
+        _ => None,
+    })
+}
+
+/// Implements Writer trait for SqliteWriter.
+impl Writer for SqliteWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        self.insert(serde_json::to_value(FieldMap(value))?)
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        self.insert(serde_json::to_value(fields)?)
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        // the records are committed by each insert, it checkpoints the WAL journal into the database.
+        self.inner
+            .lock()
+            .conn
+            .execute_batch("PRAGMA wal_checkpoint(PASSIVE)")
+            .map_err(io::Error::other)
+    }
+
+    fn healthy(&self) -> bool {
+        self.health.healthy()
+    }
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the SqliteWriter for a given database file.
+pub fn new_writer<P: AsRef<Path>>(
+    path: P,
+    opts: SqliteOptions,
+) -> Result<Box<dyn Writer>, io::Error> {
+    Ok(Box::new(SqliteWriter::new(path, opts)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sqlite_writer_works() {
+        let opts = SqliteOptions {
+            max_rows: Some(3),
+            prune_interval: 2,
+            ..Default::default()
+        };
+        let writer =
+            SqliteWriter::with_connection(Connection::open_in_memory().unwrap(), opts).unwrap();
+        for i in 0..10 {
+            let mut value = BTreeMap::new();
+            value.insert(Key::from("level"), Value::from("INFO"));
+            value.insert(Key::from("message"), Value::from("hello"));
+            value.insert(Key::from("timestamp"), Value::from(1679745592127_u64 + i));
+            value.insert(Key::from("i"), Value::from(i));
+            writer.write_log(&value).unwrap();
+        }
+        writer.flush().unwrap();
+
+        let inner = writer.inner.lock();
+        let mut stmt = inner
+            .conn
+            .prepare("SELECT timestamp, level, target, message, attributes FROM logs ORDER BY id")
+            .unwrap();
+        let rows: Vec<(i64, String, Option<String>, String, String)> = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect();
+        assert_eq!(3, rows.len());
+        assert_eq!(
+            (
+                1679745592136,
+                "INFO".to_string(),
+                None,
+                "hello".to_string(),
+                r#"{"i":9}"#.to_string()
+            ),
+            rows[2]
+        );
+        assert_eq!(r#"{"i":7}"#, rows[0].4);
+    }
+
+    #[test]
+    fn quote_table_works() {
+        assert_eq!(r#""logs""#, quote_table("logs"));
+        assert_eq!(r#""main"."Logs""#, quote_table("main.Logs"));
+        assert_eq!(
+            r#""logs; DROP TABLE users; --""#,
+            quote_table("logs; DROP TABLE users; --")
+        );
+        assert_eq!(
+            r#""logs"" (a) VALUES (1); --""#,
+            quote_table(r#"logs" (a) VALUES (1); --"#)
+        );
+
+        for table in ["main.Audit Logs", r#"app"logs"#] {
+            let opts = SqliteOptions {
+                table: table.to_string(),
+                max_rows: Some(1),
+                prune_interval: 1,
+                ..Default::default()
+            };
+            let writer =
+                SqliteWriter::with_connection(Connection::open_in_memory().unwrap(), opts).unwrap();
+            let mut value = BTreeMap::new();
+            value.insert(Key::from("message"), Value::from("hello"));
+            writer.write_log(&value).unwrap();
+            writer.write_log(&value).unwrap();
+
+            let inner = writer.inner.lock();
+            let count: i64 = inner
+                .conn
+                .query_row(
+                    &format!("SELECT COUNT(*) FROM {}", quote_table(table)),
+                    [],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert_eq!(1, count);
+        }
+    }
+}
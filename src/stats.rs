@@ -0,0 +1,186 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Self-statistics
+//!
+//! With [`Builder::with_stats`](crate::Builder::with_stats), a background thread logs a record
+//! to the `structured_logger::stats` target every interval, with the statistics of the logger since the previous one,
+//! so the health of the log pipeline is visible in the logs themselves:
+//! * `records`: the number of records written;
+//! * `errors`: the number of records that the writers failed to write;
+//! * `dropped`: the number of records dropped by the queues registered with
+//!   [`Builder::with_stats_queue`](crate::Builder::with_stats_queue);
+//! * `queue.{name}.len` and `queue.{name}.dropped`: the current length and the number of dropped records of each queue.
+//!
+//! The statistics records are written like the other records, so they can be routed
+//! to a dedicated writer with [`Builder::with_target_writer`](crate::Builder::with_target_writer),
+//! and they are not counted in the statistics. The thread stops when the logger is shut down.
+//!
+//! Example:
+//! ```rust
+//! use std::time::Duration;
+//! use structured_logger::{non_blocking::non_blocking, Builder};
+//!
+//! fn main() {
+//!     let (writer, guard) = non_blocking(std::io::stdout());
+//!     Builder::with_level("info")
+//!         .with_stats(Duration::from_secs(60))
+//!         .with_stats_queue("stdout", guard.metrics())
+//!         .with_default_writer(writer)
+//!         .init();
+//!
+//!     // {"dropped":0,"errors":0,"level":"INFO","message":"logger statistics","queue.stdout.dropped":0,...}
+//!     log::info!("hello world");
+//! }
+//! ```
+//!
+
+use std::{
+    io,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+#[cfg(feature = "json")]
+use crate::metrics::QueueMetrics;
+
+/// The target of the statistics records.
+pub const STATS_TARGET: &str = "structured_logger::stats";
+
+/// The counters of a logger with statistics.
+pub(crate) struct Stats {
+    pub(crate) interval: Duration,
+    written: AtomicU64,
+    errors: AtomicU64,
+    #[cfg(feature = "json")]
+    queues: Vec<(String, QueueMetrics)>,
+}
+
+impl Stats {
+    pub(crate) fn new(
+        interval: Duration,
+        #[cfg(feature = "json")] queues: Vec<(String, QueueMetrics)>,
+    ) -> Self {
+        Stats {
+            interval,
+            written: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            #[cfg(feature = "json")]
+            queues,
+        }
+    }
+
+    pub(crate) fn on_write(&self, res: &Result<(), io::Error>) {
+        match res {
+            Ok(()) => self.written.fetch_add(1, Ordering::Relaxed),
+            Err(_) => self.errors.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    /// Returns the key-values of a statistics record, the counters since the previous call,
+    /// and the dropped counts of the queues at this call in `last_dropped`.
+    pub(crate) fn take(&self, last_dropped: &mut Vec<u64>) -> Vec<(String, u64)> {
+        let mut kvs = vec![
+            (
+                "records".to_string(),
+                self.written.swap(0, Ordering::Relaxed),
+            ),
+            ("errors".to_string(), self.errors.swap(0, Ordering::Relaxed)),
+        ];
+        #[cfg(feature = "json")]
+        let dropped = {
+            last_dropped.resize(self.queues.len(), 0);
+            let mut dropped = 0;
+            for ((name, metrics), last) in self.queues.iter().zip(last_dropped.iter_mut()) {
+                let total = metrics.dropped();
+                let n = total - *last;
+                *last = total;
+                dropped += n;
+                kvs.push((format!("queue.{}.len", name), metrics.queue_len() as u64));
+                kvs.push((format!("queue.{}.dropped", name), n));
+            }
+            dropped
+        };
+        #[cfg(not(feature = "json"))]
+        let dropped = {
+            let _ = last_dropped;
+            0
+        };
+        kvs.push(("dropped".to_string(), dropped));
+        kvs
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+    use crate::metrics::QueueCounters;
+    use std::sync::Arc;
+
+    #[test]
+    fn stats_works() {
+        let stats = Stats::new(Duration::from_secs(1), Vec::new());
+        stats.on_write(&Ok(()));
+        stats.on_write(&Ok(()));
+        stats.on_write(&Err(io::Error::other("failed")));
+
+        let mut last_dropped = Vec::new();
+        assert_eq!(
+            vec![
+                ("records".to_string(), 2),
+                ("errors".to_string(), 1),
+                ("dropped".to_string(), 0),
+            ],
+            stats.take(&mut last_dropped)
+        );
+        // the counters are reset by each snapshot.
+        assert_eq!(
+            vec![
+                ("records".to_string(), 0),
+                ("errors".to_string(), 0),
+                ("dropped".to_string(), 0),
+            ],
+            stats.take(&mut last_dropped)
+        );
+        assert!(last_dropped.is_empty());
+    }
+
+    #[test]
+    fn stats_queues_works() {
+        let a = QueueMetrics(Arc::new(QueueCounters::default()));
+        let b = QueueMetrics(Arc::new(QueueCounters::default()));
+        let stats = Stats::new(
+            Duration::from_secs(1),
+            vec![("a".to_string(), a.clone()), ("b".to_string(), b.clone())],
+        );
+        a.0.on_push();
+        a.0.on_drop();
+        a.0.on_drop();
+        b.0.on_drop();
+        stats.on_write(&Ok(()));
+
+        let mut last_dropped = Vec::new();
+        let kvs = stats.take(&mut last_dropped);
+        assert_eq!(
+            vec![
+                ("records".to_string(), 1),
+                ("errors".to_string(), 0),
+                ("queue.a.len".to_string(), 1),
+                ("queue.a.dropped".to_string(), 2),
+                ("queue.b.len".to_string(), 0),
+                ("queue.b.dropped".to_string(), 1),
+                ("dropped".to_string(), 3),
+            ],
+            kvs
+        );
+        assert_eq!(vec![2, 1], last_dropped);
+
+        // the dropped counts are the records dropped since the previous snapshot.
+        b.0.on_drop();
+        let kvs = stats.take(&mut last_dropped);
+        assert_eq!(("queue.a.dropped".to_string(), 0), kvs[3]);
+        assert_eq!(("queue.b.dropped".to_string(), 1), kvs[5]);
+        assert_eq!(("dropped".to_string(), 1), kvs[6]);
+        assert_eq!(vec![2, 2], last_dropped);
+    }
+}
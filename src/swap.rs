@@ -0,0 +1,116 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Swappable Writer Implementation
+//!
+//! A [`Writer`] wrapper whose wrapped writer can be atomically replaced at runtime through a [`SwapHandle`],
+//! such as to switch from stdout to a file opened after a privilege drop,
+//! or to a local sink while a network destination is down.
+//! The records are never blocked by a swap: a record is written by either the old or the new writer.
+//! To create a `Box<dyn Writer>` use the [`new_writer`] function.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{json, swap, Builder};
+//!
+//! fn main() {
+//!     let (writer, handle) = swap::new_writer(json::new_writer(std::io::stdout()));
+//!     Builder::with_level("info")
+//!         .with_default_writer(writer)
+//!         .init();
+//!
+//!     log::info!("written to stdout");
+//!     let path = std::env::temp_dir().join("app.log");
+//!     handle.replace(json::new_file_writer(path).unwrap()).unwrap();
+//!     log::info!("written to the file");
+//! }
+//! ```
+//!
+
+use arc_swap::ArcSwap;
+use std::{collections::BTreeMap, io, sync::Arc};
+
+use crate::{Fields, Key, Value, Writer};
+
+/// A Writer implementation that writes logs to a wrapped writer, replaceable by its [`SwapHandle`].
+pub struct SwapWriter(Arc<ArcSwap<Box<dyn Writer>>>);
+
+/// A handle to replace the wrapped writer of a [`SwapWriter`], it can be cloned and sent to other threads.
+#[derive(Clone)]
+pub struct SwapHandle(Arc<ArcSwap<Box<dyn Writer>>>);
+
+impl SwapWriter {
+    /// Creates a new SwapWriter instance that wraps `w`, and its handle.
+    pub fn new(w: Box<dyn Writer>) -> (Self, SwapHandle) {
+        let inner = Arc::new(ArcSwap::from_pointee(w));
+        (SwapWriter(inner.clone()), SwapHandle(inner))
+    }
+}
+
+impl SwapHandle {
+    /// Replaces the wrapped writer with `w`, then flushes the replaced writer.
+    /// The records being written by the replaced writer at the time of the swap may be written after the flush;
+    /// they are flushed when the replaced writer is dropped, if it flushes on drop.
+    pub fn replace(&self, w: Box<dyn Writer>) -> Result<(), io::Error> {
+        let old = self.0.swap(Arc::new(w));
+        old.flush()
+    }
+}
+
+/// Implements Writer trait for SwapWriter.
+impl Writer for SwapWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        self.0.load().write_log(value)
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        self.0.load().write_fields(fields)
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        self.0.load().flush()
+    }
+
+    fn shutdown(&self) -> Result<(), io::Error> {
+        self.0.load().shutdown()
+    }
+
+    fn healthy(&self) -> bool {
+        self.0.load().healthy()
+    }
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the SwapWriter for a given writer, and its handle.
+pub fn new_writer(w: Box<dyn Writer>) -> (Box<dyn Writer>, SwapHandle) {
+    let (writer, handle) = SwapWriter::new(w);
+    (Box::new(writer), handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fn_writer;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn swap_writer_works() {
+        let counter = |count: &Arc<AtomicU32>| {
+            let count = count.clone();
+            fn_writer(move |_| {
+                count.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            })
+        };
+        let first = Arc::new(AtomicU32::new(0));
+        let second = Arc::new(AtomicU32::new(0));
+        let value = BTreeMap::new();
+
+        let (writer, handle) = new_writer(counter(&first));
+        writer.write_log(&value).unwrap();
+        handle.clone().replace(counter(&second)).unwrap();
+        writer.write_log(&value).unwrap();
+        writer.write_log(&value).unwrap();
+        assert_eq!(1, first.load(Ordering::Relaxed));
+        assert_eq!(2, second.load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,56 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! The message templates captured by the [`log_template!`](crate::log_template) macro.
+
+/// Logs a message like [`log::log!`], with the raw format string and the rendered positional arguments
+/// as the `message_template` and `message_args` fields, in addition to the rendered message.
+/// The records of the same log statement share the same `message_template`, even when the arguments differ,
+/// so they can be grouped cheaply in analytics. Each argument is evaluated once.
+///
+/// The syntax is the one of [`log::log!`]: an optional `target:`, a level, optional key-values followed by `;`,
+/// then a format string literal and its positional arguments.
+///
+/// Example:
+/// ```rust
+/// use log::Level;
+/// use structured_logger::log_template;
+///
+/// let uid = "user123";
+/// log_template!(Level::Info, "user {} logged in after {} attempts", uid, 2);
+/// // {"level":"INFO","message":"user user123 logged in after 2 attempts","message_args":["user123","2"],
+/// //  "message_template":"user {} logged in after {} attempts","target":"...","timestamp":...}
+///
+/// log_template!(target: "api", Level::Warn, method = "GET"; "slow request: {}ms", 1200);
+/// ```
+#[macro_export]
+macro_rules! log_template {
+    (target: $target:expr, $lvl:expr, $($key:tt $(:$capture:tt)? = $value:expr),+; $fmt:literal $(, $arg:expr)* $(,)?) => {
+        $crate::log_template!(@bind [] [$($arg),*] ($target, $lvl, { $($key $(:$capture)? = $value,)+ }, $fmt))
+    };
+    (target: $target:expr, $lvl:expr, $fmt:literal $(, $arg:expr)* $(,)?) => {
+        $crate::log_template!(@bind [] [$($arg),*] ($target, $lvl, {}, $fmt))
+    };
+    (@bind [$($bound:ident)*] [$head:expr $(, $tail:expr)*] $rest:tt) => {
+        // each expansion binds its own hygienic `arg`, so every argument is evaluated once.
+        match &$head {
+            arg => $crate::log_template!(@bind [$($bound)* arg] [$($tail),*] $rest),
+        }
+    };
+    (@bind [$($bound:ident)*] [] ($target:expr, $lvl:expr, { $($kvs:tt)* }, $fmt:literal)) => {
+        ::log::log!(
+            target: $target,
+            $lvl,
+            $($kvs)*
+            message_template = $fmt,
+            message_args:serde = ::std::vec::Vec::<::std::string::String>::from([$(::std::string::ToString::to_string($bound)),*]);
+            $fmt, $($bound),*
+        )
+    };
+    ($lvl:expr, $($key:tt $(:$capture:tt)? = $value:expr),+; $fmt:literal $(, $arg:expr)* $(,)?) => {
+        $crate::log_template!(target: ::std::module_path!(), $lvl, $($key $(:$capture)? = $value),+; $fmt $(, $arg)*)
+    };
+    ($lvl:expr, $fmt:literal $(, $arg:expr)* $(,)?) => {
+        $crate::log_template!(target: ::std::module_path!(), $lvl, $fmt $(, $arg)*)
+    };
+}
@@ -0,0 +1,401 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Test Helpers
+//!
+//! A [`Capture`] keeps the records written by its [`CaptureWriter`] in memory, so the unit tests can assert
+//! on the logs as structured values, without parsing strings:
+//! * [`Capture::logs_matching`] returns the records that match a predicate;
+//! * the [`assert_logged!`](crate::assert_logged) macro asserts that a record has the given level, target,
+//!   message or fields, and prints the captured records if none has.
+//!
+//! The [`init_capture`] function installs a global logger that captures the records of each test
+//! in its own [`Capture`], keyed by the id of the thread that logs them, so the tests that run in parallel
+//! with `cargo test` don't see the records of each other. The records logged on the threads spawned by a test
+//! are not captured, since the logger can't tell which test spawned them.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{assert_logged, test::Capture, Builder};
+//!
+//! let capture = Capture::new();
+//! Builder::with_level("info")
+//!     .with_default_writer(capture.writer())
+//!     .init();
+//!
+//! log::error!(target: "api", status = 500, path = "/orders"; "request failed");
+//!
+//! assert_logged!(capture, level: Error, target: "api", contains: {"status": 500});
+//! assert_logged!(capture, message: "failed", contains: {"path": "/orders"});
+//! assert_eq!(1, capture.logs_matching(|record| record.get("status").is_some()).len());
+//! ```
+//!
+//! With [`init_capture`]:
+//! ```rust
+//! use structured_logger::{assert_logged, test::init_capture};
+//!
+//! // at the start of each test.
+//! let _capture = init_capture();
+//! log::info!(user = "u1"; "user logged in");
+//! assert_logged!(level: Info, contains: {"user": "u1"});
+//! ```
+//!
+
+use log::Level;
+use parking_lot::Mutex;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, io,
+    sync::{Arc, Once, OnceLock},
+    thread::{self, ThreadId},
+};
+
+use crate::fields::FieldMap;
+use crate::route::{parse_level, LEVEL_KEYS};
+use crate::{Builder, Fields, Key, Value, Writer};
+
+#[doc(hidden)]
+pub use serde_json::json as __json;
+
+// The captures of the global logger installed by `init_capture`, keyed by the id of the test thread.
+static THREAD_CAPTURES: OnceLock<Mutex<HashMap<ThreadId, Capture>>> = OnceLock::new();
+
+fn thread_captures() -> &'static Mutex<HashMap<ThreadId, Capture>> {
+    THREAD_CAPTURES.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Installs a global logger that captures the records of the current thread, on the first call,
+/// and returns a new empty [`Capture`] for the current thread, see the [module level documentation](self).
+/// A test calls it once, the records of the test are captured until it calls it again.
+///
+/// All the levels are enabled. The [`assert_logged!`](crate::assert_logged) macro without a capture
+/// asserts on the capture of the current thread.
+///
+/// # Panics
+///
+/// This will panic if another global logger is already installed.
+pub fn init_capture() -> Capture {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        Builder::with_level("trace")
+            .with_default_writer(Box::new(ThreadCaptureWriter))
+            .init()
+    });
+
+    let capture = Capture::new();
+    thread_captures()
+        .lock()
+        .insert(thread::current().id(), capture.clone());
+    capture
+}
+
+/// A record captured by a [`CaptureWriter`], with its fields as JSON values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CapturedRecord {
+    fields: serde_json::Map<String, serde_json::Value>,
+}
+
+impl CapturedRecord {
+    /// Returns the level of the record, written as `level`, `severity` or `status` depending on the platform.
+    pub fn level(&self) -> Option<Level> {
+        LEVEL_KEYS
+            .iter()
+            .find_map(|key| self.fields.get(*key).and_then(|v| v.as_str()))
+            .and_then(parse_level)
+    }
+
+    /// Returns the target of the record.
+    pub fn target(&self) -> Option<&str> {
+        self.fields.get("target").and_then(|v| v.as_str())
+    }
+
+    /// Returns the message of the record.
+    pub fn message(&self) -> Option<&str> {
+        self.fields.get("message").and_then(|v| v.as_str())
+    }
+
+    /// Returns the value of a field of the record.
+    pub fn get(&self, key: &str) -> Option<&serde_json::Value> {
+        self.fields.get(key)
+    }
+
+    /// Returns the fields of the record.
+    pub fn fields(&self) -> &serde_json::Map<String, serde_json::Value> {
+        &self.fields
+    }
+}
+
+/// Formats the record as a JSON line.
+impl fmt::Display for CapturedRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match serde_json::to_string(&self.fields) {
+            Ok(json) => f.write_str(&json),
+            Err(_) => Err(fmt::Error),
+        }
+    }
+}
+
+/// The expected level, target, message and fields of a record, see [`assert_logged!`](crate::assert_logged).
+/// A `None` matches any record.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Expected {
+    /// The level of the record.
+    pub level: Option<Level>,
+    /// The target of the record.
+    pub target: Option<String>,
+    /// A text that the message of the record contains.
+    pub message: Option<String>,
+    /// The fields that the record contains, a nested object matches a subset of the fields of an object.
+    pub contains: Option<serde_json::Value>,
+}
+
+impl Expected {
+    /// Returns true if `record` matches the expected level, target, message and fields.
+    pub fn matches(&self, record: &CapturedRecord) -> bool {
+        if self.level.is_some() && record.level() != self.level {
+            return false;
+        }
+        if self.target.is_some() && record.target() != self.target.as_deref() {
+            return false;
+        }
+        if let Some(ref message) = self.message {
+            if !record
+                .message()
+                .is_some_and(|m| m.contains(message.as_str()))
+            {
+                return false;
+            }
+        }
+        match self.contains {
+            Some(ref contains) => {
+                is_subset(contains, &serde_json::Value::Object(record.fields.clone()))
+            }
+            None => true,
+        }
+    }
+}
+
+// Returns true if `expected` equals `value`, or is an object whose fields are a subset of the object `value`.
+fn is_subset(expected: &serde_json::Value, value: &serde_json::Value) -> bool {
+    match (expected, value) {
+        (serde_json::Value::Object(expected), serde_json::Value::Object(value)) => {
+            expected.iter().all(|(k, v)| match value.get(k) {
+                Some(value) => is_subset(v, value),
+                None => false,
+            })
+        }
+        (expected, value) => expected == value,
+    }
+}
+
+/// The records captured by a [`CaptureWriter`], it can be cloned and shared with the writer.
+#[derive(Debug, Clone, Default)]
+pub struct Capture {
+    records: Arc<Mutex<Vec<CapturedRecord>>>,
+}
+
+impl Capture {
+    /// Creates a new empty Capture instance.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the capture of the current thread, returned by the last call of [`init_capture`] on this thread,
+    /// or `None` if it was not called.
+    pub fn current() -> Option<Capture> {
+        thread_captures()
+            .lock()
+            .get(&thread::current().id())
+            .cloned()
+    }
+
+    /// Returns a `Box<dyn Writer>` instance with a [`CaptureWriter`] that captures the records in this Capture.
+    pub fn writer(&self) -> Box<dyn Writer> {
+        Box::new(CaptureWriter(self.records.clone()))
+    }
+
+    /// Returns the captured records, in the order they were written.
+    pub fn records(&self) -> Vec<CapturedRecord> {
+        self.records.lock().clone()
+    }
+
+    /// Returns the captured records for which `predicate` returns true.
+    pub fn logs_matching<P>(&self, predicate: P) -> Vec<CapturedRecord>
+    where
+        P: Fn(&CapturedRecord) -> bool,
+    {
+        self.records
+            .lock()
+            .iter()
+            .filter(|record| predicate(record))
+            .cloned()
+            .collect()
+    }
+
+    /// Removes the captured records.
+    pub fn clear(&self) {
+        self.records.lock().clear();
+    }
+
+    /// Panics if no captured record matches `expected`, with the captured records in the message.
+    /// It is called by the [`assert_logged!`](crate::assert_logged) macro.
+    #[track_caller]
+    pub fn assert_logged(&self, expected: &Expected) {
+        let records = self.records.lock();
+        if records.iter().any(|record| expected.matches(record)) {
+            return;
+        }
+        let lines: Vec<String> = records.iter().map(|r| r.to_string()).collect();
+        panic!(
+            "no captured record matches {:?}, the {} captured records are:\n{}",
+            expected,
+            lines.len(),
+            lines.join("\n")
+        );
+    }
+}
+
+/// A Writer implementation that captures the records in memory, see [`Capture`].
+pub struct CaptureWriter(Arc<Mutex<Vec<CapturedRecord>>>);
+
+/// Implements Writer trait for CaptureWriter.
+impl Writer for CaptureWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        let fields = match serde_json::to_value(FieldMap(value))? {
+            serde_json::Value::Object(fields) => fields,
+            _ => serde_json::Map::new(),
+        };
+        self.0.lock().push(CapturedRecord { fields });
+        Ok(())
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        self.write_log(&fields.to_map())
+    }
+}
+
+// The writer of the global logger installed by `init_capture`, it captures a record
+// in the capture of the thread that logs it, if any.
+struct ThreadCaptureWriter;
+
+impl Writer for ThreadCaptureWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        match Capture::current() {
+            Some(capture) => CaptureWriter(capture.records).write_log(value),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Asserts that a [`Capture`](crate::test::Capture) has a record with the given level, target, message and fields,
+/// see [`Expected`](crate::test::Expected). It panics with the captured records if none matches.
+///
+/// The first argument is the capture, or it is omitted for the capture of the current thread
+/// returned by [`init_capture`](crate::test::init_capture). It is followed by any of:
+/// * `level: Error`, a [`log::Level`] variant;
+/// * `target: "api"`, the exact target;
+/// * `message: "failed"`, a text that the message contains;
+/// * `contains: {"status": 500}`, the fields of the record in the syntax of `serde_json::json!`,
+///   a nested object matches a subset of the fields of an object.
+///
+/// A value that is not a literal is wrapped in parentheses, such as `target: (module_path!())`.
+///
+/// Example:
+/// ```rust
+/// use structured_logger::{assert_logged, test::Capture, Builder};
+///
+/// let capture = Capture::new();
+/// Builder::with_level("info")
+///     .with_default_writer(capture.writer())
+///     .init();
+/// log::warn!(target: "db", table = "orders"; "slow query");
+///
+/// assert_logged!(capture, level: Warn, target: "db", message: "slow", contains: {"table": "orders"});
+/// ```
+#[macro_export]
+macro_rules! assert_logged {
+    ($($key:ident : $value:tt),+ $(,)?) => {{
+        let capture = $crate::test::Capture::current()
+            .expect("init_capture was not called on the current thread");
+        $crate::assert_logged!(capture, $($key: $value),+);
+    }};
+    ($capture:expr $(, $key:ident : $value:tt)* $(,)?) => {{
+        #[allow(unused_mut)]
+        let mut expected = $crate::test::Expected::default();
+        $($crate::assert_logged!(@set expected, $key, $value);)*
+        $capture.assert_logged(&expected);
+    }};
+    (@set $expected:ident, level, $value:ident) => {
+        $expected.level = ::std::option::Option::Some(::log::Level::$value);
+    };
+    (@set $expected:ident, target, $value:expr) => {
+        $expected.target = ::std::option::Option::Some(::std::string::ToString::to_string(&$value));
+    };
+    (@set $expected:ident, message, $value:expr) => {
+        $expected.message = ::std::option::Option::Some(::std::string::ToString::to_string(&$value));
+    };
+    (@set $expected:ident, contains, $value:tt) => {
+        $expected.contains = ::std::option::Option::Some($crate::test::__json!($value));
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Builder;
+    use log::{Log, Record};
+
+    #[test]
+    fn capture_works() {
+        let capture = Capture::new();
+        let logger = Builder::with_level("info")
+            .with_default_writer(capture.writer())
+            .build();
+        let ctx = serde_json::json!({"user": "u1", "org": "o1"});
+        let log = |level: Level, message: &str, kvs: &[(&str, Value)]| {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(level)
+                    .target("api")
+                    .key_values(&kvs)
+                    .build(),
+            )
+        };
+        log(
+            Level::Info,
+            "request received",
+            &[("path", "/orders".into())],
+        );
+        log(
+            Level::Error,
+            "request failed",
+            &[("status", 500.into()), ("ctx", Value::from_serde(&ctx))],
+        );
+        log(Level::Debug, "filtered out", &[]);
+
+        assert_eq!(2, capture.records().len());
+        assert_logged!(capture, level: Error, target: "api", contains: {"status": 500});
+        assert_logged!(capture, message: "failed", contains: {"ctx": {"user": "u1"}});
+        assert_logged!(capture, level: Info, message: ("received"));
+
+        let errors = capture.logs_matching(|r| r.level() == Some(Level::Error));
+        assert_eq!(1, errors.len());
+        assert_eq!(Some("request failed"), errors[0].message());
+        assert_eq!(Some(&serde_json::json!(500)), errors[0].get("status"));
+
+        let expected = Expected {
+            level: Some(Level::Warn),
+            ..Default::default()
+        };
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            capture.assert_logged(&expected)
+        }));
+        let msg = *res.unwrap_err().downcast::<String>().unwrap();
+        assert!(msg.starts_with("no captured record matches"));
+        assert!(msg.contains(r#""message":"request failed""#));
+
+        capture.clear();
+        assert!(capture.records().is_empty());
+    }
+}
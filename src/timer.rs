@@ -0,0 +1,125 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Elapsed Time
+//!
+//! A [`Stopwatch`] started by [`start_timer`], and the [`Elapsed`] duration it measures,
+//! which is logged as both raw milliseconds and a human-readable duration:
+//! `{"elapsed_ms":1234,"elapsed":"1.234s"}`, so the latency fields are consistent across services.
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::start_timer;
+//!
+//! let timer = start_timer();
+//! // ... handle the request
+//! log::info!(target: "api", path = "/hello", latency = timer.elapsed(); "");
+//! // {"latency":{"elapsed_ms":10,"elapsed":"10.123ms"},"level":"INFO","message":"","path":"/hello",...}
+//! ```
+//!
+
+use log::kv::{ToValue, Value};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// Starts a new [`Stopwatch`].
+pub fn start_timer() -> Stopwatch {
+    Stopwatch::new()
+}
+
+/// A monotonic timer that measures the [`Elapsed`] time since it was started.
+#[derive(Debug, Clone, Copy)]
+pub struct Stopwatch(Instant);
+
+impl Default for Stopwatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stopwatch {
+    /// Creates a new Stopwatch instance, started now.
+    pub fn new() -> Self {
+        Stopwatch(Instant::now())
+    }
+
+    /// Returns the time elapsed since the stopwatch was started.
+    pub fn elapsed(&self) -> Elapsed {
+        Elapsed(self.0.elapsed())
+    }
+
+    /// Returns the time elapsed since the stopwatch was started, and restarts it.
+    pub fn lap(&mut self) -> Elapsed {
+        let now = Instant::now();
+        let elapsed = Elapsed(now - self.0);
+        self.0 = now;
+        elapsed
+    }
+}
+
+/// A duration that is logged as `{"elapsed_ms":1234,"elapsed":"1.234s"}` when passed as a key-value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Elapsed(pub Duration);
+
+impl Elapsed {
+    /// Returns the duration in whole milliseconds.
+    pub fn as_millis(&self) -> u64 {
+        self.0.as_millis() as u64
+    }
+}
+
+impl From<Duration> for Elapsed {
+    fn from(d: Duration) -> Self {
+        Elapsed(d)
+    }
+}
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // such as "1.234s", "10.5ms" or "850ns".
+        write!(f, "{:?}", self.0)
+    }
+}
+
+impl Serialize for Elapsed {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("elapsed_ms", &self.as_millis())?;
+        map.serialize_entry("elapsed", &self.to_string())?;
+        map.end()
+    }
+}
+
+impl ToValue for Elapsed {
+    fn to_value(&self) -> Value<'_> {
+        Value::from_serde(self)
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elapsed_works() {
+        let elapsed = Elapsed::from(Duration::from_millis(1234));
+        assert_eq!("1.234s", elapsed.to_string());
+        assert_eq!(
+            r#"{"elapsed_ms":1234,"elapsed":"1.234s"}"#,
+            serde_json::to_string(&elapsed).unwrap()
+        );
+        assert_eq!(
+            r#"{"elapsed_ms":1234,"elapsed":"1.234s"}"#,
+            serde_json::to_string(&elapsed.to_value()).unwrap()
+        );
+
+        let mut timer = start_timer();
+        std::thread::sleep(Duration::from_millis(5));
+        let lap = timer.lap();
+        assert!(lap.as_millis() >= 5);
+        assert!(timer.elapsed() < lap);
+    }
+}
@@ -0,0 +1,155 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Timestamp Writer
+//!
+//! A [`Writer`] wrapper that writes the `timestamp` field of the records, in Unix milliseconds,
+//! with another key and format, such as `time` in RFC 3339 for `pino-pretty` on stdout,
+//! while the other writers keep the Unix milliseconds of the logger.
+//!
+//! The records without a `timestamp` field, such as with [`Builder::with_gcp_fields`](crate::Builder::with_gcp_fields)
+//! which writes `time` instead, are written unchanged.
+//! The wrapped writer receives the records as a map, see [`Writer::write_log`].
+//!
+//! Example:
+//! ```rust
+//! use structured_logger::{json, timestamp::{self, TimestampFormat}, Builder};
+//!
+//! fn main() {
+//!     Builder::with_level("info")
+//!         .with_default_writer(timestamp::new_writer(
+//!             "time",
+//!             TimestampFormat::Rfc3339,
+//!             json::new_writer(std::io::stdout()),
+//!         ))
+//!         .init();
+//!
+//!     // {"level":"INFO","message":"hello world","target":"rust_out","time":"2023-03-25T11:59:52.127Z"}
+//!     log::info!("hello world");
+//! }
+//! ```
+//!
+
+use std::{collections::BTreeMap, io};
+
+use crate::{Fields, Key, Rfc3339, Value, Writer};
+
+/// The format of the timestamp written by a [`TimestampWriter`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampFormat {
+    /// The Unix milliseconds, such as `1679745592127`.
+    UnixMs,
+    /// The Unix seconds with the milliseconds as decimals, such as `1679745592.127`.
+    UnixSecs,
+    /// The RFC 3339 UTC time with milliseconds, such as `"2023-03-25T11:59:52.127Z"`.
+    Rfc3339,
+}
+
+/// A Writer implementation that writes the timestamp of the records with a given key and format to a wrapped writer.
+pub struct TimestampWriter {
+    key: Box<str>,
+    format: TimestampFormat,
+    inner: Box<dyn Writer>,
+}
+
+impl TimestampWriter {
+    /// Creates a new TimestampWriter instance that writes the timestamp of the records
+    /// with the given key and format to `w`.
+    pub fn new(key: &str, format: TimestampFormat, w: Box<dyn Writer>) -> Self {
+        TimestampWriter {
+            key: key.into(),
+            format,
+            inner: w,
+        }
+    }
+}
+
+/// Implements Writer trait for TimestampWriter.
+impl Writer for TimestampWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        let timestamp_key = Key::from("timestamp");
+        let ms = match value.get(&timestamp_key).and_then(|v| v.to_u64()) {
+            Some(ms) => ms,
+            None => return self.inner.write_log(value),
+        };
+
+        let time;
+        let mut value = value.clone();
+        value.remove(&timestamp_key);
+        let timestamp = match self.format {
+            TimestampFormat::UnixMs => Value::from(ms),
+            TimestampFormat::UnixSecs => Value::from(ms as f64 / 1000.0),
+            TimestampFormat::Rfc3339 => {
+                time = Rfc3339::from_unix_ms(ms);
+                Value::from(time.as_str())
+            }
+        };
+        value.insert(Key::from(self.key.as_ref()), timestamp);
+        self.inner.write_log(&value)
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        self.write_log(&fields.to_map())
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        self.inner.flush()
+    }
+
+    fn shutdown(&self) -> Result<(), io::Error> {
+        self.inner.shutdown()
+    }
+
+    fn healthy(&self) -> bool {
+        self.inner.healthy()
+    }
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the TimestampWriter
+/// that writes the timestamp of the records with the given key and format to `w`.
+pub fn new_writer(key: &str, format: TimestampFormat, w: Box<dyn Writer>) -> Box<dyn Writer> {
+    Box::new(TimestampWriter::new(key, format, w))
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+    use crate::fn_writer;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn timestamp_writer_works() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let format = |format| {
+            let lines = lines.clone();
+            new_writer(
+                "time",
+                format,
+                fn_writer(move |value| {
+                    let json = serde_json::to_string(&crate::fields::FieldMap(value)).unwrap();
+                    lines.lock().unwrap().push(json);
+                    Ok(())
+                }),
+            )
+        };
+
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("message"), Value::from("hello"));
+        value.insert(Key::from("timestamp"), Value::from(1679745592127_u64));
+        format(TimestampFormat::Rfc3339).write_log(&value).unwrap();
+        format(TimestampFormat::UnixSecs).write_log(&value).unwrap();
+        format(TimestampFormat::UnixMs).write_log(&value).unwrap();
+        value.remove(&Key::from("timestamp"));
+        format(TimestampFormat::Rfc3339).write_log(&value).unwrap();
+
+        assert_eq!(
+            vec![
+                r#"{"message":"hello","time":"2023-03-25T11:59:52.127Z"}"#,
+                r#"{"message":"hello","time":1679745592.127}"#,
+                r#"{"message":"hello","time":1679745592127}"#,
+                r#"{"message":"hello"}"#,
+            ],
+            *lines.lock().unwrap()
+        );
+    }
+}
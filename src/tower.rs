@@ -0,0 +1,235 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Tower Access Log
+//!
+//! A [`tower`](https://docs.rs/tower) [`Layer`] that logs one structured record per HTTP request
+//! through this logger, so the [`axum`](https://docs.rs/axum), [`tonic`](https://docs.rs/tonic)
+//! or [`hyper`](https://docs.rs/hyper) services get consistent access logs without their own middleware.
+//!
+//! A record has the `method`, the `path` without the query string, the `status` of the response,
+//! the `latency` as an [`Elapsed`](crate::Elapsed), and the `request_id` read from the request header
+//! set in [`AccessLogOptions::request_id_header`]. An error of the service is logged as `error` instead of `status`.
+//! The records have the `access` target by default, so they can be routed to their own writer,
+//! and the trace context of [`Builder::with_trace_context`](crate::Builder::with_trace_context) is added as usual.
+//!
+//! Example:
+//! ```rust,ignore
+//! use axum::{routing::get, Router};
+//! use structured_logger::{json, tower::AccessLogLayer, Builder};
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     Builder::with_level("info")
+//!         .with_target_writer("access", json::new_writer(std::io::stdout()))
+//!         .init();
+//!
+//!     // {"latency":{"elapsed":"52.1µs","elapsed_ms":0},"level":"INFO","message":"GET / 200","method":"GET","path":"/","status":200,"target":"access",...}
+//!     let app = Router::new()
+//!         .route("/", get(|| async { "hello world" }))
+//!         .layer(AccessLogLayer::default());
+//!     let listener = tokio::net::TcpListener::bind("127.0.0.1:3000").await.unwrap();
+//!     axum::serve(listener, app).await.unwrap();
+//! }
+//! ```
+//!
+
+use http::{Request, Response};
+use pin_project_lite::pin_project;
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::access::Access;
+use crate::{start_timer, Stopwatch};
+
+pub use crate::access::AccessLogOptions;
+
+/// A tower Layer that logs the requests of the wrapped services, see the [module level documentation](self).
+#[derive(Debug, Clone, Default)]
+pub struct AccessLogLayer {
+    opts: Arc<AccessLogOptions>,
+}
+
+impl AccessLogLayer {
+    /// Creates a new AccessLogLayer instance with the given options.
+    pub fn new(opts: AccessLogOptions) -> Self {
+        AccessLogLayer {
+            opts: Arc::new(opts),
+        }
+    }
+}
+
+impl<S> Layer<S> for AccessLogLayer {
+    type Service = AccessLog<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AccessLog {
+            inner,
+            opts: self.opts.clone(),
+        }
+    }
+}
+
+/// A tower Service that logs the requests of a wrapped service, see [`AccessLogLayer`].
+#[derive(Debug, Clone)]
+pub struct AccessLog<S> {
+    inner: S,
+    opts: Arc<AccessLogOptions>,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for AccessLog<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Error: fmt::Display,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let timer = start_timer();
+        let method = req.method().to_string();
+        let path = req.uri().path().to_string();
+        let request_id = req
+            .headers()
+            .get(self.opts.request_id_header.as_str())
+            .and_then(|v| v.to_str().ok())
+            .map(String::from);
+        ResponseFuture {
+            inner: self.inner.call(req),
+            opts: self.opts.clone(),
+            method,
+            path,
+            request_id,
+            timer,
+        }
+    }
+}
+
+pin_project! {
+    /// The response future of an [`AccessLog`] service, it logs the request when the response is ready.
+    pub struct ResponseFuture<F> {
+        #[pin]
+        inner: F,
+        opts: Arc<AccessLogOptions>,
+        method: String,
+        path: String,
+        request_id: Option<String>,
+        timer: Stopwatch,
+    }
+}
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+    E: fmt::Display,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let res = ready!(this.inner.poll(cx));
+        let (status, error) = match res {
+            Ok(ref res) => (Some(res.status().as_u16()), None),
+            Err(ref err) => (None, Some(err.to_string())),
+        };
+        this.opts.log(&Access {
+            method: this.method,
+            path: this.path,
+            request_id: this.request_id.as_deref(),
+            status,
+            error: error.as_deref(),
+            latency: this.timer.elapsed(),
+        });
+        Poll::Ready(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fn_writer, Builder, Key};
+    use std::{
+        future::{ready, Ready},
+        io,
+        sync::Mutex,
+    };
+
+    #[derive(Clone)]
+    struct Hello;
+
+    impl Service<Request<()>> for Hello {
+        type Response = Response<()>;
+        type Error = io::Error;
+        type Future = Ready<Result<Response<()>, io::Error>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: Request<()>) -> Self::Future {
+            ready(match req.uri().path() {
+                "/fail" => Err(io::Error::other("connection reset")),
+                "/missing" => Ok(Response::builder().status(404).body(()).unwrap()),
+                _ => Ok(Response::new(())),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn access_log_layer_works() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let logger = {
+            let lines = lines.clone();
+            Builder::with_level("info")
+                .with_default_writer(fn_writer(move |value| {
+                    let mut value = value.clone();
+                    assert!(value.remove(&Key::from("latency")).is_some());
+                    value.remove(&Key::from("timestamp"));
+                    let json = serde_json::to_string(&crate::fields::FieldMap(&value)).unwrap();
+                    lines.lock().unwrap().push(json);
+                    Ok(())
+                }))
+                .build()
+        };
+        let mut service = AccessLogLayer::new(AccessLogOptions {
+            logger: Some(logger),
+            ..Default::default()
+        })
+        .layer(Hello);
+
+        let req = Request::get("/hello?token=secret")
+            .header("x-request-id", "req-1")
+            .body(())
+            .unwrap();
+        service.call(req).await.unwrap();
+        service
+            .call(Request::post("/missing").body(()).unwrap())
+            .await
+            .unwrap();
+        service
+            .call(Request::get("/fail").body(()).unwrap())
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            vec![
+                r#"{"level":"INFO","message":"GET /hello 200","method":"GET","path":"/hello","request_id":"req-1","status":200,"target":"access"}"#,
+                r#"{"level":"WARN","message":"POST /missing 404","method":"POST","path":"/missing","status":404,"target":"access"}"#,
+                r#"{"error":"connection reset","level":"ERROR","message":"GET /fail failed","method":"GET","path":"/fail","target":"access"}"#,
+            ],
+            *lines.lock().unwrap()
+        );
+    }
+}
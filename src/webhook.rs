@@ -0,0 +1,455 @@
+// (c) 2023-present, IO Rust. All rights reserved.
+// See the file LICENSE for licensing terms.
+
+//! # Webhook Writer Implementation
+//!
+//! A [`Writer`] wrapper that writes the records to the wrapped writer, and also posts the high-severity records,
+//! `ERROR` by default, to a webhook, such as a Slack or Discord incoming webhook, or the PagerDuty Events API,
+//! with the [`ureq`](https://docs.rs/ureq) client. It gives basic alerting to the small services.
+//!
+//! The records with the same target and message are posted once per [`WebhookOptions::dedup_window`],
+//! and at most [`WebhookOptions::rate_limit`] records are posted per [`WebhookOptions::rate_period`],
+//! so an error loop doesn't flood the channel. The records are posted by a worker thread,
+//! the logging thread never waits for the webhook.
+//! To create a `Box<dyn Writer>` use the [`new_writer`] function.
+//!
+//! Example:
+//! ```rust,no_run
+//! use structured_logger::{json, webhook, Builder};
+//!
+//! fn main() {
+//!     let opts = webhook::WebhookOptions {
+//!         url: "https://hooks.slack.com/services/T000/B000/XXXX".to_string(),
+//!         format: webhook::WebhookFormat::Slack,
+//!         ..Default::default()
+//!     };
+//!     Builder::with_level("info")
+//!         .with_default_writer(webhook::new_writer(json::new_writer(std::io::stderr()), opts))
+//!         .init();
+//!
+//!     log::error!(order = 42; "payment failed");
+//! }
+//! ```
+//!
+
+use log::Level;
+use parking_lot::Mutex;
+use serde_json::json;
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap},
+    hash::{Hash, Hasher},
+    io,
+    sync::mpsc::{self, Receiver, SyncSender},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::fields::FieldMap;
+use crate::route::{parse_level, LEVEL_KEYS};
+use crate::{log_failure, Fields, Key, Value, Writer};
+
+/// How long [`Writer::flush`] waits for the worker thread to post the queued records.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The maximum number of records waiting to be posted, the others are dropped.
+const QUEUE_CAPACITY: usize = 64;
+
+/// The payload format of a webhook.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WebhookFormat {
+    /// A Slack incoming webhook, `{"text": "..."}`.
+    Slack,
+    /// A Discord webhook, `{"content": "..."}`.
+    Discord,
+    /// A PagerDuty Events API v2 `trigger` event of the given integration key,
+    /// deduplicated by PagerDuty with the target and the message of the record.
+    PagerDuty {
+        /// The integration key of the PagerDuty service.
+        routing_key: String,
+    },
+    /// The JSON record as is.
+    Json,
+}
+
+/// The options of a [`WebhookWriter`].
+#[derive(Debug, Clone)]
+pub struct WebhookOptions {
+    /// The URL of the webhook. The PagerDuty Events API is `https://events.pagerduty.com/v2/enqueue`.
+    pub url: String,
+    /// The payload format, the default is [`WebhookFormat::Json`].
+    pub format: WebhookFormat,
+    /// The records of this level or more severe are posted, the default is [`Level::Error`].
+    pub level: Level,
+    /// How long a record with the same target and message is not posted again, the default is 5 minutes.
+    pub dedup_window: Duration,
+    /// The maximum number of records posted per [`WebhookOptions::rate_period`], the default is 10.
+    pub rate_limit: u32,
+    /// The period of the rate limit, the default is 1 minute.
+    pub rate_period: Duration,
+    /// The timeout of a request, the default is 10 seconds.
+    pub timeout: Duration,
+}
+
+impl Default for WebhookOptions {
+    fn default() -> Self {
+        WebhookOptions {
+            url: String::new(),
+            format: WebhookFormat::Json,
+            level: Level::Error,
+            dedup_window: Duration::from_secs(300),
+            rate_limit: 10,
+            rate_period: Duration::from_secs(60),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+enum Msg {
+    Post(serde_json::Value),
+    Flush(mpsc::Sender<()>),
+}
+
+struct Limiter {
+    // the last time a record of a target and message hash was posted.
+    seen: HashMap<u64, Instant>,
+    window_start: Instant,
+    posted: u32,
+}
+
+/// A Writer implementation that writes logs to a wrapped writer, and posts the high-severity logs to a webhook.
+pub struct WebhookWriter {
+    inner: Box<dyn Writer>,
+    sender: SyncSender<Msg>,
+    level: Level,
+    dedup_window: Duration,
+    rate_limit: u32,
+    rate_period: Duration,
+    limiter: Mutex<Limiter>,
+}
+
+impl WebhookWriter {
+    /// Creates a new WebhookWriter instance that wraps `inner`, and starts its worker thread,
+    /// which stops when the writer is dropped.
+    pub fn new(inner: Box<dyn Writer>, opts: WebhookOptions) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_CAPACITY);
+        let agent: ureq::Agent = ureq::Agent::config_builder()
+            .timeout_global(Some(opts.timeout))
+            .build()
+            .into();
+        let worker = Worker {
+            agent,
+            url: opts.url,
+            format: opts.format,
+            receiver,
+        };
+        thread::Builder::new()
+            .name("structured-logger-webhook".to_string())
+            .spawn(move || worker.run())
+            .expect("failed to spawn the webhook writer thread");
+        WebhookWriter {
+            inner,
+            sender,
+            level: opts.level,
+            dedup_window: opts.dedup_window,
+            rate_limit: opts.rate_limit,
+            rate_period: opts.rate_period,
+            limiter: Mutex::new(Limiter {
+                seen: HashMap::new(),
+                window_start: Instant::now(),
+                posted: 0,
+            }),
+        }
+    }
+
+    fn notify(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        let key = |k: &str| {
+            value
+                .get(&Key::from(k))
+                .and_then(|v| v.to_borrowed_str())
+                .unwrap_or_default()
+        };
+        let mut hasher = DefaultHasher::new();
+        key("target").hash(&mut hasher);
+        key("message").hash(&mut hasher);
+        if !self.allow(hasher.finish()) {
+            return Ok(());
+        }
+
+        let record = serde_json::to_value(FieldMap(value))?;
+        // the worker is behind, the alerts are best effort.
+        let _ = self.sender.try_send(Msg::Post(record));
+        Ok(())
+    }
+
+    // Returns true if a record of the given hash is neither a duplicate nor over the rate limit.
+    fn allow(&self, hash: u64) -> bool {
+        let now = Instant::now();
+        let mut limiter = self.limiter.lock();
+        if let Some(last) = limiter.seen.get(&hash) {
+            if now.duration_since(*last) < self.dedup_window {
+                return false;
+            }
+        }
+        if now.duration_since(limiter.window_start) >= self.rate_period {
+            limiter.window_start = now;
+            limiter.posted = 0;
+            let window = self.dedup_window;
+            limiter
+                .seen
+                .retain(|_, last| now.duration_since(*last) < window);
+        }
+        if limiter.posted >= self.rate_limit {
+            return false;
+        }
+        limiter.posted += 1;
+        limiter.seen.insert(hash, now);
+        true
+    }
+
+    fn is_alert(&self, level: Option<&str>) -> bool {
+        match level.and_then(parse_level) {
+            Some(level) => level <= self.level,
+            None => false,
+        }
+    }
+}
+
+// Parses the level of a record, written as `level`, `severity` or `status` depending on the platform.
+/// Implements Writer trait for WebhookWriter.
+impl Writer for WebhookWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> Result<(), io::Error> {
+        let res = self.inner.write_log(value);
+        let level = LEVEL_KEYS.iter().find_map(|key| {
+            value
+                .get(&Key::from(*key))
+                .and_then(|v| v.to_borrowed_str())
+        });
+        if self.is_alert(level) {
+            self.notify(value)?;
+        }
+        res
+    }
+
+    fn write_fields(&self, fields: &Fields) -> Result<(), io::Error> {
+        let res = self.inner.write_fields(fields);
+        let mut alert = false;
+        let _ = fields.visit(&mut |key, value| {
+            if LEVEL_KEYS.contains(&key.as_str()) {
+                alert = self.is_alert(value.to_borrowed_str());
+            }
+            Ok(())
+        });
+        if alert {
+            self.notify(&fields.to_map())?;
+        }
+        res
+    }
+
+    fn flush(&self) -> Result<(), io::Error> {
+        let (tx, rx) = mpsc::channel();
+        if self.sender.send(Msg::Flush(tx)).is_ok() && rx.recv_timeout(FLUSH_TIMEOUT).is_err() {
+            log_failure("WebhookWriter flush timed out");
+        }
+        self.inner.flush()
+    }
+
+    fn shutdown(&self) -> Result<(), io::Error> {
+        self.flush()?;
+        self.inner.shutdown()
+    }
+
+    fn healthy(&self) -> bool {
+        self.inner.healthy()
+    }
+}
+
+struct Worker {
+    agent: ureq::Agent,
+    url: String,
+    format: WebhookFormat,
+    receiver: Receiver<Msg>,
+}
+
+impl Worker {
+    fn run(self) {
+        while let Ok(msg) = self.receiver.recv() {
+            match msg {
+                Msg::Post(record) => {
+                    if let Err(err) = self.post(&self.payload(record)) {
+                        log_failure(format!("WebhookWriter failed to post log: {}", err).as_str());
+                    }
+                }
+                Msg::Flush(ack) => {
+                    let _ = ack.send(());
+                }
+            }
+        }
+    }
+
+    fn post(&self, payload: &serde_json::Value) -> Result<(), io::Error> {
+        let body = serde_json::to_vec(payload)?;
+        self.agent
+            .post(&self.url)
+            .header("Content-Type", "application/json")
+            .send(&body[..])
+            .map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    fn payload(&self, record: serde_json::Value) -> serde_json::Value {
+        let field = |key: &str| record.get(key).and_then(|v| v.as_str()).unwrap_or_default();
+        let level = LEVEL_KEYS
+            .iter()
+            .map(|key| field(key))
+            .find(|v| !v.is_empty())
+            .unwrap_or("ERROR")
+            .to_uppercase();
+        let summary = format!("{} {}: {}", level, field("target"), field("message"));
+        match self.format {
+            WebhookFormat::Slack => json!({ "text": summary }),
+            WebhookFormat::Discord => json!({ "content": summary }),
+            WebhookFormat::PagerDuty { ref routing_key } => {
+                let severity = match parse_level(&level) {
+                    Some(Level::Error) => "error",
+                    Some(Level::Warn) => "warning",
+                    _ => "info",
+                };
+                json!({
+                    "routing_key": routing_key,
+                    "event_action": "trigger",
+                    "dedup_key": format!("{}: {}", field("target"), field("message")),
+                    "payload": {
+                        "summary": summary,
+                        "source": field("target"),
+                        "severity": severity,
+                        "custom_details": record,
+                    },
+                })
+            }
+            WebhookFormat::Json => record,
+        }
+    }
+}
+
+/// Creates a new `Box<dyn Writer>` instance with the WebhookWriter, that wraps `inner`.
+pub fn new_writer(inner: Box<dyn Writer>, opts: WebhookOptions) -> Box<dyn Writer> {
+    Box::new(WebhookWriter::new(inner, opts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fn_writer;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    // A minimal HTTP server that replies 200 to `n` requests, it returns the bodies of the requests.
+    fn serve(listener: TcpListener, n: usize) -> Vec<serde_json::Value> {
+        let mut bodies = Vec::new();
+        for _ in 0..n {
+            let (stream, _) = listener.accept().unwrap();
+            let mut w = stream.try_clone().unwrap();
+            let mut r = BufReader::new(stream);
+            let mut len = 0;
+            loop {
+                let mut line = String::new();
+                r.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some(v) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    len = v.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0_u8; len];
+            r.read_exact(&mut body).unwrap();
+            w.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            bodies.push(serde_json::from_slice(&body).unwrap());
+        }
+        bodies
+    }
+
+    fn record(
+        level: &'static str,
+        message: &'static str,
+    ) -> BTreeMap<Key<'static>, Value<'static>> {
+        let mut value = BTreeMap::new();
+        value.insert(Key::from("level"), Value::from(level));
+        value.insert(Key::from("target"), Value::from("app"));
+        value.insert(Key::from("message"), Value::from(message));
+        value
+    }
+
+    #[test]
+    fn webhook_writer_works() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || serve(listener, 2));
+
+        let written = std::sync::Arc::new(Mutex::new(0));
+        let counter = written.clone();
+        let inner = fn_writer(move |_| {
+            *counter.lock() += 1;
+            Ok(())
+        });
+        let writer = WebhookWriter::new(
+            inner,
+            WebhookOptions {
+                url,
+                format: WebhookFormat::Slack,
+                rate_limit: 2,
+                ..Default::default()
+            },
+        );
+        writer.write_log(&record("INFO", "started")).unwrap();
+        writer
+            .write_log(&record("ERROR", "payment failed"))
+            .unwrap();
+        // a duplicate.
+        writer
+            .write_log(&record("ERROR", "payment failed"))
+            .unwrap();
+        writer.write_log(&record("ERROR", "db down")).unwrap();
+        // over the rate limit.
+        writer.write_log(&record("ERROR", "disk full")).unwrap();
+        writer.flush().unwrap();
+
+        assert_eq!(5, *written.lock());
+        assert_eq!(
+            vec![
+                json!({"text": "ERROR app: payment failed"}),
+                json!({"text": "ERROR app: db down"}),
+            ],
+            server.join().unwrap()
+        );
+    }
+
+    #[test]
+    fn pagerduty_payload_works() {
+        let (_, receiver) = mpsc::sync_channel(1);
+        let worker = Worker {
+            agent: ureq::Agent::new_with_defaults(),
+            url: String::new(),
+            format: WebhookFormat::PagerDuty {
+                routing_key: "key".to_string(),
+            },
+            receiver,
+        };
+        let record = json!({"level": "ERROR", "target": "app", "message": "db down"});
+        assert_eq!(
+            json!({
+                "routing_key": "key",
+                "event_action": "trigger",
+                "dedup_key": "app: db down",
+                "payload": {
+                    "summary": "ERROR app: db down",
+                    "source": "app",
+                    "severity": "error",
+                    "custom_details": record.clone(),
+                },
+            }),
+            worker.payload(record)
+        );
+    }
+}
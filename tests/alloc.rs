@@ -0,0 +1,68 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    io,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use structured_logger::{json, Builder};
+
+// Counts the allocations of the threads that armed it, so the allocations of the test harness
+// and of the other threads of the process are not counted.
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static ARMED: Cell<bool> = const { Cell::new(false) };
+}
+
+fn count() {
+    if ARMED.try_with(|armed| armed.get()).unwrap_or(false) {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count();
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+#[test]
+fn default_path_does_not_allocate() {
+    Builder::with_level("info")
+        .with_default_writer(json::new_writer(io::sink()))
+        .with_field_writer("tenant", "acme", json::new_writer(io::sink()))
+        .with_field_writer("audit", true, json::new_writer(io::sink()))
+        .with_field_writer("shard", 7, json::new_writer(io::sink()))
+        .init();
+    let log = || {
+        log::info!(user = "u1", count = 42, ok = true; "hello {}", "world");
+        log::warn!(path = "/api"; "slow request");
+        // the key-values are compared to the field writers without allocating.
+        log::info!(tenant = "other", audit = false, shard = 3; "not routed");
+        log::info!(tenant = "acme"; "routed");
+    };
+
+    // the first records warm up the thread-local buffers.
+    log();
+    ARMED.with(|armed| armed.set(true));
+    for _ in 0..100 {
+        log();
+    }
+    ARMED.with(|armed| armed.set(false));
+    assert_eq!(0, ALLOCATIONS.load(Ordering::Relaxed));
+}
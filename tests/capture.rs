@@ -0,0 +1,38 @@
+use log::Level;
+use std::{thread, time::Duration};
+use structured_logger::{assert_logged, test::init_capture};
+
+#[test]
+fn init_capture_works() {
+    let capture = init_capture();
+    log::info!(target: "api", test = "first"; "request received");
+    thread::sleep(Duration::from_millis(20));
+    log::error!(target: "api", test = "first"; "request failed");
+
+    assert_logged!(level: Info, contains: {"test": "first"});
+    assert_logged!(capture, level: Error, target: "api", message: "failed");
+    // the records of the tests running in parallel are not captured.
+    assert_eq!(2, capture.records().len());
+
+    // a new capture starts empty.
+    let capture = init_capture();
+    log::trace!("verbose");
+    assert_eq!(1, capture.records().len());
+    assert_eq!(Some(Level::Trace), capture.records()[0].level());
+}
+
+#[test]
+fn init_capture_in_parallel_works() {
+    let capture = init_capture();
+    log::warn!(target: "db", test = "second"; "slow query");
+    thread::sleep(Duration::from_millis(20));
+    log::warn!(target: "db", test = "second"; "slow query");
+
+    assert_logged!(level: Warn, target: "db", contains: {"test": "second"});
+    assert_eq!(2, capture.logs_matching(|r| r.target() == Some("db")).len());
+    assert_eq!(2, capture.records().len());
+
+    // the records logged on another thread are not captured.
+    thread::spawn(|| log::info!("spawned")).join().unwrap();
+    assert_eq!(2, capture.records().len());
+}
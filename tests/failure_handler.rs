@@ -0,0 +1,28 @@
+use std::{collections::BTreeMap, io, sync::Mutex};
+use structured_logger::{Builder, Writer};
+
+static FAILURES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+struct BrokenWriter;
+
+impl Writer for BrokenWriter {
+    fn write_log(&self, _value: &BTreeMap<log::kv::Key, log::kv::Value>) -> Result<(), io::Error> {
+        Err(io::Error::other("broken"))
+    }
+}
+
+#[test]
+fn failure_handler_works() {
+    Builder::with_level("info")
+        .with_default_writer(Box::new(BrokenWriter))
+        .with_failure_handler(Box::new(|msg| {
+            FAILURES.lock().unwrap().push(msg.to_string())
+        }))
+        .init();
+
+    log::info!("hello world");
+    assert_eq!(
+        vec!["Logger failed to log: broken".to_string()],
+        *FAILURES.lock().unwrap()
+    );
+}
@@ -0,0 +1,42 @@
+#![cfg(feature = "log-panic")]
+
+use serde_json::{de, value};
+use std::{collections::BTreeMap, io, panic, sync::Mutex};
+use structured_logger::{Builder, Writer};
+
+static RECORDS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+struct MemoryWriter;
+
+impl Writer for MemoryWriter {
+    fn write_log(&self, value: &BTreeMap<log::kv::Key, log::kv::Value>) -> Result<(), io::Error> {
+        RECORDS.lock().unwrap().push(serde_json::to_string(value)?);
+        Ok(())
+    }
+}
+
+#[test]
+fn panic_fields_works() {
+    Builder::with_level("info")
+        .with_default_writer(Box::new(MemoryWriter))
+        .init();
+
+    let res = panic::catch_unwind(|| panic!("boom {}", 42));
+    assert!(res.is_err());
+
+    let records = RECORDS.lock().unwrap();
+    assert_eq!(1, records.len());
+    let res = de::from_str::<BTreeMap<String, value::Value>>(&records[0]).unwrap();
+    assert_eq!("ERROR", res.get("level").unwrap());
+    assert_eq!("panic", res.get("target").unwrap());
+    assert_eq!("boom 42", res.get("panic.payload").unwrap());
+    assert!(res
+        .get("thread_id")
+        .unwrap()
+        .as_str()
+        .unwrap()
+        .starts_with("ThreadId("));
+    assert_eq!("tests/panic.rs", res.get("location.file").unwrap());
+    assert_eq!(res.get("line").unwrap(), res.get("location.line").unwrap());
+    assert!(res.get("location.column").unwrap().as_u64().unwrap() > 0);
+}
@@ -0,0 +1,26 @@
+#![cfg(feature = "log-panic")]
+
+use std::{panic, sync::Mutex};
+use structured_logger::{fn_writer, Builder};
+
+static KEYS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+#[test]
+fn panic_backtrace_works() {
+    Builder::with_level("info")
+        .with_default_writer(fn_writer(|value| {
+            let mut keys = KEYS.lock().unwrap();
+            keys.extend(value.keys().map(|k| k.to_string()));
+            Ok(())
+        }))
+        .with_panic_backtrace(false)
+        .init();
+
+    let res = panic::catch_unwind(|| panic!("boom"));
+    assert!(res.is_err());
+
+    let keys = KEYS.lock().unwrap();
+    assert!(keys.contains(&"panic.payload".to_string()));
+    assert!(keys.contains(&"location.file".to_string()));
+    assert!(!keys.contains(&"backtrace".to_string()));
+}
@@ -0,0 +1,118 @@
+use log::Level;
+use serde_json::json;
+use structured_logger::{log_template, test::init_capture};
+
+// Logs with the global capturing logger, and returns the fields of the records
+// without the timestamp and the source location.
+fn capture(log: impl FnOnce()) -> Vec<serde_json::Value> {
+    let capture = init_capture();
+    log();
+    capture
+        .records()
+        .into_iter()
+        .map(|record| {
+            let mut fields = record.fields().clone();
+            for key in ["timestamp", "file", "line"] {
+                fields.remove(key);
+            }
+            serde_json::Value::Object(fields)
+        })
+        .collect()
+}
+
+#[test]
+fn log_template_works() {
+    let mut calls = 0;
+    let mut next = || {
+        calls += 1;
+        calls
+    };
+    let records = capture(|| {
+        log_template!(
+            Level::Info,
+            "user {} logged in after {} attempts",
+            "user123",
+            next()
+        );
+        log_template!(target: "api", Level::Warn, method = "GET"; "slow request: {}ms", 1200);
+        log_template!(Level::Info, "no arguments");
+    });
+    assert_eq!(1, calls);
+    assert_eq!(3, records.len());
+
+    assert_eq!(
+        "user user123 logged in after 1 attempts",
+        records[0]["message"]
+    );
+    assert_eq!(
+        "user {} logged in after {} attempts",
+        records[0]["message_template"]
+    );
+    assert_eq!(json!(["user123", "1"]), records[0]["message_args"]);
+    assert_eq!("template", records[0]["target"]);
+
+    assert_eq!("slow request: 1200ms", records[1]["message"]);
+    assert_eq!("slow request: {}ms", records[1]["message_template"]);
+    assert_eq!(json!(["1200"]), records[1]["message_args"]);
+    assert_eq!("GET", records[1]["method"]);
+    assert_eq!("api", records[1]["target"]);
+
+    assert_eq!("no arguments", records[2]["message_template"]);
+    assert_eq!(json!([]), records[2]["message_args"]);
+}
+
+#[test]
+fn escaped_braces_works() {
+    let records = capture(|| {
+        log_template!(target: "api", Level::Info, "{{id}} of {} is {{{}}}", "user", 42);
+    });
+    assert_eq!(
+        vec![json!({
+            "level": "INFO",
+            "message": "{id} of user is {42}",
+            "message_args": ["user", "42"],
+            "message_template": "{{id}} of {} is {{{}}}",
+            "target": "api",
+        })],
+        records
+    );
+}
+
+#[test]
+fn missing_fields_works() {
+    // a template without arguments nor key-values only adds the template and the empty arguments.
+    let records = capture(|| {
+        log_template!(Level::Warn, "no {{}} arguments");
+    });
+    assert_eq!(
+        vec![json!({
+            "level": "WARN",
+            "message": "no {} arguments",
+            "message_args": [],
+            "message_template": "no {{}} arguments",
+            "module": "template",
+            "target": "template",
+        })],
+        records
+    );
+}
+
+#[test]
+fn nested_values_works() {
+    let order = json!({"id": 7, "items": [{"sku": "a1", "qty": 2}]});
+    let records = capture(|| {
+        log_template!(target: "api", Level::Info, order:serde = order; "order {} created: {}", 7, order);
+    });
+    // the arguments are rendered as strings, the key-values keep their structure.
+    assert_eq!(
+        vec![json!({
+            "level": "INFO",
+            "message": r#"order 7 created: {"id":7,"items":[{"qty":2,"sku":"a1"}]}"#,
+            "message_args": ["7", r#"{"id":7,"items":[{"qty":2,"sku":"a1"}]}"#],
+            "message_template": "order {} created: {}",
+            "order": {"id": 7, "items": [{"qty": 2, "sku": "a1"}]},
+            "target": "api",
+        })],
+        records
+    );
+}